@@ -0,0 +1,45 @@
+// A value worked out from a key and kept until it is asked for with a
+// different one, so code running every frame only recomputes filter
+// coefficients and the like when their settings actually move.
+#[derive(Copy, Clone, Debug)]
+pub struct Cached<K, V> {
+    entry: Option<(K, V)>,
+}
+
+impl<K, V> Default for Cached<K, V> {
+    fn default() -> Cached<K, V> {
+        Cached { entry: None }
+    }
+}
+
+impl<K: PartialEq, V: Copy> Cached<K, V> {
+    pub fn get(&mut self, key: K, compute: impl FnOnce(&K) -> V) -> V {
+        match &self.entry {
+            Some((cached, value)) if *cached == key => *value,
+            _ => {
+                let value = compute(&key);
+                self.entry = Some((key, value));
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recomputes_only_when_the_key_changes() {
+        let mut cached = Cached::default();
+        let mut computed = 0;
+        for key in [1, 1, 2, 2, 1] {
+            let value = cached.get(key, |key| {
+                computed += 1;
+                key * 10
+            });
+            assert_eq!(value, key * 10);
+        }
+        assert_eq!(computed, 3);
+    }
+}
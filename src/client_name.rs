@@ -0,0 +1,33 @@
+// What JACK does when another client already has the requested name.
+// AutoNumber lets it pick a free variant such as rust_client-01; Exact
+// makes client creation fail instead.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum NameCollision {
+    #[default]
+    AutoNumber,
+    Exact,
+}
+
+impl NameCollision {
+    pub fn client_options(self) -> jack::ClientOptions {
+        match self {
+            NameCollision::AutoNumber => jack::ClientOptions::NO_START_SERVER,
+            NameCollision::Exact => jack::ClientOptions::NO_START_SERVER | jack::ClientOptions::USE_EXACT_NAME,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_strategy_selects_its_client_options() {
+        let auto = NameCollision::AutoNumber.client_options();
+        assert!(!auto.contains(jack::ClientOptions::USE_EXACT_NAME));
+        assert!(auto.contains(jack::ClientOptions::NO_START_SERVER));
+        let exact = NameCollision::Exact.client_options();
+        assert!(exact.contains(jack::ClientOptions::USE_EXACT_NAME | jack::ClientOptions::NO_START_SERVER));
+        assert_eq!(NameCollision::default(), NameCollision::AutoNumber);
+    }
+}
@@ -0,0 +1,119 @@
+use std::io::{self, BufRead, BufReader};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::params::ParamTarget;
+
+// A command from the text control interface. Channels are 0..16 as in
+// Synthesizer; they default to 0 when a line leaves them out.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Command {
+    NoteOn(u8, u8, u8),
+    NoteOff(u8, u8),
+    Set(u8, ParamTarget, f32),
+    Panic,
+}
+
+impl Command {
+    // Parses one line:
+    //
+    //   noteon PITCH VELOCITY [CHANNEL]
+    //   noteoff PITCH [CHANNEL]
+    //   set PARAM VALUE [CHANNEL]
+    //   panic
+    //
+    // PARAM is a ParamTarget name and VALUE runs 0..1. Anything else, including
+    // out-of-range numbers and trailing words, gives None.
+    pub fn parse(line: &str) -> Option<Command> {
+        let mut words = line.split_whitespace();
+        let command = match words.next()? {
+            "noteon" => {
+                let pitch = midi_value(words.next()?)?;
+                let velocity = midi_value(words.next()?)?;
+                Command::NoteOn(channel(words.next())?, pitch, velocity)
+            }
+            "noteoff" => {
+                let pitch = midi_value(words.next()?)?;
+                Command::NoteOff(channel(words.next())?, pitch)
+            }
+            "set" => {
+                let target = ParamTarget::from_name(words.next()?)?;
+                let value = words.next()?.parse::<f32>().ok().filter(|value| value.is_finite())?;
+                Command::Set(channel(words.next())?, target, value)
+            }
+            "panic" => Command::Panic,
+            _ => return None,
+        };
+        match words.next() {
+            Some(_) => None,
+            None => Some(command),
+        }
+    }
+}
+
+fn midi_value(word: &str) -> Option<u8> {
+    word.parse::<u8>().ok().filter(|&value| value < 128)
+}
+
+fn channel(word: Option<&str>) -> Option<u8> {
+    match word {
+        Some(word) => word.parse::<u8>().ok().filter(|&channel| channel < 16),
+        None => Some(0),
+    }
+}
+
+// Listens for TCP connections on `address` and forwards every well-formed line
+// as a Command. Malformed lines are dropped. Each connection gets its own
+// thread; the audio thread only ever calls try_recv on the returned receiver.
+pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<Receiver<Command>> {
+    let listener = TcpListener::bind(address)?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            thread::spawn(move || read_commands(BufReader::new(stream), &sender));
+        }
+    });
+    Ok(receiver)
+}
+
+fn read_commands<R: BufRead>(reader: R, sender: &Sender<Command>) {
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if let Some(command) = Command::parse(&line) {
+            if sender.send(command).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_command() {
+        assert_eq!(Command::parse("noteon 60 100"), Some(Command::NoteOn(0, 60, 100)));
+        assert_eq!(Command::parse("  noteoff 60 9 "), Some(Command::NoteOff(9, 60)));
+        assert_eq!(Command::parse("set cutoff 0.5 2"), Some(Command::Set(2, ParamTarget::Cutoff, 0.5)));
+        assert_eq!(Command::parse("panic"), Some(Command::Panic));
+    }
+
+    #[test]
+    fn malformed_lines_are_dropped() {
+        for line in ["", "noteon", "noteon 128 100", "noteon 60 100 16", "noteoff 60 0 extra", "set nothing 0.5", "set cutoff NaN", "NOTEON 60 100", "panic now"] {
+            assert_eq!(Command::parse(line), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn reads_only_the_good_lines() {
+        let (sender, receiver) = mpsc::channel();
+        read_commands(&b"noteon 60 100\ngarbage\nnoteoff 60\n"[..], &sender);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![Command::NoteOn(0, 60, 100), Command::NoteOff(0, 60)]);
+    }
+}
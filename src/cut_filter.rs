@@ -0,0 +1,110 @@
+use std::f32::consts::PI;
+
+const LOW_CUT_OFF: f32 = 20.0;
+const HIGH_CUT_OFF: f32 = 20000.0;
+
+// Gentle one-pole low-cut (high-pass) and high-cut (low-pass) filters at the
+// head of the master effects chain, to clear sub rumble or fizz before the
+// effects see it. Cutoffs are in Hz; a low cut at or below LOW_CUT_OFF or a
+// high cut at or above HIGH_CUT_OFF is switched off and passes the signal
+// untouched.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CutFilter {
+    pub low_cut: f32,
+    pub high_cut: f32,
+}
+
+impl Default for CutFilter {
+    fn default() -> CutFilter {
+        CutFilter {
+            low_cut: LOW_CUT_OFF,
+            high_cut: HIGH_CUT_OFF,
+        }
+    }
+}
+
+// One-pole smoothing coefficient for a cutoff, or None when that side is off.
+fn coefficient(cutoff: f32, off: bool, sample_rate: usize) -> Option<f32> {
+    (!off).then(|| 1.0 - (-2.0 * PI * cutoff.min(0.49 * sample_rate as f32) / sample_rate as f32).exp())
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CutFilterState {
+    low_cut: Option<f32>,
+    high_cut: Option<f32>,
+    low_memory: [f32; 2],
+    high_memory: [f32; 2],
+}
+
+impl CutFilterState {
+    pub fn new() -> CutFilterState {
+        CutFilterState::default()
+    }
+
+    pub fn configure(&mut self, cut: &CutFilter, sample_rate: usize) {
+        self.low_cut = coefficient(cut.low_cut, cut.low_cut <= LOW_CUT_OFF, sample_rate);
+        self.high_cut = coefficient(cut.high_cut, cut.high_cut >= HIGH_CUT_OFF, sample_rate);
+        if self.low_cut.is_none() {
+            self.low_memory = [0.0; 2];
+        }
+        if self.high_cut.is_none() {
+            self.high_memory = [0.0; 2];
+        }
+    }
+
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let mut values = [left, right];
+        for (i, value) in values.iter_mut().enumerate() {
+            if let Some(a) = self.low_cut {
+                self.low_memory[i] += a * (*value - self.low_memory[i]);
+                *value -= self.low_memory[i];
+            }
+            if let Some(a) = self.high_cut {
+                self.high_memory[i] += a * (*value - self.high_memory[i]);
+                *value = self.high_memory[i];
+            }
+        }
+        (values[0], values[1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    // Steady-state gain in dB for a sine at `frequency`.
+    fn gain_db(cut: &CutFilter, frequency: f32) -> f32 {
+        let mut state = CutFilterState::new();
+        state.configure(cut, SAMPLE_RATE);
+        let step = 2.0 * PI * frequency / SAMPLE_RATE as f32;
+        let peak = (0..19200).map(|i| state.process((step * i as f32).sin(), 0.0).0).skip(9600).fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        20.0 * peak.log10()
+    }
+
+    #[test]
+    fn the_high_cut_attenuates_the_highs() {
+        let cut = CutFilter { high_cut: 1000.0, ..CutFilter::default() };
+        assert!((gain_db(&cut, 1000.0) + 3.0).abs() < 0.3, "{}", gain_db(&cut, 1000.0));
+        assert!((gain_db(&cut, 10000.0) + 20.0).abs() < 1.5, "{}", gain_db(&cut, 10000.0));
+        assert!(gain_db(&cut, 50.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn the_low_cut_attenuates_the_lows() {
+        let cut = CutFilter { low_cut: 200.0, ..CutFilter::default() };
+        assert!((gain_db(&cut, 200.0) + 3.0).abs() < 0.3, "{}", gain_db(&cut, 200.0));
+        assert!((gain_db(&cut, 20.0) + 20.0).abs() < 1.0, "{}", gain_db(&cut, 20.0));
+        assert!(gain_db(&cut, 6000.0).abs() < 0.2, "{}", gain_db(&cut, 6000.0));
+    }
+
+    #[test]
+    fn cuts_at_the_extremes_pass_samples_untouched() {
+        let mut state = CutFilterState::new();
+        state.configure(&CutFilter { low_cut: 10.0, high_cut: 22000.0 }, SAMPLE_RATE);
+        for sample in [0.3, -0.7, 0.01] {
+            assert_eq!(state.process(sample, -sample), (sample, -sample));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+const TIME: f32 = 0.375;
+const FEEDBACK: f32 = 0.4;
+const MAX_TIME: f32 = 2.0;
+const MAX_FEEDBACK: f32 = 0.95;
+
+// Mono feeds the mono sum through one delay line and adds the echoes to both
+// sides equally. PingPong sends the repeats back and forth across two
+// cross-coupled lines, so the first echo is on the left, the second on the
+// right and so on.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum DelayMode {
+    #[default]
+    Mono,
+    PingPong,
+}
+
+// An echo added on top of the dry master signal at `level`: repeats every
+// `time` seconds (up to MAX_TIME), each `feedback` of the one before. Level 0
+// bypasses it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Delay {
+    pub mode: DelayMode,
+    pub level: f32,
+    pub time: f32,
+    pub feedback: f32,
+}
+
+impl Default for Delay {
+    fn default() -> Delay {
+        Delay {
+            mode: DelayMode::default(),
+            level: 0.0,
+            time: TIME,
+            feedback: FEEDBACK,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DelayState {
+    buffer: Vec<(f32, f32)>,
+    position: usize,
+}
+
+impl DelayState {
+    pub fn new() -> DelayState {
+        DelayState {
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+
+    // Allocates the delay lines for `sample_rate`, or frees them while the
+    // delay is bypassed.
+    pub fn configure(&mut self, delay: &Delay, sample_rate: usize) {
+        self.buffer.clear();
+        self.position = 0;
+        if delay.level == 0.0 {
+            return;
+        }
+        let length = (delay.time.clamp(0.0, MAX_TIME) * sample_rate as f32).round() as usize;
+        self.buffer.resize(length.max(1), (0.0, 0.0));
+    }
+
+    // Silences the delay lines without reallocating them.
+    pub fn reset(&mut self) {
+        self.buffer.fill((0.0, 0.0));
+        self.position = 0;
+    }
+
+    pub fn process(&mut self, delay: &Delay, left: f32, right: f32) -> (f32, f32) {
+        self.process_send(delay, left, right, left, right)
+    }
+
+    // Feeds the delay lines from a separate send bus instead of the signal the
+    // echoes are added to.
+    pub fn process_send(&mut self, delay: &Delay, left: f32, right: f32, send_left: f32, send_right: f32) -> (f32, f32) {
+        if delay.level == 0.0 || self.buffer.is_empty() {
+            return (left, right);
+        }
+        let feedback = delay.feedback.clamp(0.0, MAX_FEEDBACK);
+        let input = 0.5 * (send_left + send_right);
+        let (echo_left, echo_right) = self.buffer[self.position];
+        let (wet_left, wet_right) = match delay.mode {
+            DelayMode::Mono => {
+                self.buffer[self.position] = (input + echo_left * feedback, 0.0);
+                (echo_left, echo_left)
+            }
+            DelayMode::PingPong => {
+                self.buffer[self.position] = (input + echo_right * feedback, echo_left * feedback);
+                (echo_left, echo_right)
+            }
+        };
+        self.position = (self.position + 1) % self.buffer.len();
+        (left + wet_left * delay.level, right + wet_right * delay.level)
+    }
+}
+
+impl Default for DelayState {
+    fn default() -> DelayState {
+        DelayState::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 1000;
+
+    // The output for a single click into the left input on frame 0, with a
+    // 100-sample delay.
+    fn echoes(mode: DelayMode, frames: usize) -> Vec<(f32, f32)> {
+        let delay = Delay { mode, level: 1.0, time: 0.1, feedback: 0.5 };
+        let mut state = DelayState::new();
+        state.configure(&delay, SAMPLE_RATE);
+        (0..frames).map(|i| state.process(&delay, if i == 0 { 1.0 } else { 0.0 }, 0.0)).collect()
+    }
+
+    #[test]
+    fn ping_pong_echoes_alternate_sides() {
+        let out = echoes(DelayMode::PingPong, 400);
+        assert_eq!(out[100], (0.5, 0.0));
+        assert_eq!(out[200], (0.0, 0.25));
+        assert_eq!(out[300], (0.125, 0.0));
+        let echo_frames: Vec<_> = out.iter().enumerate().skip(1).filter(|(_, &(l, r))| l != 0.0 || r != 0.0).map(|(i, _)| i).collect();
+        assert_eq!(echo_frames, [100, 200, 300]);
+    }
+
+    #[test]
+    fn mono_echoes_land_on_both_sides() {
+        let out = echoes(DelayMode::Mono, 400);
+        assert_eq!(out[100], (0.5, 0.5));
+        assert_eq!(out[200], (0.25, 0.25));
+        assert_eq!(out[300], (0.125, 0.125));
+    }
+
+    #[test]
+    fn level_zero_bypasses_the_delay() {
+        let delay = Delay::default();
+        let mut state = DelayState::new();
+        state.configure(&delay, SAMPLE_RATE);
+        assert_eq!(state.process(&delay, 0.3, -0.2), (0.3, -0.2));
+    }
+}
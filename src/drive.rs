@@ -0,0 +1,185 @@
+use std::f32::consts::PI;
+
+const MAX_DRIVE: f32 = 20.0;
+const TAPS_PER_FACTOR: usize = 24;
+
+// A tanh saturator on the master signal. `amount` 0 bypasses it. With
+// `oversampling` at 2 or 4 the shaper runs at that multiple of the sample
+// rate between a windowed-sinc interpolator and decimator, so its harmonics
+// above Nyquist are filtered out instead of aliasing back down. That costs
+// TAPS_PER_FACTOR frames of latency; oversampling 1 runs the shaper directly.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Drive {
+    pub amount: f32,
+    pub oversampling: usize,
+}
+
+impl Default for Drive {
+    fn default() -> Drive {
+        Drive {
+            amount: 0.0,
+            oversampling: 1,
+        }
+    }
+}
+
+impl Drive {
+    fn factor(&self) -> usize {
+        match self.oversampling {
+            0 | 1 => 1,
+            2 | 3 => 2,
+            _ => 4,
+        }
+    }
+
+    fn shape(&self, value: f32) -> f32 {
+        (value * (1.0 + self.amount.clamp(0.0, 1.0) * MAX_DRIVE)).tanh()
+    }
+}
+
+// Per-channel filter histories: `input` holds recent base-rate samples for
+// the polyphase interpolator, `shaped` the recent oversampled output of the
+// shaper for the decimator. Both are newest-first.
+#[derive(Clone, Debug, Default)]
+struct Resampler {
+    input: Vec<f32>,
+    shaped: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DriveState {
+    factor: usize,
+    kernel: Vec<f32>,
+    channels: [Resampler; 2],
+}
+
+impl DriveState {
+    pub fn new() -> DriveState {
+        DriveState::default()
+    }
+
+    pub fn configure(&mut self, drive: &Drive) {
+        let factor = drive.factor();
+        self.factor = factor;
+        self.kernel.clear();
+        self.channels = Default::default();
+        if drive.amount == 0.0 || factor == 1 {
+            return;
+        }
+        self.kernel = kernel(factor);
+        let taps = self.kernel.len();
+        for channel in self.channels.iter_mut() {
+            channel.input = vec![0.0; taps.div_ceil(factor)];
+            channel.shaped = vec![0.0; taps];
+        }
+    }
+
+    pub fn latency(&self) -> usize {
+        if self.kernel.is_empty() {
+            0
+        } else {
+            (self.kernel.len() - 1) / self.factor
+        }
+    }
+
+    pub fn process(&mut self, drive: &Drive, left: f32, right: f32) -> (f32, f32) {
+        if drive.amount == 0.0 {
+            return (left, right);
+        }
+        if self.kernel.is_empty() {
+            return (drive.shape(left), drive.shape(right));
+        }
+        let factor = self.factor;
+        let kernel = &self.kernel;
+        let [channel_left, channel_right] = &mut self.channels;
+        (oversample(drive, kernel, factor, channel_left, left), oversample(drive, kernel, factor, channel_right, right))
+    }
+}
+
+fn oversample(drive: &Drive, kernel: &[f32], factor: usize, channel: &mut Resampler, value: f32) -> f32 {
+    channel.input.rotate_right(1);
+    channel.input[0] = value;
+    for phase in 0..factor {
+        let upsampled: f32 = kernel.iter().skip(phase).step_by(factor).zip(channel.input.iter()).map(|(h, x)| h * x).sum();
+        channel.shaped.rotate_right(1);
+        channel.shaped[0] = drive.shape(upsampled * factor as f32);
+    }
+    kernel.iter().zip(channel.shaped.iter()).map(|(h, z)| h * z).sum()
+}
+
+// Blackman-windowed sinc low-pass at the base-rate Nyquist, for the
+// oversampled rate. Unity gain at DC.
+fn kernel(factor: usize) -> Vec<f32> {
+    let taps = TAPS_PER_FACTOR * factor + 1;
+    let center = (taps - 1) as f32 / 2.0;
+    let cutoff = 0.5 / factor as f32;
+    let mut kernel: Vec<f32> = (0..taps)
+        .map(|i| {
+            let x = i as f32 - center;
+            let sinc = if x == 0.0 { 2.0 * cutoff } else { (2.0 * PI * cutoff * x).sin() / (PI * x) };
+            let window = 0.42 - 0.5 * (2.0 * PI * i as f32 / (taps - 1) as f32).cos() + 0.08 * (4.0 * PI * i as f32 / (taps - 1) as f32).cos();
+            sinc * window
+        })
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    for h in kernel.iter_mut() {
+        *h /= sum;
+    }
+    kernel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    // A 15 kHz sine through the drive, then the level at `frequency`.
+    fn level_at(drive: &Drive, frequency: f32) -> f32 {
+        let mut state = DriveState::new();
+        state.configure(drive);
+        let step = 2.0 * std::f32::consts::PI * 15000.0 / SAMPLE_RATE;
+        let out: Vec<f32> = (0..9600).map(|i| state.process(drive, 0.5 * (step * i as f32).sin(), 0.0).0).skip(4800).collect();
+        let step = 2.0 * std::f32::consts::PI * frequency / SAMPLE_RATE;
+        let (cos, sin) = out.iter().enumerate().fold((0.0, 0.0), |(cos, sin), (i, &sample)| {
+            let (s, c) = (step * i as f32).sin_cos();
+            (cos + sample * c, sin + sample * s)
+        });
+        2.0 * (cos * cos + sin * sin).sqrt() / out.len() as f32
+    }
+
+    // The third and seventh harmonics, at 45 and 105 kHz, fold back to 3 and
+    // 9 kHz.
+    fn aliasing(oversampling: usize) -> f32 {
+        let drive = Drive { amount: 1.0, oversampling };
+        level_at(&drive, 3000.0) + level_at(&drive, 9000.0)
+    }
+
+    #[test]
+    fn oversampling_cuts_the_aliasing() {
+        let (plain, oversampled) = (aliasing(1), aliasing(4));
+        assert!(plain > 0.1, "{}", plain);
+        assert!(oversampled < plain / 5.0, "{} {}", plain, oversampled);
+        assert!(aliasing(2) < plain);
+        // The fundamental itself comes through either way.
+        assert!(level_at(&Drive { amount: 1.0, oversampling: 4 }, 15000.0) > 0.5);
+    }
+
+    #[test]
+    fn oversampling_reports_its_latency() {
+        let mut state = DriveState::new();
+        for (oversampling, latency) in [(1, 0), (2, TAPS_PER_FACTOR), (4, TAPS_PER_FACTOR)] {
+            state.configure(&Drive { amount: 0.5, oversampling });
+            assert_eq!(state.latency(), latency);
+        }
+    }
+
+    #[test]
+    fn zero_amount_is_a_bypass() {
+        let drive = Drive { amount: 0.0, oversampling: 4 };
+        let mut state = DriveState::new();
+        state.configure(&drive);
+        assert_eq!(state.latency(), 0);
+        assert_eq!(state.process(&drive, 0.9, -0.4), (0.9, -0.4));
+    }
+}
@@ -0,0 +1,194 @@
+use std::f32::consts::PI;
+
+const LOW_FREQUENCY: f32 = 200.0;
+const MID_FREQUENCY: f32 = 1000.0;
+const MID_Q: f32 = 0.7;
+const HIGH_FREQUENCY: f32 = 5000.0;
+const SHELF_SLOPE: f32 = 1.0;
+
+// Three-band master EQ: low shelf, mid peak and high shelf, with gains in dB.
+// A band at 0 dB is skipped, so a flat EQ passes the signal untouched.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Equalizer {
+    pub low_gain: f32,
+    pub low_frequency: f32,
+    pub mid_gain: f32,
+    pub mid_frequency: f32,
+    pub mid_q: f32,
+    pub high_gain: f32,
+    pub high_frequency: f32,
+}
+
+impl Default for Equalizer {
+    fn default() -> Equalizer {
+        Equalizer {
+            low_gain: 0.0,
+            low_frequency: LOW_FREQUENCY,
+            mid_gain: 0.0,
+            mid_frequency: MID_FREQUENCY,
+            mid_q: MID_Q,
+            high_gain: 0.0,
+            high_frequency: HIGH_FREQUENCY,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Band {
+    LowShelf,
+    Peak,
+    HighShelf,
+}
+
+// RBJ cookbook biquad, normalized so a0 = 1.
+#[derive(Copy, Clone, Debug)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn new(band: Band, gain: f32, frequency: f32, q: f32, sample_rate: f32) -> Biquad {
+        let a = 10.0_f32.powf(gain / 40.0);
+        let w0 = 2.0 * PI * frequency.clamp(10.0, 0.49 * sample_rate) / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let (b0, b1, b2, a0, a1, a2) = match band {
+            Band::Peak => {
+                let alpha = sin / (2.0 * q.max(0.01));
+                (1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a, 1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a)
+            }
+            Band::LowShelf | Band::HighShelf => {
+                let alpha = sin / 2.0 * ((a + 1.0 / a) * (1.0 / SHELF_SLOPE - 1.0) + 2.0).sqrt();
+                let root = 2.0 * a.sqrt() * alpha;
+                if band == Band::LowShelf {
+                    (
+                        a * ((a + 1.0) - (a - 1.0) * cos + root),
+                        2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                        a * ((a + 1.0) - (a - 1.0) * cos - root),
+                        (a + 1.0) + (a - 1.0) * cos + root,
+                        -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                        (a + 1.0) + (a - 1.0) * cos - root,
+                    )
+                } else {
+                    (
+                        a * ((a + 1.0) + (a - 1.0) * cos + root),
+                        -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                        a * ((a + 1.0) + (a - 1.0) * cos - root),
+                        (a + 1.0) - (a - 1.0) * cos + root,
+                        2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                        (a + 1.0) - (a - 1.0) * cos - root,
+                    )
+                }
+            }
+        };
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+// Transposed direct form II state for one channel of one band.
+#[derive(Copy, Clone, Debug, Default)]
+struct BiquadState {
+    z1: f32,
+    z2: f32,
+}
+
+impl BiquadState {
+    fn process(&mut self, biquad: &Biquad, input: f32) -> f32 {
+        let output = biquad.b0 * input + self.z1;
+        self.z1 = biquad.b1 * input - biquad.a1 * output + self.z2;
+        self.z2 = biquad.b2 * input - biquad.a2 * output;
+        output
+    }
+}
+
+// Coefficients are worked out in `configure`, only when the settings or the
+// sample rate change; `process` just runs the filters. Filter state is kept
+// across reconfiguring so gain changes don't click.
+#[derive(Clone, Debug, Default)]
+pub struct EqualizerState {
+    bands: [(Option<Biquad>, BiquadState, BiquadState); 3],
+}
+
+impl EqualizerState {
+    pub fn new() -> EqualizerState {
+        EqualizerState::default()
+    }
+
+    pub fn configure(&mut self, equalizer: &Equalizer, sample_rate: usize) {
+        let sample_rate = sample_rate as f32;
+        let settings = [
+            (Band::LowShelf, equalizer.low_gain, equalizer.low_frequency, SHELF_SLOPE),
+            (Band::Peak, equalizer.mid_gain, equalizer.mid_frequency, equalizer.mid_q),
+            (Band::HighShelf, equalizer.high_gain, equalizer.high_frequency, SHELF_SLOPE),
+        ];
+        for ((biquad, state_left, state_right), (band, gain, frequency, q)) in self.bands.iter_mut().zip(settings) {
+            if gain == 0.0 {
+                *biquad = None;
+                *state_left = BiquadState::default();
+                *state_right = BiquadState::default();
+            } else {
+                *biquad = Some(Biquad::new(band, gain, frequency, q, sample_rate));
+            }
+        }
+    }
+
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let (mut left, mut right) = (left, right);
+        for (biquad, state_left, state_right) in self.bands.iter_mut() {
+            if let Some(biquad) = biquad {
+                left = state_left.process(biquad, left);
+                right = state_right.process(biquad, right);
+            }
+        }
+        (left, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    // Steady-state gain in dB for a sine at `frequency`.
+    fn gain_db(equalizer: &Equalizer, frequency: f32) -> f32 {
+        let mut state = EqualizerState::new();
+        state.configure(equalizer, SAMPLE_RATE);
+        let step = 2.0 * std::f32::consts::PI * frequency / SAMPLE_RATE as f32;
+        let peak = (0..9600).map(|i| state.process((step * i as f32).sin(), 0.0).0).skip(4800).fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        20.0 * peak.log10()
+    }
+
+    #[test]
+    fn a_high_shelf_boosts_only_the_highs() {
+        let equalizer = Equalizer { high_gain: 6.0, ..Equalizer::default() };
+        assert!((gain_db(&equalizer, 15000.0) - 6.0).abs() < 0.3, "{}", gain_db(&equalizer, 15000.0));
+        assert!(gain_db(&equalizer, 100.0).abs() < 0.1);
+        assert!(gain_db(&Equalizer::default(), 15000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn a_low_shelf_and_mid_peak_boost_their_bands() {
+        let equalizer = Equalizer { low_gain: -6.0, mid_gain: 6.0, ..Equalizer::default() };
+        assert!((gain_db(&equalizer, 30.0) + 6.0).abs() < 0.3);
+        assert!((gain_db(&equalizer, 1000.0) - 6.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn a_flat_equalizer_passes_samples_untouched() {
+        let mut state = EqualizerState::new();
+        state.configure(&Equalizer::default(), SAMPLE_RATE);
+        for sample in [0.3, -0.7, 0.01] {
+            assert_eq!(state.process(sample, -sample), (sample, -sample));
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::f32::consts::PI;
+
+const MAX_CUTOFF_RATIO: f32 = 0.49;
+const MIN_CUTOFF: f32 = 10.0;
+pub const OPEN_CUTOFF: f32 = 20000.0;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum FilterMode {
+    #[default]
+    LowPass,
+    HighPass,
+    BandPass,
+}
+
+// Naive is the classic Chamberlin state-variable filter: cheap, but its
+// one-sample delay in the feedback path detunes the cutoff and resonance as the
+// cutoff approaches Nyquist. ZeroDelay is the topology-preserving (TPT) version,
+// which stays accurate up to Nyquist and self-oscillates cleanly.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum FilterTopology {
+    Naive,
+    #[default]
+    ZeroDelay,
+}
+
+// PerVoice gives every note its own filter, so per-note modulation of the
+// cutoff and resonance is heard per note. Master runs one filter over the
+// channel's summed voices: cheaper with many notes, but every note shares the
+// channel patch's cutoff.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum FilterPlacement {
+    #[default]
+    PerVoice,
+    Master,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Filter {
+    pub mode: FilterMode,
+    pub topology: FilterTopology,
+    pub placement: FilterPlacement,
+    pub cutoff: f32,
+    pub resonance: f32,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            mode: FilterMode::default(),
+            topology: FilterTopology::default(),
+            placement: FilterPlacement::default(),
+            cutoff: OPEN_CUTOFF,
+            resonance: 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FilterCoefficients {
+    mode: FilterMode,
+    topology: FilterTopology,
+    g: f32,
+    k: f32,
+    a1: f32,
+    a2: f32,
+    a3: f32,
+}
+
+impl FilterCoefficients {
+    pub fn new(filter: &Filter, cutoff: f32, sample_rate: f32) -> FilterCoefficients {
+        let cutoff = cutoff.clamp(MIN_CUTOFF, MAX_CUTOFF_RATIO * sample_rate);
+        let k = 2.0 * (1.0 - filter.resonance.clamp(0.0, 1.0));
+        let (g, a1, a2, a3) = match filter.topology {
+            // Keep g inside the region where the naive loop stays stable
+            // (g² + 2gk < 4); this is where it stops tracking the cutoff.
+            FilterTopology::Naive => {
+                let limit = 0.99 * ((k * k + 4.0).sqrt() - k);
+                ((2.0 * (PI * cutoff / sample_rate).sin()).min(limit), 0.0, 0.0, 0.0)
+            }
+            FilterTopology::ZeroDelay => {
+                let g = (PI * cutoff / sample_rate).tan();
+                let a1 = 1.0 / (1.0 + g * (g + k));
+                let a2 = g * a1;
+                (g, a1, a2, g * a2)
+            }
+        };
+
+        FilterCoefficients {
+            mode: filter.mode,
+            topology: filter.topology,
+            g,
+            k,
+            a1,
+            a2,
+            a3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FilterState {
+    s1: f32,
+    s2: f32,
+}
+
+impl FilterState {
+    pub fn reset(&mut self) {
+        *self = FilterState::default();
+    }
+
+    pub fn process(&mut self, coefficients: &FilterCoefficients, input: f32) -> f32 {
+        let (low, band, high) = match coefficients.topology {
+            FilterTopology::Naive => {
+                let low = self.s2 + coefficients.g * self.s1;
+                let high = input - low - coefficients.k * self.s1;
+                let band = coefficients.g * high + self.s1;
+                self.s1 = band;
+                self.s2 = low;
+                (low, band, high)
+            }
+            FilterTopology::ZeroDelay => {
+                let v3 = input - self.s2;
+                let v1 = coefficients.a1 * self.s1 + coefficients.a2 * v3;
+                let v2 = self.s2 + coefficients.a2 * self.s1 + coefficients.a3 * v3;
+                self.s1 = 2.0 * v1 - self.s1;
+                self.s2 = 2.0 * v2 - self.s2;
+                (v2, v1, input - coefficients.k * v1 - v2)
+            }
+        };
+
+        match coefficients.mode {
+            FilterMode::LowPass => low,
+            FilterMode::HighPass => high,
+            FilterMode::BandPass => band,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn gain(filter: &Filter, frequency: f32) -> f32 {
+        let coefficients = FilterCoefficients::new(filter, filter.cutoff, SAMPLE_RATE);
+        let mut state = FilterState::default();
+        let step = 2.0 * PI * frequency / SAMPLE_RATE;
+        (0..9600).map(|i| state.process(&coefficients, (step * i as f32).sin())).skip(4800).fold(0.0, |peak, sample: f32| peak.max(sample.abs()))
+    }
+
+    // The sine frequency, swept from 20% below the cutoff to 20% above (or
+    // to just under Nyquist), that comes out loudest.
+    fn resonant_peak(filter: &Filter) -> f32 {
+        let top = (1.2 * filter.cutoff).min(0.49 * SAMPLE_RATE);
+        let bottom = 0.8 * filter.cutoff;
+        let mut loudest = (0.0, 0.0);
+        for i in 0..=400 {
+            let frequency = bottom + (top - bottom) * i as f32 / 400.0;
+            let gain = gain(filter, frequency);
+            if gain > loudest.1 {
+                loudest = (frequency, gain);
+            }
+        }
+        loudest.0
+    }
+
+    fn peak_error(topology: FilterTopology, cutoff: f32) -> f32 {
+        let filter = Filter { topology, cutoff, resonance: 0.95, ..Filter::default() };
+        (resonant_peak(&filter) / cutoff - 1.0).abs()
+    }
+
+    #[test]
+    fn the_zero_delay_peak_stays_on_the_cutoff_near_nyquist() {
+        assert!(peak_error(FilterTopology::ZeroDelay, 1000.0) < 0.01);
+        assert!(peak_error(FilterTopology::Naive, 1000.0) < 0.01);
+        assert!(peak_error(FilterTopology::ZeroDelay, 15000.0) < 0.01);
+        assert!(peak_error(FilterTopology::Naive, 15000.0) > 0.05);
+    }
+
+    #[test]
+    fn full_resonance_self_oscillates_without_running_away() {
+        let filter = Filter { cutoff: 2000.0, resonance: 1.0, ..Filter::default() };
+        let coefficients = FilterCoefficients::new(&filter, filter.cutoff, SAMPLE_RATE);
+        let mut state = FilterState::default();
+        state.process(&coefficients, 1.0);
+        let ring: Vec<f32> = (0..48000).map(|_| state.process(&coefficients, 0.0)).collect();
+        let early = ring[..4800].iter().fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        let late = ring[43200..].iter().fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        assert!(late > 0.9 * early && late < 1.1 * early);
+    }
+}
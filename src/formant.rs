@@ -0,0 +1,137 @@
+use crate::filter::{Filter, FilterCoefficients, FilterMode, FilterState, FilterTopology};
+
+pub const VOWELS: usize = 5;
+pub const BANDS: usize = 3;
+const RESONANCE: f32 = 0.9;
+
+// Formant centers in Hz and band levels in dB for a low voice, by vowel.
+const FORMANTS: [[(f32, f32); BANDS]; VOWELS] = [
+    [(800.0, 0.0), (1150.0, -6.0), (2900.0, -32.0)],
+    [(350.0, 0.0), (2000.0, -20.0), (2800.0, -15.0)],
+    [(270.0, 0.0), (2140.0, -12.0), (2950.0, -26.0)],
+    [(450.0, 0.0), (800.0, -11.0), (2830.0, -22.0)],
+    [(325.0, 0.0), (700.0, -16.0), (2700.0, -35.0)],
+];
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Vowel {
+    #[default]
+    A,
+    E,
+    I,
+    O,
+    U,
+}
+
+impl Vowel {
+    // The vowel's place on the morph scale.
+    pub fn position(&self) -> f32 {
+        *self as usize as f32
+    }
+}
+
+// A bank of three parallel band-passes on the formants of a sung vowel, mixed
+// over the channel's voices at `mix`; mix 0 bypasses it. `vowel` morphs
+// through A, E, I, O and U at 0, 1, 2, 3 and 4, and values between two vowels
+// slide the formants from one to the other.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Formant {
+    pub mix: f32,
+    pub vowel: f32,
+}
+
+impl Formant {
+    // Center frequency and linear level of each band at the current morph.
+    pub fn bands(&self) -> [(f32, f32); BANDS] {
+        let position = self.vowel.clamp(0.0, (VOWELS - 1) as f32);
+        let index = (position as usize).min(VOWELS - 2);
+        let t = position - index as f32;
+        std::array::from_fn(|band| {
+            let (from_frequency, from_level) = FORMANTS[index][band];
+            let (to_frequency, to_level) = FORMANTS[index + 1][band];
+            let frequency = from_frequency * (to_frequency / from_frequency).powf(t);
+            let level = from_level + (to_level - from_level) * t;
+            (frequency, 10.0_f32.powf(level / 20.0))
+        })
+    }
+
+    pub fn coefficients(&self, sample_rate: f32) -> [(FilterCoefficients, f32); BANDS] {
+        let bands = self.bands();
+        std::array::from_fn(|band| {
+            let (frequency, level) = bands[band];
+            let filter = Filter {
+                mode: FilterMode::BandPass,
+                topology: FilterTopology::ZeroDelay,
+                cutoff: frequency,
+                resonance: RESONANCE,
+                ..Filter::default()
+            };
+            // The band-pass peaks at 1 / k; scale that back to unity.
+            (FilterCoefficients::new(&filter, frequency, sample_rate), level * 2.0 * (1.0 - RESONANCE))
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FormantState {
+    bands: [FilterState; BANDS],
+}
+
+impl FormantState {
+    pub fn reset(&mut self) {
+        *self = FormantState::default();
+    }
+
+    pub fn process(&mut self, formant: &Formant, coefficients: &[(FilterCoefficients, f32); BANDS], input: f32) -> f32 {
+        let mut wet = 0.0;
+        for (state, (coefficients, level)) in self.bands.iter_mut().zip(coefficients.iter()) {
+            wet += level * state.process(coefficients, input);
+        }
+        let mix = formant.mix.clamp(0.0, 1.0);
+        input + (wet - input) * mix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    // Steady-state gain for a sine at `frequency` through the fully wet bank.
+    fn gain(formant: &Formant, frequency: f32) -> f32 {
+        let coefficients = formant.coefficients(SAMPLE_RATE as f32);
+        let mut state = FormantState::default();
+        let step = 2.0 * std::f32::consts::PI * frequency / SAMPLE_RATE as f32;
+        (0..9600).map(|i| state.process(formant, &coefficients, (step * i as f32).sin())).skip(4800).fold(0.0_f32, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn the_a_vowel_peaks_at_its_formants() {
+        let formant = Formant { mix: 1.0, vowel: Vowel::A.position() };
+        let frequencies: Vec<f32> = (4..=60).map(|step| step as f32 * 50.0).collect();
+        let gains: Vec<f32> = frequencies.iter().map(|&frequency| gain(&formant, frequency)).collect();
+        let peaks: Vec<f32> = (1..gains.len() - 1).filter(|&i| gains[i] > gains[i - 1] && gains[i] > gains[i + 1]).map(|i| frequencies[i]).collect();
+        assert!(peaks.len() >= 2 && (peaks[0] - 800.0).abs() <= 50.0 && (peaks[1] - 1150.0).abs() <= 50.0, "{:?}", peaks);
+        assert!((gain(&formant, 800.0) - 1.0).abs() < 0.2, "{}", gain(&formant, 800.0));
+        assert!(gain(&formant, 300.0) < 0.5 * gain(&formant, 800.0));
+    }
+
+    #[test]
+    fn the_vowel_morphs_between_neighbours() {
+        let halfway = Formant { mix: 1.0, vowel: 0.5 }.bands();
+        assert!((halfway[0].0 - (800.0_f32 * 350.0).sqrt()).abs() < 0.1);
+        assert_eq!(Formant { vowel: 1.0, ..Formant::default() }.bands()[0].0, 350.0);
+        assert_eq!(Formant { vowel: 9.0, ..Formant::default() }.bands(), Formant { vowel: Vowel::U.position(), ..Formant::default() }.bands());
+    }
+
+    #[test]
+    fn mix_zero_bypasses_the_bank() {
+        let formant = Formant::default();
+        let coefficients = formant.coefficients(SAMPLE_RATE as f32);
+        let mut state = FormantState::default();
+        for sample in [0.3, -0.7, 0.01] {
+            assert_eq!(state.process(&formant, &coefficients, sample), sample);
+        }
+    }
+}
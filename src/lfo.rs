@@ -0,0 +1,88 @@
+use std::f32::consts::PI;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum LfoWaveform {
+    #[default]
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+}
+
+impl LfoWaveform {
+    pub fn sample(&self, phase: f32) -> f32 {
+        match self {
+            LfoWaveform::Sine => (phase * 2.0 * PI).sin(),
+            LfoWaveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            LfoWaveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            LfoWaveform::Saw => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+// When an LFO's phase restarts: never (Free), on every note-on, or on every
+// beat of the transport so it stays locked to the groove.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum LfoRetrigger {
+    BeatSync,
+    Free,
+    #[default]
+    NoteOn,
+}
+
+// Whether a mod matrix LFO runs once per channel with one phase every note
+// shares, or once per note with its own phase from the note's start, so
+// voices started at different times wobble independently.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum LfoMode {
+    #[default]
+    Global,
+    PerVoice,
+}
+
+// A rate in Hz, or a note length in beats (1.0 = quarter note) that takes over
+// whenever a tempo is known.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LfoRate {
+    pub hz: f32,
+    pub division: Option<f32>,
+}
+
+impl LfoRate {
+    pub fn frequency(&self, tempo: Option<f32>) -> f32 {
+        match (self.division, tempo) {
+            (Some(division), Some(bpm)) if division > 0.0 => bpm / 60.0 / division,
+            _ => self.hz,
+        }
+    }
+}
+
+impl Default for LfoRate {
+    fn default() -> LfoRate {
+        LfoRate {
+            hz: 4.0,
+            division: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Lfo {
+    phase: f32,
+}
+
+impl Lfo {
+    pub fn phase(&self) -> f32 {
+        self.phase
+    }
+
+    pub fn reset(&mut self, phase: f32) {
+        self.phase = phase.rem_euclid(1.0);
+    }
+
+    pub fn next(&mut self, waveform: LfoWaveform, frequency: f32, time_step: f32) -> f32 {
+        let value = waveform.sample(self.phase);
+        self.phase = (self.phase + frequency * time_step).rem_euclid(1.0);
+        value
+    }
+}
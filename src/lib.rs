@@ -0,0 +1,76 @@
+mod cached;
+mod client_name;
+mod control;
+mod cut_filter;
+mod delay;
+mod drive;
+mod equalizer;
+mod filter;
+mod formant;
+mod lfo;
+mod limiter;
+mod macros;
+mod master;
+mod midi_log;
+mod mod_matrix;
+mod monitor;
+mod mpe;
+mod note;
+mod overload;
+mod params;
+mod patch;
+mod pcm;
+mod preset;
+mod random;
+mod reverb;
+mod sample;
+mod scale;
+mod self_test;
+mod sequencer;
+mod synthesizer;
+mod test_tone;
+mod tilt;
+mod transport;
+mod tuning;
+mod velocity_range;
+mod watchdog;
+mod wavetable;
+mod wow_flutter;
+
+pub use client_name::NameCollision;
+pub use control::{listen, Command};
+pub use cut_filter::{CutFilter, CutFilterState};
+pub use delay::{Delay, DelayMode, DelayState};
+pub use drive::{Drive, DriveState};
+pub use equalizer::{Equalizer, EqualizerState};
+pub use filter::{Filter, FilterCoefficients, FilterMode, FilterPlacement, FilterState, FilterTopology};
+pub use formant::{Formant, FormantState, Vowel, VOWELS};
+pub use lfo::{Lfo, LfoMode, LfoRate, LfoRetrigger, LfoWaveform};
+pub use limiter::{Limiter, LimiterState};
+pub use macros::{Macro, MacroMapping, MAX_MACROS};
+pub use master::{speaker_gains, Modulation, MAX_SPEAKERS};
+pub use midi_log::{describe_midi, MidiLog};
+pub use mod_matrix::{ModDestination, ModLfo, ModMatrix, ModRoute, ModSource, MAX_MOD_ENVELOPES, MAX_MOD_LFOS, MAX_MOD_ROUTES};
+pub use monitor::{VoiceEvent, VoiceEventKind, VoiceEventQueue, VoiceMonitor, VoiceState};
+pub use mpe::{Mpe, MpeZone, SLIDE_CUTOFF};
+pub use note::EnvelopePhase;
+pub use overload::{guard_output, NonFiniteCounter, OverloadIndicator, MAX_OUTPUT, OVERLOAD_HOLD};
+pub use params::{ParamInfo, ParamScale, ParamTarget, PARAM_TARGETS};
+pub use patch::{Crossfade, Envelope, GlideCurve, GlideMode, NotePriority, PanSpreadMode, Patch, VelocityLayers, Waveform};
+pub use pcm::{write_wav, BitDepth, Dither, PcmConverter};
+pub use preset::{decode_patch, encode_patch, CcBindings, PRESET_VERSION};
+pub use random::{Random, DEFAULT_SEED};
+pub use reverb::{Reverb, ReverbState};
+pub use sample::Sample;
+pub use scale::Scale;
+pub use self_test::{self_test, SelfTestCheck, SelfTestReport};
+pub use sequencer::{humanize, ArpMode, Arpeggiator, Sequencer, Step, StepClock};
+pub use synthesizer::{Synthesizer, VoiceStealMode, CHANNELS};
+pub use test_tone::{TestTone, TEST_TONE_LEVEL};
+pub use tilt::{Tilt, TiltState};
+pub use transport::Transport;
+pub use tuning::Tuning;
+pub use velocity_range::VelocityRange;
+pub use watchdog::{FeedbackStage, Watchdog, WatchdogLog, WATCHDOG_THRESHOLD};
+pub use wavetable::Wavetable;
+pub use wow_flutter::{WowFlutter, WowFlutterState};
@@ -0,0 +1,192 @@
+const THRESHOLD: f32 = 1.0;
+const ATTACK: f32 = 0.001;
+const RELEASE: f32 = 0.1;
+
+// A peak limiter on the master output. Without look-ahead the gain reacts
+// over ATTACK seconds once the level is already over threshold, so sharp
+// transients get through. With `lookahead` seconds the signal is delayed by
+// that much and the gain is taken from the loudest sample still to come, so
+// the output never exceeds the threshold, at the cost of that much latency.
+//
+// `attack` and `release` are time constants in seconds and set the
+// character: a short release recovers quickly and pumps on dense material,
+// a long one is smoother but holds the level down after a peak. The 0.1 s
+// default is slow enough not to distort bass and fast enough not to leave
+// audible holes. With look-ahead the attack is the window itself.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Limiter {
+    pub enabled: bool,
+    pub threshold: f32,
+    pub lookahead: f32,
+    pub attack: f32,
+    pub release: f32,
+}
+
+impl Default for Limiter {
+    fn default() -> Limiter {
+        Limiter {
+            enabled: false,
+            threshold: THRESHOLD,
+            lookahead: 0.0,
+            attack: ATTACK,
+            release: RELEASE,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LimiterState {
+    delay: Vec<(f32, f32, f32)>,
+    position: usize,
+    gain: f32,
+}
+
+impl LimiterState {
+    pub fn new() -> LimiterState {
+        LimiterState {
+            delay: Vec::new(),
+            position: 0,
+            gain: 1.0,
+        }
+    }
+
+    // Sizes the look-ahead buffer; call whenever the settings or the sample
+    // rate change.
+    pub fn configure(&mut self, limiter: &Limiter, sample_rate: usize) {
+        let frames = if limiter.enabled { (limiter.lookahead.max(0.0) * sample_rate as f32).round() as usize } else { 0 };
+        self.delay.clear();
+        self.delay.resize(frames, (0.0, 0.0, 1.0));
+        self.position = 0;
+        self.gain = 1.0;
+    }
+
+    // The gain currently applied, 1 when the limiter isn't reducing.
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    // Latency in frames added by the look-ahead delay.
+    pub fn latency(&self) -> usize {
+        self.delay.len()
+    }
+
+    pub fn process(&mut self, limiter: &Limiter, left: f32, right: f32, time_step: f32) -> (f32, f32) {
+        if !limiter.enabled {
+            return (left, right);
+        }
+        let peak = left.abs().max(right.abs());
+        let threshold = limiter.threshold.max(f32::EPSILON);
+        let target = if peak > threshold { threshold / peak } else { 1.0 };
+        let release = 1.0 - (-time_step / limiter.release.max(time_step)).exp();
+
+        if self.delay.is_empty() {
+            if target < self.gain {
+                self.gain += (target - self.gain) * (1.0 - (-time_step / limiter.attack.max(time_step)).exp());
+            } else {
+                self.gain += (target - self.gain) * release;
+            }
+            return (left * self.gain, right * self.gain);
+        }
+
+        let (l, r, delayed_target) = std::mem::replace(&mut self.delay[self.position], (left, right, target));
+        self.position = (self.position + 1) % self.delay.len();
+        let upcoming = self.delay.iter().fold(delayed_target, |a, &(_, _, t)| a.min(t));
+        if upcoming < self.gain {
+            // Ease down across the look-ahead window; the clamp below catches
+            // whatever is left when the peak itself comes out.
+            self.gain -= (self.gain - upcoming) / self.delay.len() as f32;
+        } else {
+            self.gain += (upcoming - self.gain) * release;
+        }
+        let gain = self.gain.min(delayed_target);
+        (l * gain, r * gain)
+    }
+}
+
+impl Default for LimiterState {
+    fn default() -> LimiterState {
+        LimiterState::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    // A quiet signal that jumps straight to four times the threshold.
+    fn transient_peak(lookahead: f32) -> (f32, usize) {
+        let limiter = Limiter { enabled: true, lookahead, ..Limiter::default() };
+        let mut state = LimiterState::new();
+        state.configure(&limiter, SAMPLE_RATE);
+        let mut peak: f32 = 0.0;
+        for frame in 0..4800 {
+            let input = if frame < 2400 { 0.1 } else { 4.0 };
+            let (left, right) = state.process(&limiter, input, -input, 1.0 / SAMPLE_RATE as f32);
+            peak = peak.max(left.abs()).max(right.abs());
+        }
+        (peak, state.latency())
+    }
+
+    #[test]
+    fn lookahead_holds_a_transient_under_the_threshold() {
+        let (peak, latency) = transient_peak(0.005);
+        assert_eq!(latency, 240);
+        assert!(peak <= THRESHOLD, "peak {}", peak);
+    }
+
+    #[test]
+    fn without_lookahead_a_transient_overshoots() {
+        let (peak, latency) = transient_peak(0.0);
+        assert_eq!(latency, 0);
+        assert!(peak > 2.0 * THRESHOLD, "peak {}", peak);
+    }
+
+    #[test]
+    fn disabled_by_default_with_no_latency() {
+        let limiter = Limiter::default();
+        let mut state = LimiterState::new();
+        state.configure(&limiter, SAMPLE_RATE);
+        assert_eq!(state.latency(), 0);
+        assert_eq!(state.process(&limiter, 4.0, -4.0, 1.0 / SAMPLE_RATE as f32), (4.0, -4.0));
+    }
+
+    // The gain after 10 ms at +12 dB and then `quiet` frames of quiet input.
+    fn gain_after_burst(limiter: Limiter, quiet: usize) -> f32 {
+        let mut state = LimiterState::new();
+        state.configure(&limiter, SAMPLE_RATE);
+        for frame in 0..480 + quiet {
+            let input = if frame < 480 { 4.0 } else { 0.1 };
+            state.process(&limiter, input, input, 1.0 / SAMPLE_RATE as f32);
+        }
+        state.gain()
+    }
+
+    #[test]
+    fn a_shorter_release_recovers_faster() {
+        let limiter = |release| Limiter { enabled: true, release, ..Limiter::default() };
+        assert!((gain_after_burst(limiter(0.02), 0) - 0.25).abs() < 0.01);
+        let (fast, slow) = (gain_after_burst(limiter(0.02), 2400), gain_after_burst(limiter(0.2), 2400));
+        assert!(fast > 0.9 && slow < 0.5, "{} {}", fast, slow);
+        let trajectory: Vec<f32> = (0..5).map(|step| gain_after_burst(limiter(0.2), step * 2400)).collect();
+        assert!(trajectory.windows(2).all(|pair| pair[1] > pair[0]));
+    }
+
+    #[test]
+    fn a_longer_attack_lets_more_of_a_burst_through() {
+        // The gain `frames` into a steady +12 dB burst.
+        let gain_after = |attack, frames| {
+            let limiter = Limiter { enabled: true, attack, ..Limiter::default() };
+            let mut state = LimiterState::new();
+            state.configure(&limiter, SAMPLE_RATE);
+            for _ in 0..frames {
+                state.process(&limiter, 4.0, 4.0, 1.0 / SAMPLE_RATE as f32);
+            }
+            state.gain()
+        };
+        let (fast, slow) = (gain_after(ATTACK, 48), gain_after(0.01, 48));
+        assert!(slow > fast + 0.2, "{} {}", fast, slow);
+        assert!((gain_after(0.01, 4800) - 0.25).abs() < 0.01);
+    }
+}
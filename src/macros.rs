@@ -0,0 +1,41 @@
+use crate::params::ParamTarget;
+
+pub const MAX_MACROS: usize = 8;
+
+// One parameter a macro moves. As the macro runs 0..1 the target's
+// normalized value runs from `min` to `max`; min above max turns it down.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MacroMapping {
+    pub target: ParamTarget,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl MacroMapping {
+    pub fn value(&self, amount: f32) -> f32 {
+        self.min + (self.max - self.min) * amount.clamp(0.0, 1.0)
+    }
+}
+
+// A single performance knob driving any number of parameters at once.
+// `value` is where the knob was last set, normalized 0..1.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Macro {
+    pub mappings: Vec<MacroMapping>,
+    pub value: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_mapping_interpolates_its_range() {
+        let mapping = MacroMapping { target: ParamTarget::Cutoff, min: 0.2, max: 0.6 };
+        assert_eq!(mapping.value(0.0), 0.2);
+        assert!((mapping.value(0.5) - 0.4).abs() < 1e-6);
+        assert_eq!(mapping.value(2.0), 0.6);
+        let reversed = MacroMapping { min: 0.8, max: 0.0, ..mapping };
+        assert!((reversed.value(0.25) - 0.6).abs() < 1e-6);
+    }
+}
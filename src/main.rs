@@ -1,176 +1,168 @@
-use jack;
+use std::sync::{Arc, Mutex};
 
-const MAX_AMPLITUDE: f32 = 0.2;
-
-const ATTACK: usize = 2000;
-const DECAY: usize = 5000;
-const SUSTAIN: f32 = 0.6;
-const RELEASE: usize = 10000;
+use synthesizer::{listen, self_test, FeedbackStage, NameCollision, Synthesizer, Tuning, MAX_SPEAKERS, TEST_TONE_LEVEL};
 
 fn main() {
-    let (client, _status) = jack::Client::new("rust_client", jack::ClientOptions::NO_START_SERVER).unwrap();
-    let midi_in_port = client.register_port("midi_in", jack::MidiIn::default()).unwrap();
-    let mut audio_out_port = client.register_port("audio_out", jack::AudioOut::default()).unwrap();
-
-    let mut frequencies: [f32; 128] = [0.0; 128];
-    for (i, f) in frequencies.iter_mut().enumerate() {
-        *f = 13.75 * 2.0_f32.powf((i as f32 - 9.0) / 12.0);
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|arg| arg == "--self-test") {
+        let report = self_test();
+        println!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
     }
+    let test_tone = args.iter().position(|arg| arg == "--test-tone").map(|i| {
+        let frequency = args.get(i + 1).and_then(|value| value.parse::<f32>().ok()).expect("--test-tone expects a frequency in Hz");
+        let level = args.get(i + 2).and_then(|value| value.parse::<f32>().ok()).unwrap_or(TEST_TONE_LEVEL);
+        (frequency, level)
+    });
+    let stems = args.iter().position(|arg| arg == "--stems").map_or(0, |i| {
+        args.get(i + 1).and_then(|value| value.parse::<usize>().ok()).expect("--stems expects a number of stem ports")
+    });
+    let speakers = args.iter().position(|arg| arg == "--speakers").map_or(0, |i| {
+        args.get(i + 1).and_then(|value| value.parse::<usize>().ok()).filter(|&speakers| (1..=MAX_SPEAKERS).contains(&speakers)).expect("--speakers expects a number of speaker ports from 1 to 64")
+    });
+    let stretch = args.iter().position(|arg| arg == "--stretch").map_or(0.0, |i| {
+        args.get(i + 1).and_then(|value| value.parse::<f32>().ok()).expect("--stretch expects cents per octave")
+    });
+    let panic_note = args.iter().position(|arg| arg == "--panic-note").map(|i| {
+        args.get(i + 1).and_then(|value| value.parse::<u8>().ok()).filter(|&pitch| pitch < 128).expect("--panic-note expects a MIDI note number")
+    });
+    let stereo = args.iter().any(|arg| arg == "--stereo");
+    let midi_log = args.iter().any(|arg| arg == "--midi-log");
+    let jack_transport = args.iter().any(|arg| arg == "--jack-transport");
+    let name_collision = if args.iter().any(|arg| arg == "--exact-name") { NameCollision::Exact } else { NameCollision::AutoNumber };
+    let control = args.iter().position(|arg| arg == "--control").map(|i| {
+        let address = args.get(i + 1).expect("--control expects an address such as 127.0.0.1:9000");
+        listen(address.as_str()).expect("could not open the control socket")
+    });
+
+    let (client, _status) = jack::Client::new("rust_client", name_collision.client_options()).expect("could not open the JACK client; with --exact-name, is another instance running?");
+    eprintln!("JACK client name: {}", client.name());
+    let midi_in_port = client.register_port("midi_in", jack::MidiIn).unwrap();
+    let mut audio_out: Vec<_> = if stereo {
+        vec![client.register_port("audio_out_l", jack::AudioOut).unwrap(), client.register_port("audio_out_r", jack::AudioOut).unwrap()]
+    } else {
+        vec![client.register_port("audio_out", jack::AudioOut).unwrap()]
+    };
+    let mut stem_ports: Vec<_> = (0..stems).map(|i| client.register_port(&format!("stem_{}", i + 1), jack::AudioOut).unwrap()).collect();
+    let mut speaker_ports = register_speaker_ports(&client, speakers).unwrap_or_else(|(port, error)| {
+        eprintln!("JACK refused speaker port {} of {} ({}); try fewer --speakers", port, speakers, error);
+        std::process::exit(1);
+    });
+
+    let frequencies = Tuning { stretch, ..Tuning::default() }.frequencies();
 
     let mut synthesizer = Synthesizer::new(client.sample_rate(), frequencies);
+    for pitch in synthesizer.disabled_pitches() {
+        eprintln!("warning: ignoring invalid frequency {} for note {}", frequencies[pitch as usize], pitch);
+    }
+    if let Some((frequency, level)) = test_tone {
+        synthesizer.set_test_tone(frequency, level);
+    }
+    synthesizer.set_stem_count(stems);
+    synthesizer.set_speaker_count(speakers);
+    synthesizer.set_midi_logging(midi_log);
+    synthesizer.set_panic_note(panic_note);
+    synthesizer.prepare(client.buffer_size() as usize);
+    let watchdog_log = synthesizer.watchdog_log();
+    let synthesizer = Arc::new(Mutex::new(synthesizer));
+    let notifications = SampleRateFollower { synthesizer: synthesizer.clone(), max_frames: client.buffer_size() as usize };
 
     let process = jack::ClosureProcessHandler::new(
-        move |_:&jack::Client, ps: &jack::ProcessScope| {
+        move |client: &jack::Client, ps: &jack::ProcessScope| {
+            // Only a sample-rate change holds the lock; the block it lands on
+            // is left silent rather than waiting.
+            let Ok(mut synthesizer) = synthesizer.try_lock() else {
+                for port in audio_out.iter_mut().chain(stem_ports.iter_mut()).chain(speaker_ports.iter_mut()) {
+                    port.as_mut_slice(ps).fill(0.0);
+                }
+                return jack::Control::Continue;
+            };
+            if jack_transport {
+                follow_jack_transport(client, &mut synthesizer);
+            }
+            if let Some(commands) = control.as_ref() {
+                while let Ok(command) = commands.try_recv() {
+                    synthesizer.handle_command(command);
+                }
+            }
             for raw_midi in midi_in_port.iter(ps) {
                 synthesizer.handle_midi(raw_midi);
             };
 
-            for (frame, value) in audio_out_port.as_mut_slice(ps).iter_mut().enumerate() {
-                *value = synthesizer.get_audio_data(frame as usize);
+            match audio_out.as_mut_slice() {
+                [left, right] => synthesizer.process_block(left.as_mut_slice(ps), right.as_mut_slice(ps)),
+                [mono] => synthesizer.process_mono(mono.as_mut_slice(ps)),
+                _ => (),
+            }
+            for (bus, port) in stem_ports.iter_mut().enumerate() {
+                port.as_mut_slice(ps).copy_from_slice(synthesizer.stem(bus));
+            }
+            for (index, port) in speaker_ports.iter_mut().enumerate() {
+                port.as_mut_slice(ps).copy_from_slice(synthesizer.speaker(index));
             }
-
-            synthesizer.notes_gc();
 
             jack::Control::Continue
         }
     );
 
-    let _active_client = client.activate_async((), process).unwrap();
-    loop {}
-}
-
-#[derive(Copy, Clone, PartialEq)]
-enum EnvelopePhase {
-    Stage(usize),
-    Attack(usize),
-    Decay(usize),
-    Sustain,
-    Release(usize, f32),
-    Off,
-}
-
-#[derive(Copy, Clone)]
-struct Note {
-    pitch: u8,
-    velocity: u8,
-    time: usize,
-    env_phase: EnvelopePhase,
-}
-
-impl Note {
-    fn new(pitch: u8, velocity: u8, start_time: usize) -> Note {
-        Note {
-            pitch,
-            velocity,
-            time: 0,
-            env_phase: EnvelopePhase::Stage(start_time),
-        }
-    }
-
-    fn increment_time(&mut self, time: usize) {
-        self.time += 1;
-
-        if let EnvelopePhase::Stage(start_time) = self.env_phase {
-            if start_time == time {
-                self.env_phase = EnvelopePhase::Attack(0);
-            }
-        } else if let EnvelopePhase::Attack(phase_timer) = self.env_phase {
-            if phase_timer == ATTACK {
-                self.env_phase = EnvelopePhase::Decay(0);
-            } else {
-                self.env_phase = EnvelopePhase::Attack(phase_timer + 1);
+    let _active_client = client.activate_async(notifications, process).unwrap();
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        for stage in [FeedbackStage::Delay, FeedbackStage::Reverb] {
+            let resets = watchdog_log.take(stage);
+            if resets > 0 {
+                eprintln!("watchdog: reset runaway {:?} state {} time(s)", stage, resets);
             }
-        } else if let EnvelopePhase::Decay(phase_timer) = self.env_phase {
-            if phase_timer == DECAY {
-                self.env_phase = EnvelopePhase::Sustain;
-            } else {
-                self.env_phase = EnvelopePhase::Decay(phase_timer + 1);
-            }
-        } else if let EnvelopePhase::Release(phase_timer, released_amplitude) = self.env_phase {
-            if phase_timer >= RELEASE {
-                self.env_phase = EnvelopePhase::Off;
-            } else {
-                self.env_phase = EnvelopePhase::Release(phase_timer + 1, released_amplitude);
-            }
-        }
-    }
-
-    fn amplitude(&self) -> f32 {
-        match self.env_phase {
-            EnvelopePhase::Stage(_) => 0.0,
-            EnvelopePhase::Attack(phase_timer) => phase_timer as f32 / ATTACK as f32,
-            EnvelopePhase::Decay(phase_timer) => 1.0 - ((1.0 - SUSTAIN) * phase_timer as f32 / DECAY as f32),
-            EnvelopePhase::Sustain => SUSTAIN,
-            EnvelopePhase::Release(phase_timer, released_amplitude) => released_amplitude - (released_amplitude * phase_timer as f32 / RELEASE as f32),
-            EnvelopePhase::Off => 0.0,
         }
     }
-
-    fn release(&mut self) {
-        self.env_phase = EnvelopePhase::Release(0, self.amplitude());
-    }
 }
 
-struct Synthesizer {
-    time_step: f32,
-    notes: Vec<Note>,
-    frequencies: [f32; 128],
+// Takes JACK's sample-rate changes on its notification thread, where
+// prepare can build the master delay lines for the new rate before the audio
+// thread fades over to it.
+struct SampleRateFollower {
+    synthesizer: Arc<Mutex<Synthesizer>>,
+    max_frames: usize,
 }
 
-impl Synthesizer {
-    fn new(sample_rate: usize, frequencies: [f32; 128]) -> Synthesizer {
-        let time_step = 1.0 / sample_rate as f32;
-        let notes = Vec::new();
-
-        Synthesizer {
-            time_step,
-            notes,
-            frequencies,
+impl jack::NotificationHandler for SampleRateFollower {
+    fn sample_rate(&mut self, _: &jack::Client, sample_rate: jack::Frames) -> jack::Control {
+        if let Ok(mut synthesizer) = self.synthesizer.lock() {
+            synthesizer.set_sample_rate(sample_rate as usize);
+            synthesizer.prepare(self.max_frames);
         }
+        jack::Control::Continue
     }
+}
 
-    fn handle_midi(&mut self, raw_midi: jack::RawMidi) {
-        let status = raw_midi.bytes[0];
-        let pitch = raw_midi.bytes[1];
-        let velocity = raw_midi.bytes[2];
-        let start_time = raw_midi.time as usize;
-
-        match status >> 4 {
-            0b1000 => self.note_off(pitch),
-            0b1001 => self.note_on(pitch, velocity, start_time),
-            _ => return,
-        };
-    }
-
-    fn note_on(&mut self, pitch: u8, velocity: u8, start_time: usize) {
-        self.notes.push(Note::new(pitch, velocity, start_time));
-    }
-
-    fn note_off(&mut self, pitch: u8) {
-        for note in self.notes.iter_mut() {
-            if note.pitch == pitch {
-                note.release();
+// Registers speaker_1 to speaker_{count}. If JACK refuses one, the ports
+// already registered are taken down again and the refused port's number is
+// returned with the error.
+fn register_speaker_ports(client: &jack::Client, count: usize) -> Result<Vec<jack::Port<jack::AudioOut>>, (usize, jack::Error)> {
+    let mut ports = Vec::with_capacity(count);
+    for i in 1..=count {
+        match client.register_port(&format!("speaker_{}", i), jack::AudioOut) {
+            Ok(port) => ports.push(port),
+            Err(error) => {
+                for port in ports {
+                    let _ = client.unregister_port(port);
+                }
+                return Err((i, error));
             }
         }
     }
+    Ok(ports)
+}
 
-    fn get_audio_data(&mut self, frame: usize) -> f32 {
-        let mut value = 0.0;
-        for note in self.notes.iter_mut() {
-            let x: f32 = self.frequencies[note.pitch as usize] * self.time_step * note.time as f32 * 2.0 * std::f32::consts::PI;
-            let y = MAX_AMPLITUDE * note.amplitude() * note.velocity as f32 / 127.0 * x.sin();
-            value += y;
-
-            note.increment_time(frame);
-        }
-        value
-    }
-
-    fn notes_gc(&mut self) {
-        for i in (0..self.notes.len()).rev() {
-            if self.notes[i].env_phase == EnvelopePhase::Off {
-                self.notes.swap_remove(i);
-            }
+// Hands the JACK transport's position to the synthesizer while it rolls with
+// bar/beat/tick information, and lets go of it otherwise.
+fn follow_jack_transport(client: &jack::Client, synthesizer: &mut Synthesizer) {
+    let rolling = client.transport().query().ok().filter(|status| status.state == jack::TransportState::Rolling);
+    match rolling.and_then(|status| status.pos.bbt()) {
+        Some(bbt) => {
+            let beat = (bbt.bar.max(1) - 1) as f64 * bbt.sig_num as f64 + (bbt.beat.max(1) - 1) as f64 + bbt.tick as f64 / bbt.ticks_per_beat.max(1.0);
+            synthesizer.transport_mut().follow_host(beat, bbt.bpm as f32);
         }
+        None => synthesizer.transport_mut().release_host(),
     }
 }
@@ -0,0 +1,449 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+use crate::cut_filter::{CutFilter, CutFilterState};
+use crate::delay::{Delay, DelayState};
+use crate::drive::{Drive, DriveState};
+use crate::equalizer::{Equalizer, EqualizerState};
+use crate::lfo::{Lfo, LfoRate, LfoRetrigger, LfoWaveform};
+use crate::limiter::{Limiter, LimiterState};
+use crate::reverb::{Reverb, ReverbState};
+use crate::watchdog::{FeedbackStage, Watchdog, WatchdogLog, WATCHDOG_THRESHOLD};
+use crate::wow_flutter::{WowFlutter, WowFlutterState};
+
+pub const MAX_SPEAKERS: usize = 64;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Modulation {
+    pub depth: f32,
+    pub rate: LfoRate,
+    pub waveform: LfoWaveform,
+    pub retrigger: LfoRetrigger,
+}
+
+type StageSettings = (CutFilter, WowFlutter, Equalizer, Delay, Reverb, Limiter);
+
+// The stage states rebuilt ahead of a sample-rate change, with the rate and
+// settings they were built for. After a swap the slot holds the old states
+// until the next prepare frees them, so the audio thread never deallocates.
+struct StagedStates {
+    sample_rate: usize,
+    settings: StageSettings,
+    cut_filter_state: CutFilterState,
+    wow_flutter_state: WowFlutterState,
+    equalizer_state: EqualizerState,
+    delay_state: DelayState,
+    reverb_state: ReverbState,
+    limiter_state: LimiterState,
+}
+
+pub struct Master {
+    pub cut_filter: CutFilter,
+    pub drive: Drive,
+    pub tremolo: Modulation,
+    pub auto_pan: Modulation,
+    pub wow_flutter: WowFlutter,
+    pub equalizer: Equalizer,
+    pub delay: Delay,
+    pub reverb: Reverb,
+    pub limiter: Limiter,
+    pub watchdog_threshold: f32,
+    tremolo_lfo: Lfo,
+    auto_pan_lfo: Lfo,
+    cut_filter_state: CutFilterState,
+    drive_state: DriveState,
+    wow_flutter_state: WowFlutterState,
+    equalizer_state: EqualizerState,
+    delay_state: DelayState,
+    reverb_state: ReverbState,
+    limiter_state: LimiterState,
+    delay_watchdog: Watchdog,
+    reverb_watchdog: Watchdog,
+    watchdog_log: WatchdogLog,
+    staged: Option<StagedStates>,
+}
+
+impl Master {
+    pub fn new() -> Master {
+        Master {
+            cut_filter: CutFilter::default(),
+            drive: Drive::default(),
+            tremolo: Modulation::default(),
+            auto_pan: Modulation::default(),
+            wow_flutter: WowFlutter::default(),
+            equalizer: Equalizer::default(),
+            delay: Delay::default(),
+            reverb: Reverb::default(),
+            limiter: Limiter::default(),
+            watchdog_threshold: WATCHDOG_THRESHOLD,
+            tremolo_lfo: Lfo::default(),
+            auto_pan_lfo: Lfo::default(),
+            cut_filter_state: CutFilterState::new(),
+            drive_state: DriveState::new(),
+            wow_flutter_state: WowFlutterState::new(),
+            equalizer_state: EqualizerState::new(),
+            delay_state: DelayState::new(),
+            reverb_state: ReverbState::new(),
+            limiter_state: LimiterState::new(),
+            delay_watchdog: Watchdog::new(),
+            reverb_watchdog: Watchdog::new(),
+            watchdog_log: WatchdogLog::default(),
+            staged: None,
+        }
+    }
+
+    fn settings(&self) -> StageSettings {
+        (self.cut_filter, self.wow_flutter, self.equalizer, self.delay, self.reverb, self.limiter)
+    }
+
+    // Builds every stage's state for `sample_rate` ahead of time, so
+    // set_sample_rate can swap them in without allocating. Meant for outside
+    // the audio thread, like anything that resizes the delay lines.
+    pub fn prepare_sample_rate(&mut self, sample_rate: usize) {
+        let mut staged = StagedStates {
+            sample_rate,
+            settings: self.settings(),
+            cut_filter_state: self.cut_filter_state.clone(),
+            wow_flutter_state: self.wow_flutter_state.clone(),
+            equalizer_state: self.equalizer_state.clone(),
+            delay_state: self.delay_state.clone(),
+            reverb_state: self.reverb_state.clone(),
+            limiter_state: self.limiter_state.clone(),
+        };
+        staged.cut_filter_state.configure(&self.cut_filter, sample_rate);
+        staged.wow_flutter_state.configure(&self.wow_flutter, sample_rate);
+        staged.equalizer_state.configure(&self.equalizer, sample_rate);
+        staged.delay_state.configure(&self.delay, sample_rate);
+        staged.reverb_state.configure(&self.reverb, sample_rate);
+        staged.limiter_state.configure(&self.limiter, sample_rate);
+        self.staged = Some(staged);
+    }
+
+    // Frees whatever prepare_sample_rate built or a swap left behind.
+    pub fn release_staged(&mut self) {
+        self.staged = None;
+    }
+
+    // Reconfigures every stage for a new sample rate. States prepared for this
+    // rate and the current settings are swapped in; otherwise the delay lines
+    // are resized here.
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        let settings = self.settings();
+        if let Some(staged) = self.staged.as_mut().filter(|staged| staged.sample_rate == sample_rate && staged.settings == settings) {
+            std::mem::swap(&mut self.cut_filter_state, &mut staged.cut_filter_state);
+            std::mem::swap(&mut self.wow_flutter_state, &mut staged.wow_flutter_state);
+            std::mem::swap(&mut self.equalizer_state, &mut staged.equalizer_state);
+            std::mem::swap(&mut self.delay_state, &mut staged.delay_state);
+            std::mem::swap(&mut self.reverb_state, &mut staged.reverb_state);
+            std::mem::swap(&mut self.limiter_state, &mut staged.limiter_state);
+            staged.sample_rate = 0;
+            return;
+        }
+        self.cut_filter_state.configure(&self.cut_filter, sample_rate);
+        self.wow_flutter_state.configure(&self.wow_flutter, sample_rate);
+        self.equalizer_state.configure(&self.equalizer, sample_rate);
+        self.delay_state.configure(&self.delay, sample_rate);
+        self.reverb_state.configure(&self.reverb, sample_rate);
+        self.limiter_state.configure(&self.limiter, sample_rate);
+    }
+
+    // Puts every stage back to its default setting, keeping the watchdog log
+    // handle so whoever polls it keeps seeing resets.
+    pub fn reset_settings(&mut self, sample_rate: usize) {
+        self.tremolo = Modulation::default();
+        self.auto_pan = Modulation::default();
+        self.watchdog_threshold = WATCHDOG_THRESHOLD;
+        self.set_drive(Drive::default());
+        self.cut_filter = CutFilter::default();
+        self.wow_flutter = WowFlutter::default();
+        self.equalizer = Equalizer::default();
+        self.delay = Delay::default();
+        self.reverb = Reverb::default();
+        self.limiter = Limiter::default();
+        self.set_sample_rate(sample_rate);
+    }
+
+    pub fn set_cut_filter(&mut self, cut_filter: CutFilter, sample_rate: usize) {
+        self.cut_filter = cut_filter;
+        self.cut_filter_state.configure(&self.cut_filter, sample_rate);
+    }
+
+    pub fn set_drive(&mut self, drive: Drive) {
+        self.drive = drive;
+        self.drive_state.configure(&self.drive);
+    }
+
+    pub fn set_wow_flutter(&mut self, wow_flutter: WowFlutter, sample_rate: usize) {
+        self.wow_flutter = wow_flutter;
+        self.wow_flutter_state.configure(&self.wow_flutter, sample_rate);
+    }
+
+    pub fn set_equalizer(&mut self, equalizer: Equalizer, sample_rate: usize) {
+        self.equalizer = equalizer;
+        self.equalizer_state.configure(&self.equalizer, sample_rate);
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.wow_flutter_state.set_seed(seed);
+        if let Some(staged) = self.staged.as_mut() {
+            staged.wow_flutter_state.set_seed(seed);
+        }
+    }
+
+    pub fn set_delay(&mut self, delay: Delay, sample_rate: usize) {
+        self.delay = delay;
+        self.delay_state.configure(&self.delay, sample_rate);
+    }
+
+    pub fn set_reverb(&mut self, reverb: Reverb, sample_rate: usize) {
+        self.reverb = reverb;
+        self.reverb_state.configure(&self.reverb, sample_rate);
+    }
+
+    pub fn set_limiter(&mut self, limiter: Limiter, sample_rate: usize) {
+        self.limiter = limiter;
+        self.limiter_state.configure(&self.limiter, sample_rate);
+    }
+
+    pub fn watchdog_log(&self) -> WatchdogLog {
+        self.watchdog_log.clone()
+    }
+
+    pub fn note_on(&mut self) {
+        self.retrigger(LfoRetrigger::NoteOn);
+    }
+
+    pub fn beat(&mut self) {
+        self.retrigger(LfoRetrigger::BeatSync);
+    }
+
+    pub fn reset_lfos(&mut self) {
+        self.tremolo_lfo.reset(0.0);
+        self.auto_pan_lfo.reset(0.0);
+    }
+
+    fn retrigger(&mut self, event: LfoRetrigger) {
+        if self.tremolo.retrigger == event {
+            self.tremolo_lfo.reset(0.0);
+        }
+        if self.auto_pan.retrigger == event {
+            self.auto_pan_lfo.reset(0.0);
+        }
+    }
+
+    // Frames of delay the master chain adds, for host latency compensation.
+    pub fn latency(&self) -> usize {
+        self.drive_state.latency() + self.wow_flutter_state.latency() + self.limiter_state.latency()
+    }
+
+    // `send`, when given, is what the delay and reverb hear instead of the
+    // chain's own signal: the voices weighted by their per-note sends, tapped
+    // ahead of the master chain.
+    pub fn process(&mut self, left: f32, right: f32, send: Option<(f32, f32)>, tempo: Option<f32>, time_step: f32) -> (f32, f32) {
+        let (left, right) = self.cut_filter_state.process(left, right);
+        let (mut left, mut right) = self.drive_state.process(&self.drive, left, right);
+        if self.tremolo.depth != 0.0 {
+            let lfo = self.tremolo_lfo.next(self.tremolo.waveform, self.tremolo.rate.frequency(tempo), time_step);
+            let gain = 1.0 - self.tremolo.depth * 0.5 * (1.0 - lfo);
+            left *= gain;
+            right *= gain;
+        }
+
+        if self.auto_pan.depth != 0.0 {
+            let lfo = self.auto_pan_lfo.next(self.auto_pan.waveform, self.auto_pan.rate.frequency(tempo), time_step);
+            let (l, r) = pan_gains(self.auto_pan.depth.min(1.0) * lfo);
+            left *= l;
+            right *= r;
+        }
+        let (left, right) = self.wow_flutter_state.process(&self.wow_flutter, left, right, time_step);
+        let (left, right) = self.equalizer_state.process(left, right);
+        let (delayed_left, delayed_right) = match send {
+            Some((send_left, send_right)) => self.delay_state.process_send(&self.delay, left, right, send_left, send_right),
+            None => self.delay_state.process(&self.delay, left, right),
+        };
+        if self.delay_watchdog.check(delayed_left, delayed_right, self.watchdog_threshold, time_step) {
+            self.delay_state.reset();
+            self.watchdog_log.add(FeedbackStage::Delay);
+        }
+        let (delayed_left, delayed_right) = self.delay_watchdog.blend((left, right), (delayed_left, delayed_right), time_step);
+        let (reverb_left, reverb_right) = match send {
+            // The echoes go on into the reverb along with the send.
+            Some((send_left, send_right)) => {
+                let (send_left, send_right) = (send_left + delayed_left - left, send_right + delayed_right - right);
+                self.reverb_state.process_send(&self.reverb, delayed_left, delayed_right, send_left, send_right, time_step)
+            }
+            None => self.reverb_state.process(&self.reverb, delayed_left, delayed_right, time_step),
+        };
+        if self.reverb_watchdog.check(reverb_left, reverb_right, self.watchdog_threshold, time_step) {
+            self.reverb_state.reset();
+            self.watchdog_log.add(FeedbackStage::Reverb);
+        }
+        let (left, right) = self.reverb_watchdog.blend((delayed_left, delayed_right), (reverb_left, reverb_right), time_step);
+        self.limiter_state.process(&self.limiter, left, right, time_step)
+    }
+}
+
+// Constant-power gains for `pan` in -1..1, scaled so the center is unity on
+// both sides and a centered signal is unchanged.
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    if pan == 0.0 {
+        return (1.0, 1.0);
+    }
+    let angle = (1.0 + pan.clamp(-1.0, 1.0)) * FRAC_PI_4;
+    let scale = std::f32::consts::SQRT_2;
+    (angle.cos() * scale, angle.sin() * scale)
+}
+
+// Constant-power gains placing `pan` in -1..1 on a row of speakers evenly
+// spaced from the left (the first gain) to the right (the last). A position
+// between two speakers is shared by that pair only; one speaker gets
+// everything.
+pub fn speaker_gains(pan: f32, gains: &mut [f32]) {
+    gains.fill(0.0);
+    match gains.len() {
+        0 => (),
+        1 => gains[0] = 1.0,
+        speakers => {
+            let position = (pan.clamp(-1.0, 1.0) + 1.0) * 0.5 * (speakers - 1) as f32;
+            let index = (position as usize).min(speakers - 2);
+            let fraction = position - index as f32;
+            gains[index] = (fraction * FRAC_PI_2).cos();
+            gains[index + 1] = (fraction * FRAC_PI_2).sin();
+        }
+    }
+}
+
+impl Default for Master {
+    fn default() -> Master {
+        Master::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    fn master() -> Master {
+        let mut master = Master::new();
+        master.set_sample_rate(SAMPLE_RATE);
+        master
+    }
+
+    // One second of a steady 0.5 on both sides through the master chain.
+    fn run(master: &mut Master, tempo: Option<f32>) -> Vec<(f32, f32)> {
+        (0..SAMPLE_RATE).map(|_| master.process(0.5, 0.5, None, tempo, 1.0 / SAMPLE_RATE as f32)).collect()
+    }
+
+    fn rising_crossings(values: impl Iterator<Item = f32>) -> usize {
+        let values: Vec<f32> = values.collect();
+        values.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count()
+    }
+
+    #[test]
+    fn tremolo_modulates_the_level_at_its_rate() {
+        let mut master = master();
+        master.tremolo = Modulation { depth: 1.0, rate: LfoRate { hz: 5.0, division: None }, ..Default::default() };
+        let out = run(&mut master, None);
+        assert!((4..=5).contains(&rising_crossings(out.iter().map(|&(left, _)| left - 0.25))));
+        let (low, high) = out.iter().fold((1.0_f32, 0.0_f32), |(low, high), &(left, _)| (low.min(left), high.max(left)));
+        assert!(low < 0.01 && high > 0.49, "{} {}", low, high);
+        assert!(out.iter().all(|&(left, right)| left == right));
+
+        // A division follows the tempo: half a beat at 120 BPM is 4 Hz.
+        master.tremolo.rate.division = Some(0.5);
+        let out = run(&mut master, Some(120.0));
+        assert!((3..=4).contains(&rising_crossings(out.iter().map(|&(left, _)| left - 0.25))));
+    }
+
+    #[test]
+    fn auto_pan_moves_the_signal_between_the_sides() {
+        let mut master = master();
+        master.auto_pan = Modulation { depth: 1.0, rate: LfoRate { hz: 2.0, division: None }, ..Default::default() };
+        let out = run(&mut master, None);
+        assert!((1..=2).contains(&rising_crossings(out.iter().map(|&(left, right)| right - left))));
+        assert!(out.iter().any(|&(left, right)| left < 0.01 && right > 0.6));
+        assert!(out.iter().any(|&(left, right)| right < 0.01 && left > 0.6));
+    }
+
+    #[test]
+    fn zero_depth_bypasses_both() {
+        let mut master = master();
+        master.tremolo.rate.hz = 5.0;
+        master.auto_pan.rate.hz = 5.0;
+        assert!(run(&mut master, None).iter().all(|&out| out == (0.5, 0.5)));
+    }
+
+    #[test]
+    fn each_retrigger_mode_restarts_on_its_own_event() {
+        let mut master = master();
+        for retrigger in [LfoRetrigger::NoteOn, LfoRetrigger::BeatSync, LfoRetrigger::Free] {
+            master.tremolo.retrigger = retrigger;
+            master.tremolo.depth = 1.0;
+            master.tremolo.rate.hz = 4.3;
+            run(&mut master, None);
+            let phase = master.tremolo_lfo.phase();
+            assert!(phase > 0.0);
+            master.note_on();
+            assert_eq!(master.tremolo_lfo.phase() == 0.0, retrigger == LfoRetrigger::NoteOn);
+            master.tremolo_lfo.reset(phase);
+            master.beat();
+            assert_eq!(master.tremolo_lfo.phase() == 0.0, retrigger == LfoRetrigger::BeatSync);
+        }
+        assert_eq!(LfoRetrigger::default(), LfoRetrigger::NoteOn);
+    }
+
+    fn gains(pan: f32, speakers: usize) -> Vec<f32> {
+        let mut gains = vec![0.5; speakers];
+        speaker_gains(pan, &mut gains);
+        gains
+    }
+
+    #[test]
+    fn speaker_gains_share_a_position_between_the_nearest_pair() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let centre = gains(0.0, 4);
+        assert_eq!(centre[0], 0.0);
+        assert!((centre[1] - half).abs() < 1e-6 && (centre[2] - half).abs() < 1e-6);
+        assert_eq!(centre[3], 0.0);
+        assert_eq!(gains(-1.0, 4), [1.0, 0.0, 0.0, 0.0]);
+        assert!((gains(1.0, 4)[3] - 1.0).abs() < 1e-6);
+        assert!((gains(5.0, 4)[3] - 1.0).abs() < 1e-6);
+        assert_eq!(gains(0.3, 1), [1.0]);
+        for pan in [-0.9, -0.2, 0.4, 0.77] {
+            let power: f32 = gains(pan, 5).iter().map(|gain| gain * gain).sum();
+            assert!((power - 1.0).abs() < 1e-5, "{}", pan);
+        }
+    }
+
+    #[test]
+    fn the_watchdog_resets_a_runaway_delay() {
+        let mut master = Master::new();
+        master.delay = Delay { level: 1.0, time: 0.01, feedback: 1.0, ..Delay::default() };
+        master.watchdog_threshold = 2.0;
+        master.set_sample_rate(SAMPLE_RATE);
+        let log = master.watchdog_log();
+        let time_step = 1.0 / SAMPLE_RATE as f32;
+        let step = 2.0 * std::f32::consts::PI * 100.0 * time_step;
+        for frame in 0..SAMPLE_RATE {
+            let tone = (step * frame as f32).sin();
+            master.process(tone, tone, None, None, time_step);
+        }
+        assert!(log.count(FeedbackStage::Delay) > 0);
+        let tail: Vec<(f32, f32)> = (0..SAMPLE_RATE).map(|_| master.process(0.0, 0.0, None, None, time_step)).collect();
+        let peak = tail[SAMPLE_RATE / 2..].iter().fold(0.0_f32, |peak, &(left, right)| peak.max(left.abs()).max(right.abs()));
+        assert!(peak < 2.0, "{}", peak);
+    }
+
+    #[test]
+    fn the_watchdog_clears_a_nan_from_the_reverb() {
+        let mut master = master();
+        master.set_reverb(Reverb { level: 0.5, ..Reverb::default() }, SAMPLE_RATE);
+        let log = master.watchdog_log();
+        let time_step = 1.0 / SAMPLE_RATE as f32;
+        master.process(f32::NAN, f32::NAN, None, None, time_step);
+        assert_eq!(log.count(FeedbackStage::Reverb), 1);
+        let out = run(&mut master, None);
+        assert!(out.iter().all(|&(left, right)| left.is_finite() && right.is_finite()));
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::{Duration, Instant};
+
+const MAX_MESSAGES_PER_SECOND: usize = 50;
+
+// Logs incoming MIDI to stderr, decoded. A controller streaming clock or
+// aftertouch would flood the terminal, so at most MAX_MESSAGES_PER_SECOND
+// lines go out each second; the rest are counted and reported as skipped.
+#[derive(Clone, Debug)]
+pub struct MidiLog {
+    window_start: Instant,
+    logged: usize,
+    skipped: usize,
+}
+
+impl MidiLog {
+    pub fn new() -> MidiLog {
+        MidiLog {
+            window_start: Instant::now(),
+            logged: 0,
+            skipped: 0,
+        }
+    }
+
+    pub fn log(&mut self, time: u32, bytes: &[u8]) {
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            if self.skipped > 0 {
+                eprintln!("midi: {} messages skipped", self.skipped);
+            }
+            self.window_start = now;
+            self.logged = 0;
+            self.skipped = 0;
+        }
+        if self.logged < MAX_MESSAGES_PER_SECOND {
+            self.logged += 1;
+            eprintln!("midi: +{} {}", time, describe_midi(bytes));
+        } else {
+            self.skipped += 1;
+        }
+    }
+}
+
+impl Default for MidiLog {
+    fn default() -> MidiLog {
+        MidiLog::new()
+    }
+}
+
+// A human-readable description of one MIDI message. Channels are numbered
+// 1-16 as on a controller.
+pub fn describe_midi(bytes: &[u8]) -> String {
+    let hex = || bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ");
+    let Some(&status) = bytes.first() else {
+        return "empty message".to_string();
+    };
+    let name = match status {
+        0xF8 => Some("clock"),
+        0xFA => Some("start"),
+        0xFB => Some("continue"),
+        0xFC => Some("stop"),
+        0xFE => Some("active sensing"),
+        0xFF => Some("reset"),
+        _ => None,
+    };
+    if let Some(name) = name {
+        return name.to_string();
+    }
+
+    let channel = (status & 0x0F) + 1;
+    let data_1 = bytes.get(1).copied();
+    let data_2 = bytes.get(2).copied();
+    match (status >> 4, data_1, data_2) {
+        (0x8, Some(pitch), Some(velocity)) => format!("note off channel {} pitch {} velocity {}", channel, pitch, velocity),
+        (0x9, Some(pitch), Some(velocity)) => format!("note on channel {} pitch {} velocity {}", channel, pitch, velocity),
+        (0xA, Some(pitch), Some(pressure)) => format!("aftertouch channel {} pitch {} pressure {}", channel, pitch, pressure),
+        (0xB, Some(cc), Some(value)) => format!("control change channel {} cc {} value {}", channel, cc, value),
+        (0xC, Some(program), _) => format!("program change channel {} program {}", channel, program),
+        (0xD, Some(pressure), _) => format!("channel pressure channel {} pressure {}", channel, pressure),
+        (0xE, Some(low), Some(high)) => format!("pitch bend channel {} value {}", channel, ((high as i32) << 7 | low as i32) - 8192),
+        (0xF, _, _) if status == 0xF0 => format!("system exclusive, {} bytes", bytes.len()),
+        _ => format!("unknown message {}", hex()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_representative_messages() {
+        assert_eq!(describe_midi(&[0x90, 60, 100]), "note on channel 1 pitch 60 velocity 100");
+        assert_eq!(describe_midi(&[0x8F, 60, 0]), "note off channel 16 pitch 60 velocity 0");
+        assert_eq!(describe_midi(&[0xB2, 7, 127]), "control change channel 3 cc 7 value 127");
+        assert_eq!(describe_midi(&[0xE0, 0x00, 0x40]), "pitch bend channel 1 value 0");
+        assert_eq!(describe_midi(&[0xE0, 0x7F, 0x7F]), "pitch bend channel 1 value 8191");
+        assert_eq!(describe_midi(&[0xF8]), "clock");
+    }
+
+    #[test]
+    fn describes_truncated_messages_as_unknown() {
+        assert_eq!(describe_midi(&[]), "empty message");
+        assert_eq!(describe_midi(&[0x90, 60]), "unknown message 90 3c");
+    }
+
+    #[test]
+    fn logs_at_most_the_rate_limit_per_second() {
+        let mut log = MidiLog::new();
+        for _ in 0..MAX_MESSAGES_PER_SECOND + 5 {
+            log.log(0, &[0xF8]);
+        }
+        assert_eq!((log.logged, log.skipped), (MAX_MESSAGES_PER_SECOND, 5));
+    }
+}
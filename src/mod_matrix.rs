@@ -0,0 +1,304 @@
+use crate::lfo::{Lfo, LfoMode, LfoRate, LfoRetrigger, LfoWaveform};
+use crate::params::ParamTarget;
+use crate::patch::{Envelope, Patch};
+
+pub const MAX_MOD_ENVELOPES: usize = 4;
+pub const MAX_MOD_LFOS: usize = 2;
+pub const MAX_MOD_ROUTES: usize = 8;
+
+// What a route reads. Envelope 0 is the patch's amp envelope and 1..=
+// envelope_count the extra generators; LFOs are bipolar, the rest 0..1.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ModSource {
+    Envelope(usize),
+    Lfo(usize),
+    Velocity,
+    ModWheel,
+    Aftertouch,
+}
+
+// Where a route sends its envelope: the note's output level, the share of
+// the note sent on to the master delay and reverb, or any modulatable patch
+// parameter.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ModDestination {
+    Amplitude,
+    Send,
+    Param(ParamTarget),
+}
+
+// `amount` is -1..1: on a parameter it is the share of the parameter's
+// normalized range a full-scale source moves it by; on Amplitude and Send, 1
+// follows the source fully, 0 not at all and negative values duck the level
+// as the source rises.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ModRoute {
+    pub source: ModSource,
+    pub destination: ModDestination,
+    pub amount: f32,
+}
+
+// An LFO for the matrix. A Global one runs per channel and restarts as
+// `retrigger` says; a PerVoice one runs per note from phase 0 at its start.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct ModLfo {
+    pub rate: LfoRate,
+    pub waveform: LfoWaveform,
+    pub retrigger: LfoRetrigger,
+    pub mode: LfoMode,
+}
+
+// The current value of every source, for one note on one frame.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ModSources {
+    pub envelopes: [f32; MAX_MOD_ENVELOPES + 1],
+    pub lfos: [f32; MAX_MOD_LFOS],
+    pub velocity: f32,
+    pub mod_wheel: f32,
+    pub aftertouch: f32,
+}
+
+impl ModSources {
+    fn value(&self, source: ModSource) -> f32 {
+        match source {
+            ModSource::Envelope(index) => self.envelopes.get(index).copied().unwrap_or(0.0),
+            ModSource::Lfo(index) => self.lfos.get(index).copied().unwrap_or(0.0),
+            ModSource::Velocity => self.velocity,
+            ModSource::ModWheel => self.mod_wheel,
+            ModSource::Aftertouch => self.aftertouch,
+        }
+    }
+}
+
+// The modulation matrix: generic envelope generators, LFOs and the routes
+// from them and the performance sources to note level and patch parameters.
+// Every extra envelope runs per note alongside the amp envelope and releases
+// with it; the amp envelope always shapes the note and ends it, and
+// Amplitude routes scale the level on top. An empty matrix, the default, is
+// no modulation at all.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ModMatrix {
+    pub envelopes: [Envelope; MAX_MOD_ENVELOPES],
+    pub envelope_count: usize,
+    pub lfos: [ModLfo; MAX_MOD_LFOS],
+    routes: [Option<ModRoute>; MAX_MOD_ROUTES],
+}
+
+impl Default for ModMatrix {
+    fn default() -> ModMatrix {
+        ModMatrix {
+            envelopes: [Envelope::default(); MAX_MOD_ENVELOPES],
+            envelope_count: 0,
+            lfos: [ModLfo::default(); MAX_MOD_LFOS],
+            routes: [None; MAX_MOD_ROUTES],
+        }
+    }
+}
+
+impl ModMatrix {
+    pub fn routes(&self) -> &[Option<ModRoute>; MAX_MOD_ROUTES] {
+        &self.routes
+    }
+
+    // Sets or clears one route slot. A route is refused, leaving the slot as
+    // it was, if its amount is outside -1..1, its source isn't one of the
+    // active envelopes or its destination can't be modulated per note.
+    pub fn set_route(&mut self, slot: usize, route: Option<ModRoute>) -> bool {
+        if slot >= MAX_MOD_ROUTES || route.is_some_and(|route| !self.is_valid(&route)) {
+            return false;
+        }
+        self.routes[slot] = route;
+        true
+    }
+
+    fn is_valid(&self, route: &ModRoute) -> bool {
+        let destination = match route.destination {
+            ModDestination::Amplitude | ModDestination::Send => true,
+            ModDestination::Param(target) => target.info().modulatable && Patch::default().param(target).is_some(),
+        };
+        let source = match route.source {
+            ModSource::Envelope(index) => index <= self.active_envelopes(),
+            ModSource::Lfo(index) => index < MAX_MOD_LFOS,
+            _ => true,
+        };
+        source && destination && route.amount.is_finite() && route.amount.abs() <= 1.0
+    }
+
+    // Steps a channel's LFOs for this matrix one frame, returning their values.
+    pub fn next_lfos(&self, lfos: &mut [Lfo; MAX_MOD_LFOS], tempo: Option<f32>, time_step: f32) -> [f32; MAX_MOD_LFOS] {
+        std::array::from_fn(|i| lfos[i].next(self.lfos[i].waveform, self.lfos[i].rate.frequency(tempo), time_step))
+    }
+
+    pub fn has_voice_lfos(&self) -> bool {
+        self.lfos.iter().any(|lfo| lfo.mode == LfoMode::PerVoice)
+    }
+
+    // Steps a note's own LFOs one frame and returns the LFO values the note
+    // hears: its own for PerVoice LFOs, the channel's `shared` values for the
+    // rest.
+    pub fn next_voice_lfos(&self, lfos: &mut [Lfo; MAX_MOD_LFOS], shared: [f32; MAX_MOD_LFOS], tempo: Option<f32>, time_step: f32) -> [f32; MAX_MOD_LFOS] {
+        std::array::from_fn(|i| match self.lfos[i].mode {
+            LfoMode::Global => shared[i],
+            LfoMode::PerVoice => lfos[i].next(self.lfos[i].waveform, self.lfos[i].rate.frequency(tempo), time_step),
+        })
+    }
+
+    pub fn retrigger_lfos(&self, lfos: &mut [Lfo; MAX_MOD_LFOS], event: LfoRetrigger) {
+        for (lfo, settings) in lfos.iter_mut().zip(self.lfos.iter()) {
+            if settings.retrigger == event {
+                lfo.reset(0.0);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.iter().all(Option::is_none)
+    }
+
+    pub fn active_envelopes(&self) -> usize {
+        self.envelope_count.min(MAX_MOD_ENVELOPES)
+    }
+
+    pub fn has_param_routes(&self) -> bool {
+        self.routes.iter().flatten().any(|route| matches!(route.destination, ModDestination::Param(_)))
+    }
+
+    pub fn has_send_routes(&self) -> bool {
+        self.routes.iter().flatten().any(|route| route.destination == ModDestination::Send)
+    }
+
+    // The factor Amplitude routes scale a note's output by, kept within 0..1.
+    pub fn amplitude(&self, sources: &ModSources) -> f32 {
+        self.gain(ModDestination::Amplitude, sources)
+    }
+
+    // The share of a note's output sent on to the effects, 1 without Send
+    // routes.
+    pub fn send(&self, sources: &ModSources) -> f32 {
+        self.gain(ModDestination::Send, sources)
+    }
+
+    fn gain(&self, destination: ModDestination, sources: &ModSources) -> f32 {
+        let mut gain: f32 = 1.0;
+        for route in self.routes.iter().flatten().filter(|route| route.destination == destination) {
+            let value = sources.value(route.source);
+            gain *= if route.amount >= 0.0 { 1.0 - route.amount * (1.0 - value) } else { 1.0 + route.amount * value };
+        }
+        gain.clamp(0.0, 1.0)
+    }
+
+    // Moves the routed parameters of `patch` by the sum of their routes, in
+    // normalized units. The sum is applied once and clamped to each
+    // parameter's range, so stacked routes can't push it out of bounds.
+    pub fn modulate(&self, patch: &mut Patch, sources: &ModSources) {
+        let mut offsets: [Option<(ParamTarget, f32)>; MAX_MOD_ROUTES] = [None; MAX_MOD_ROUTES];
+        for route in self.routes.iter().flatten() {
+            let ModDestination::Param(target) = route.destination else {
+                continue;
+            };
+            let offset = route.amount * sources.value(route.source);
+            match offsets.iter_mut().flatten().find(|(t, _)| *t == target) {
+                Some((_, sum)) => *sum += offset,
+                None => {
+                    if let Some(slot) = offsets.iter_mut().find(|slot| slot.is_none()) {
+                        *slot = Some((target, offset));
+                    }
+                }
+            }
+        }
+        for &(target, offset) in offsets.iter().flatten() {
+            if let Some(value) = patch.param(target) {
+                let info = target.info();
+                patch.set_param(target, info.denormalize(info.normalize(value) + offset));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(source: ModSource, destination: ModDestination, amount: f32) -> Option<ModRoute> {
+        Some(ModRoute { source, destination, amount })
+    }
+
+    #[test]
+    fn set_route_refuses_invalid_routes() {
+        let mut matrix = ModMatrix { envelope_count: 1, ..ModMatrix::default() };
+        for amount in [1.5, -1.01, f32::NAN, f32::INFINITY] {
+            assert!(!matrix.set_route(0, route(ModSource::Envelope(1), ModDestination::Amplitude, amount)));
+        }
+        assert!(!matrix.set_route(0, route(ModSource::Envelope(2), ModDestination::Amplitude, 1.0)));
+        assert!(!matrix.set_route(0, route(ModSource::Lfo(MAX_MOD_LFOS), ModDestination::Amplitude, 1.0)));
+        assert!(!matrix.set_route(MAX_MOD_ROUTES, route(ModSource::Envelope(1), ModDestination::Amplitude, 1.0)));
+        assert!(matrix.is_empty());
+        assert!(matrix.set_route(0, route(ModSource::Envelope(1), ModDestination::Amplitude, -1.0)));
+        // A refused route leaves the slot as it was.
+        assert!(!matrix.set_route(0, route(ModSource::Envelope(1), ModDestination::Amplitude, 2.0)));
+        assert_eq!(matrix.routes()[0], route(ModSource::Envelope(1), ModDestination::Amplitude, -1.0));
+        assert!(matrix.set_route(0, None));
+        assert!(matrix.is_empty());
+    }
+
+    #[test]
+    fn an_empty_matrix_leaves_the_level_alone() {
+        let matrix = ModMatrix::default();
+        let sources = ModSources { envelopes: [0.3; MAX_MOD_ENVELOPES + 1], ..ModSources::default() };
+        assert_eq!(matrix.amplitude(&sources), 1.0);
+        assert_eq!(matrix.send(&sources), 1.0);
+        let mut patch = Patch::default();
+        matrix.modulate(&mut patch, &sources);
+        assert_eq!(patch, Patch::default());
+    }
+
+    #[test]
+    fn amplitude_routes_follow_or_duck_the_source() {
+        let mut matrix = ModMatrix { envelope_count: 1, ..ModMatrix::default() };
+        let sources = ModSources { envelopes: [0.0, 0.25, 0.0, 0.0, 0.0], ..ModSources::default() };
+        matrix.set_route(0, route(ModSource::Envelope(1), ModDestination::Amplitude, 1.0));
+        assert!((matrix.amplitude(&sources) - 0.25).abs() < 1e-6);
+        matrix.set_route(0, route(ModSource::Envelope(1), ModDestination::Amplitude, 0.5));
+        assert!((matrix.amplitude(&sources) - 0.625).abs() < 1e-6);
+        matrix.set_route(0, route(ModSource::Envelope(1), ModDestination::Amplitude, -1.0));
+        assert!((matrix.amplitude(&sources) - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stacked_param_routes_stay_in_range() {
+        let mut matrix = ModMatrix::default();
+        let cutoff = ModDestination::Param(ParamTarget::Cutoff);
+        matrix.set_route(0, route(ModSource::ModWheel, cutoff, 1.0));
+        matrix.set_route(1, route(ModSource::Velocity, cutoff, 1.0));
+        let mut patch = Patch::default();
+        matrix.modulate(&mut patch, &ModSources { velocity: 1.0, mod_wheel: 1.0, ..ModSources::default() });
+        let info = ParamTarget::Cutoff.info();
+        assert_eq!(patch.param(ParamTarget::Cutoff), Some(info.denormalize(1.0)));
+    }
+
+    #[test]
+    fn routes_to_the_same_parameter_sum() {
+        let mut matrix = ModMatrix::default();
+        let resonance = ModDestination::Param(ParamTarget::Resonance);
+        matrix.set_route(0, route(ModSource::ModWheel, resonance, 0.25));
+        matrix.set_route(1, route(ModSource::Aftertouch, resonance, 0.5));
+        let mut patch = Patch::default();
+        matrix.modulate(&mut patch, &ModSources { mod_wheel: 1.0, aftertouch: 0.5, ..ModSources::default() });
+        assert_eq!(patch.param(ParamTarget::Resonance), Some(0.5));
+        // A negative route takes away from the sum.
+        matrix.set_route(1, route(ModSource::Aftertouch, resonance, -0.5));
+        let mut patch = Patch::default();
+        matrix.modulate(&mut patch, &ModSources { mod_wheel: 1.0, aftertouch: 0.5, ..ModSources::default() });
+        assert_eq!(patch.param(ParamTarget::Resonance), Some(0.0));
+    }
+
+    #[test]
+    fn send_routes_only_move_the_send() {
+        let mut matrix = ModMatrix::default();
+        matrix.set_route(0, route(ModSource::Envelope(0), ModDestination::Send, -1.0));
+        let sources = ModSources { envelopes: [0.25, 0.0, 0.0, 0.0, 0.0], ..ModSources::default() };
+        assert_eq!(matrix.send(&sources), 0.75);
+        assert_eq!(matrix.amplitude(&sources), 1.0);
+        assert!(matrix.has_send_routes() && !matrix.has_param_routes());
+    }
+}
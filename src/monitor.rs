@@ -0,0 +1,176 @@
+use std::sync::{Arc, Mutex};
+
+use crate::note::EnvelopePhase;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct VoiceState {
+    pub channel: u8,
+    pub pitch: u8,
+    pub velocity: u8,
+    pub stage: EnvelopePhase,
+    pub amplitude: f32,
+}
+
+// Cloneable handle for reading voice states from another thread. The audio
+// thread publishes a complete snapshot at the end of each block, so a reader
+// never sees a half-updated block.
+#[derive(Clone, Debug)]
+pub struct VoiceMonitor {
+    voices: Arc<Mutex<Vec<VoiceState>>>,
+}
+
+impl VoiceMonitor {
+    pub fn new(capacity: usize) -> VoiceMonitor {
+        VoiceMonitor {
+            voices: Arc::new(Mutex::new(Vec::with_capacity(capacity))),
+        }
+    }
+
+    pub fn snapshot(&self, out: &mut Vec<VoiceState>) {
+        out.clear();
+        if let Ok(voices) = self.voices.lock() {
+            out.extend_from_slice(&voices);
+        }
+    }
+
+    // Never blocks the audio thread: if a reader holds the lock this block is
+    // simply skipped.
+    pub fn publish<I: Iterator<Item = VoiceState>>(&self, voices: I) {
+        if let Ok(mut published) = self.voices.try_lock() {
+            published.clear();
+            published.extend(voices);
+        }
+    }
+}
+
+const VOICE_EVENT_CAPACITY: usize = 1024;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VoiceEventKind {
+    NoteOn,
+    NoteOff,
+    Freed,
+}
+
+// One step in a voice's life. `id` is unique per triggered voice and counts
+// up from 0, so the same input always produces the same ids.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct VoiceEvent {
+    pub kind: VoiceEventKind,
+    pub id: u64,
+    pub channel: u8,
+    pub pitch: u8,
+    pub velocity: u8,
+}
+
+// Cloneable handle for reading voice events from another thread, in the order
+// they happened. The audio thread hands over each block's events at the end
+// of the block without ever waiting on a reader; if nobody drains the queue
+// it stops growing at VOICE_EVENT_CAPACITY and newer events are dropped.
+#[derive(Clone, Debug)]
+pub struct VoiceEventQueue {
+    events: Arc<Mutex<Vec<VoiceEvent>>>,
+}
+
+impl VoiceEventQueue {
+    pub fn new() -> VoiceEventQueue {
+        VoiceEventQueue {
+            events: Arc::new(Mutex::new(Vec::with_capacity(VOICE_EVENT_CAPACITY))),
+        }
+    }
+
+    // Moves every queued event into `out`.
+    pub fn drain(&self, out: &mut Vec<VoiceEvent>) {
+        if let Ok(mut events) = self.events.lock() {
+            out.append(&mut events);
+        }
+    }
+
+    // Hands `pending` over, leaving it empty, unless a reader holds the lock,
+    // in which case the events wait for the next block.
+    pub fn publish(&self, pending: &mut Vec<VoiceEvent>) {
+        if let Ok(mut events) = self.events.try_lock() {
+            let room = VOICE_EVENT_CAPACITY.saturating_sub(events.len());
+            events.extend(pending.drain(..).take(room));
+        }
+    }
+}
+
+impl Default for VoiceEventQueue {
+    fn default() -> VoiceEventQueue {
+        VoiceEventQueue::new()
+    }
+}
+
+// The audio thread's side: events collected during a block, capped so
+// recording them never allocates.
+#[derive(Clone, Debug)]
+pub struct VoiceEvents {
+    queue: VoiceEventQueue,
+    pending: Vec<VoiceEvent>,
+}
+
+impl VoiceEvents {
+    pub fn new(queue: VoiceEventQueue) -> VoiceEvents {
+        VoiceEvents {
+            queue,
+            pending: Vec::with_capacity(VOICE_EVENT_CAPACITY),
+        }
+    }
+
+    pub fn queue(&self) -> &VoiceEventQueue {
+        &self.queue
+    }
+
+    pub fn push(&mut self, kind: VoiceEventKind, channel: usize, id: u64, pitch: u8, velocity: u8) {
+        if self.pending.len() < VOICE_EVENT_CAPACITY {
+            self.pending.push(VoiceEvent {
+                kind,
+                id,
+                channel: channel as u8,
+                pitch,
+                velocity,
+            });
+        }
+    }
+
+    pub fn publish(&mut self) {
+        self.queue.publish(&mut self.pending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: u64) -> VoiceEvent {
+        VoiceEvent { kind: VoiceEventKind::NoteOn, id, channel: 0, pitch: 60, velocity: 100 }
+    }
+
+    #[test]
+    fn a_full_queue_drops_newer_events() {
+        let queue = VoiceEventQueue::new();
+        let mut pending: Vec<_> = (0..VOICE_EVENT_CAPACITY as u64 + 10).map(event).collect();
+        queue.publish(&mut pending);
+        assert!(pending.is_empty());
+        let mut out = Vec::new();
+        queue.drain(&mut out);
+        assert_eq!(out.len(), VOICE_EVENT_CAPACITY);
+        assert_eq!(out.last(), Some(&event(VOICE_EVENT_CAPACITY as u64 - 1)));
+    }
+
+    #[test]
+    fn events_wait_while_a_reader_holds_the_queue() {
+        let queue = VoiceEventQueue::new();
+        let mut pending = vec![event(0)];
+        {
+            let _reader = queue.events.lock().unwrap();
+            queue.publish(&mut pending);
+        }
+        assert_eq!(pending, [event(0)]);
+        queue.publish(&mut pending);
+        let mut out = Vec::new();
+        queue.drain(&mut out);
+        assert_eq!(out, [event(0)]);
+    }
+}
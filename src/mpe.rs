@@ -0,0 +1,93 @@
+const MEMBER_CHANNELS: usize = 15;
+const MEMBER_BEND_RANGE: f32 = 48.0;
+// How far CC74 at full scale opens the cutoff, as a fraction of its
+// normalized range; over the 10 Hz to 20 kHz span that is about four
+// octaves.
+pub const SLIDE_CUTOFF: f32 = 0.4;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum MpeZone {
+    // Master on channel 1, members counting up from channel 2.
+    #[default]
+    Lower,
+    // Master on channel 16, members counting down from channel 15.
+    Upper,
+}
+
+// MIDI Polyphonic Expression: every note arrives on a member channel of its
+// own, so that channel's pitch bend, pressure and CC74 slide become per-note
+// expression. Member channels play the master channel's patch and bend by
+// bend_range semitones on top of the master channel's zone-wide bend.
+// Pressure raises the level by up to pressure_level and opens the cutoff by
+// up to pressure_cutoff of its normalized range; slide adds up to
+// slide_cutoff to the cutoff.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Mpe {
+    pub zone: MpeZone,
+    pub member_channels: usize,
+    pub bend_range: f32,
+    pub pressure_level: f32,
+    pub pressure_cutoff: f32,
+    pub slide_cutoff: f32,
+}
+
+impl Default for Mpe {
+    fn default() -> Mpe {
+        Mpe {
+            zone: MpeZone::default(),
+            member_channels: MEMBER_CHANNELS,
+            bend_range: MEMBER_BEND_RANGE,
+            pressure_level: 0.0,
+            pressure_cutoff: 0.0,
+            slide_cutoff: SLIDE_CUTOFF,
+        }
+    }
+}
+
+impl Mpe {
+    // Channels are 0-based here.
+    pub fn master_channel(&self) -> usize {
+        match self.zone {
+            MpeZone::Lower => 0,
+            MpeZone::Upper => 15,
+        }
+    }
+
+    pub fn is_member(&self, channel: usize) -> bool {
+        let members = self.member_channels.clamp(1, MEMBER_CHANNELS);
+        match self.zone {
+            MpeZone::Lower => (1..=members).contains(&channel),
+            MpeZone::Upper => (15 - members..15).contains(&channel),
+        }
+    }
+
+    // The level scale for a member channel's 0..1 pressure.
+    pub fn pressure_gain(&self, pressure: f32) -> f32 {
+        let amount = self.pressure_level.clamp(0.0, 1.0);
+        1.0 - amount + amount * pressure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_zone_has_its_own_master_and_members() {
+        let lower = Mpe { member_channels: 3, ..Mpe::default() };
+        assert_eq!(lower.master_channel(), 0);
+        assert_eq!((0..16).filter(|&channel| lower.is_member(channel)).collect::<Vec<_>>(), [1, 2, 3]);
+        let upper = Mpe { zone: MpeZone::Upper, member_channels: 3, ..Mpe::default() };
+        assert_eq!(upper.master_channel(), 15);
+        assert_eq!((0..16).filter(|&channel| upper.is_member(channel)).collect::<Vec<_>>(), [12, 13, 14]);
+        assert_eq!((0..16).filter(|&channel| Mpe::default().is_member(channel)).count(), 15);
+    }
+
+    #[test]
+    fn pressure_scales_the_level_by_its_amount() {
+        let mpe = Mpe { pressure_level: 0.5, ..Mpe::default() };
+        assert_eq!(mpe.pressure_gain(0.0), 0.5);
+        assert_eq!(mpe.pressure_gain(1.0), 1.0);
+        assert_eq!(Mpe::default().pressure_gain(0.0), 1.0);
+    }
+}
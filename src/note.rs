@@ -0,0 +1,636 @@
+use crate::cached::Cached;
+use crate::filter::{Filter, FilterCoefficients, FilterState};
+use crate::lfo::Lfo;
+use crate::mod_matrix::{MAX_MOD_ENVELOPES, MAX_MOD_LFOS};
+use crate::patch::{curve, inverse_curve, Envelope, GlideCurve, Patch, MAX_UNISON};
+use crate::tilt::{Tilt, TiltState};
+
+// Unison voices start spread around the cycle so the stack doesn't begin
+// with every voice in phase.
+const UNISON_PHASE_STEP: f32 = 0.618034;
+// The share of the remaining distance a sustaining envelope moves toward a
+// changed sustain level each frame, so the change ramps instead of stepping.
+const SUSTAIN_RAMP: f32 = 0.002;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EnvelopePhase {
+    Stage(usize),
+    Attack(usize),
+    Hold(usize, f32),
+    Decay(usize),
+    Sustain(f32),
+    Release(usize, f32),
+    Off,
+}
+
+impl EnvelopePhase {
+    // The phase one frame on. `time` is the current frame, for a note still
+    // waiting on its start time.
+    fn advance(self, time: usize, envelope: &Envelope, velocity: f32) -> EnvelopePhase {
+        match self {
+            EnvelopePhase::Stage(start_time) if start_time <= time => EnvelopePhase::Attack(0),
+            EnvelopePhase::Attack(phase_timer) if phase_timer >= envelope.attack => {
+                if envelope.hold == 0 {
+                    EnvelopePhase::Decay(0)
+                } else {
+                    EnvelopePhase::Hold(0, self.level(envelope, velocity))
+                }
+            }
+            EnvelopePhase::Attack(phase_timer) => EnvelopePhase::Attack(phase_timer + 1),
+            EnvelopePhase::Hold(phase_timer, _) if phase_timer >= envelope.hold => EnvelopePhase::Decay(0),
+            EnvelopePhase::Hold(phase_timer, level) => EnvelopePhase::Hold(phase_timer + 1, level),
+            EnvelopePhase::Decay(phase_timer) if phase_timer >= envelope.decay => EnvelopePhase::Sustain(envelope.sustain_level(velocity)),
+            EnvelopePhase::Decay(phase_timer) => EnvelopePhase::Decay(phase_timer + 1),
+            EnvelopePhase::Release(phase_timer, _) if phase_timer >= envelope.release => EnvelopePhase::Off,
+            EnvelopePhase::Release(phase_timer, released_amplitude) => EnvelopePhase::Release(phase_timer + 1, released_amplitude),
+            EnvelopePhase::Sustain(level) => EnvelopePhase::Sustain(level + (envelope.sustain_level(velocity) - level) * SUSTAIN_RAMP),
+            phase => phase,
+        }
+    }
+
+    // Stage times can be shortened while a note is in that stage, leaving the
+    // timer past the end for a frame; progress is held at 1 so the level
+    // never extrapolates beyond the stage.
+    fn level(self, envelope: &Envelope, velocity: f32) -> f32 {
+        let sustain = envelope.sustain_level(velocity);
+        let progress = |timer: usize, length: usize| (timer as f32 / length.max(1) as f32).clamp(0.0, 1.0);
+        match self {
+            EnvelopePhase::Stage(_) => 0.0,
+            EnvelopePhase::Attack(phase_timer) => curve(progress(phase_timer, envelope.attack), -envelope.attack_curve),
+            EnvelopePhase::Hold(_, level) => level,
+            EnvelopePhase::Decay(phase_timer) => 1.0 - ((1.0 - sustain) * curve(progress(phase_timer, envelope.decay), envelope.decay_curve)),
+            EnvelopePhase::Sustain(level) => level,
+            EnvelopePhase::Release(phase_timer, released_amplitude) => released_amplitude - (released_amplitude * curve(progress(phase_timer, envelope.release), envelope.release_curve)),
+            EnvelopePhase::Off => 0.0,
+        }
+    }
+
+    fn retrigger(self, envelope: &Envelope, velocity: f32) -> EnvelopePhase {
+        let amplitude = self.level(envelope, velocity).clamp(0.0, 1.0);
+        let progress = inverse_curve(amplitude, -envelope.attack_curve);
+        EnvelopePhase::Attack((progress * envelope.attack as f32) as usize)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Note {
+    pub pitch: u8,
+    pub velocity: u8,
+    pub velocity_fraction: u8,
+    pub time: usize,
+    pub phase: f32,
+    pub drift: f32,
+    pub drift_target: f32,
+    pub unison_drifts: [f32; MAX_UNISON],
+    pub unison_drift_targets: [f32; MAX_UNISON],
+    pub sustained: bool,
+    pub glide: f32,
+    pub glide_step: f32,
+    pub glide_interval: f32,
+    pub filter: FilterState,
+    pub filter_right: FilterState,
+    // The coefficients for a filter modulated per note, keyed on the
+    // modulated settings and the sample rate.
+    pub filter_coefficients: Cached<(Filter, f32), FilterCoefficients>,
+    pub tilt: [TiltState; 2],
+    // Keyed on the tilt amount and the sample rate.
+    pub tilt_coefficients: Cached<(f32, f32), Tilt>,
+    pub damping: [f32; 2],
+    pub unison_phases: [f32; MAX_UNISON],
+    // The right-hand copy's phase under voice_stereo_detune.
+    pub stereo_phase: f32,
+    pub sample_position: f64,
+    pub voice: usize,
+    pub id: u64,
+    pub release_reported: bool,
+    pub pan: f32,
+    pub env_phase: EnvelopePhase,
+    pub mod_phases: [EnvelopePhase; MAX_MOD_ENVELOPES],
+    pub mod_lfos: [Lfo; MAX_MOD_LFOS],
+}
+
+impl Note {
+    pub fn new(pitch: u8, velocity: u8, start_time: usize, phase: f32) -> Note {
+        Note {
+            pitch,
+            velocity,
+            velocity_fraction: 0,
+            time: 0,
+            phase: phase.rem_euclid(1.0),
+            drift: 0.0,
+            drift_target: 0.0,
+            unison_drifts: [0.0; MAX_UNISON],
+            unison_drift_targets: [0.0; MAX_UNISON],
+            sustained: false,
+            glide: 0.0,
+            glide_step: 0.0,
+            glide_interval: 0.0,
+            filter: FilterState::default(),
+            filter_right: FilterState::default(),
+            filter_coefficients: Cached::default(),
+            tilt: [TiltState::default(); 2],
+            tilt_coefficients: Cached::default(),
+            damping: [0.0; 2],
+            unison_phases: std::array::from_fn(|i| (phase + i as f32 * UNISON_PHASE_STEP).rem_euclid(1.0)),
+            stereo_phase: phase.rem_euclid(1.0),
+            sample_position: 0.0,
+            voice: 0,
+            id: 0,
+            release_reported: false,
+            pan: 0.0,
+            env_phase: EnvelopePhase::Stage(start_time),
+            mod_phases: [EnvelopePhase::Stage(start_time); MAX_MOD_ENVELOPES],
+            mod_lfos: [Lfo::default(); MAX_MOD_LFOS],
+        }
+    }
+
+    pub fn advance_phase(&mut self, increment: f32) {
+        if let EnvelopePhase::Stage(_) = self.env_phase {
+            return;
+        }
+        self.phase = (self.phase + increment).rem_euclid(1.0);
+    }
+
+    // Puts the oscillators and filter back where a fresh note would start them.
+    pub fn reset_phase(&mut self, phase: f32) {
+        self.phase = phase.rem_euclid(1.0);
+        self.unison_phases = std::array::from_fn(|i| (phase + i as f32 * UNISON_PHASE_STEP).rem_euclid(1.0));
+        self.stereo_phase = self.phase;
+        self.sample_position = 0.0;
+        self.filter.reset();
+        self.filter_right.reset();
+        self.tilt.iter_mut().for_each(TiltState::reset);
+        self.damping = [0.0; 2];
+    }
+
+    // Runs one side of the note through its age damping low-pass, if the
+    // patch has one.
+    pub fn apply_damping(&mut self, side: usize, coefficient: Option<f32>, input: f32) -> f32 {
+        match coefficient {
+            Some(coefficient) => {
+                self.damping[side] += (input - self.damping[side]) * coefficient;
+                self.damping[side]
+            }
+            None => input,
+        }
+    }
+
+    // Runs one side of the note through its velocity tilt, if the patch has one.
+    pub fn apply_tilt(&mut self, side: usize, tilt: Option<&Tilt>, input: f32) -> f32 {
+        match tilt {
+            Some(tilt) => self.tilt[side].process(tilt, input),
+            None => input,
+        }
+    }
+
+    pub fn advance_unison_phase(&mut self, index: usize, increment: f32) {
+        if let EnvelopePhase::Stage(_) = self.env_phase {
+            return;
+        }
+        self.unison_phases[index] = (self.unison_phases[index] + increment).rem_euclid(1.0);
+    }
+
+    pub fn advance_stereo_phase(&mut self, increment: f32) {
+        if let EnvelopePhase::Stage(_) = self.env_phase {
+            return;
+        }
+        self.stereo_phase = (self.stereo_phase + increment).rem_euclid(1.0);
+    }
+
+    // velocity_fraction is the low 7 bits from a CC88 high-resolution velocity
+    // prefix, placing the velocity between two adjacent 7-bit steps.
+    pub fn fractional_velocity(&self) -> f32 {
+        ((self.velocity as f32 + self.velocity_fraction as f32 / 128.0) / 127.0).min(1.0)
+    }
+
+    // A frozen envelope holds its stage and timer, so the amplitude stays put;
+    // notes still waiting on their start time begin as scheduled. The patch's
+    // extra mod envelopes step along with the amp envelope. The note's age
+    // counts from its attack, not from the frame it was scheduled on.
+    pub fn increment_time(&mut self, time: usize, patch: &Patch, frozen: bool) {
+        if !matches!(self.env_phase, EnvelopePhase::Stage(_)) {
+            self.time += 1;
+        }
+        if frozen && !matches!(self.env_phase, EnvelopePhase::Stage(_)) {
+            return;
+        }
+        let velocity = self.fractional_velocity();
+        self.env_phase = self.env_phase.advance(time, &patch.envelope, velocity);
+        let count = patch.modulation.active_envelopes();
+        for (phase, envelope) in self.mod_phases.iter_mut().zip(patch.modulation.envelopes.iter()).take(count) {
+            *phase = phase.advance(time, envelope, velocity);
+        }
+    }
+
+    // Starts the envelopes of a note whose start time has come, so it sounds
+    // from exactly that frame. A start time already past, e.g. for a note
+    // scheduled before the clock was moved, starts it now.
+    pub fn start_if_due(&mut self, time: usize) {
+        for phase in std::iter::once(&mut self.env_phase).chain(self.mod_phases.iter_mut()) {
+            if matches!(*phase, EnvelopePhase::Stage(start_time) if start_time <= time) {
+                *phase = EnvelopePhase::Attack(0);
+            }
+        }
+    }
+
+    pub fn amplitude(&self, envelope: &Envelope) -> f32 {
+        self.env_phase.level(envelope, self.fractional_velocity())
+    }
+
+    // Moves a start time that is still pending `frames` earlier, for when the
+    // sample clock is rewound.
+    pub fn rewind(&mut self, frames: usize) {
+        for phase in std::iter::once(&mut self.env_phase).chain(self.mod_phases.iter_mut()) {
+            if let EnvelopePhase::Stage(start_time) = phase {
+                *start_time = start_time.saturating_sub(frames);
+            }
+        }
+    }
+
+    // Ends a releasing note early once its level has fallen below `threshold`.
+    pub fn retire_below(&mut self, envelope: &Envelope, threshold: f32) {
+        if let EnvelopePhase::Release(..) = self.env_phase {
+            if self.amplitude(envelope) < threshold {
+                self.env_phase = EnvelopePhase::Off;
+            }
+        }
+    }
+
+    // The amp envelope's level followed by each mod envelope's, indexed by
+    // mod route source.
+    pub fn envelope_levels(&self, patch: &Patch) -> [f32; MAX_MOD_ENVELOPES + 1] {
+        let velocity = self.fractional_velocity();
+        let mut levels = [0.0; MAX_MOD_ENVELOPES + 1];
+        levels[0] = self.amplitude(&patch.envelope);
+        let count = patch.modulation.active_envelopes();
+        for (level, (phase, envelope)) in levels[1..].iter_mut().zip(self.mod_phases.iter().zip(patch.modulation.envelopes.iter())).take(count) {
+            *level = phase.level(envelope, velocity);
+        }
+        levels
+    }
+
+    pub fn start_glide(&mut self, interval: f32, patch: &Patch, time_step: f32) {
+        self.glide = interval;
+        self.glide_interval = interval;
+        self.glide_step = patch.glide_step(interval, time_step);
+    }
+
+    // The factor the glide scales the note's frequency by this sample. Under
+    // GlideCurve::Hz the share of the glide still to go is spent in
+    // frequency instead of in pitch.
+    pub fn glide_ratio(&self, curve: GlideCurve) -> f32 {
+        match curve {
+            _ if self.glide == 0.0 => 1.0,
+            GlideCurve::Hz if self.glide_interval != 0.0 => 1.0 + self.glide / self.glide_interval * (2.0_f32.powf(self.glide_interval / 12.0) - 1.0),
+            _ => 2.0_f32.powf(self.glide / 12.0),
+        }
+    }
+
+    // `glide` is the signed distance in semitones still to travel to the note's
+    // own pitch.
+    pub fn advance_glide(&mut self) {
+        if self.glide == 0.0 {
+            return;
+        }
+        if let EnvelopePhase::Stage(_) = self.env_phase {
+            return;
+        }
+        if self.glide.abs() <= self.glide_step {
+            self.glide = 0.0;
+        } else {
+            self.glide -= self.glide_step * self.glide.signum();
+        }
+    }
+
+    // Restarts the attack from the current level instead of from zero, so the
+    // retrigger doesn't click.
+    pub fn retrigger(&mut self, patch: &Patch) {
+        let velocity = self.fractional_velocity();
+        self.env_phase = self.env_phase.retrigger(&patch.envelope, velocity);
+        for (phase, envelope) in self.mod_phases.iter_mut().zip(patch.modulation.envelopes.iter()) {
+            *phase = phase.retrigger(envelope, velocity);
+        }
+        self.sustained = false;
+    }
+
+    // Starts every envelope over from silence.
+    pub fn restart_envelopes(&mut self) {
+        self.env_phase = EnvelopePhase::Attack(0);
+        self.mod_phases = [EnvelopePhase::Attack(0); MAX_MOD_ENVELOPES];
+    }
+
+    pub fn is_released(&self) -> bool {
+        matches!(self.env_phase, EnvelopePhase::Release(..) | EnvelopePhase::Off)
+    }
+
+    pub fn release(&mut self, patch: &Patch) {
+        if self.is_released() {
+            return;
+        }
+        let velocity = self.fractional_velocity();
+        self.env_phase = EnvelopePhase::Release(0, self.amplitude(&patch.envelope));
+        for (phase, envelope) in self.mod_phases.iter_mut().zip(patch.modulation.envelopes.iter()) {
+            *phase = EnvelopePhase::Release(0, phase.level(envelope, velocity));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::GlideMode;
+
+    // Frames from note start to the first frame of decay.
+    const ATTACK_FRAMES: usize = crate::patch::ATTACK + 1;
+
+    fn run(note: &mut Note, patch: &Patch, frames: usize) {
+        for time in 0..frames {
+            note.increment_time(time, patch, false);
+        }
+    }
+
+    #[test]
+    fn shortening_the_attack_mid_stage_ends_it() {
+        let mut patch = Patch::default();
+        let mut note = Note::new(60, 127, 0, 0.0);
+        run(&mut note, &patch, 1000);
+        assert!(matches!(note.env_phase, EnvelopePhase::Attack(_)));
+        patch.envelope.attack = 10;
+        assert!(note.amplitude(&patch.envelope) <= 1.0);
+        run(&mut note, &patch, 1);
+        assert_eq!(note.env_phase, EnvelopePhase::Decay(0));
+        assert!((0.0..=1.0).contains(&note.amplitude(&patch.envelope)));
+    }
+
+    #[test]
+    fn shortening_the_decay_mid_stage_ends_it() {
+        let mut patch = Patch::default();
+        let mut note = Note::new(60, 127, 0, 0.0);
+        run(&mut note, &patch, ATTACK_FRAMES + 3000);
+        assert!(matches!(note.env_phase, EnvelopePhase::Decay(_)));
+        patch.envelope.decay = 0;
+        let amplitude = note.amplitude(&patch.envelope);
+        assert!((patch.envelope.sustain..=1.0).contains(&amplitude), "{}", amplitude);
+        run(&mut note, &patch, 1);
+        assert!(matches!(note.env_phase, EnvelopePhase::Sustain(_)));
+        assert!((0.0..=1.0).contains(&note.amplitude(&patch.envelope)));
+    }
+
+    #[test]
+    fn shortening_the_release_holds_its_end_level() {
+        let mut patch = Patch::default();
+        let mut note = Note::new(60, 127, 0, 0.0);
+        run(&mut note, &patch, 100);
+        note.release(&patch);
+        run(&mut note, &patch, 5000);
+        patch.envelope.release = 10;
+        assert_eq!(note.amplitude(&patch.envelope), 0.0);
+        run(&mut note, &patch, 1);
+        assert_eq!(note.env_phase, EnvelopePhase::Off);
+    }
+
+    fn amplitudes(patch: &Patch, frames: usize) -> Vec<(EnvelopePhase, f32)> {
+        let mut note = Note::new(60, 127, 0, 0.0);
+        (0..frames)
+            .map(|time| {
+                note.increment_time(time, patch, false);
+                (note.env_phase, note.amplitude(&patch.envelope))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_hold_keeps_the_peak_flat() {
+        let mut patch = Patch::default();
+        patch.envelope.hold = 500;
+        let frames = amplitudes(&patch, ATTACK_FRAMES + 1000);
+        let held: Vec<_> = frames.iter().filter(|(phase, _)| matches!(phase, EnvelopePhase::Hold(..))).collect();
+        assert_eq!(held.len(), 501);
+        assert!(held.iter().all(|&&(_, amplitude)| amplitude == held[0].1));
+        assert!((held[0].1 - 1.0).abs() < 1e-3);
+        let after = frames.iter().position(|(phase, _)| matches!(phase, EnvelopePhase::Decay(_))).unwrap();
+        assert!(frames[after + 100].1 < held[0].1);
+    }
+
+    #[test]
+    fn a_zero_hold_goes_straight_to_decay() {
+        let frames = amplitudes(&Patch::default(), ATTACK_FRAMES + 10);
+        assert!(frames.iter().all(|(phase, _)| !matches!(phase, EnvelopePhase::Hold(..))));
+        let decay = frames.iter().position(|(phase, _)| matches!(phase, EnvelopePhase::Decay(_))).unwrap();
+        assert!(matches!(frames[decay - 1].0, EnvelopePhase::Attack(_)));
+        assert_eq!(frames[decay].0, EnvelopePhase::Decay(0));
+    }
+
+    #[test]
+    fn a_second_release_leaves_the_release_running() {
+        let patch = Patch::default();
+        let mut note = Note::new(60, 127, 0, 0.0);
+        run(&mut note, &patch, 3000);
+        note.release(&patch);
+        run(&mut note, &patch, 500);
+        let phase = note.env_phase;
+        note.release(&patch);
+        assert_eq!(note.env_phase, phase);
+        note.env_phase = EnvelopePhase::Off;
+        note.release(&patch);
+        assert_eq!(note.env_phase, EnvelopePhase::Off);
+    }
+
+    fn glide_frames(patch: &Patch, interval: f32) -> usize {
+        let mut note = Note::new(60, 127, 0, 0.0);
+        note.env_phase = EnvelopePhase::Attack(0);
+        note.start_glide(interval, patch, 1.0 / 48000.0);
+        let mut frames = 0;
+        while note.glide != 0.0 {
+            note.advance_glide();
+            frames += 1;
+        }
+        frames
+    }
+
+    #[test]
+    fn time_glides_take_the_same_time_for_any_interval() {
+        let patch = Patch { glide_time: 0.1, ..Patch::default() };
+        assert_eq!(patch.glide_mode, GlideMode::Time);
+        let (octave, semitone) = (glide_frames(&patch, -12.0), glide_frames(&patch, 1.0));
+        assert!(octave.abs_diff(4800) <= 1 && semitone.abs_diff(4800) <= 1, "{} {}", octave, semitone);
+    }
+
+    #[test]
+    fn rate_glides_take_longer_over_wider_intervals() {
+        let patch = Patch { glide_time: 0.1, glide_mode: GlideMode::Rate, ..Patch::default() };
+        let (octave, semitone) = (glide_frames(&patch, -12.0), glide_frames(&patch, 1.0));
+        assert!(octave.abs_diff(4800) <= 1, "{}", octave);
+        assert!(semitone.abs_diff(400) <= 1, "{}", semitone);
+    }
+
+    fn settled_level(patch: &Patch, velocity: u8) -> f32 {
+        let mut note = Note::new(60, velocity, 0, 0.0);
+        run(&mut note, patch, 48000);
+        note.amplitude(&patch.envelope)
+    }
+
+    #[test]
+    fn harder_notes_sustain_higher_with_velocity_modulation() {
+        let mut patch = Patch::default();
+        patch.envelope.sustain_velocity = 0.5;
+        let (soft, hard) = (settled_level(&patch, 30), settled_level(&patch, 120));
+        assert!(hard > soft, "soft {} hard {}", soft, hard);
+        assert!(hard <= patch.envelope.sustain);
+        let expected = patch.envelope.sustain * (1.0 - 0.5 * (1.0 - 30.0 / 127.0));
+        assert!((soft - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sustain_ignores_velocity_without_modulation() {
+        let patch = Patch::default();
+        assert_eq!(settled_level(&patch, 30), settled_level(&patch, 120));
+    }
+
+    #[test]
+    fn a_frozen_envelope_holds_its_amplitude_and_then_resumes() {
+        let patch = Patch::default();
+        let mut note = Note::new(60, 127, 0, 0.0);
+        run(&mut note, &patch, 1000);
+        let held = note.amplitude(&patch.envelope);
+        assert!(held > 0.0);
+        for time in 1000..11000 {
+            note.increment_time(time, &patch, true);
+            assert_eq!(note.amplitude(&patch.envelope), held);
+        }
+        // It picks up exactly where it left off.
+        let mut unfrozen = Note::new(60, 127, 0, 0.0);
+        run(&mut unfrozen, &patch, 1001);
+        note.increment_time(11000, &patch, false);
+        assert_eq!(note.amplitude(&patch.envelope), unfrozen.amplitude(&patch.envelope));
+        assert!(note.amplitude(&patch.envelope) > held);
+    }
+
+    // The amp envelope's level halfway through a stage, the stage being
+    // reached after `frames`.
+    fn midpoint(envelope: Envelope, frames: usize) -> f32 {
+        let patch = Patch { envelope, ..Patch::default() };
+        let mut note = Note::new(60, 127, 0, 0.0);
+        run(&mut note, &patch, frames);
+        note.amplitude(&patch.envelope)
+    }
+
+    #[test]
+    fn curve_amounts_bend_each_stage_away_from_linear() {
+        let attack = |attack_curve| midpoint(Envelope { attack_curve, ..Envelope::default() }, 1 + crate::patch::ATTACK / 2);
+        let linear = attack(0.0);
+        assert!((linear - 0.5).abs() < 1e-3);
+        assert!(attack(0.5) < linear && attack(-0.5) > linear);
+
+        let decay = |decay_curve| midpoint(Envelope { decay_curve, ..Envelope::default() }, ATTACK_FRAMES + crate::patch::DECAY / 2);
+        let linear = decay(0.0);
+        assert!((linear - 0.8).abs() < 1e-3);
+        assert!(decay(0.5) < linear && decay(-0.5) > linear);
+        // The stages still end exactly at their levels.
+        assert_eq!(midpoint(Envelope { decay_curve: 0.7, ..Envelope::default() }, ATTACK_FRAMES + crate::patch::DECAY + 10), crate::patch::SUSTAIN);
+    }
+
+    // The level through a release from sustain, one entry a frame.
+    fn release_levels(release_curve: f32) -> Vec<f32> {
+        let patch = Patch { envelope: Envelope { release_curve, ..Envelope::default() }, ..Patch::default() };
+        let mut note = Note::new(60, 127, 0, 0.0);
+        run(&mut note, &patch, 48000);
+        note.release(&patch);
+        let mut levels = vec![note.amplitude(&patch.envelope)];
+        for time in 48000..48000 + crate::patch::RELEASE + 1 {
+            note.increment_time(time, &patch, false);
+            levels.push(note.amplitude(&patch.envelope));
+        }
+        assert_eq!(note.env_phase, EnvelopePhase::Off);
+        levels
+    }
+
+    #[test]
+    fn the_release_curve_bends_only_the_release() {
+        let linear = release_levels(0.0);
+        let exponential = release_levels(0.7);
+        let half = crate::patch::RELEASE / 2;
+        assert!((linear[half] - crate::patch::SUSTAIN / 2.0).abs() < 1e-3, "{}", linear[half]);
+        assert!(exponential[half] < 0.5 * linear[half], "{} {}", exponential[half], linear[half]);
+        // Both start from the released level and reach zero exactly at the end.
+        for levels in [&linear, &exponential] {
+            assert_eq!(levels[0], crate::patch::SUSTAIN);
+            assert_eq!(levels[crate::patch::RELEASE], 0.0);
+            assert!(levels[crate::patch::RELEASE - 1] > 0.0);
+        }
+        // Attack and decay are untouched.
+        assert_eq!(midpoint(Envelope { release_curve: 0.7, ..Envelope::default() }, ATTACK_FRAMES + crate::patch::DECAY / 2), midpoint(Envelope::default(), ATTACK_FRAMES + crate::patch::DECAY / 2));
+    }
+
+    // The glide ratio halfway through an octave glide down onto the note.
+    fn glide_midpoint(glide_curve: GlideCurve) -> f32 {
+        let patch = Patch { glide_time: 0.1, glide_curve, ..Patch::default() };
+        let mut note = Note::new(60, 127, 0, 0.0);
+        note.env_phase = EnvelopePhase::Attack(0);
+        note.start_glide(12.0, &patch, 1.0 / 48000.0);
+        assert_eq!(note.glide_ratio(glide_curve), 2.0);
+        for _ in 0..2400 {
+            note.advance_glide();
+        }
+        note.glide_ratio(glide_curve)
+    }
+
+    #[test]
+    fn cents_glides_pass_the_geometric_midpoint_and_hz_glides_the_arithmetic() {
+        assert_eq!(Patch::default().glide_curve, GlideCurve::Cents);
+        let cents = glide_midpoint(GlideCurve::Cents);
+        assert!((cents - 2.0_f32.sqrt()).abs() < 1e-3, "{}", cents);
+        let hz = glide_midpoint(GlideCurve::Hz);
+        assert!((hz - 1.5).abs() < 1e-3, "{}", hz);
+    }
+
+    #[test]
+    fn only_a_releasing_note_is_retired() {
+        let patch = Patch::default();
+        let mut note = Note::new(60, 127, 0, 0.0);
+        note.env_phase = EnvelopePhase::Attack(0);
+        note.retire_below(&patch.envelope, 0.5);
+        assert_eq!(note.env_phase, EnvelopePhase::Attack(0));
+        note.env_phase = EnvelopePhase::Release(0, 0.6);
+        note.retire_below(&patch.envelope, 0.5);
+        assert_eq!(note.env_phase, EnvelopePhase::Release(0, 0.6));
+        note.env_phase = EnvelopePhase::Release(0, 0.4);
+        note.retire_below(&patch.envelope, 0.5);
+        assert_eq!(note.env_phase, EnvelopePhase::Off);
+    }
+
+    #[test]
+    fn a_sustain_change_ramps_and_a_release_starts_from_the_ramp() {
+        let mut patch = Patch::default();
+        let mut note = Note::new(60, 127, 0, 0.0);
+        run(&mut note, &patch, ATTACK_FRAMES + crate::patch::DECAY + 10);
+        assert_eq!(note.amplitude(&patch.envelope), crate::patch::SUSTAIN);
+        patch.envelope.sustain = 0.2;
+        let ramp: Vec<f32> = (0..500)
+            .map(|time| {
+                note.increment_time(time, &patch, false);
+                note.amplitude(&patch.envelope)
+            })
+            .collect();
+        assert!(ramp.windows(2).all(|pair| pair[1] < pair[0] && pair[0] - pair[1] < 0.001));
+        let level = ramp[ramp.len() - 1];
+        assert!((0.3..0.5).contains(&level), "{}", level);
+        note.release(&patch);
+        assert_eq!(note.env_phase, EnvelopePhase::Release(0, level));
+        run(&mut note, &patch, 1);
+        assert!((level - note.amplitude(&patch.envelope)).abs() < 0.001);
+    }
+
+    #[test]
+    fn a_note_starts_on_or_after_its_start_time() {
+        let mut note = Note::new(60, 127, 300, 0.0);
+        note.start_if_due(299);
+        assert_eq!(note.env_phase, EnvelopePhase::Stage(300));
+        note.start_if_due(300);
+        assert_eq!(note.env_phase, EnvelopePhase::Attack(0));
+        assert!(note.mod_phases.iter().all(|&phase| phase == EnvelopePhase::Attack(0)));
+        let mut late = Note::new(60, 127, 300, 0.0);
+        late.start_if_due(5000);
+        assert_eq!(late.env_phase, EnvelopePhase::Attack(0));
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub const OVERLOAD_HOLD: f32 = 0.5;
+// Far enough above full scale that ordinary clipping passes through to the
+// overload flag untouched.
+pub const MAX_OUTPUT: f32 = 4.0;
+
+// Cloneable handle a UI thread can poll for a clip LED.
+#[derive(Clone, Debug)]
+pub struct OverloadIndicator {
+    flag: Arc<AtomicBool>,
+}
+
+impl OverloadIndicator {
+    pub fn is_set(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    // Reads and clears the flag.
+    pub fn take(&self) -> bool {
+        self.flag.swap(false, Ordering::Relaxed)
+    }
+}
+
+pub struct Overload {
+    flag: Arc<AtomicBool>,
+    hold: usize,
+    since_overload: usize,
+}
+
+impl Overload {
+    pub fn new(hold: usize) -> Overload {
+        Overload {
+            flag: Arc::new(AtomicBool::new(false)),
+            hold,
+            since_overload: 0,
+        }
+    }
+
+    pub fn indicator(&self) -> OverloadIndicator {
+        OverloadIndicator {
+            flag: self.flag.clone(),
+        }
+    }
+
+    pub fn set_hold(&mut self, hold: usize) {
+        self.hold = hold;
+    }
+
+    pub fn update(&mut self, peak: f32, frames: usize) {
+        if peak > 1.0 {
+            self.flag.store(true, Ordering::Relaxed);
+            self.since_overload = 0;
+        } else {
+            self.since_overload = self.since_overload.saturating_add(frames);
+            if self.since_overload > self.hold {
+                self.flag.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// Cloneable handle counting output samples that came out NaN or infinite and
+// were replaced with silence. Any count at all points at a DSP bug or a bad
+// parameter somewhere upstream.
+#[derive(Clone, Debug, Default)]
+pub struct NonFiniteCounter {
+    count: Arc<AtomicU64>,
+}
+
+impl NonFiniteCounter {
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    // Reads and clears the count.
+    pub fn take(&self) -> u64 {
+        self.count.swap(0, Ordering::Relaxed)
+    }
+
+    pub fn add(&self, samples: u64) {
+        if samples > 0 {
+            self.count.fetch_add(samples, Ordering::Relaxed);
+        }
+    }
+}
+
+// The last line of defence before a sample leaves the synthesizer: NaN and
+// infinity become silence, counted in `non_finite`, and anything else is
+// clamped to +-MAX_OUTPUT.
+pub fn guard_output(value: f32, non_finite: &mut u64) -> f32 {
+    if value.is_finite() {
+        value.clamp(-MAX_OUTPUT, MAX_OUTPUT)
+    } else {
+        *non_finite += 1;
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_flag_clears_once_read_or_after_the_hold() {
+        let mut overload = Overload::new(100);
+        let indicator = overload.indicator();
+        overload.update(1.5, 64);
+        assert!(indicator.take());
+        assert!(!indicator.is_set());
+
+        overload.update(1.5, 64);
+        overload.update(0.5, 64);
+        assert!(indicator.is_set());
+        overload.update(0.5, 64);
+        assert!(!indicator.is_set());
+    }
+
+    #[test]
+    fn non_finite_samples_become_silence_and_are_counted() {
+        let mut non_finite = 0;
+        let guarded: Vec<_> = [0.5, f32::NAN, f32::INFINITY, -10.0, f32::NEG_INFINITY].into_iter().map(|value| guard_output(value, &mut non_finite)).collect();
+        assert_eq!(guarded, [0.5, 0.0, 0.0, -MAX_OUTPUT, 0.0]);
+        assert_eq!(non_finite, 3);
+        let counter = NonFiniteCounter::default();
+        counter.add(non_finite);
+        assert_eq!(counter.take(), 3);
+        assert_eq!(counter.count(), 0);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::filter::OPEN_CUTOFF;
+use crate::formant::VOWELS;
+use crate::patch::{ATTACK, BEND_RANGE, DECAY, HOLD, RELEASE, SUSTAIN};
+
+const MAX_ENVELOPE_TIME: f32 = 96000.0;
+const MAX_BEND_RANGE: f32 = 24.0;
+const MAX_COARSE_TUNE: f32 = 24.0;
+const MAX_FINE_TUNE: f32 = 100.0;
+const MAX_DRIFT_AMOUNT: f32 = 50.0;
+const MAX_DRIFT_RATE: f32 = 10.0;
+const MIN_CUTOFF: f32 = 20.0;
+const MAX_TRIM: f32 = 24.0;
+const MAX_HF_DECAY: f32 = 10.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ParamTarget {
+    Volume,
+    Expression,
+    Mute,
+    ModWheel,
+    SustainPedal,
+    Attack,
+    Hold,
+    Decay,
+    SustainLevel,
+    SustainVelocity,
+    Release,
+    BendRange,
+    CoarseTune,
+    FineTune,
+    DriftAmount,
+    DriftRate,
+    WavetablePosition,
+    WavetableEnvelope,
+    WavetableVelocity,
+    PanSpread,
+    VelocityLoudness,
+    Cutoff,
+    Resonance,
+    PhaseDistortion,
+    Freeze,
+    AttackCurve,
+    DecayCurve,
+    ReleaseCurve,
+    MidiLog,
+    Trim,
+    FormantMix,
+    Vowel,
+    DriftCorrelation,
+    VelocityBrightness,
+    VoiceDrive,
+    HfDecay,
+}
+
+pub const PARAM_TARGETS: [ParamTarget; 36] = [
+    ParamTarget::Volume,
+    ParamTarget::Expression,
+    ParamTarget::Mute,
+    ParamTarget::ModWheel,
+    ParamTarget::SustainPedal,
+    ParamTarget::Attack,
+    ParamTarget::Hold,
+    ParamTarget::Decay,
+    ParamTarget::SustainLevel,
+    ParamTarget::SustainVelocity,
+    ParamTarget::Release,
+    ParamTarget::BendRange,
+    ParamTarget::CoarseTune,
+    ParamTarget::FineTune,
+    ParamTarget::DriftAmount,
+    ParamTarget::DriftRate,
+    ParamTarget::WavetablePosition,
+    ParamTarget::WavetableEnvelope,
+    ParamTarget::WavetableVelocity,
+    ParamTarget::PanSpread,
+    ParamTarget::VelocityLoudness,
+    ParamTarget::Cutoff,
+    ParamTarget::Resonance,
+    ParamTarget::PhaseDistortion,
+    ParamTarget::Freeze,
+    ParamTarget::AttackCurve,
+    ParamTarget::DecayCurve,
+    ParamTarget::ReleaseCurve,
+    ParamTarget::MidiLog,
+    ParamTarget::Trim,
+    ParamTarget::FormantMix,
+    ParamTarget::Vowel,
+    ParamTarget::DriftCorrelation,
+    ParamTarget::VelocityBrightness,
+    ParamTarget::VoiceDrive,
+    ParamTarget::HfDecay,
+];
+
+// How a normalized 0..1 value spreads over a parameter's range. Stepped
+// values snap to whole numbers, so a 0..1 stepped parameter is a switch.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ParamScale {
+    Linear,
+    Logarithmic,
+    Stepped,
+}
+
+// Describes one parameter for hosts and generated UIs. min, max and default
+// are in the parameter's own unit.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    pub scale: ParamScale,
+    pub modulatable: bool,
+}
+
+impl ParamInfo {
+    pub fn denormalize(&self, value: f32) -> f32 {
+        let value = value.clamp(0.0, 1.0);
+        match self.scale {
+            ParamScale::Linear => self.min + value * (self.max - self.min),
+            ParamScale::Logarithmic => self.min * (self.max / self.min).powf(value),
+            ParamScale::Stepped => self.min + (value * (self.max - self.min)).round(),
+        }
+    }
+
+    pub fn normalize(&self, value: f32) -> f32 {
+        let value = value.clamp(self.min, self.max);
+        let normalized = match self.scale {
+            ParamScale::Linear | ParamScale::Stepped => (value - self.min) / (self.max - self.min),
+            ParamScale::Logarithmic => (value / self.min).ln() / (self.max / self.min).ln(),
+        };
+        normalized.clamp(0.0, 1.0)
+    }
+}
+
+impl ParamTarget {
+    // The parameter's stable index into PARAM_TARGETS.
+    pub fn id(&self) -> usize {
+        PARAM_TARGETS.iter().position(|target| target == self).unwrap_or(0)
+    }
+
+    pub fn from_id(id: usize) -> Option<ParamTarget> {
+        PARAM_TARGETS.get(id).copied()
+    }
+
+    pub fn info(&self) -> ParamInfo {
+        use ParamScale::{Linear, Logarithmic, Stepped};
+        let (unit, min, max, default, scale, modulatable) = match self {
+            ParamTarget::Volume => ("", 0.0, 1.0, 1.0, Linear, true),
+            ParamTarget::Expression => ("", 0.0, 1.0, 1.0, Linear, true),
+            ParamTarget::Mute => ("", 0.0, 1.0, 0.0, Stepped, false),
+            ParamTarget::ModWheel => ("", 0.0, 1.0, 0.0, Linear, true),
+            ParamTarget::SustainPedal => ("", 0.0, 1.0, 0.0, Stepped, false),
+            ParamTarget::Attack => ("samples", 0.0, MAX_ENVELOPE_TIME, ATTACK as f32, Linear, false),
+            ParamTarget::Hold => ("samples", 0.0, MAX_ENVELOPE_TIME, HOLD as f32, Linear, false),
+            ParamTarget::Decay => ("samples", 0.0, MAX_ENVELOPE_TIME, DECAY as f32, Linear, false),
+            ParamTarget::SustainLevel => ("", 0.0, 1.0, SUSTAIN, Linear, false),
+            ParamTarget::SustainVelocity => ("", 0.0, 1.0, 0.0, Linear, false),
+            ParamTarget::Release => ("samples", 0.0, MAX_ENVELOPE_TIME, RELEASE as f32, Linear, false),
+            ParamTarget::BendRange => ("semitones", 0.0, MAX_BEND_RANGE, BEND_RANGE, Linear, false),
+            ParamTarget::CoarseTune => ("semitones", -MAX_COARSE_TUNE, MAX_COARSE_TUNE, 0.0, Stepped, false),
+            ParamTarget::FineTune => ("cents", -MAX_FINE_TUNE, MAX_FINE_TUNE, 0.0, Linear, true),
+            ParamTarget::DriftAmount => ("cents", 0.0, MAX_DRIFT_AMOUNT, 0.0, Linear, true),
+            ParamTarget::DriftRate => ("Hz", 0.0, MAX_DRIFT_RATE, 0.0, Linear, true),
+            ParamTarget::WavetablePosition => ("", 0.0, 1.0, 0.0, Linear, true),
+            ParamTarget::WavetableEnvelope => ("", 0.0, 1.0, 0.0, Linear, true),
+            ParamTarget::WavetableVelocity => ("", 0.0, 1.0, 0.0, Linear, true),
+            ParamTarget::PanSpread => ("", 0.0, 1.0, 0.0, Linear, false),
+            ParamTarget::VelocityLoudness => ("", 0.0, 1.0, 0.0, Linear, false),
+            ParamTarget::Cutoff => ("Hz", MIN_CUTOFF, OPEN_CUTOFF, OPEN_CUTOFF, Logarithmic, true),
+            ParamTarget::Resonance => ("", 0.0, 1.0, 0.0, Linear, true),
+            ParamTarget::PhaseDistortion => ("", 0.0, 1.0, 0.0, Linear, true),
+            ParamTarget::Freeze => ("", 0.0, 1.0, 0.0, Stepped, false),
+            ParamTarget::AttackCurve => ("", -1.0, 1.0, 0.0, Linear, false),
+            ParamTarget::DecayCurve => ("", -1.0, 1.0, 0.0, Linear, false),
+            ParamTarget::ReleaseCurve => ("", -1.0, 1.0, 0.0, Linear, false),
+            ParamTarget::MidiLog => ("", 0.0, 1.0, 0.0, Stepped, false),
+            ParamTarget::Trim => ("dB", -MAX_TRIM, MAX_TRIM, 0.0, Linear, false),
+            ParamTarget::FormantMix => ("", 0.0, 1.0, 0.0, Linear, false),
+            ParamTarget::Vowel => ("", 0.0, (VOWELS - 1) as f32, 0.0, Linear, false),
+            ParamTarget::DriftCorrelation => ("", 0.0, 1.0, 1.0, Linear, false),
+            ParamTarget::VelocityBrightness => ("", 0.0, 1.0, 0.0, Linear, false),
+            ParamTarget::VoiceDrive => ("", 0.0, 1.0, 0.0, Linear, true),
+            ParamTarget::HfDecay => ("1/s", 0.0, MAX_HF_DECAY, 0.0, Linear, false),
+        };
+        ParamInfo {
+            name: self.name(),
+            unit,
+            min,
+            max,
+            default,
+            scale,
+            modulatable,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParamTarget::Volume => "volume",
+            ParamTarget::Expression => "expression",
+            ParamTarget::Mute => "mute",
+            ParamTarget::ModWheel => "mod_wheel",
+            ParamTarget::SustainPedal => "sustain_pedal",
+            ParamTarget::Attack => "attack",
+            ParamTarget::Hold => "hold",
+            ParamTarget::Decay => "decay",
+            ParamTarget::SustainLevel => "sustain",
+            ParamTarget::SustainVelocity => "sustain_velocity",
+            ParamTarget::Release => "release",
+            ParamTarget::BendRange => "bend_range",
+            ParamTarget::CoarseTune => "coarse_tune",
+            ParamTarget::FineTune => "fine_tune",
+            ParamTarget::DriftAmount => "drift_amount",
+            ParamTarget::DriftRate => "drift_rate",
+            ParamTarget::WavetablePosition => "wavetable_position",
+            ParamTarget::WavetableEnvelope => "wavetable_envelope",
+            ParamTarget::WavetableVelocity => "wavetable_velocity",
+            ParamTarget::PanSpread => "pan_spread",
+            ParamTarget::VelocityLoudness => "velocity_loudness",
+            ParamTarget::Cutoff => "cutoff",
+            ParamTarget::Resonance => "resonance",
+            ParamTarget::PhaseDistortion => "pd_amount",
+            ParamTarget::Freeze => "freeze",
+            ParamTarget::AttackCurve => "attack_curve",
+            ParamTarget::DecayCurve => "decay_curve",
+            ParamTarget::ReleaseCurve => "release_curve",
+            ParamTarget::MidiLog => "midi_log",
+            ParamTarget::Trim => "trim",
+            ParamTarget::FormantMix => "formant_mix",
+            ParamTarget::Vowel => "vowel",
+            ParamTarget::DriftCorrelation => "drift_correlation",
+            ParamTarget::VelocityBrightness => "velocity_brightness",
+            ParamTarget::VoiceDrive => "voice_drive",
+            ParamTarget::HfDecay => "hf_decay",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ParamTarget> {
+        PARAM_TARGETS.iter().copied().find(|target| target.name() == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_parameter_round_trips_through_normalize() {
+        for target in PARAM_TARGETS {
+            let info = target.info();
+            assert!(info.min <= info.default && info.default <= info.max, "{}", info.name);
+            for step in 0..=10 {
+                let normalized = step as f32 / 10.0;
+                let value = info.denormalize(normalized);
+                assert!(value >= info.min && value <= info.max, "{} {}", info.name, value);
+                if info.scale == ParamScale::Stepped {
+                    assert_eq!(info.denormalize(info.normalize(value)), value, "{}", info.name);
+                } else {
+                    assert!((info.normalize(value) - normalized).abs() < 1e-4, "{} {}", info.name, normalized);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn logarithmic_parameters_put_the_geometric_mean_in_the_middle() {
+        let cutoff = ParamTarget::Cutoff.info();
+        assert_eq!(cutoff.scale, ParamScale::Logarithmic);
+        let middle = cutoff.denormalize(0.5);
+        assert!((middle - (cutoff.min * cutoff.max).sqrt()).abs() < 0.01 * middle);
+    }
+
+    #[test]
+    fn ids_and_names_find_their_parameter() {
+        for (id, target) in PARAM_TARGETS.into_iter().enumerate() {
+            assert_eq!(target.id(), id);
+            assert_eq!(ParamTarget::from_id(id), Some(target));
+            assert_eq!(ParamTarget::from_name(target.name()), Some(target));
+            assert_eq!(target.info().name, target.name());
+        }
+        assert_eq!(ParamTarget::from_id(PARAM_TARGETS.len()), None);
+    }
+}
@@ -0,0 +1,607 @@
+use crate::filter::Filter;
+use crate::formant::Formant;
+use crate::mod_matrix::ModMatrix;
+use crate::params::ParamTarget;
+use crate::scale::Scale;
+
+pub const ATTACK: usize = 2000;
+pub const HOLD: usize = 0;
+pub const DECAY: usize = 5000;
+pub const SUSTAIN: f32 = 0.6;
+pub const RELEASE: usize = 10000;
+const LAYER_PHASE_OFFSET: f32 = 0.05;
+pub const BEND_RANGE: f32 = 2.0;
+const LEVEL_KEY_CENTER: u8 = 60;
+const LOUDNESS_EXPONENT: f32 = 0.6;
+pub const MAX_UNISON: usize = 8;
+const MAX_CURVE: f32 = 6.0;
+const MAX_VOICE_DRIVE: f32 = 8.0;
+const HF_DECAY_START: f32 = 20000.0;
+const HF_DECAY_FLOOR: f32 = 200.0;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Waveform {
+    Sine,
+    #[default]
+    Saw,
+    Square,
+    Triangle,
+    Wavetable,
+    PhaseDistortion,
+    Sample,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Crossfade {
+    Linear,
+    #[default]
+    EqualPower,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct VelocityLayers {
+    pub soft: Waveform,
+    pub hard: Waveform,
+    pub crossfade: Crossfade,
+    pub phase_offset: f32,
+}
+
+impl Default for VelocityLayers {
+    fn default() -> VelocityLayers {
+        VelocityLayers {
+            soft: Waveform::Sine,
+            hard: Waveform::Saw,
+            crossfade: Crossfade::default(),
+            phase_offset: LAYER_PHASE_OFFSET,
+        }
+    }
+}
+
+impl VelocityLayers {
+    pub fn gains(&self, velocity: f32) -> (f32, f32) {
+        let mix = velocity.clamp(0.0, 1.0);
+        if mix == 0.0 {
+            return (1.0, 0.0);
+        }
+        if mix == 1.0 {
+            return (0.0, 1.0);
+        }
+
+        match self.crossfade {
+            Crossfade::Linear => (1.0 - mix, mix),
+            Crossfade::EqualPower => {
+                let angle = mix * std::f32::consts::FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+        }
+    }
+}
+
+// Time glides take glide_time seconds whatever the interval, Rate glides move
+// at one octave per glide_time seconds so wider jumps take longer.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum GlideMode {
+    #[default]
+    Time,
+    Rate,
+}
+
+// How a glide travels between the two pitches: Cents moves evenly in pitch,
+// so it passes the geometric mean of the two frequencies halfway, and Hz moves
+// evenly in frequency, passing their arithmetic mean.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum GlideCurve {
+    #[default]
+    Cents,
+    Hz,
+}
+
+// Where each note sits in the stereo field before pan_spread scales it: by
+// pitch (low notes left, high notes right), alternating left and right per
+// voice, or at a random position on each note-on.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum PanSpreadMode {
+    #[default]
+    Pitch,
+    Alternate,
+    Random,
+}
+
+// Which held key sounds in mono mode: the most recent one, the lowest or the
+// highest.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum NotePriority {
+    #[default]
+    Last,
+    Low,
+    High,
+}
+
+impl NotePriority {
+    // `held` is in the order the keys went down.
+    pub fn select(&self, held: &[u8]) -> Option<u8> {
+        match self {
+            NotePriority::Last => held.last().copied(),
+            NotePriority::Low => held.iter().min().copied(),
+            NotePriority::High => held.iter().max().copied(),
+        }
+    }
+}
+
+// Stage times are in samples. Each stage has its own curve (see `curve`),
+// so a linear attack can pair with an exponential release; the release
+// always starts from the level the note had when it was released and lands
+// on exactly zero after `release` samples, whatever its curve.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Envelope {
+    pub attack: usize,
+    pub hold: usize,
+    pub decay: usize,
+    pub sustain: f32,
+    pub sustain_velocity: f32,
+    pub release: usize,
+    pub attack_curve: f32,
+    pub decay_curve: f32,
+    pub release_curve: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Envelope {
+        Envelope {
+            attack: ATTACK,
+            hold: HOLD,
+            decay: DECAY,
+            sustain: SUSTAIN,
+            sustain_velocity: 0.0,
+            release: RELEASE,
+            attack_curve: 0.0,
+            decay_curve: 0.0,
+            release_curve: 0.0,
+        }
+    }
+}
+
+impl Envelope {
+    // The sustain level for a note of 0..1 velocity. With sustain_velocity at
+    // 0 it is just `sustain`; at 1 it scales all the way down with velocity, so
+    // soft notes settle lower than hard ones.
+    pub fn sustain_level(&self, velocity: f32) -> f32 {
+        if self.sustain_velocity == 0.0 {
+            return self.sustain;
+        }
+        self.sustain * (1.0 - self.sustain_velocity.clamp(0.0, 1.0) * (1.0 - velocity))
+    }
+}
+
+// Bends a stage's 0..1 progress. A -1..1 curve of 0 leaves it linear; a
+// positive one moves fast early and slows towards the end (an exponential
+// decay, or a logarithmic rise), a negative one the reverse. 0 and 1 always
+// map to themselves. Attack stages pass their curve negated so +1 reads as
+// exponential for every stage.
+pub fn curve(progress: f32, amount: f32) -> f32 {
+    let k = amount.clamp(-1.0, 1.0) * MAX_CURVE;
+    if k == 0.0 || progress <= 0.0 || progress >= 1.0 {
+        return progress;
+    }
+    (1.0 - (-k * progress).exp()) / (1.0 - (-k).exp())
+}
+
+// The progress at which `curve` reaches `value`.
+pub fn inverse_curve(value: f32, amount: f32) -> f32 {
+    let k = amount.clamp(-1.0, 1.0) * MAX_CURVE;
+    if k == 0.0 || value <= 0.0 || value >= 1.0 {
+        return value;
+    }
+    -(1.0 - value * (1.0 - (-k).exp())).ln() / k
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Patch {
+    pub waveform: Waveform,
+    pub envelope: Envelope,
+    pub filter: Filter,
+    pub phase: f32,
+    pub random_phase: bool,
+    pub env_retrigger: bool,
+    pub retrigger_phase_reset: bool,
+    pub retrigger_envelope_reset: bool,
+    pub bend_range: f32,
+    pub glide_time: f32,
+    pub glide_mode: GlideMode,
+    pub glide_curve: GlideCurve,
+    pub glide_window: f32,
+    pub snap_scale: Scale,
+    pub snap_root: u8,
+    pub mono: bool,
+    pub note_priority: NotePriority,
+    pub max_voices: Option<usize>,
+    pub voice_priority: u8,
+    pub drift_amount: f32,
+    pub drift_rate: f32,
+    pub drift_correlation: f32,
+    pub level_key_track: f32,
+    pub level_key_center: u8,
+    pub wavetable_position: f32,
+    pub wavetable_envelope: f32,
+    pub wavetable_velocity: f32,
+    pub velocity_layers: Option<VelocityLayers>,
+    pub pan_spread: f32,
+    pub pan_spread_mode: PanSpreadMode,
+    pub velocity_loudness: f32,
+    pub velocity_floor: f32,
+    pub velocity_brightness: f32,
+    pub voice_drive: f32,
+    pub hf_decay: f32,
+    pub voice_stereo_detune: f32,
+    pub pd_amount: f32,
+    pub pd_envelope: f32,
+    pub unison_voices: usize,
+    pub unison_detune: f32,
+    pub unison_stereo_spread: f32,
+    pub modulation: ModMatrix,
+    pub trim: f32,
+    pub invert: bool,
+    pub formant: Formant,
+}
+
+// The default patch is the init sound: a plain saw through an open low-pass
+// filter with the medium default envelope and no wavetable or velocity
+// modulation. Partial presets can fill the
+// remaining fields with `..Patch::default()`.
+impl Default for Patch {
+    fn default() -> Patch {
+        Patch {
+            waveform: Waveform::default(),
+            envelope: Envelope::default(),
+            filter: Filter::default(),
+            phase: 0.0,
+            random_phase: false,
+            env_retrigger: true,
+            retrigger_phase_reset: false,
+            retrigger_envelope_reset: false,
+            bend_range: BEND_RANGE,
+            glide_time: 0.0,
+            glide_mode: GlideMode::default(),
+            glide_curve: GlideCurve::default(),
+            glide_window: f32::INFINITY,
+            snap_scale: Scale::default(),
+            snap_root: 0,
+            mono: false,
+            note_priority: NotePriority::default(),
+            max_voices: None,
+            voice_priority: 0,
+            drift_amount: 0.0,
+            drift_rate: 0.0,
+            drift_correlation: 1.0,
+            level_key_track: 0.0,
+            level_key_center: LEVEL_KEY_CENTER,
+            wavetable_position: 0.0,
+            wavetable_envelope: 0.0,
+            wavetable_velocity: 0.0,
+            velocity_layers: None,
+            pan_spread: 0.0,
+            pan_spread_mode: PanSpreadMode::default(),
+            velocity_loudness: 0.0,
+            velocity_floor: 0.0,
+            velocity_brightness: 0.0,
+            voice_drive: 0.0,
+            hf_decay: 0.0,
+            voice_stereo_detune: 0.0,
+            pd_amount: 0.0,
+            pd_envelope: 0.0,
+            unison_voices: 1,
+            unison_detune: 0.0,
+            unison_stereo_spread: 0.0,
+            modulation: ModMatrix::default(),
+            trim: 0.0,
+            invert: false,
+            formant: Formant::default(),
+        }
+    }
+}
+
+impl Patch {
+    // Whether a note starting `gap` seconds after the channel's last key was
+    // let go glides from it; 0 while a key is still down. Only gaps shorter
+    // than glide_window glide, so a short window glides just the lines played
+    // legato or nearly so, 0 never glides and the default, infinity, always
+    // does.
+    pub fn glides_after(&self, gap: f32) -> bool {
+        self.glide_time > 0.0 && gap < self.glide_window
+    }
+
+    // Semitones per sample for a glide covering `interval` semitones.
+    pub fn glide_step(&self, interval: f32, time_step: f32) -> f32 {
+        if self.glide_time <= 0.0 {
+            return f32::INFINITY;
+        }
+        match self.glide_mode {
+            GlideMode::Time => interval.abs() * time_step / self.glide_time,
+            GlideMode::Rate => 12.0 * time_step / self.glide_time,
+        }
+    }
+
+    // Amplitude for a 0..1 velocity. Loudness grows roughly with amplitude to
+    // the power LOUDNESS_EXPONENT, so with velocity_loudness at 1 the curve
+    // follows that contour and soft notes keep more presence than the linear
+    // mapping gives them. 0 is the plain linear mapping; 0 and 1 velocity are
+    // unchanged either way. The result never drops below velocity_floor, so
+    // the softest notes stay faintly audible; only the loudness is floored,
+    // other velocity routes still see the played velocity.
+    pub fn velocity_gain(&self, velocity: f32) -> f32 {
+        let gain = if self.velocity_loudness == 0.0 {
+            velocity
+        } else {
+            let amount = self.velocity_loudness.clamp(0.0, 1.0);
+            velocity.powf(1.0 - amount * (1.0 - LOUDNESS_EXPONENT))
+        };
+        // A NaN floor stays NaN through the clamp, and max ignores it.
+        gain.max(self.velocity_floor.clamp(0.0, 1.0))
+    }
+
+    // Position of unison voice `index` across the stack, -1..1, with the
+    // outermost voices at the ends. It sets both the voice's detune (as a
+    // fraction of unison_detune cents) and its pan (of unison_stereo_spread).
+    pub fn unison_offset(&self, index: usize) -> f32 {
+        let voices = self.unison_voices.clamp(1, MAX_UNISON);
+        if voices == 1 {
+            return 0.0;
+        }
+        2.0 * index as f32 / (voices - 1) as f32 - 1.0
+    }
+
+    // The tilt a note of `velocity` (0..1) gets from velocity_brightness: up
+    // to fully bright at full velocity and fully dark at zero.
+    pub fn velocity_tilt(&self, velocity: f32) -> f32 {
+        self.velocity_brightness.clamp(0.0, 1.0) * (2.0 * velocity - 1.0)
+    }
+
+    // The per-voice tanh shaper on the raw oscillator, ahead of the filter and
+    // the envelope's gain. It is scaled so a full-scale wave still peaks at 1.
+    pub fn saturate(&self, value: f32) -> f32 {
+        if self.voice_drive <= 0.0 {
+            return value;
+        }
+        let drive = 1.0 + self.voice_drive.min(1.0) * MAX_VOICE_DRIVE;
+        (value * drive).tanh() / drive.tanh()
+    }
+
+    // The one-pole coefficient of the per-voice damping low-pass for a note
+    // `age` seconds old, or None with hf_decay off. The cutoff starts at
+    // HF_DECAY_START and falls exponentially at `hf_decay` per second, down
+    // to HF_DECAY_FLOOR, so the highs die away first.
+    pub fn hf_damping(&self, age: f32, sample_rate: f32) -> Option<f32> {
+        if self.hf_decay <= 0.0 {
+            return None;
+        }
+        let cutoff = (HF_DECAY_START * (-self.hf_decay * age).exp()).max(HF_DECAY_FLOOR).min(0.49 * sample_rate);
+        Some(1.0 - (-2.0 * std::f32::consts::PI * cutoff / sample_rate).exp())
+    }
+
+    // The pitch ratio each side's copy of the oscillator sits from the note
+    // with voice_stereo_detune on: the left copy plays below by half the
+    // detune and the right above. None at 0 or under unison, which has its own
+    // stereo spread.
+    pub fn stereo_detune(&self) -> Option<f32> {
+        if self.voice_stereo_detune == 0.0 || self.unison_voices > 1 {
+            return None;
+        }
+        Some(2.0_f32.powf(0.5 * self.voice_stereo_detune / 1200.0))
+    }
+
+    // Whether each unison voice needs drift of its own: at a drift
+    // correlation of 1 the whole stack follows the note's drift.
+    pub fn unison_drifts(&self) -> bool {
+        self.drift_amount != 0.0 && self.unison_voices > 1 && self.drift_correlation < 1.0
+    }
+
+    // The value of a parameter that lives in the patch, in its own unit, or
+    // None for channel and engine parameters.
+    pub fn param(&self, target: ParamTarget) -> Option<f32> {
+        let value = match target {
+            ParamTarget::Attack => self.envelope.attack as f32,
+            ParamTarget::Hold => self.envelope.hold as f32,
+            ParamTarget::Decay => self.envelope.decay as f32,
+            ParamTarget::SustainLevel => self.envelope.sustain,
+            ParamTarget::SustainVelocity => self.envelope.sustain_velocity,
+            ParamTarget::Release => self.envelope.release as f32,
+            ParamTarget::AttackCurve => self.envelope.attack_curve,
+            ParamTarget::DecayCurve => self.envelope.decay_curve,
+            ParamTarget::ReleaseCurve => self.envelope.release_curve,
+            ParamTarget::BendRange => self.bend_range,
+            ParamTarget::DriftAmount => self.drift_amount,
+            ParamTarget::DriftRate => self.drift_rate,
+            ParamTarget::WavetablePosition => self.wavetable_position,
+            ParamTarget::WavetableEnvelope => self.wavetable_envelope,
+            ParamTarget::WavetableVelocity => self.wavetable_velocity,
+            ParamTarget::PanSpread => self.pan_spread,
+            ParamTarget::VelocityLoudness => self.velocity_loudness,
+            ParamTarget::Cutoff => self.filter.cutoff,
+            ParamTarget::Resonance => self.filter.resonance,
+            ParamTarget::PhaseDistortion => self.pd_amount,
+            ParamTarget::Trim => self.trim,
+            ParamTarget::FormantMix => self.formant.mix,
+            ParamTarget::Vowel => self.formant.vowel,
+            ParamTarget::DriftCorrelation => self.drift_correlation,
+            ParamTarget::VelocityBrightness => self.velocity_brightness,
+            ParamTarget::VoiceDrive => self.voice_drive,
+            ParamTarget::HfDecay => self.hf_decay,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // Returns false, changing nothing, for parameters outside the patch.
+    pub fn set_param(&mut self, target: ParamTarget, value: f32) -> bool {
+        match target {
+            ParamTarget::Attack => self.envelope.attack = value as usize,
+            ParamTarget::Hold => self.envelope.hold = value as usize,
+            ParamTarget::Decay => self.envelope.decay = value as usize,
+            ParamTarget::SustainLevel => self.envelope.sustain = value,
+            ParamTarget::SustainVelocity => self.envelope.sustain_velocity = value,
+            ParamTarget::Release => self.envelope.release = value as usize,
+            ParamTarget::AttackCurve => self.envelope.attack_curve = value,
+            ParamTarget::DecayCurve => self.envelope.decay_curve = value,
+            ParamTarget::ReleaseCurve => self.envelope.release_curve = value,
+            ParamTarget::BendRange => self.bend_range = value,
+            ParamTarget::DriftAmount => self.drift_amount = value,
+            ParamTarget::DriftRate => self.drift_rate = value,
+            ParamTarget::WavetablePosition => self.wavetable_position = value,
+            ParamTarget::WavetableEnvelope => self.wavetable_envelope = value,
+            ParamTarget::WavetableVelocity => self.wavetable_velocity = value,
+            ParamTarget::PanSpread => self.pan_spread = value,
+            ParamTarget::VelocityLoudness => self.velocity_loudness = value,
+            ParamTarget::Cutoff => self.filter.cutoff = value,
+            ParamTarget::Resonance => self.filter.resonance = value,
+            ParamTarget::PhaseDistortion => self.pd_amount = value,
+            ParamTarget::Trim => self.trim = value,
+            ParamTarget::FormantMix => self.formant.mix = value,
+            ParamTarget::Vowel => self.formant.vowel = value,
+            ParamTarget::DriftCorrelation => self.drift_correlation = value,
+            ParamTarget::VelocityBrightness => self.velocity_brightness = value,
+            ParamTarget::VoiceDrive => self.voice_drive = value,
+            ParamTarget::HfDecay => self.hf_decay = value,
+            _ => return false,
+        }
+        true
+    }
+
+    // The factor the channel's voice sum is scaled by ahead of its volume:
+    // `trim` in dB, negated when `invert` flips the patch's polarity for
+    // layering.
+    pub fn output_gain(&self) -> f32 {
+        let gain = if self.trim == 0.0 { 1.0 } else { 10.0_f32.powf(self.trim / 20.0) };
+        if self.invert { -gain } else { gain }
+    }
+
+    // Level key tracking is in dB per octave away from level_key_center.
+    pub fn key_level(&self, pitch: u8) -> f32 {
+        if self.level_key_track == 0.0 {
+            return 1.0;
+        }
+        let octaves = (pitch as f32 - self.level_key_center as f32) / 12.0;
+        10.0_f32.powf(self.level_key_track * octaves / 20.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn velocity_floor_lifts_only_the_softest_gains() {
+        let patch = Patch { velocity_floor: 0.1, ..Patch::default() };
+        assert_eq!(patch.velocity_gain(1.0 / 127.0), 0.1);
+        assert_eq!(patch.velocity_gain(0.5), 0.5);
+        let curved = Patch { velocity_loudness: 1.0, ..patch };
+        assert!(curved.velocity_gain(0.001) >= 0.1);
+        assert_eq!(Patch::default().velocity_gain(1.0 / 127.0), 1.0 / 127.0);
+    }
+
+    #[test]
+    fn invalid_velocity_floors_do_not_panic() {
+        assert_eq!(Patch { velocity_floor: f32::NAN, ..Patch::default() }.velocity_gain(0.25), 0.25);
+        assert_eq!(Patch { velocity_floor: 2.0, ..Patch::default() }.velocity_gain(0.25), 1.0);
+        assert_eq!(Patch { velocity_floor: -1.0, ..Patch::default() }.velocity_gain(0.25), 0.25);
+    }
+
+    #[test]
+    fn the_velocity_extremes_hit_one_layer_exactly() {
+        for crossfade in [Crossfade::Linear, Crossfade::EqualPower] {
+            let layers = VelocityLayers { crossfade, ..VelocityLayers::default() };
+            assert_eq!(layers.gains(0.0), (1.0, 0.0));
+            assert_eq!(layers.gains(1.0), (0.0, 1.0));
+            let (soft, hard) = layers.gains(0.5);
+            assert!(soft > 0.0 && hard > 0.0);
+        }
+        let (soft, hard) = VelocityLayers::default().gains(0.3);
+        assert!((soft * soft + hard * hard - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn key_tracking_makes_high_notes_louder() {
+        let patch = Patch { level_key_track: 6.0, ..Patch::default() };
+        assert!(patch.key_level(84) > patch.key_level(36));
+        assert_eq!(patch.key_level(patch.level_key_center), 1.0);
+        assert!((patch.key_level(patch.level_key_center + 12) - 10.0_f32.powf(0.3)).abs() < 1e-5);
+        for pitch in [0, 60, 127] {
+            assert_eq!(Patch::default().key_level(pitch), 1.0);
+        }
+    }
+
+    #[test]
+    fn loudness_compensation_lifts_soft_notes_below_full_level() {
+        let patch = Patch { velocity_loudness: 1.0, ..Patch::default() };
+        for velocity in [0.1, 0.25, 0.5] {
+            let compensated = patch.velocity_gain(velocity);
+            assert!(compensated > velocity && compensated < 1.0, "velocity {} gain {}", velocity, compensated);
+        }
+        assert_eq!(patch.velocity_gain(1.0), 1.0);
+        assert_eq!(Patch::default().velocity_gain(0.25), 0.25);
+    }
+
+    #[test]
+    fn curves_bend_the_midpoint_both_ways_and_keep_the_ends() {
+        assert_eq!(curve(0.5, 0.0), 0.5);
+        assert!(curve(0.5, 0.5) > 0.5 && curve(0.5, -0.5) < 0.5);
+        for amount in [-1.0, -0.3, 0.3, 1.0] {
+            assert_eq!((curve(0.0, amount), curve(1.0, amount)), (0.0, 1.0));
+            assert!((inverse_curve(curve(0.3, amount), amount) - 0.3).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn output_gain_follows_trim_and_invert() {
+        assert_eq!(Patch::default().output_gain(), 1.0);
+        assert!((Patch { trim: 6.0, ..Patch::default() }.output_gain() - 1.9953).abs() < 1e-3);
+        assert!((Patch { trim: -20.0, invert: true, ..Patch::default() }.output_gain() + 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_glide_window_bounds_the_gap_that_glides() {
+        let patch = Patch { glide_time: 0.1, glide_window: 0.05, ..Patch::default() };
+        assert!(patch.glides_after(0.0) && patch.glides_after(0.049));
+        assert!(!patch.glides_after(0.05));
+        assert!(!Patch { glide_window: 0.0, ..patch }.glides_after(0.0));
+        assert!(Patch { glide_window: f32::INFINITY, ..patch }.glides_after(1e6));
+        assert!(!Patch::default().glides_after(0.0));
+    }
+
+    #[test]
+    fn velocity_tilt_pivots_at_mid_velocity() {
+        let patch = Patch { velocity_brightness: 0.5, ..Patch::default() };
+        assert_eq!(patch.velocity_tilt(1.0), 0.5);
+        assert_eq!(patch.velocity_tilt(0.5), 0.0);
+        assert_eq!(patch.velocity_tilt(0.0), -0.5);
+        assert_eq!(Patch::default().velocity_tilt(1.0), 0.0);
+    }
+
+    #[test]
+    fn saturate_is_transparent_at_zero_drive_and_keeps_full_scale() {
+        let clean = Patch::default();
+        let driven = Patch { voice_drive: 0.5, ..Patch::default() };
+        for value in [-1.0, -0.3, 0.0, 0.25, 1.0] {
+            assert_eq!(clean.saturate(value), value);
+            assert_eq!(driven.saturate(-value), -driven.saturate(value));
+        }
+        assert!((driven.saturate(1.0) - 1.0).abs() < 1e-6);
+        assert!(driven.saturate(0.25) > 0.25);
+    }
+
+    #[test]
+    fn hf_damping_closes_with_age_down_to_its_floor() {
+        assert_eq!(Patch::default().hf_damping(1.0, 48000.0), None);
+        let patch = Patch { hf_decay: 2.0, ..Patch::default() };
+        let coefficients: Vec<f32> = [0.0, 0.5, 1.0, 2.0].iter().map(|&age| patch.hf_damping(age, 48000.0).unwrap()).collect();
+        assert!(coefficients.windows(2).all(|pair| pair[1] < pair[0]));
+        assert_eq!(patch.hf_damping(10.0, 48000.0), patch.hf_damping(100.0, 48000.0));
+    }
+
+    #[test]
+    fn stereo_detune_splits_the_detune_between_the_sides() {
+        assert_eq!(Patch::default().stereo_detune(), None);
+        let patch = Patch { voice_stereo_detune: 24.0, ..Patch::default() };
+        assert!((patch.stereo_detune().unwrap() - 2.0_f32.powf(12.0 / 1200.0)).abs() < 1e-6);
+        assert_eq!(Patch { unison_voices: 2, ..patch }.stereo_detune(), None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::{self, Write};
+
+use crate::random::{Random, DEFAULT_SEED};
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Dither {
+    None,
+    Rectangular,
+    #[default]
+    Triangular,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum BitDepth {
+    #[default]
+    Sixteen,
+    TwentyFour,
+}
+
+impl BitDepth {
+    pub fn bits(&self) -> u16 {
+        match self {
+            BitDepth::Sixteen => 16,
+            BitDepth::TwentyFour => 24,
+        }
+    }
+
+    fn max(&self) -> f32 {
+        ((1i32 << (self.bits() - 1)) - 1) as f32
+    }
+}
+
+// Turns engine f32 samples into integer PCM: scale, add dither, round, clamp.
+pub struct PcmConverter {
+    pub bit_depth: BitDepth,
+    pub dither: Dither,
+    random: Random,
+}
+
+impl PcmConverter {
+    pub fn new(bit_depth: BitDepth, dither: Dither) -> PcmConverter {
+        PcmConverter {
+            bit_depth,
+            dither,
+            random: Random::new(DEFAULT_SEED),
+        }
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    pub fn convert(&mut self, sample: f32) -> i32 {
+        self.quantize(sample, self.bit_depth)
+    }
+
+    fn quantize(&mut self, sample: f32, bit_depth: BitDepth) -> i32 {
+        let max = bit_depth.max();
+        let noise = match self.dither {
+            Dither::None => 0.0,
+            Dither::Rectangular => self.random.next_f32() - 0.5,
+            Dither::Triangular => self.random.next_f32() - self.random.next_f32(),
+        };
+        let value = (sample * max + noise).round();
+        value.clamp(-max - 1.0, max) as i32
+    }
+
+    // Always 16-bit, whatever bit_depth is set to.
+    pub fn convert_i16(&mut self, samples: &[f32], out: &mut [i16]) {
+        for (sample, value) in samples.iter().zip(out.iter_mut()) {
+            *value = self.quantize(*sample, BitDepth::Sixteen) as i16;
+        }
+    }
+
+    pub fn convert_block(&mut self, samples: &[f32], out: &mut [i32]) {
+        for (sample, value) in samples.iter().zip(out.iter_mut()) {
+            *value = self.convert(*sample);
+        }
+    }
+}
+
+impl Default for PcmConverter {
+    fn default() -> PcmConverter {
+        PcmConverter::new(BitDepth::default(), Dither::default())
+    }
+}
+
+// Writes interleaved integer samples as a PCM WAV file.
+pub fn write_wav<W: Write>(mut writer: W, sample_rate: u32, channels: u16, bit_depth: BitDepth, samples: &[i32]) -> io::Result<()> {
+    let bytes_per_sample = bit_depth.bits() as u32 / 8;
+    let data_size = samples.len() as u32 * bytes_per_sample;
+    let block_align = channels as u32 * bytes_per_sample;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align).to_le_bytes())?;
+    writer.write_all(&(block_align as u16).to_le_bytes())?;
+    writer.write_all(&bit_depth.bits().to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+
+    for sample in samples {
+        let bytes = sample.to_le_bytes();
+        writer.write_all(&bytes[..bytes_per_sample as usize])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_ramp_rounds_and_clamps_to_16_bits() {
+        let mut converter = PcmConverter::new(BitDepth::Sixteen, Dither::None);
+        let ramp = [-2.0, -1.0, -0.5, 0.0, 0.4 / 32767.0, 0.6 / 32767.0, 0.5, 1.0, 2.0];
+        let mut out = [0; 9];
+        converter.convert_i16(&ramp, &mut out);
+        assert_eq!(out, [-32768, -32767, -16384, 0, 0, 1, 16384, 32767, 32767]);
+    }
+
+    #[test]
+    fn triangular_dither_turns_a_sub_lsb_level_into_noise_around_it() {
+        let level = 0.25 / 32767.0;
+        let mut plain = PcmConverter::new(BitDepth::Sixteen, Dither::None);
+        let mut dithered = PcmConverter::new(BitDepth::Sixteen, Dither::Triangular);
+        let (mut plain_out, mut dithered_out) = ([0; 10000], [0; 10000]);
+        plain.convert_i16(&[level; 10000], &mut plain_out);
+        dithered.convert_i16(&[level; 10000], &mut dithered_out);
+        assert!(plain_out.iter().all(|&value| value == 0));
+        let mean = dithered_out.iter().map(|&value| value as f32).sum::<f32>() / 10000.0;
+        assert!((mean - 0.25).abs() < 0.05, "mean {}", mean);
+        // Triangular dither never spreads more than one step either side.
+        assert!(dithered_out.iter().all(|value| (-1..=2).contains(value)));
+    }
+
+    #[test]
+    fn twenty_four_bit_samples_take_three_bytes_each() {
+        let mut converter = PcmConverter::new(BitDepth::TwentyFour, Dither::None);
+        let mut samples = [0; 2];
+        converter.convert_block(&[1.0, -1.0], &mut samples);
+        assert_eq!(samples, [8388607, -8388607]);
+        let mut wav = Vec::new();
+        write_wav(&mut wav, 48000, 1, BitDepth::TwentyFour, &samples).unwrap();
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[44..], &[0xFF, 0xFF, 0x7F, 0x01, 0x00, 0x80]);
+    }
+}
@@ -0,0 +1,637 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::filter::{Filter, FilterMode, FilterPlacement, FilterTopology};
+use crate::formant::Formant;
+use crate::lfo::{LfoMode, LfoRate, LfoRetrigger, LfoWaveform};
+use crate::macros::MAX_MACROS;
+use crate::mod_matrix::{ModDestination, ModLfo, ModMatrix, ModRoute, ModSource, MAX_MOD_ENVELOPES, MAX_MOD_ROUTES};
+use crate::params::ParamTarget;
+use crate::patch::{Crossfade, Envelope, GlideCurve, GlideMode, NotePriority, PanSpreadMode, Patch, VelocityLayers, Waveform, MAX_UNISON};
+use crate::scale::Scale;
+
+// Bumped whenever a field's encoding changes meaning; strings of any other
+// version are rejected rather than decoded into a different sound.
+pub const PRESET_VERSION: u8 = 3;
+
+// Bounds for the fields with no ParamTarget to take a range from. Decoded
+// values outside them are clamped, like parameters to their ParamInfo range.
+const MAX_GLIDE_TIME: f32 = 60.0;
+const MAX_KEY_TRACK: f32 = 24.0;
+const MAX_DETUNE: f32 = 1200.0;
+const MAX_LFO_RATE: f32 = 1000.0;
+const MAX_LFO_DIVISION: f32 = 64.0;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const WAVEFORMS: [Waveform; 7] = [
+    Waveform::Sine,
+    Waveform::Saw,
+    Waveform::Square,
+    Waveform::Triangle,
+    Waveform::Wavetable,
+    Waveform::PhaseDistortion,
+    Waveform::Sample,
+];
+const CROSSFADES: [Crossfade; 2] = [Crossfade::Linear, Crossfade::EqualPower];
+const FILTER_MODES: [FilterMode; 3] = [FilterMode::LowPass, FilterMode::HighPass, FilterMode::BandPass];
+const FILTER_TOPOLOGIES: [FilterTopology; 2] = [FilterTopology::Naive, FilterTopology::ZeroDelay];
+const FILTER_PLACEMENTS: [FilterPlacement; 2] = [FilterPlacement::PerVoice, FilterPlacement::Master];
+const GLIDE_MODES: [GlideMode; 2] = [GlideMode::Time, GlideMode::Rate];
+const GLIDE_CURVES: [GlideCurve; 2] = [GlideCurve::Cents, GlideCurve::Hz];
+const SCALES: [Scale; 5] = [Scale::Chromatic, Scale::Major, Scale::Minor, Scale::MajorPentatonic, Scale::MinorPentatonic];
+const NOTE_PRIORITIES: [NotePriority; 3] = [NotePriority::Last, NotePriority::Low, NotePriority::High];
+const PAN_SPREAD_MODES: [PanSpreadMode; 3] = [PanSpreadMode::Pitch, PanSpreadMode::Alternate, PanSpreadMode::Random];
+const LFO_WAVEFORMS: [LfoWaveform; 4] = [LfoWaveform::Sine, LfoWaveform::Triangle, LfoWaveform::Square, LfoWaveform::Saw];
+const LFO_RETRIGGERS: [LfoRetrigger; 3] = [LfoRetrigger::BeatSync, LfoRetrigger::Free, LfoRetrigger::NoteOn];
+const LFO_MODES: [LfoMode; 2] = [LfoMode::Global, LfoMode::PerVoice];
+
+// The controller bindings a preset carries next to its patch: which CC drives
+// which parameter, and which drives which macro.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CcBindings {
+    pub params: HashMap<u8, ParamTarget>,
+    pub macros: HashMap<u8, usize>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &byte| sum.rotate_left(1) ^ byte)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn u32(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value.min(u32::MAX as usize) as u32).to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn choice<T: PartialEq>(&mut self, values: &[T], value: &T) {
+        self.u8(values.iter().position(|v| v == value).unwrap_or(0) as u8);
+    }
+
+    fn envelope(&mut self, envelope: &Envelope) {
+        for time in [envelope.attack, envelope.hold, envelope.decay, envelope.release] {
+            self.u32(time);
+        }
+        for value in [envelope.sustain, envelope.sustain_velocity, envelope.attack_curve, envelope.decay_curve, envelope.release_curve] {
+            self.f32(value);
+        }
+    }
+
+    fn route(&mut self, route: Option<ModRoute>) {
+        self.bool(route.is_some());
+        let Some(route) = route else {
+            return;
+        };
+        let (source, index) = match route.source {
+            ModSource::Envelope(index) => (0, index),
+            ModSource::Lfo(index) => (1, index),
+            ModSource::Velocity => (2, 0),
+            ModSource::ModWheel => (3, 0),
+            ModSource::Aftertouch => (4, 0),
+        };
+        self.u8(source);
+        self.u8(index as u8);
+        let (destination, target) = match route.destination {
+            ModDestination::Amplitude => (0, 0),
+            ModDestination::Send => (1, 0),
+            ModDestination::Param(target) => (2, target.id()),
+        };
+        self.u8(destination);
+        self.u8(target as u8);
+        self.f32(route.amount);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let (head, rest) = self.bytes.split_first_chunk::<N>().ok_or_else(|| invalid("patch string is truncated or corrupted"))?;
+        self.bytes = rest;
+        Ok(*head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn bool(&mut self) -> io::Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("bad switch in patch string")),
+        }
+    }
+
+    fn u32(&mut self, min: usize, max: usize) -> io::Result<usize> {
+        Ok((u32::from_le_bytes(self.take()?) as usize).clamp(min, max))
+    }
+
+    // NaN is refused; anything else, infinities included, is clamped.
+    fn f32(&mut self, min: f32, max: f32) -> io::Result<f32> {
+        let value = f32::from_le_bytes(self.take()?);
+        if value.is_nan() {
+            return Err(invalid("patch string contains a value that is not a number"));
+        }
+        Ok(value.clamp(min, max))
+    }
+
+    fn param(&mut self, target: ParamTarget) -> io::Result<f32> {
+        let info = target.info();
+        self.f32(info.min, info.max)
+    }
+
+    // A stage time in samples, within its parameter's range.
+    fn time(&mut self, target: ParamTarget) -> io::Result<usize> {
+        self.u32(0, target.info().max as usize)
+    }
+
+    fn cc(&mut self) -> io::Result<u8> {
+        let cc = self.u8()?;
+        if cc > 127 {
+            return Err(invalid("bad controller number in patch string"));
+        }
+        Ok(cc)
+    }
+
+    fn choice<T: Copy>(&mut self, values: &[T], what: &str) -> io::Result<T> {
+        let index = self.u8()?;
+        values.get(index as usize).copied().ok_or_else(|| invalid(&format!("unknown {} in patch string", what)))
+    }
+
+    fn envelope(&mut self) -> io::Result<Envelope> {
+        Ok(Envelope {
+            attack: self.time(ParamTarget::Attack)?,
+            hold: self.time(ParamTarget::Hold)?,
+            decay: self.time(ParamTarget::Decay)?,
+            release: self.time(ParamTarget::Release)?,
+            sustain: self.param(ParamTarget::SustainLevel)?,
+            sustain_velocity: self.param(ParamTarget::SustainVelocity)?,
+            attack_curve: self.param(ParamTarget::AttackCurve)?,
+            decay_curve: self.param(ParamTarget::DecayCurve)?,
+            release_curve: self.param(ParamTarget::ReleaseCurve)?,
+        })
+    }
+
+    fn route(&mut self) -> io::Result<Option<ModRoute>> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+        let (source, index) = (self.u8()?, self.u8()? as usize);
+        let source = match source {
+            0 => ModSource::Envelope(index),
+            1 => ModSource::Lfo(index),
+            2 => ModSource::Velocity,
+            3 => ModSource::ModWheel,
+            4 => ModSource::Aftertouch,
+            _ => return Err(invalid("unknown mod source in patch string")),
+        };
+        let (destination, target) = (self.u8()?, self.u8()? as usize);
+        let destination = match destination {
+            0 => ModDestination::Amplitude,
+            1 => ModDestination::Send,
+            2 => ModDestination::Param(ParamTarget::from_id(target).ok_or_else(|| invalid("unknown parameter in patch string"))?),
+            _ => return Err(invalid("unknown mod destination in patch string")),
+        };
+        Ok(Some(ModRoute { source, destination, amount: self.f32(-1.0, 1.0)? }))
+    }
+}
+
+// The whole patch as a URL-safe base64 string: the format version, every
+// field of the patch in declaration order, the CC bindings in controller
+// order, and a checksum byte that catches a string cut short when pasting.
+pub fn encode_patch(patch: &Patch, bindings: &CcBindings) -> String {
+    let mut writer = Writer { bytes: vec![PRESET_VERSION] };
+    let w = &mut writer;
+    w.choice(&WAVEFORMS, &patch.waveform);
+    w.envelope(&patch.envelope);
+    w.choice(&FILTER_MODES, &patch.filter.mode);
+    w.choice(&FILTER_TOPOLOGIES, &patch.filter.topology);
+    w.choice(&FILTER_PLACEMENTS, &patch.filter.placement);
+    w.f32(patch.filter.cutoff);
+    w.f32(patch.filter.resonance);
+    w.f32(patch.phase);
+    for switch in [patch.random_phase, patch.env_retrigger, patch.retrigger_phase_reset, patch.retrigger_envelope_reset] {
+        w.bool(switch);
+    }
+    w.f32(patch.bend_range);
+    w.f32(patch.glide_time);
+    w.choice(&GLIDE_MODES, &patch.glide_mode);
+    w.choice(&GLIDE_CURVES, &patch.glide_curve);
+    w.f32(patch.glide_window);
+    w.choice(&SCALES, &patch.snap_scale);
+    w.u8(patch.snap_root);
+    w.bool(patch.mono);
+    w.choice(&NOTE_PRIORITIES, &patch.note_priority);
+    w.bool(patch.max_voices.is_some());
+    w.u32(patch.max_voices.unwrap_or(0));
+    w.u8(patch.voice_priority);
+    for value in [patch.drift_amount, patch.drift_rate, patch.drift_correlation, patch.level_key_track] {
+        w.f32(value);
+    }
+    w.u8(patch.level_key_center);
+    for value in [patch.wavetable_position, patch.wavetable_envelope, patch.wavetable_velocity] {
+        w.f32(value);
+    }
+    w.bool(patch.velocity_layers.is_some());
+    let layers = patch.velocity_layers.unwrap_or_default();
+    w.choice(&WAVEFORMS, &layers.soft);
+    w.choice(&WAVEFORMS, &layers.hard);
+    w.choice(&CROSSFADES, &layers.crossfade);
+    w.f32(layers.phase_offset);
+    w.f32(patch.pan_spread);
+    w.choice(&PAN_SPREAD_MODES, &patch.pan_spread_mode);
+    for value in [
+        patch.velocity_loudness,
+        patch.velocity_floor,
+        patch.velocity_brightness,
+        patch.voice_drive,
+        patch.hf_decay,
+        patch.voice_stereo_detune,
+        patch.pd_amount,
+        patch.pd_envelope,
+    ] {
+        w.f32(value);
+    }
+    w.u32(patch.unison_voices);
+    w.f32(patch.unison_detune);
+    w.f32(patch.unison_stereo_spread);
+    for envelope in patch.modulation.envelopes.iter() {
+        w.envelope(envelope);
+    }
+    w.u32(patch.modulation.envelope_count);
+    for lfo in patch.modulation.lfos.iter() {
+        w.f32(lfo.rate.hz);
+        w.bool(lfo.rate.division.is_some());
+        w.f32(lfo.rate.division.unwrap_or(0.0));
+        w.choice(&LFO_WAVEFORMS, &lfo.waveform);
+        w.choice(&LFO_RETRIGGERS, &lfo.retrigger);
+        w.choice(&LFO_MODES, &lfo.mode);
+    }
+    for &route in patch.modulation.routes() {
+        w.route(route);
+    }
+    w.f32(patch.trim);
+    w.bool(patch.invert);
+    w.f32(patch.formant.mix);
+    w.f32(patch.formant.vowel);
+    let mut params: Vec<_> = bindings.params.iter().map(|(&cc, target)| (cc, target.id() as u8)).collect();
+    let mut macros: Vec<_> = bindings.macros.iter().map(|(&cc, &index)| (cc, index as u8)).collect();
+    for pairs in [&mut params, &mut macros] {
+        pairs.sort_unstable();
+        w.u8(pairs.len() as u8);
+        for &(cc, value) in pairs.iter() {
+            w.u8(cc);
+            w.u8(value);
+        }
+    }
+
+    let mut bytes = writer.bytes;
+    bytes.push(checksum(&bytes));
+    encode_base64(&bytes)
+}
+
+// Values outside a field's range are clamped into it; a wrong version, a
+// bad checksum, an unknown choice, route or binding, a NaN or leftover bytes
+// reject the whole string.
+pub fn decode_patch(text: &str) -> io::Result<(Patch, CcBindings)> {
+    let bytes = decode_base64(text.trim())?;
+    let (&version, _) = bytes.split_first().ok_or_else(|| invalid("patch string is too short"))?;
+    if version != PRESET_VERSION {
+        return Err(invalid(&format!("patch string has format version {}, expected {}", version, PRESET_VERSION)));
+    }
+    let (&sum, body) = bytes[1..].split_last().ok_or_else(|| invalid("patch string is too short"))?;
+    if sum != checksum(&bytes[..bytes.len() - 1]) {
+        return Err(invalid("patch string is truncated or corrupted"));
+    }
+
+    let r = &mut Reader { bytes: body };
+    let waveform = r.choice(&WAVEFORMS, "waveform")?;
+    let envelope = r.envelope()?;
+    let filter = Filter {
+        mode: r.choice(&FILTER_MODES, "filter mode")?,
+        topology: r.choice(&FILTER_TOPOLOGIES, "filter topology")?,
+        placement: r.choice(&FILTER_PLACEMENTS, "filter placement")?,
+        cutoff: r.param(ParamTarget::Cutoff)?,
+        resonance: r.param(ParamTarget::Resonance)?,
+    };
+    let phase = r.f32(0.0, 1.0)?;
+    let (random_phase, env_retrigger, retrigger_phase_reset, retrigger_envelope_reset) = (r.bool()?, r.bool()?, r.bool()?, r.bool()?);
+    let bend_range = r.param(ParamTarget::BendRange)?;
+    let glide_time = r.f32(0.0, MAX_GLIDE_TIME)?;
+    let glide_mode = r.choice(&GLIDE_MODES, "glide mode")?;
+    let glide_curve = r.choice(&GLIDE_CURVES, "glide curve")?;
+    let glide_window = r.f32(0.0, f32::INFINITY)?;
+    let snap_scale = r.choice(&SCALES, "scale")?;
+    let snap_root = r.u8()? % 12;
+    let mono = r.bool()?;
+    let note_priority = r.choice(&NOTE_PRIORITIES, "note priority")?;
+    let limited = r.bool()?;
+    let max_voices = r.u32(1, u16::MAX as usize)?;
+    let voice_priority = r.u8()?;
+    let drift_amount = r.param(ParamTarget::DriftAmount)?;
+    let drift_rate = r.param(ParamTarget::DriftRate)?;
+    let drift_correlation = r.param(ParamTarget::DriftCorrelation)?;
+    let level_key_track = r.f32(-MAX_KEY_TRACK, MAX_KEY_TRACK)?;
+    let level_key_center = r.u8()?.min(127);
+    let wavetable_position = r.param(ParamTarget::WavetablePosition)?;
+    let wavetable_envelope = r.param(ParamTarget::WavetableEnvelope)?;
+    let wavetable_velocity = r.param(ParamTarget::WavetableVelocity)?;
+    let layered = r.bool()?;
+    let layers = VelocityLayers {
+        soft: r.choice(&WAVEFORMS, "waveform")?,
+        hard: r.choice(&WAVEFORMS, "waveform")?,
+        crossfade: r.choice(&CROSSFADES, "crossfade")?,
+        phase_offset: r.f32(0.0, 1.0)?,
+    };
+    let pan_spread = r.param(ParamTarget::PanSpread)?;
+    let pan_spread_mode = r.choice(&PAN_SPREAD_MODES, "pan spread mode")?;
+    let velocity_loudness = r.param(ParamTarget::VelocityLoudness)?;
+    let velocity_floor = r.f32(0.0, 1.0)?;
+    let velocity_brightness = r.param(ParamTarget::VelocityBrightness)?;
+    let voice_drive = r.param(ParamTarget::VoiceDrive)?;
+    let hf_decay = r.param(ParamTarget::HfDecay)?;
+    let voice_stereo_detune = r.f32(-MAX_DETUNE, MAX_DETUNE)?;
+    let pd_amount = r.param(ParamTarget::PhaseDistortion)?;
+    let pd_envelope = r.f32(-1.0, 1.0)?;
+    let unison_voices = r.u32(1, MAX_UNISON)?;
+    let unison_detune = r.f32(0.0, MAX_DETUNE)?;
+    let unison_stereo_spread = r.f32(0.0, 1.0)?;
+
+    let mut modulation = ModMatrix::default();
+    for envelope in modulation.envelopes.iter_mut() {
+        *envelope = r.envelope()?;
+    }
+    modulation.envelope_count = r.u32(0, MAX_MOD_ENVELOPES)?;
+    for lfo in modulation.lfos.iter_mut() {
+        let hz = r.f32(0.0, MAX_LFO_RATE)?;
+        let synced = r.bool()?;
+        let division = r.f32(0.0, MAX_LFO_DIVISION)?;
+        *lfo = ModLfo {
+            rate: LfoRate { hz, division: synced.then_some(division) },
+            waveform: r.choice(&LFO_WAVEFORMS, "LFO waveform")?,
+            retrigger: r.choice(&LFO_RETRIGGERS, "LFO retrigger")?,
+            mode: r.choice(&LFO_MODES, "LFO mode")?,
+        };
+    }
+    for slot in 0..MAX_MOD_ROUTES {
+        if !modulation.set_route(slot, r.route()?) {
+            return Err(invalid("patch string contains a mod route the matrix refuses"));
+        }
+    }
+    let trim = r.param(ParamTarget::Trim)?;
+    let invert = r.bool()?;
+    let formant = Formant { mix: r.param(ParamTarget::FormantMix)?, vowel: r.param(ParamTarget::Vowel)? };
+    let mut bindings = CcBindings::default();
+    for _ in 0..r.u8()? {
+        let cc = r.cc()?;
+        let target = ParamTarget::from_id(r.u8()? as usize).ok_or_else(|| invalid("unknown parameter in patch string"))?;
+        bindings.params.insert(cc, target);
+    }
+    for _ in 0..r.u8()? {
+        let cc = r.cc()?;
+        let index = r.u8()? as usize;
+        if index >= MAX_MACROS {
+            return Err(invalid("unknown macro in patch string"));
+        }
+        bindings.macros.insert(cc, index);
+    }
+    if !r.bytes.is_empty() {
+        return Err(invalid("patch string has trailing data"));
+    }
+
+    let patch = Patch {
+        waveform,
+        envelope,
+        filter,
+        phase,
+        random_phase,
+        env_retrigger,
+        retrigger_phase_reset,
+        retrigger_envelope_reset,
+        bend_range,
+        glide_time,
+        glide_mode,
+        glide_curve,
+        glide_window,
+        snap_scale,
+        snap_root,
+        mono,
+        note_priority,
+        max_voices: limited.then_some(max_voices),
+        voice_priority,
+        drift_amount,
+        drift_rate,
+        drift_correlation,
+        level_key_track,
+        level_key_center,
+        wavetable_position,
+        wavetable_envelope,
+        wavetable_velocity,
+        velocity_layers: layered.then_some(layers),
+        pan_spread,
+        pan_spread_mode,
+        velocity_loudness,
+        velocity_floor,
+        velocity_brightness,
+        voice_drive,
+        hf_decay,
+        voice_stereo_detune,
+        pd_amount,
+        pd_envelope,
+        unison_voices,
+        unison_detune,
+        unison_stereo_spread,
+        modulation,
+        trim,
+        invert,
+        formant,
+    };
+    Ok((patch, bindings))
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            text.push(ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3F] as char);
+        }
+    }
+    text
+}
+
+fn decode_base64(text: &str) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.as_bytes().chunks(4) {
+        if chunk.len() == 1 {
+            return Err(invalid("patch string is truncated or corrupted"));
+        }
+        let mut bits = 0u32;
+        for (i, &symbol) in chunk.iter().enumerate() {
+            let value = ALPHABET.iter().position(|&a| a == symbol).ok_or_else(|| invalid("patch string contains a character outside base64"))?;
+            bits |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            bytes.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A patch with every field moved off its default.
+    fn custom_patch() -> Patch {
+        let mut modulation = ModMatrix::default();
+        modulation.envelope_count = 2;
+        modulation.envelopes[1] = Envelope { attack: 10, hold: 20, decay: 30, release: 40, sustain: 0.25, sustain_velocity: 0.5, attack_curve: -0.5, decay_curve: 0.75, release_curve: 0.1 };
+        modulation.lfos[1] = ModLfo {
+            rate: LfoRate { hz: 0.3, division: Some(0.5) },
+            waveform: LfoWaveform::Square,
+            retrigger: LfoRetrigger::Free,
+            mode: LfoMode::PerVoice,
+        };
+        assert!(modulation.set_route(0, Some(ModRoute { source: ModSource::Lfo(1), destination: ModDestination::Param(ParamTarget::Cutoff), amount: 0.5 })));
+        assert!(modulation.set_route(3, Some(ModRoute { source: ModSource::Envelope(2), destination: ModDestination::Send, amount: -0.25 })));
+        Patch {
+            waveform: Waveform::Square,
+            envelope: Envelope { attack: 123, hold: 45, decay: 678, release: 9000, sustain: 0.4, sustain_velocity: 0.3, attack_curve: 0.2, decay_curve: -0.6, release_curve: 0.9 },
+            filter: Filter { mode: FilterMode::BandPass, topology: FilterTopology::Naive, placement: FilterPlacement::Master, cutoff: 1234.5, resonance: 0.3 },
+            phase: 0.25,
+            random_phase: true,
+            env_retrigger: false,
+            retrigger_phase_reset: true,
+            retrigger_envelope_reset: true,
+            bend_range: 12.0,
+            glide_time: 0.2,
+            glide_mode: GlideMode::Rate,
+            glide_curve: GlideCurve::Hz,
+            glide_window: 0.1,
+            snap_scale: Scale::MinorPentatonic,
+            snap_root: 7,
+            mono: true,
+            note_priority: NotePriority::High,
+            max_voices: Some(3),
+            voice_priority: 9,
+            drift_amount: 5.0,
+            drift_rate: 0.5,
+            drift_correlation: 0.2,
+            level_key_track: -3.0,
+            level_key_center: 48,
+            wavetable_position: 0.1,
+            wavetable_envelope: 0.2,
+            wavetable_velocity: 0.3,
+            velocity_layers: Some(VelocityLayers { soft: Waveform::Triangle, hard: Waveform::Wavetable, crossfade: Crossfade::Linear, phase_offset: 0.4 }),
+            pan_spread: 0.6,
+            pan_spread_mode: PanSpreadMode::Random,
+            velocity_loudness: 0.7,
+            velocity_floor: 0.05,
+            velocity_brightness: 0.8,
+            voice_drive: 0.9,
+            hf_decay: 2.5,
+            voice_stereo_detune: 12.0,
+            pd_amount: 0.15,
+            pd_envelope: -0.35,
+            unison_voices: 4,
+            unison_detune: 18.0,
+            unison_stereo_spread: 0.45,
+            modulation,
+            trim: -6.0,
+            invert: true,
+            formant: Formant { mix: 0.5, vowel: 2.5 },
+        }
+    }
+
+    #[test]
+    fn every_field_round_trips() {
+        let patch = custom_patch();
+        let bindings = CcBindings { params: HashMap::from([(20, ParamTarget::Resonance), (74, ParamTarget::Cutoff)]), macros: HashMap::from([(21, 3)]) };
+        assert_eq!(decode_patch(&encode_patch(&patch, &bindings)).unwrap(), (patch, bindings));
+        assert_eq!(decode_patch(&encode_patch(&Patch::default(), &CcBindings::default())).unwrap(), (Patch::default(), CcBindings::default()));
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_ignored() {
+        let patch = custom_patch();
+        assert_eq!(decode_patch(&format!("  {}\n", encode_patch(&patch, &CcBindings::default()))).unwrap().0, patch);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = decode_base64(&encode_patch(&custom_patch(), &CcBindings::default())).unwrap();
+        bytes[0] = PRESET_VERSION + 1;
+        let last = bytes.len() - 1;
+        bytes[last] = checksum(&bytes[..last]);
+        let error = decode_patch(&encode_base64(&bytes)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("version"));
+    }
+
+    #[test]
+    fn truncated_and_garbled_strings_are_rejected() {
+        let text = encode_patch(&custom_patch(), &CcBindings::default());
+        for length in [0, 1, 2, 20, text.len() / 2, text.len() - 1] {
+            assert!(decode_patch(&text[..length]).is_err(), "length {}", length);
+        }
+        assert!(decode_patch(&text.replace('A', "!")).is_err());
+        let mut flipped = text.into_bytes();
+        flipped[10] = if flipped[10] == b'B' { b'C' } else { b'B' };
+        assert!(decode_patch(std::str::from_utf8(&flipped).unwrap()).is_err());
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let patch = Patch {
+            envelope: Envelope { attack: usize::MAX, ..Envelope::default() },
+            filter: Filter { cutoff: -5.0, resonance: f32::INFINITY, ..Filter::default() },
+            unison_voices: 1000,
+            velocity_floor: 3.0,
+            ..Patch::default()
+        };
+        let (decoded, _) = decode_patch(&encode_patch(&patch, &CcBindings::default())).unwrap();
+        assert_eq!(decoded.envelope.attack as f32, ParamTarget::Attack.info().max);
+        assert_eq!(decoded.filter.cutoff, ParamTarget::Cutoff.info().min);
+        assert_eq!(decoded.filter.resonance, 1.0);
+        assert_eq!(decoded.unison_voices, MAX_UNISON);
+        assert_eq!(decoded.velocity_floor, 1.0);
+    }
+
+    #[test]
+    fn not_a_number_is_rejected() {
+        let patch = Patch { drift_amount: f32::NAN, ..Patch::default() };
+        assert!(decode_patch(&encode_patch(&patch, &CcBindings::default())).is_err());
+    }
+
+    #[test]
+    fn unknown_bindings_are_rejected() {
+        for bindings in [
+            CcBindings { params: HashMap::from([(128, ParamTarget::Cutoff)]), ..CcBindings::default() },
+            CcBindings { macros: HashMap::from([(20, MAX_MACROS)]), ..CcBindings::default() },
+        ] {
+            assert!(decode_patch(&encode_patch(&Patch::default(), &bindings)).is_err(), "{:?}", bindings);
+        }
+    }
+}
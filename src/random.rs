@@ -0,0 +1,37 @@
+pub const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+// xorshift64*, small and fast enough to call per sample. Not for anything but
+// audio-rate randomness.
+#[derive(Copy, Clone, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random {
+            state: if seed == 0 { DEFAULT_SEED } else { seed },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn next_bipolar(&mut self) -> f32 {
+        2.0 * self.next_f32() - 1.0
+    }
+}
+
+impl Default for Random {
+    fn default() -> Random {
+        Random::new(DEFAULT_SEED)
+    }
+}
@@ -0,0 +1,241 @@
+// Freeverb tunings, in samples at 44.1 kHz. The right side is offset by
+// STEREO_SPREAD so the two sides decorrelate.
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const TUNING_RATE: f32 = 44100.0;
+const INPUT_GAIN: f32 = 0.015;
+const WET_GAIN: f32 = 3.0;
+const ALLPASS_FEEDBACK: f32 = 0.5;
+const SIZE: f32 = 0.5;
+const DAMPING: f32 = 0.5;
+const GATE_HOLD: f32 = 0.1;
+const GATE_RELEASE: f32 = 0.01;
+
+// A Schroeder/Moorer reverb (the Freeverb layout) added on top of the dry
+// master signal at `level`; level 0 bypasses it.
+//
+// The gate follows the dry signal: while the dry peak is above
+// gate_threshold the tail passes, and gate_hold seconds after the dry signal
+// drops below it the tail is faded out over gate_release seconds, giving the
+// classic cut-off gated reverb. A threshold of 0 leaves the gate open.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Reverb {
+    pub level: f32,
+    pub size: f32,
+    pub damping: f32,
+    pub gate_threshold: f32,
+    pub gate_hold: f32,
+    pub gate_release: f32,
+}
+
+impl Default for Reverb {
+    fn default() -> Reverb {
+        Reverb {
+            level: 0.0,
+            size: SIZE,
+            damping: DAMPING,
+            gate_threshold: 0.0,
+            gate_hold: GATE_HOLD,
+            gate_release: GATE_RELEASE,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Comb {
+    buffer: Vec<f32>,
+    position: usize,
+    store: f32,
+}
+
+impl Comb {
+    fn new(length: usize) -> Comb {
+        Comb {
+            buffer: vec![0.0; length.max(1)],
+            position: 0,
+            store: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.position];
+        self.store = output * (1.0 - damping) + self.store * damping;
+        self.buffer[self.position] = input + self.store * feedback;
+        self.position = (self.position + 1) % self.buffer.len();
+        output
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Allpass {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl Allpass {
+    fn new(length: usize) -> Allpass {
+        Allpass {
+            buffer: vec![0.0; length.max(1)],
+            position: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.position];
+        self.buffer[self.position] = input + delayed * ALLPASS_FEEDBACK;
+        self.position = (self.position + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReverbState {
+    combs: Vec<(Comb, Comb)>,
+    allpasses: Vec<(Allpass, Allpass)>,
+    gate: f32,
+    gate_timer: usize,
+}
+
+impl ReverbState {
+    pub fn new() -> ReverbState {
+        ReverbState {
+            combs: Vec::new(),
+            allpasses: Vec::new(),
+            gate: 1.0,
+            gate_timer: 0,
+        }
+    }
+
+    // Allocates the delay lines for `sample_rate`, or frees them while the
+    // reverb is bypassed.
+    pub fn configure(&mut self, reverb: &Reverb, sample_rate: usize) {
+        if reverb.level == 0.0 {
+            self.combs.clear();
+            self.allpasses.clear();
+            return;
+        }
+        let scale = sample_rate as f32 / TUNING_RATE;
+        let length = |tuning: usize| (tuning as f32 * scale) as usize;
+        self.combs = COMB_TUNINGS.iter().map(|&t| (Comb::new(length(t)), Comb::new(length(t + STEREO_SPREAD)))).collect();
+        self.allpasses = ALLPASS_TUNINGS.iter().map(|&t| (Allpass::new(length(t)), Allpass::new(length(t + STEREO_SPREAD)))).collect();
+        self.gate = 1.0;
+        self.gate_timer = 0;
+    }
+
+    // Silences the combs and allpasses without reallocating them.
+    pub fn reset(&mut self) {
+        for comb in self.combs.iter_mut().flat_map(|(left, right)| [left, right]) {
+            comb.buffer.fill(0.0);
+            comb.store = 0.0;
+        }
+        for allpass in self.allpasses.iter_mut().flat_map(|(left, right)| [left, right]) {
+            allpass.buffer.fill(0.0);
+        }
+    }
+
+    pub fn process(&mut self, reverb: &Reverb, left: f32, right: f32, time_step: f32) -> (f32, f32) {
+        self.process_send(reverb, left, right, left, right, time_step)
+    }
+
+    // Feeds the reverb from a separate send bus; the gate still follows the
+    // dry signal the tail is added to.
+    pub fn process_send(&mut self, reverb: &Reverb, left: f32, right: f32, send_left: f32, send_right: f32, time_step: f32) -> (f32, f32) {
+        if reverb.level == 0.0 || self.combs.is_empty() {
+            return (left, right);
+        }
+        let input = (send_left + send_right) * 0.5 * INPUT_GAIN;
+        let feedback = 0.7 + 0.28 * reverb.size.clamp(0.0, 1.0);
+        let damping = reverb.damping.clamp(0.0, 1.0);
+
+        let (mut wet_left, mut wet_right) = (0.0, 0.0);
+        for (comb_left, comb_right) in self.combs.iter_mut() {
+            wet_left += comb_left.process(input, feedback, damping);
+            wet_right += comb_right.process(input, feedback, damping);
+        }
+        for (allpass_left, allpass_right) in self.allpasses.iter_mut() {
+            wet_left = allpass_left.process(wet_left);
+            wet_right = allpass_right.process(wet_right);
+        }
+
+        let gate = self.update_gate(reverb, left.abs().max(right.abs()), time_step);
+        let wet = reverb.level * WET_GAIN * gate;
+        (left + wet_left * wet, right + wet_right * wet)
+    }
+
+    fn update_gate(&mut self, reverb: &Reverb, peak: f32, time_step: f32) -> f32 {
+        if reverb.gate_threshold <= 0.0 {
+            return 1.0;
+        }
+        if peak > reverb.gate_threshold {
+            self.gate = 1.0;
+            self.gate_timer = (reverb.gate_hold.max(0.0) / time_step) as usize;
+        } else if self.gate_timer > 0 {
+            self.gate_timer -= 1;
+        } else {
+            let step = if reverb.gate_release > 0.0 { time_step / reverb.gate_release } else { 1.0 };
+            self.gate = (self.gate - step).max(0.0);
+        }
+        self.gate
+    }
+}
+
+impl Default for ReverbState {
+    fn default() -> ReverbState {
+        ReverbState::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    // A 10 ms burst followed by silence; returns just the wet tail.
+    fn tail(reverb: &Reverb) -> Vec<f32> {
+        let mut state = ReverbState::new();
+        state.configure(reverb, SAMPLE_RATE);
+        (0..SAMPLE_RATE)
+            .map(|frame| {
+                let input = if frame < 480 { if frame % 2 == 0 { 0.8 } else { -0.8 } } else { 0.0 };
+                let (left, _) = state.process(reverb, input, input, 1.0 / SAMPLE_RATE as f32);
+                left - input
+            })
+            .collect()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn a_closing_gate_cuts_the_tail_off() {
+        let open = Reverb { level: 0.5, ..Reverb::default() };
+        let gated = Reverb { gate_threshold: 0.1, gate_hold: 0.05, gate_release: 0.01, ..open };
+        let (open, gated) = (tail(&open), tail(&gated));
+        // Past the burst, the hold and the release the gated tail is gone.
+        let after = 480 + (0.07 * SAMPLE_RATE as f32) as usize;
+        assert!(peak(&open[after..after + 4800]) > 0.01);
+        assert_eq!(peak(&gated[after..]), 0.0);
+        // During the hold both tails are the same.
+        assert_eq!(open[..2400], gated[..2400]);
+    }
+
+    #[test]
+    fn an_open_gate_never_closes() {
+        let reverb = Reverb { level: 0.5, ..Reverb::default() };
+        assert_eq!(reverb.gate_threshold, 0.0);
+        let mut state = ReverbState::new();
+        state.configure(&reverb, SAMPLE_RATE);
+        assert!((0..SAMPLE_RATE).all(|_| state.update_gate(&reverb, 0.0, 1.0 / SAMPLE_RATE as f32) == 1.0));
+    }
+
+    #[test]
+    fn zero_level_bypasses_it() {
+        let reverb = Reverb::default();
+        let mut state = ReverbState::new();
+        state.configure(&reverb, SAMPLE_RATE);
+        assert_eq!(state.process(&reverb, 0.3, -0.2, 1.0 / SAMPLE_RATE as f32), (0.3, -0.2));
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+const ROOT: u8 = 60;
+
+// A mono audio sample played back as an oscillator source. Each note reads
+// through it at frequency / root frequency, adjusted for the sample's own
+// rate. A one-shot sample ends the note when it runs out; a looped one
+// wraps back to loop_start from the end.
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub data: Vec<f32>,
+    pub sample_rate: usize,
+    pub root: u8,
+    pub looped: bool,
+    pub loop_start: usize,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl Sample {
+    pub fn new(data: Vec<f32>, sample_rate: usize) -> Sample {
+        Sample {
+            data,
+            sample_rate,
+            root: ROOT,
+            looped: false,
+            loop_start: 0,
+        }
+    }
+
+    // Reads a PCM (8, 16, 24 or 32 bit) or 32-bit float WAV file. Multi-channel
+    // files are mixed down to mono.
+    pub fn from_wav<P: AsRef<Path>>(path: P) -> io::Result<Sample> {
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+        Sample::from_wav_bytes(&bytes)
+    }
+
+    pub fn from_wav_bytes(bytes: &[u8]) -> io::Result<Sample> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("not a RIFF WAVE file"));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+        let mut format = None;
+        let mut data = None;
+        let mut offset = 12;
+        while offset + 8 <= bytes.len() {
+            let size = u32_at(offset + 4) as usize;
+            let body = offset + 8;
+            let end = (body + size).min(bytes.len());
+            match &bytes[offset..offset + 4] {
+                b"fmt " if size >= 16 && end - body >= 16 => format = Some((u16_at(body), u16_at(body + 2), u32_at(body + 4), u16_at(body + 14))),
+                b"data" => data = Some(&bytes[body..end]),
+                _ => (),
+            }
+            offset = body + size + size % 2;
+        }
+        let (encoding, channels, sample_rate, bits) = format.ok_or_else(|| invalid("WAV file has no fmt chunk"))?;
+        let data = data.ok_or_else(|| invalid("WAV file has no data chunk"))?;
+        if channels == 0 || sample_rate == 0 {
+            return Err(invalid("WAV file has no channels"));
+        }
+
+        let width = bits as usize / 8;
+        let decode: fn(&[u8]) -> f32 = match (encoding, bits) {
+            (1, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+            (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            (1, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0,
+            (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+            (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            _ => return Err(invalid("unsupported WAV sample format")),
+        };
+        let frame = width * channels as usize;
+        let samples = data
+            .chunks_exact(frame)
+            .map(|frame| frame.chunks_exact(width).map(decode).sum::<f32>() / channels as f32)
+            .collect();
+        Ok(Sample::new(samples, sample_rate as usize))
+    }
+
+    // The sample value at a fractional frame position, or None once a one-shot
+    // sample has played past its end.
+    pub fn read(&self, position: f64) -> Option<f32> {
+        let length = self.data.len();
+        if length == 0 {
+            return None;
+        }
+        let position = if self.looped && position >= length as f64 {
+            let start = self.loop_start.min(length - 1) as f64;
+            start + (position - start) % (length as f64 - start)
+        } else {
+            position
+        };
+        let index = position as usize;
+        if index >= length {
+            return None;
+        }
+        let fraction = (position - index as f64) as f32;
+        let next = match self.data.get(index + 1) {
+            Some(&next) => next,
+            None if self.looped => self.data[self.loop_start.min(length - 1)],
+            None => 0.0,
+        };
+        Some(self.data[index] + (next - self.data[index]) * fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_between_frames_and_stops_at_the_end() {
+        let sample = Sample::new(vec![0.0, 1.0, 0.5], 48000);
+        assert_eq!(sample.read(0.5), Some(0.5));
+        assert_eq!(sample.read(1.5), Some(0.75));
+        assert_eq!(sample.read(2.5), Some(0.25));
+        assert_eq!(sample.read(3.0), None);
+    }
+
+    #[test]
+    fn a_looped_sample_wraps_to_the_loop_start() {
+        let sample = Sample { looped: true, loop_start: 1, ..Sample::new(vec![0.0, 1.0, 2.0, 3.0], 48000) };
+        assert_eq!(sample.read(4.0), Some(1.0));
+        assert_eq!(sample.read(6.5), Some(3.0 - 1.0));
+        assert_eq!(sample.read(3.5), Some(2.0));
+    }
+
+    #[test]
+    fn decodes_a_stereo_16_bit_wav_to_mono() {
+        let mut wav = Vec::new();
+        crate::pcm::write_wav(&mut wav, 22050, 2, crate::pcm::BitDepth::Sixteen, &[16384, 0, -32768, -32768]).unwrap();
+        let sample = Sample::from_wav_bytes(&wav).unwrap();
+        assert_eq!(sample.sample_rate, 22050);
+        assert_eq!(sample.data, vec![0.25, -1.0]);
+        assert!(Sample::from_wav_bytes(b"RIFF\0\0\0\0WAVE").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+// Which of the twelve pitch classes above the root a scale uses, as a bit
+// mask with the root in bit 0.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Scale {
+    #[default]
+    Chromatic,
+    Major,
+    Minor,
+    MajorPentatonic,
+    MinorPentatonic,
+}
+
+impl Scale {
+    fn mask(&self) -> u16 {
+        match self {
+            Scale::Chromatic => 0b1111_1111_1111,
+            Scale::Major => 0b1010_1011_0101,
+            Scale::Minor => 0b0101_1010_1101,
+            Scale::MajorPentatonic => 0b0010_1001_0101,
+            Scale::MinorPentatonic => 0b0100_1010_1001,
+        }
+    }
+
+    pub fn contains(&self, pitch: i32, root: u8) -> bool {
+        let class = (pitch - root as i32).rem_euclid(12);
+        self.mask() & (1 << class) != 0
+    }
+
+    // The scale note nearest to `pitch`, a fractional MIDI note, with ties
+    // going down. Chromatic leaves `pitch` as it is.
+    pub fn snap(&self, pitch: f32, root: u8) -> f32 {
+        if *self == Scale::Chromatic {
+            return pitch;
+        }
+        let below = pitch.floor() as i32;
+        let mut best = below;
+        for distance in 0..12 {
+            if self.contains(below - distance, root) {
+                best = below - distance;
+                break;
+            }
+        }
+        for distance in 1..=12 {
+            let candidate = below + distance;
+            if candidate as f32 - pitch >= pitch - best as f32 {
+                break;
+            }
+            if self.contains(candidate, root) {
+                best = candidate;
+                break;
+            }
+        }
+        best as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snap_finds_the_nearest_scale_note() {
+        // C major around middle C: C# snaps down to C on the tie, D# too, F#
+        // down to F.
+        let snapped: Vec<f32> = (60..=72).map(|pitch| Scale::Major.snap(pitch as f32, 0)).collect();
+        assert_eq!(snapped, [60.0, 60.0, 62.0, 62.0, 64.0, 65.0, 65.0, 67.0, 67.0, 69.0, 69.0, 71.0, 72.0]);
+        assert_eq!(Scale::Major.snap(61.6, 0), 62.0);
+        assert_eq!(Scale::Major.snap(65.4, 0), 65.0);
+        // The root moves the scale.
+        assert_eq!(Scale::Major.snap(61.0, 2), 61.0);
+        assert_eq!(Scale::MinorPentatonic.snap(64.0, 9), 64.0);
+        assert_eq!(Scale::MinorPentatonic.snap(65.0, 9), 64.0);
+    }
+
+    #[test]
+    fn chromatic_leaves_the_pitch_alone() {
+        for pitch in [0.0, 60.3, 61.5, 127.0] {
+            assert_eq!(Scale::Chromatic.snap(pitch, 0), pitch);
+        }
+        assert!((0..12).all(|class| Scale::Chromatic.contains(class, 5)));
+        assert_eq!((0..12).filter(|&class| Scale::MajorPentatonic.contains(class, 0)).count(), 5);
+    }
+}
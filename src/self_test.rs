@@ -0,0 +1,112 @@
+use std::fmt;
+
+use crate::limiter::Limiter;
+use crate::random::DEFAULT_SEED;
+use crate::synthesizer::Synthesizer;
+use crate::tuning::Tuning;
+
+const SAMPLE_RATE: usize = 48000;
+const BLOCK_SIZE: usize = 256;
+const CHORD: [u8; 4] = [48, 60, 64, 67];
+const TAIL_BLOCKS: usize = 400;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    pub passed: bool,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SelfTestReport {
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in self.checks.iter() {
+            writeln!(f, "{} {}", if check.passed { "PASS" } else { "FAIL" }, check.name)?;
+        }
+        write!(f, "self-test {}", if self.passed() { "passed" } else { "failed" })
+    }
+}
+
+// Runs a fixed, seeded script through the engine without JACK: a chord, a
+// mod wheel and volume sweep, a pitch bend sweep and a release, checking the
+// output and voice bookkeeping along the way.
+pub fn self_test() -> SelfTestReport {
+    let mut synthesizer = Synthesizer::new(SAMPLE_RATE, Tuning::default().frequencies());
+    synthesizer.set_seed(DEFAULT_SEED);
+    synthesizer.set_limiter(Limiter { enabled: true, lookahead: 0.005, ..Limiter::default() });
+    synthesizer.prepare(BLOCK_SIZE);
+
+    let (mut left, mut right) = (vec![0.0; BLOCK_SIZE], vec![0.0; BLOCK_SIZE]);
+    let (mut finite, mut peak) = (true, 0.0_f32);
+    let mut render = |synthesizer: &mut Synthesizer| {
+        synthesizer.process_block(&mut left, &mut right);
+        for &value in left.iter().chain(right.iter()) {
+            finite &= value.is_finite();
+            peak = peak.max(value.abs());
+        }
+    };
+
+    for &pitch in CHORD.iter() {
+        synthesizer.note_on(0, pitch, 127, 0);
+    }
+    let sounding = synthesizer.voice_count() == CHORD.len();
+    for value in 0..128 {
+        synthesizer.control_change(0, 1, value);
+        synthesizer.control_change(0, 7, 127 - value / 2);
+        render(&mut synthesizer);
+    }
+    for step in 0..=64 {
+        synthesizer.pitch_bend(0, step as f32 / 32.0 - 1.0);
+        render(&mut synthesizer);
+    }
+    synthesizer.pitch_bend(0, 0.0);
+    for &pitch in CHORD.iter() {
+        synthesizer.note_off(0, pitch);
+    }
+    let released = synthesizer.held_notes().is_empty();
+    for _ in 0..TAIL_BLOCKS {
+        render(&mut synthesizer);
+    }
+
+    SelfTestReport {
+        checks: vec![
+            SelfTestCheck { name: "every note of the chord got a voice", passed: sounding },
+            SelfTestCheck { name: "output is finite", passed: finite },
+            SelfTestCheck { name: "limited output stays within +/-1.0", passed: peak <= 1.0 },
+            SelfTestCheck { name: "note-offs release every held note", passed: released },
+            SelfTestCheck { name: "released voices are freed", passed: synthesizer.voice_count() == 0 },
+            SelfTestCheck { name: "no notes left sounding", passed: synthesizer.sounding_notes().is_empty() },
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_healthy_build_passes() {
+        let report = self_test();
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.checks.len(), 6);
+        assert!(report.to_string().ends_with("self-test passed"));
+    }
+
+    #[test]
+    fn one_failed_check_fails_the_report() {
+        let check = |name, passed| SelfTestCheck { name, passed };
+        let report = SelfTestReport { checks: vec![check("voices", true), check("output is finite", false)] };
+        assert!(!report.passed());
+        assert!(report.to_string().contains("FAIL output is finite"));
+        assert!(report.to_string().ends_with("self-test failed"));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::random::Random;
+
+const DIVISION: f32 = 0.25;
+const GATE: f32 = 0.5;
+// Ten octaves already span the MIDI range; more would only add rests.
+const MAX_OCTAVES: usize = 10;
+const MAX_HUMANIZE_TIME: f32 = 0.02;
+const MAX_HUMANIZE_VELOCITY: f32 = 24.0;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Step {
+    pub pitch: u8,
+    pub velocity: u8,
+}
+
+// A step sequencer that plays on `channel` while the transport runs: one step
+// every `division` beats (0.25 is sixteenths), None steps rest, and each
+// note is held for `gate` of its step.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Sequencer {
+    pub steps: Vec<Option<Step>>,
+    pub channel: u8,
+    pub division: f32,
+    pub gate: f32,
+    pub humanize: f32,
+}
+
+impl Default for Sequencer {
+    fn default() -> Sequencer {
+        Sequencer {
+            steps: Vec::new(),
+            channel: 0,
+            division: DIVISION,
+            gate: GATE,
+            humanize: 0.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum ArpMode {
+    #[default]
+    Up,
+    Down,
+    UpDown,
+    Played,
+}
+
+// Plays the keys held on `channel` one at a time instead of together, on the
+// same step grid as the sequencer, across `octaves` octaves (1 to
+// MAX_OCTAVES).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Arpeggiator {
+    pub channel: u8,
+    pub mode: ArpMode,
+    pub octaves: usize,
+    pub division: f32,
+    pub gate: f32,
+    pub humanize: f32,
+}
+
+impl Default for Arpeggiator {
+    fn default() -> Arpeggiator {
+        Arpeggiator {
+            channel: 0,
+            mode: ArpMode::default(),
+            octaves: 1,
+            division: DIVISION,
+            gate: GATE,
+            humanize: 0.0,
+        }
+    }
+}
+
+impl Arpeggiator {
+    // The pitch and velocity for step `index` over `held`, given as (pitch,
+    // velocity) in the order the keys went down. None rests the step.
+    pub fn note(&self, held: &[(u8, u8)], index: usize) -> Option<(u8, u8)> {
+        let count = held.len().min(128);
+        if count == 0 {
+            return None;
+        }
+        let mut keys = [(0, 0); 128];
+        keys[..count].copy_from_slice(&held[..count]);
+        if self.mode != ArpMode::Played {
+            keys[..count].sort_unstable();
+        }
+        let length = count * self.octaves.clamp(1, MAX_OCTAVES);
+        let position = match self.mode {
+            ArpMode::Up | ArpMode::Played => index % length,
+            ArpMode::Down => length - 1 - index % length,
+            ArpMode::UpDown if length == 1 => 0,
+            ArpMode::UpDown => {
+                let position = index % (2 * length - 2);
+                if position < length { position } else { 2 * length - 2 - position }
+            }
+        };
+        let (pitch, velocity) = keys[position % count];
+        let pitch = pitch as usize + 12 * (position / count);
+        (pitch < 128).then_some((pitch as u8, velocity))
+    }
+}
+
+// Random timing and velocity variation for one step, returned as the delay
+// in frames behind the grid and the varied velocity. The delay stays within
+// `amount` of MAX_HUMANIZE_TIME and the velocity within `amount` of
+// MAX_HUMANIZE_VELOCITY; amount 0 leaves the step exactly on the grid.
+pub fn humanize(random: &mut Random, amount: f32, velocity: u8, sample_rate: usize) -> (usize, u8) {
+    let amount = amount.clamp(0.0, 1.0);
+    if amount == 0.0 {
+        return (0, velocity);
+    }
+    let delay = (random.next_f32() * amount * MAX_HUMANIZE_TIME * sample_rate as f32) as usize;
+    let velocity = (velocity as f32 + random.next_bipolar() * amount * MAX_HUMANIZE_VELOCITY).round().clamp(1.0, 127.0);
+    (delay, velocity as u8)
+}
+
+// Tracks which step of the grid the transport is on.
+#[derive(Clone, Debug, Default)]
+pub struct StepClock {
+    step: Option<i64>,
+}
+
+impl StepClock {
+    // The new step's index when `beat` has moved into a step other than the
+    // last one seen.
+    pub fn tick(&mut self, beat: f64, division: f32) -> Option<i64> {
+        if division <= 0.0 {
+            return None;
+        }
+        let step = (beat / division as f64).floor() as i64;
+        if self.step == Some(step) {
+            return None;
+        }
+        self.step = Some(step);
+        Some(step)
+    }
+
+    pub fn reset(&mut self) {
+        self.step = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_huge_octave_count_is_clamped() {
+        let arpeggiator = Arpeggiator { octaves: usize::MAX, ..Default::default() };
+        let held = [(0, 100)];
+        let pitches: Vec<_> = (0..MAX_OCTAVES + 1).map(|index| arpeggiator.note(&held, index)).collect();
+        assert_eq!(pitches[MAX_OCTAVES - 1], Some((108, 100)));
+        assert_eq!(pitches[MAX_OCTAVES], Some((0, 100)));
+    }
+
+    #[test]
+    fn humanize_stays_within_its_bounds() {
+        let mut random = Random::new(7);
+        let steps: Vec<_> = (0..1000).map(|_| humanize(&mut random, 0.5, 100, 48000)).collect();
+        assert!(steps.iter().all(|&(delay, velocity)| delay <= 480 && (88..=112).contains(&velocity)));
+        assert!(steps.iter().any(|&(delay, _)| delay > 240));
+        assert!(steps.iter().any(|&(_, velocity)| velocity != 100));
+        let mut random = Random::new(7);
+        assert!((0..1000).map(|_| humanize(&mut random, 0.5, 100, 48000)).eq(steps.iter().copied()));
+    }
+
+    #[test]
+    fn no_humanize_keeps_steps_on_the_grid() {
+        let mut random = Random::new(7);
+        assert!((0..100).all(|_| humanize(&mut random, 0.0, 100, 48000) == (0, 100)));
+        // Varied velocities never reach 0, which would be a note off.
+        assert!((0..100).all(|_| humanize(&mut random, 1.0, 1, 48000).1 >= 1));
+    }
+}
@@ -0,0 +1,4633 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::cached::Cached;
+use crate::control::Command;
+use crate::cut_filter::CutFilter;
+use crate::delay::Delay;
+use crate::drive::Drive;
+use crate::equalizer::Equalizer;
+use crate::filter::{Filter, FilterCoefficients, FilterPlacement, FilterState};
+use crate::formant::{Formant, FormantState, BANDS};
+use crate::lfo::{Lfo, LfoRetrigger};
+use crate::limiter::Limiter;
+use crate::macros::{Macro, MacroMapping, MAX_MACROS};
+use crate::master::{pan_gains, speaker_gains, Master, Modulation, MAX_SPEAKERS};
+use crate::midi_log::MidiLog;
+use crate::mod_matrix::{ModSources, MAX_MOD_LFOS};
+use crate::monitor::{VoiceEventKind, VoiceEventQueue, VoiceEvents, VoiceMonitor, VoiceState};
+use crate::mpe::{Mpe, SLIDE_CUTOFF};
+use crate::note::{EnvelopePhase, Note};
+use crate::overload::{guard_output, NonFiniteCounter, Overload, OverloadIndicator, OVERLOAD_HOLD};
+use crate::params::{ParamTarget, PARAM_TARGETS};
+use crate::patch::{PanSpreadMode, Patch, Waveform, MAX_UNISON};
+use crate::preset::{decode_patch, encode_patch, CcBindings};
+use crate::random::Random;
+use crate::reverb::Reverb;
+use crate::sample::Sample;
+use crate::scale::Scale;
+use crate::sequencer::{humanize, Arpeggiator, Sequencer, StepClock};
+use crate::test_tone::TestTone;
+use crate::tilt::Tilt;
+use crate::wow_flutter::WowFlutter;
+use crate::transport::Transport;
+use crate::velocity_range::VelocityRange;
+use crate::watchdog::WatchdogLog;
+use crate::wavetable::Wavetable;
+
+const MAX_AMPLITUDE: f32 = 0.2;
+
+pub const CHANNELS: usize = 16;
+const MAX_VOICES: usize = 32;
+const EVENT_CAPACITY: usize = 256;
+const PITCH_BEND_SMOOTHING: f32 = 0.002;
+const MAX_PHASE_DISTORTION: f32 = 0.98;
+const FADE_TIME: f32 = 0.005;
+const MUTE_TIME: f32 = 0.02;
+const START_TIME: f32 = 0.005;
+const DUCKING_TIME: f32 = 0.03;
+// -100 dB, far below anything audible.
+const SILENCE_THRESHOLD: f32 = 0.00001;
+
+// How a voice is chosen when the voice limit is reached. Quietest steals the
+// voice with the lowest envelope level, Oldest the one that started first,
+// RoundRobin the one in the next allocation slot, and SamePitch reuses a voice
+// already playing the incoming pitch before falling back to the quietest.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum VoiceStealMode {
+    RoundRobin,
+    Oldest,
+    #[default]
+    Quietest,
+    SamePitch,
+}
+
+#[derive(Copy, Clone)]
+enum Event {
+    NoteOn(u8, u8, u8),
+    NoteOff(u8, u8),
+    PitchBend(u8, f32),
+    Control(u8, u8, u8),
+    Aftertouch(u8, u8),
+    StepOn(u8, u8, u8),
+    StepOff(u8, u8),
+    Clock,
+    Start,
+    Continue,
+    Stop,
+}
+
+struct Channel {
+    patch: Patch,
+    bus: usize,
+    notes: Vec<Note>,
+    bend_target: f32,
+    bend: f32,
+    volume: f32,
+    expression: f32,
+    mod_wheel: f32,
+    aftertouch: f32,
+    slide: f32,
+    mod_lfos: [Lfo; MAX_MOD_LFOS],
+    sustain_pedal: bool,
+    last_pitch: Option<u8>,
+    released_at: usize,
+    // The clock time of the last accepted note-on for each pitch.
+    note_on_at: [Option<usize>; 128],
+    held: Vec<u8>,
+    // The shared filter for FilterPlacement::Master, over the mono stem sum
+    // and the left and right mixes.
+    filters: [FilterState; 3],
+    formants: [FormantState; 3],
+    // Coefficients get_audio_data would otherwise work out every frame, keyed
+    // on the settings and the sample rate or time step they depend on.
+    filter_coefficients: Cached<(Filter, f32), FilterCoefficients>,
+    formant_coefficients: Cached<(Formant, f32), [(FilterCoefficients, f32); BANDS]>,
+    drift_coefficient: Cached<(f32, f32), f32>,
+    velocity_prefix: Option<u8>,
+}
+
+impl Channel {
+    fn new(bus: usize) -> Channel {
+        Channel {
+            patch: Patch::default(),
+            bus,
+            notes: Vec::new(),
+            bend_target: 0.0,
+            bend: 0.0,
+            volume: 1.0,
+            expression: 1.0,
+            mod_wheel: 0.0,
+            aftertouch: 0.0,
+            slide: 0.0,
+            mod_lfos: [Lfo::default(); MAX_MOD_LFOS],
+            sustain_pedal: false,
+            last_pitch: None,
+            released_at: 0,
+            note_on_at: [None; 128],
+            held: Vec::new(),
+            filters: [FilterState::default(); 3],
+            formants: [FormantState::default(); 3],
+            filter_coefficients: Cached::default(),
+            formant_coefficients: Cached::default(),
+            drift_coefficient: Cached::default(),
+            velocity_prefix: None,
+        }
+    }
+}
+
+pub struct Synthesizer {
+    sample_rate: usize,
+    time_step: f32,
+    channels: Vec<Channel>,
+    frequencies: [f32; 128],
+    max_voices: usize,
+    steal_mode: VoiceStealMode,
+    max_note_duration: Option<usize>,
+    next_voice: usize,
+    wavetable: Wavetable,
+    sample: Option<Sample>,
+    events: Vec<(usize, Event)>,
+    next_event: usize,
+    test_tone: Option<TestTone>,
+    pitch_bend_smoothing: f32,
+    bend_coefficient: f32,
+    voice_ducking: f32,
+    silence_threshold: f32,
+    retune_time: f32,
+    debounce_time: f32,
+    brightness_cutoff: f32,
+    ducking_gain: f32,
+    ducking_coefficient: f32,
+    random: Random,
+    cc_map: HashMap<u8, ParamTarget>,
+    learning: Option<ParamTarget>,
+    macros: [Macro; MAX_MACROS],
+    macro_cc_map: HashMap<u8, usize>,
+    overload: Overload,
+    overload_hold: f32,
+    non_finite: NonFiniteCounter,
+    master: Master,
+    transport: Transport,
+    stem_values: Vec<f32>,
+    stem_buffers: Vec<Vec<f32>>,
+    speaker_values: Vec<f32>,
+    speaker_buffers: Vec<Vec<f32>>,
+    speaker_gains: Vec<f32>,
+    channel_speakers: Vec<f32>,
+    block_frames: usize,
+    prepared_frames: usize,
+    send: Option<(f32, f32)>,
+    clock: usize,
+    block_start: usize,
+    mono_buffer: Vec<f32>,
+    fade_time: f32,
+    fade_gain: f32,
+    start_time: f32,
+    start_gain: f32,
+    pending_sample_rate: Option<usize>,
+    pending_sound_off: u16,
+    voice_monitor: Option<VoiceMonitor>,
+    voice_events: Option<VoiceEvents>,
+    mpe: Option<Mpe>,
+    panic_note: Option<u8>,
+    key_range: (u8, u8),
+    velocity_range: Option<VelocityRange>,
+    next_voice_id: u64,
+    midi_log: Option<MidiLog>,
+    sequencer: Option<Sequencer>,
+    sequencer_clock: StepClock,
+    arpeggiator: Option<Arpeggiator>,
+    arpeggiator_clock: StepClock,
+    arpeggiator_held: Vec<(u8, u8)>,
+    muted: bool,
+    frozen: bool,
+    mute_time: f32,
+    mute_gain: f32,
+    coarse_tune: i32,
+    fine_tune: f32,
+    tuning: f32,
+}
+
+impl Synthesizer {
+    pub fn new(sample_rate: usize, mut frequencies: [f32; 128]) -> Synthesizer {
+        sanitize_frequencies(&mut frequencies);
+        let time_step = 1.0 / sample_rate as f32;
+        let channels = (0..CHANNELS).map(Channel::new).collect();
+
+        Synthesizer {
+            sample_rate,
+            time_step,
+            channels,
+            frequencies,
+            max_voices: MAX_VOICES,
+            steal_mode: VoiceStealMode::default(),
+            max_note_duration: None,
+            next_voice: 0,
+            wavetable: Wavetable::default(),
+            sample: None,
+            events: Vec::with_capacity(EVENT_CAPACITY),
+            next_event: 0,
+            test_tone: None,
+            pitch_bend_smoothing: PITCH_BEND_SMOOTHING,
+            bend_coefficient: smoothing_coefficient(PITCH_BEND_SMOOTHING, time_step),
+            voice_ducking: 0.0,
+            silence_threshold: SILENCE_THRESHOLD,
+            retune_time: 0.0,
+            debounce_time: 0.0,
+            brightness_cutoff: SLIDE_CUTOFF,
+            ducking_gain: 1.0,
+            ducking_coefficient: smoothing_coefficient(DUCKING_TIME, time_step),
+            random: Random::default(),
+            cc_map: default_cc_map(),
+            learning: None,
+            macros: Default::default(),
+            macro_cc_map: HashMap::new(),
+            overload: Overload::new((OVERLOAD_HOLD / time_step) as usize),
+            overload_hold: OVERLOAD_HOLD,
+            non_finite: NonFiniteCounter::default(),
+            master: Master::new(),
+            transport: Transport::new(),
+            stem_values: Vec::new(),
+            stem_buffers: Vec::new(),
+            speaker_values: Vec::new(),
+            speaker_buffers: Vec::new(),
+            speaker_gains: Vec::new(),
+            channel_speakers: Vec::new(),
+            block_frames: 0,
+            prepared_frames: 0,
+            send: None,
+            clock: 0,
+            block_start: 0,
+            mono_buffer: Vec::new(),
+            fade_time: FADE_TIME,
+            fade_gain: 1.0,
+            start_time: START_TIME,
+            start_gain: 0.0,
+            pending_sample_rate: None,
+            pending_sound_off: 0,
+            voice_monitor: None,
+            voice_events: None,
+            mpe: None,
+            panic_note: None,
+            key_range: (0, 127),
+            velocity_range: None,
+            next_voice_id: 0,
+            midi_log: None,
+            sequencer: None,
+            sequencer_clock: StepClock::default(),
+            arpeggiator: None,
+            arpeggiator_clock: StepClock::default(),
+            arpeggiator_held: Vec::new(),
+            muted: false,
+            frozen: false,
+            mute_time: MUTE_TIME,
+            mute_gain: 1.0,
+            coarse_tune: 0,
+            fine_tune: 0.0,
+            tuning: 1.0,
+        }
+    }
+
+    // Channels are numbered 0..16 as on the wire, i.e. MIDI channel 1 is 0.
+    pub fn patch(&self, channel: u8) -> &Patch {
+        &self.channels[channel as usize % CHANNELS].patch
+    }
+
+    pub fn set_patch(&mut self, channel: u8, patch: Patch) {
+        self.channels[channel as usize % CHANNELS].patch = patch;
+        self.sync_mpe_patches(channel);
+    }
+
+    // The channel's patch and the CC bindings as a string that
+    // patch_from_string turns back into the same sound and controls; see
+    // encode_patch for what it covers.
+    pub fn patch_to_string(&self, channel: u8) -> String {
+        let bindings = CcBindings { params: self.cc_map.clone(), macros: self.macro_cc_map.clone() };
+        encode_patch(self.patch(channel), &bindings)
+    }
+
+    // Replaces the channel's patch and every CC binding; leaves all of them
+    // alone if the string doesn't decode. The bindings go through bind_cc and
+    // bind_macro_cc in controller order, so a hand-edited string can't bind
+    // one parameter or macro to two controllers.
+    pub fn patch_from_string(&mut self, channel: u8, text: &str) -> io::Result<()> {
+        let (patch, bindings) = decode_patch(text)?;
+        self.set_patch(channel, patch);
+        self.cc_map.clear();
+        self.macro_cc_map.clear();
+        let mut params: Vec<_> = bindings.params.into_iter().collect();
+        params.sort_unstable_by_key(|&(cc, _)| cc);
+        for (cc, target) in params {
+            self.bind_cc(cc, target);
+        }
+        let mut macros: Vec<_> = bindings.macros.into_iter().collect();
+        macros.sort_unstable();
+        for (cc, index) in macros {
+            self.bind_macro_cc(cc, index);
+        }
+        Ok(())
+    }
+
+    pub fn mpe(&self) -> Option<&Mpe> {
+        self.mpe.as_ref()
+    }
+
+    // With MPE on, the zone's member channels take the master channel's patch
+    // and follow every later change to it; None turns MPE off, leaving the
+    // member channels with the patch they had.
+    pub fn set_mpe(&mut self, mpe: Option<Mpe>) {
+        self.mpe = mpe;
+        if let Some(mpe) = mpe {
+            self.sync_mpe_patches(mpe.master_channel() as u8);
+        }
+    }
+
+    fn sync_mpe_patches(&mut self, channel: u8) {
+        let Some(mpe) = self.mpe.filter(|mpe| mpe.master_channel() == channel as usize % CHANNELS) else {
+            return;
+        };
+        let patch = self.channels[mpe.master_channel()].patch;
+        for (c, member) in self.channels.iter_mut().enumerate() {
+            if mpe.is_member(c) {
+                member.patch = patch;
+            }
+        }
+    }
+
+    pub fn set_wavetable(&mut self, wavetable: Wavetable) {
+        self.wavetable = wavetable;
+    }
+
+    // The sample played by patches using Waveform::Sample; without one those
+    // notes end immediately.
+    pub fn set_sample(&mut self, sample: Option<Sample>) {
+        self.sample = sample;
+    }
+
+    // Back to the init sound, a plain saw through an open filter with a medium
+    // envelope: every channel gets Patch::default(), the master chain, macros
+    // and CC bindings their defaults, and the voice limit MAX_VOICES. The
+    // parameters outside the patch (channel controllers, mute, freeze, tuning
+    // and the MIDI log) go back to their ParamInfo defaults through set_param,
+    // so a pedal-held note is released as if the pedal came up.
+    pub fn init_patch(&mut self) {
+        for channel in self.channels.iter_mut() {
+            channel.patch = Patch::default();
+        }
+        for channel in 0..CHANNELS as u8 {
+            self.reset_controllers(channel);
+            for target in PARAM_TARGETS.into_iter().filter(|&target| Patch::default().param(target).is_none()) {
+                let info = target.info();
+                self.set_param(channel, target, info.normalize(info.default));
+            }
+        }
+        self.set_max_voices(MAX_VOICES);
+        self.master.reset_settings(self.sample_rate);
+        self.macros = Default::default();
+        self.macro_cc_map.clear();
+        self.cc_map = default_cc_map();
+        self.learning = None;
+        self.brightness_cutoff = SLIDE_CUTOFF;
+    }
+
+    pub fn test_tone(&self) -> Option<&TestTone> {
+        self.test_tone.as_ref()
+    }
+
+    // Updating a running test tone keeps its phase so live changes don't click.
+    pub fn set_test_tone(&mut self, frequency: f32, level: f32) {
+        match self.test_tone.as_mut() {
+            Some(tone) => {
+                tone.frequency = frequency;
+                tone.level = level;
+            }
+            None => self.test_tone = Some(TestTone::new(frequency, level)),
+        }
+    }
+
+    pub fn stop_test_tone(&mut self) {
+        self.test_tone = None;
+    }
+
+    pub fn pitch_bend_smoothing(&self) -> f32 {
+        self.pitch_bend_smoothing
+    }
+
+    // Time constant in seconds of the one-pole glide toward a new bend value,
+    // 0 applies bends immediately.
+    pub fn set_pitch_bend_smoothing(&mut self, seconds: f32) {
+        self.pitch_bend_smoothing = seconds.max(0.0);
+        self.bend_coefficient = smoothing_coefficient(self.pitch_bend_smoothing, self.time_step);
+    }
+
+    pub fn voice_ducking(&self) -> f32 {
+        self.voice_ducking
+    }
+
+    // Turns every voice down as more of them sound, by voices^(-amount / 2):
+    // at 1 a chord of uncorrelated voices keeps the power of a single note, at
+    // 0, the default, nothing is ducked. A lone note always plays at full
+    // level, and the gain glides over DUCKING_TIME so notes coming and going
+    // don't click.
+    pub fn set_voice_ducking(&mut self, amount: f32) {
+        self.voice_ducking = amount.clamp(0.0, 1.0);
+    }
+
+    pub fn silence_threshold(&self) -> f32 {
+        self.silence_threshold
+    }
+
+    // Releasing voices whose envelope level drops below `threshold` are freed
+    // without waiting out the rest of the release. 0 lets every release run
+    // to its end.
+    pub fn set_silence_threshold(&mut self, threshold: f32) {
+        self.silence_threshold = threshold.max(0.0);
+    }
+
+    pub fn coarse_tune(&self) -> i32 {
+        self.coarse_tune
+    }
+
+    pub fn fine_tune(&self) -> f32 {
+        self.fine_tune
+    }
+
+    // Global offsets on top of the frequency table: coarse in semitones, fine in
+    // cents.
+    pub fn set_tuning(&mut self, coarse: i32, fine: f32) {
+        let (coarse_range, fine_range) = (ParamTarget::CoarseTune.info(), ParamTarget::FineTune.info());
+        self.coarse_tune = coarse.clamp(coarse_range.min as i32, coarse_range.max as i32);
+        self.fine_tune = fine.clamp(fine_range.min, fine_range.max);
+        let cents = self.coarse_tune as f32 * 100.0 + self.fine_tune;
+        self.tuning = if cents == 0.0 { 1.0 } else { 2.0_f32.powf(cents / 1200.0) };
+    }
+
+    pub fn frequencies(&self) -> &[f32; 128] {
+        &self.frequencies
+    }
+
+    // The pitches whose table entry was rejected, so that note-ons for them
+    // are ignored; the caller decides whether that is worth reporting.
+    pub fn disabled_pitches(&self) -> Vec<u8> {
+        (0..128).filter(|&pitch| self.frequencies[pitch as usize] == 0.0).collect()
+    }
+
+    pub fn retune_time(&self) -> f32 {
+        self.retune_time
+    }
+
+    // Seconds sounding notes take to glide from their old tuning to a new
+    // frequency table. 0, the default, retunes them instantly.
+    pub fn set_retune_time(&mut self, seconds: f32) {
+        self.retune_time = seconds.max(0.0);
+    }
+
+    pub fn debounce_time(&self) -> f32 {
+        self.debounce_time
+    }
+
+    // A note-on arriving within `seconds` of the last one for the same pitch
+    // on its channel is taken for a bouncing key and ignored. 0, the default,
+    // passes every note-on.
+    pub fn set_debounce_time(&mut self, seconds: f32) {
+        self.debounce_time = seconds.max(0.0);
+    }
+
+    pub fn brightness_cutoff(&self) -> f32 {
+        self.brightness_cutoff
+    }
+
+    // How far CC74 (brightness) at full scale opens a channel's cutoff, as a
+    // fraction of its normalized range, outside MPE member channels; those use
+    // Mpe::slide_cutoff per note instead. 0 turns the routing off.
+    pub fn set_brightness_cutoff(&mut self, amount: f32) {
+        self.brightness_cutoff = amount.clamp(0.0, 1.0);
+    }
+
+    // Entries that are zero, negative or not finite are disabled: note-ons for
+    // those pitches are ignored, so they stay silent instead of producing DC or
+    // running the oscillator backwards, and notes already sounding on a pitch
+    // the new table disables are silenced. With a retune time set, the other
+    // sounding notes glide from where they were to their new frequency.
+    // Returns the pitches whose entries were disabled.
+    pub fn set_frequencies(&mut self, mut frequencies: [f32; 128]) -> Vec<u8> {
+        let rejected = sanitize_frequencies(&mut frequencies);
+        for note in self.channels.iter_mut().flat_map(|channel| channel.notes.iter_mut()) {
+            if frequencies[note.pitch as usize] == 0.0 {
+                note.env_phase = EnvelopePhase::Off;
+            }
+        }
+        if self.retune_time > 0.0 {
+            for channel in self.channels.iter_mut() {
+                for note in channel.notes.iter_mut() {
+                    let (old, new) = (self.frequencies[note.pitch as usize], frequencies[note.pitch as usize]);
+                    if old <= 0.0 || new <= 0.0 || old == new {
+                        continue;
+                    }
+                    let current = 12.0 * note.glide_ratio(channel.patch.glide_curve).log2();
+                    let interval = current + 12.0 * (old / new).log2();
+                    note.glide = interval;
+                    note.glide_interval = interval;
+                    note.glide_step = interval.abs() * self.time_step / self.retune_time;
+                }
+            }
+        }
+        self.frequencies = frequencies;
+        rejected
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+        self.master.set_seed(seed);
+    }
+
+    pub fn learn(&mut self, target: ParamTarget) {
+        self.learning = Some(target);
+    }
+
+    pub fn cancel_learn(&mut self) {
+        self.learning = None;
+    }
+
+    pub fn learning(&self) -> Option<ParamTarget> {
+        self.learning
+    }
+
+    pub fn cc_map(&self) -> &HashMap<u8, ParamTarget> {
+        &self.cc_map
+    }
+
+    // Binding a target drops any other CC bound to it, so a parameter only ever
+    // follows one controller.
+    pub fn bind_cc(&mut self, cc: u8, target: ParamTarget) {
+        self.cc_map.retain(|_, bound| *bound != target);
+        self.macro_cc_map.remove(&cc);
+        self.cc_map.insert(cc, target);
+    }
+
+    pub fn unbind_cc(&mut self, cc: u8) {
+        self.cc_map.remove(&cc);
+        self.macro_cc_map.remove(&cc);
+    }
+
+    pub fn macro_knob(&self, index: usize) -> Option<&Macro> {
+        self.macros.get(index)
+    }
+
+    pub fn macro_cc_map(&self) -> &HashMap<u8, usize> {
+        &self.macro_cc_map
+    }
+
+    // Replaces what macro `index` moves; out-of-range indices are ignored.
+    pub fn set_macro_mappings(&mut self, index: usize, mappings: Vec<MacroMapping>) {
+        if let Some(knob) = self.macros.get_mut(index) {
+            knob.mappings = mappings;
+        }
+    }
+
+    // Sets macro `index` to `value`, clamped to 0..1, and moves every
+    // parameter mapped to it on `channel` to its interpolated value. NaN is
+    // ignored.
+    pub fn set_macro(&mut self, channel: u8, index: usize, value: f32) {
+        let Some(knob) = self.macros.get_mut(index).filter(|_| !value.is_nan()) else {
+            return;
+        };
+        let value = value.clamp(0.0, 1.0);
+        knob.value = value;
+        let mappings = std::mem::take(&mut knob.mappings);
+        for mapping in mappings.iter() {
+            self.set_param(channel, mapping.target, mapping.value(value));
+        }
+        self.macros[index].mappings = mappings;
+    }
+
+    // Like bind_cc, a controller drives either one macro or one parameter,
+    // and a macro follows only one controller.
+    pub fn bind_macro_cc(&mut self, cc: u8, index: usize) {
+        if index >= MAX_MACROS {
+            return;
+        }
+        self.macro_cc_map.retain(|_, bound| *bound != index);
+        self.cc_map.remove(&cc);
+        self.macro_cc_map.insert(cc, index);
+    }
+
+    pub fn overload_indicator(&self) -> OverloadIndicator {
+        self.overload.indicator()
+    }
+
+    pub fn non_finite_counter(&self) -> NonFiniteCounter {
+        self.non_finite.clone()
+    }
+
+    // How long the overload flag stays lit after the last clipping block.
+    pub fn set_overload_hold(&mut self, seconds: f32) {
+        self.overload_hold = seconds.max(0.0);
+        self.overload.set_hold((self.overload_hold / self.time_step) as usize);
+    }
+
+    // The sample clock: frames rendered since the clock was last reset. Note
+    // start times and envelopes run on it, not on the frame numbers passed
+    // to get_audio_data, which only place events within the block.
+    pub fn clock(&self) -> usize {
+        self.clock
+    }
+
+    // Rewinds the sample clock to 0 between blocks and restarts the free
+    // running LFOs, so the same input renders the same output again. Notes
+    // waiting on a start time keep their place relative to the clock.
+    pub fn reset_clock(&mut self) {
+        for note in self.channels.iter_mut().flat_map(|channel| channel.notes.iter_mut()) {
+            note.rewind(self.block_start);
+        }
+        for channel in self.channels.iter_mut() {
+            channel.released_at = channel.released_at.saturating_sub(self.block_start);
+            for time in channel.note_on_at.iter_mut().flatten() {
+                *time = time.saturating_sub(self.block_start);
+            }
+            for lfo in channel.mod_lfos.iter_mut() {
+                lfo.reset(0.0);
+            }
+        }
+        self.master.reset_lfos();
+        self.clock = 0;
+        self.block_start = 0;
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    // The change is applied once the master fade has reached silence. Call
+    // prepare after this, off the audio thread, so applying it only swaps in
+    // master delay lines already built for the new rate.
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        if sample_rate > 0 && sample_rate != self.sample_rate {
+            self.pending_sample_rate = Some(sample_rate);
+        }
+    }
+
+    fn apply_sample_rate(&mut self, sample_rate: usize) {
+        self.sample_rate = sample_rate;
+        self.time_step = 1.0 / sample_rate as f32;
+        self.bend_coefficient = smoothing_coefficient(self.pitch_bend_smoothing, self.time_step);
+        self.ducking_coefficient = smoothing_coefficient(DUCKING_TIME, self.time_step);
+        self.overload.set_hold((self.overload_hold / self.time_step) as usize);
+        self.master.set_sample_rate(sample_rate);
+    }
+
+    pub fn fade_time(&self) -> f32 {
+        self.fade_time
+    }
+
+    // Length of the master fade out and back in around sample-rate changes and
+    // All Sound Off.
+    pub fn set_fade_time(&mut self, seconds: f32) {
+        self.fade_time = seconds.max(0.0);
+    }
+
+    pub fn start_time(&self) -> f32 {
+        self.start_time
+    }
+
+    // Length of the one-shot fade in at the very start of output, so the first
+    // block after the engine starts can't pop. 0 starts at full level.
+    pub fn set_start_time(&mut self, seconds: f32) {
+        self.start_time = seconds.max(0.0);
+        if self.start_time == 0.0 {
+            self.start_gain = 1.0;
+        }
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    // Voices keep running underneath, only the outputs ramp to silence.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn frozen(&self) -> bool {
+        self.frozen
+    }
+
+    // Stops every sounding note's envelope where it is, whatever stage it is
+    // in, until unfrozen; oscillators keep running. Unlike the sustain pedal
+    // this also holds attacks, decays and releases already under way.
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    pub fn mute_time(&self) -> f32 {
+        self.mute_time
+    }
+
+    pub fn set_mute_time(&mut self, seconds: f32) {
+        self.mute_time = seconds.max(0.0);
+    }
+
+    fn update_mute(&mut self) {
+        let step = if self.mute_time > 0.0 { self.time_step / self.mute_time } else { 1.0 };
+        if self.muted {
+            self.mute_gain = (self.mute_gain - step).max(0.0);
+        } else {
+            self.mute_gain = (self.mute_gain + step).min(1.0);
+        }
+    }
+
+    pub fn all_sound_off(&mut self, channel: u8) {
+        self.pending_sound_off |= 1 << (channel as usize % CHANNELS);
+    }
+
+    pub fn reset(&mut self) {
+        self.pending_sound_off = u16::MAX;
+    }
+
+    pub fn key_range(&self) -> (u8, u8) {
+        self.key_range
+    }
+
+    // Only keys from `low` to `high` inclusive are played; note-ons and
+    // note-offs outside it are ignored. Notes left outside by a narrower range
+    // are released, so none is stranded without its note-off.
+    pub fn set_key_range(&mut self, low: u8, high: u8) {
+        let (low, high) = (low.min(high).min(127), high.max(low).min(127));
+        self.key_range = (low, high);
+        for channel in 0..CHANNELS {
+            let pitches: Vec<u8> = self.channels[channel].notes.iter().map(|note| note.pitch).filter(|pitch| !(low..=high).contains(pitch)).collect();
+            for pitch in pitches {
+                self.release_key(channel as u8, pitch);
+            }
+        }
+    }
+
+    fn in_key_range(&self, pitch: u8) -> bool {
+        (self.key_range.0..=self.key_range.1).contains(&pitch)
+    }
+
+    pub fn panic_note(&self) -> Option<u8> {
+        self.panic_note
+    }
+
+    // A key that resets the engine instead of sounding, on any channel, for
+    // controllers without a panic button. None, the default, disables it.
+    pub fn set_panic_note(&mut self, pitch: Option<u8>) {
+        self.panic_note = pitch.map(|pitch| pitch.min(127));
+    }
+
+    fn apply_sound_off(&mut self, channels: u16) {
+        for (c, channel) in self.channels.iter_mut().enumerate() {
+            if channels & (1 << c) != 0 {
+                for note in channel.notes.iter() {
+                    report_freed(&mut self.voice_events, c, note);
+                }
+                channel.notes.clear();
+                channel.held.clear();
+                for filter in channel.filters.iter_mut() {
+                    filter.reset();
+                }
+                for formant in channel.formants.iter_mut() {
+                    formant.reset();
+                }
+            }
+        }
+    }
+
+    fn update_fade(&mut self) {
+        let step = if self.fade_time > 0.0 { self.time_step / self.fade_time } else { 1.0 };
+        if self.pending_sample_rate.is_none() && self.pending_sound_off == 0 {
+            self.fade_gain = (self.fade_gain + step).min(1.0);
+            return;
+        }
+
+        self.fade_gain = (self.fade_gain - step).max(0.0);
+        if self.fade_gain == 0.0 {
+            if let Some(sample_rate) = self.pending_sample_rate.take() {
+                self.apply_sample_rate(sample_rate);
+            }
+            let channels = std::mem::take(&mut self.pending_sound_off);
+            self.apply_sound_off(channels);
+        }
+    }
+
+    // Without a tempo, tempo-synced rates fall back to their free-running Hz.
+    pub fn tempo(&self) -> Option<f32> {
+        self.transport.tempo()
+    }
+
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut Transport {
+        &mut self.transport
+    }
+
+    pub fn tremolo(&self) -> &Modulation {
+        &self.master.tremolo
+    }
+
+    pub fn set_tremolo(&mut self, tremolo: Modulation) {
+        self.master.tremolo = tremolo;
+    }
+
+    pub fn auto_pan(&self) -> &Modulation {
+        &self.master.auto_pan
+    }
+
+    pub fn set_auto_pan(&mut self, auto_pan: Modulation) {
+        self.master.auto_pan = auto_pan;
+    }
+
+    pub fn drive(&self) -> &Drive {
+        &self.master.drive
+    }
+
+    pub fn set_drive(&mut self, drive: Drive) {
+        self.master.set_drive(drive);
+    }
+
+    pub fn wow_flutter(&self) -> &WowFlutter {
+        &self.master.wow_flutter
+    }
+
+    pub fn set_wow_flutter(&mut self, wow_flutter: WowFlutter) {
+        self.master.set_wow_flutter(wow_flutter, self.sample_rate);
+    }
+
+    pub fn cut_filter(&self) -> &CutFilter {
+        &self.master.cut_filter
+    }
+
+    pub fn set_cut_filter(&mut self, cut_filter: CutFilter) {
+        self.master.set_cut_filter(cut_filter, self.sample_rate);
+    }
+
+    pub fn equalizer(&self) -> &Equalizer {
+        &self.master.equalizer
+    }
+
+    pub fn set_equalizer(&mut self, equalizer: Equalizer) {
+        self.master.set_equalizer(equalizer, self.sample_rate);
+    }
+
+    pub fn delay(&self) -> &Delay {
+        &self.master.delay
+    }
+
+    pub fn set_delay(&mut self, delay: Delay) {
+        self.master.set_delay(delay, self.sample_rate);
+    }
+
+    pub fn reverb(&self) -> &Reverb {
+        &self.master.reverb
+    }
+
+    pub fn set_reverb(&mut self, reverb: Reverb) {
+        self.master.set_reverb(reverb, self.sample_rate);
+    }
+
+    pub fn watchdog_threshold(&self) -> f32 {
+        self.master.watchdog_threshold
+    }
+
+    // The level the delay or reverb output has to stay above before the
+    // watchdog treats it as runaway feedback and clears that stage.
+    // Infinity leaves only NaN and infinite output tripping it.
+    pub fn set_watchdog_threshold(&mut self, threshold: f32) {
+        if threshold > 0.0 {
+            self.master.watchdog_threshold = threshold;
+        }
+    }
+
+    // Returns a handle other threads can poll to log watchdog resets.
+    pub fn watchdog_log(&self) -> WatchdogLog {
+        self.master.watchdog_log()
+    }
+
+    pub fn limiter(&self) -> &Limiter {
+        &self.master.limiter
+    }
+
+    pub fn set_limiter(&mut self, limiter: Limiter) {
+        self.master.set_limiter(limiter, self.sample_rate);
+    }
+
+    // Output latency in frames: the drive's oversampling filters, the center
+    // delay of wow and flutter and the limiter look-ahead. Zero while all three
+    // are off.
+    pub fn latency(&self) -> usize {
+        self.master.latency()
+    }
+
+    pub fn stem_count(&self) -> usize {
+        self.stem_buffers.len()
+    }
+
+    // Stems are extra dry mono outputs next to the main mix, one per bus. 0 stems
+    // is the plain single-bus mode.
+    pub fn set_stem_count(&mut self, stems: usize) {
+        let frames = self.prepared_frames;
+        self.stem_values.resize(stems, 0.0);
+        self.stem_buffers.resize_with(stems, || vec![0.0; frames]);
+    }
+
+    // By default channel n feeds bus n, wrapped to the number of stems.
+    pub fn set_bus(&mut self, channel: u8, bus: usize) {
+        self.channels[channel as usize % CHANNELS].bus = bus;
+    }
+
+    pub fn bus(&self, channel: u8) -> usize {
+        let bus = self.channels[channel as usize % CHANNELS].bus;
+        if self.stem_values.is_empty() {
+            bus
+        } else {
+            bus % self.stem_values.len()
+        }
+    }
+
+    pub fn stem(&self, bus: usize) -> &[f32] {
+        &self.stem_buffers[bus][..self.block_frames]
+    }
+
+    pub fn speaker_count(&self) -> usize {
+        self.speaker_buffers.len()
+    }
+
+    // Speaker outputs are a multichannel mix next to the main one: each voice
+    // is placed by its pan on a row of `speakers` outputs with speaker_gains.
+    // Like stems they are dry, and they are tapped ahead of a channel's
+    // shared filter and formant bank. 0 turns them off; at most MAX_SPEAKERS.
+    pub fn set_speaker_count(&mut self, speakers: usize) {
+        let speakers = speakers.min(MAX_SPEAKERS);
+        let frames = self.prepared_frames;
+        self.speaker_values.resize(speakers, 0.0);
+        self.speaker_buffers.resize_with(speakers, || vec![0.0; frames]);
+        self.speaker_gains.resize(speakers, 0.0);
+        self.channel_speakers.resize(speakers, 0.0);
+    }
+
+    pub fn speaker(&self, index: usize) -> &[f32] {
+        &self.speaker_buffers[index][..self.block_frames]
+    }
+
+    pub fn voices(&self, out: &mut Vec<VoiceState>) {
+        out.clear();
+        out.extend(self.voice_states());
+    }
+
+    fn voice_states(&self) -> impl Iterator<Item = VoiceState> + '_ {
+        self.channels.iter().enumerate().flat_map(|(c, channel)| {
+            channel.notes.iter().map(move |note| VoiceState {
+                channel: c as u8,
+                pitch: note.pitch,
+                velocity: note.velocity,
+                stage: note.env_phase,
+                amplitude: note.amplitude(&channel.patch.envelope),
+            })
+        })
+    }
+
+    // Returns a handle other threads can snapshot; once requested the voices
+    // are published at the end of every block.
+    pub fn voice_monitor(&mut self) -> VoiceMonitor {
+        let max_voices = self.max_voices;
+        self.voice_monitor.get_or_insert_with(|| VoiceMonitor::new(max_voices)).clone()
+    }
+
+    // Returns a handle other threads can drain voice events from: each voice's
+    // note-on, its release, and when it is freed. Until it is first requested
+    // no events are recorded.
+    pub fn voice_event_queue(&mut self) -> VoiceEventQueue {
+        self.voice_events.get_or_insert_with(|| VoiceEvents::new(VoiceEventQueue::new())).queue().clone()
+    }
+
+    pub fn max_voices(&self) -> usize {
+        self.max_voices
+    }
+
+    // Reserves everything the audio thread would otherwise allocate on first
+    // touch, for blocks of up to `max_frames`: the note and held-key lists at
+    // the current voice limit, the stem, speaker and mono buffers and the
+    // event queue. Stems and speakers added later are sized to match. Call it
+    // before activating the client, again after raising the voice limit and
+    // whenever a sample-rate change is pending, which gets the master delay
+    // lines built for the new rate; extra calls are harmless.
+    pub fn prepare(&mut self, max_frames: usize) {
+        self.prepared_frames = self.prepared_frames.max(max_frames);
+        let max_frames = self.prepared_frames;
+        for channel in self.channels.iter_mut() {
+            channel.notes.reserve(self.max_voices + 1);
+            channel.held.reserve(128);
+        }
+        self.arpeggiator_held.reserve(128);
+        self.events.reserve(EVENT_CAPACITY);
+        for buffer in self.stem_buffers.iter_mut().chain(self.speaker_buffers.iter_mut()) {
+            if buffer.len() < max_frames {
+                buffer.resize(max_frames, 0.0);
+            }
+        }
+        self.mono_buffer.reserve(max_frames);
+        match self.pending_sample_rate {
+            Some(sample_rate) => self.master.prepare_sample_rate(sample_rate),
+            None => self.master.release_staged(),
+        }
+    }
+
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        self.max_voices = max_voices.max(1);
+        self.next_voice %= self.max_voices;
+        while self.voice_count() > self.max_voices {
+            self.steal_voice(None, None, u8::MAX);
+        }
+    }
+
+    pub fn steal_mode(&self) -> VoiceStealMode {
+        self.steal_mode
+    }
+
+    pub fn set_steal_mode(&mut self, mode: VoiceStealMode) {
+        self.steal_mode = mode;
+    }
+
+    pub fn max_note_duration(&self) -> Option<usize> {
+        self.max_note_duration
+    }
+
+    // A safety net against stuck notes: once a note has sounded for this many
+    // samples it is released as if its note-off had arrived, sustain pedal or
+    // not. None (the default) means no limit.
+    pub fn set_max_note_duration(&mut self, samples: Option<usize>) {
+        self.max_note_duration = samples;
+    }
+
+    // Pitches whose key is still down: note-on received and no note-off yet.
+    // A note whose key was released while the sustain pedal holds it is not
+    // held, but it is still sounding, as is a note in its release stage.
+    pub fn held_notes(&self) -> Vec<u8> {
+        self.note_pitches(|note| !note.is_released() && !note.sustained)
+    }
+
+    pub fn sounding_notes(&self) -> Vec<u8> {
+        self.note_pitches(|note| note.env_phase != EnvelopePhase::Off)
+    }
+
+    fn note_pitches(&self, filter: impl Fn(&Note) -> bool) -> Vec<u8> {
+        let mut pitches: Vec<u8> = self.channels.iter().flat_map(|channel| channel.notes.iter()).filter(|note| filter(note)).map(|note| note.pitch).collect();
+        pitches.sort_unstable();
+        pitches.dedup();
+        pitches
+    }
+
+    pub fn voice_count(&self) -> usize {
+        self.channels.iter().map(|channel| channel.notes.len()).sum()
+    }
+
+    pub fn midi_logging(&self) -> bool {
+        self.midi_log.is_some()
+    }
+
+    // Prints every incoming MIDI message to stderr, rate limited. Off by default.
+    pub fn set_midi_logging(&mut self, enabled: bool) {
+        if enabled != self.midi_log.is_some() {
+            self.midi_log = enabled.then(MidiLog::new);
+        }
+    }
+
+    pub fn velocity_range(&self) -> Option<&VelocityRange> {
+        self.velocity_range.as_ref()
+    }
+
+    // Rescales incoming MIDI note velocities to the full range as the
+    // controller's own range is learned. Off by default.
+    pub fn set_velocity_auto_range(&mut self, enabled: bool) {
+        if enabled != self.velocity_range.is_some() {
+            self.velocity_range = enabled.then(VelocityRange::new);
+        }
+    }
+
+    // Forgets the learned range, e.g. after switching controllers.
+    pub fn reset_velocity_range(&mut self) {
+        if let Some(range) = self.velocity_range.as_mut() {
+            range.reset();
+        }
+    }
+
+    pub fn handle_midi(&mut self, raw_midi: jack::RawMidi) {
+        if let Some(log) = self.midi_log.as_mut() {
+            log.log(raw_midi.time, raw_midi.bytes);
+        }
+        let realtime = match raw_midi.bytes.first() {
+            Some(0xF8) => Some(Event::Clock),
+            Some(0xFA) => Some(Event::Start),
+            Some(0xFB) => Some(Event::Continue),
+            Some(0xFC) => Some(Event::Stop),
+            _ => None,
+        };
+        if let Some(event) = realtime {
+            return self.schedule(raw_midi.time as usize, event);
+        }
+
+        if let [status, pressure] = *raw_midi.bytes {
+            if status >> 4 == 0b1101 {
+                self.schedule(raw_midi.time as usize, Event::Aftertouch(status & 0x0F, pressure));
+            }
+            return;
+        }
+        if raw_midi.bytes.len() < 3 {
+            return;
+        }
+        let status = raw_midi.bytes[0];
+        let channel = status & 0x0F;
+        let pitch = raw_midi.bytes[1];
+        let velocity = raw_midi.bytes[2];
+        let start_time = raw_midi.time as usize;
+
+        let event = match status >> 4 {
+            0b1000 => Event::NoteOff(channel, pitch),
+            0b1001 => Event::NoteOn(channel, pitch, self.velocity_range.as_mut().map_or(velocity, |range| range.apply(velocity))),
+            0b1110 => {
+                let value = ((raw_midi.bytes[2] as i32) << 7 | raw_midi.bytes[1] as i32) - 8192;
+                Event::PitchBend(channel, value as f32 / 8192.0)
+            }
+            0b1011 => Event::Control(channel, raw_midi.bytes[1], raw_midi.bytes[2]),
+            _ => return,
+        };
+        self.schedule(start_time, event);
+    }
+
+    pub fn sequencer(&self) -> Option<&Sequencer> {
+        self.sequencer.as_ref()
+    }
+
+    pub fn set_sequencer(&mut self, sequencer: Option<Sequencer>) {
+        self.sequencer = sequencer;
+        self.sequencer_clock.reset();
+    }
+
+    pub fn arpeggiator(&self) -> Option<&Arpeggiator> {
+        self.arpeggiator.as_ref()
+    }
+
+    // While an arpeggiator is set, note on and off on its channel only change
+    // the keys it plays from.
+    pub fn set_arpeggiator(&mut self, arpeggiator: Option<Arpeggiator>) {
+        self.arpeggiator = arpeggiator;
+        self.arpeggiator_clock.reset();
+        self.arpeggiator_held.clear();
+    }
+
+    fn arpeggiates(&self, channel: u8) -> bool {
+        self.arpeggiator.is_some_and(|arpeggiator| arpeggiator.channel % CHANNELS as u8 == channel % CHANNELS as u8)
+    }
+
+    // Schedules the notes for any sequencer or arpeggiator step that starts on
+    // this frame. Both only run while the transport plays.
+    fn run_steps(&mut self, frame: usize) {
+        if !self.transport.is_playing() {
+            self.sequencer_clock.reset();
+            self.arpeggiator_clock.reset();
+            return;
+        }
+        let beat = self.transport.beat_position();
+        let samples_per_beat = self.transport.samples_per_beat(self.sample_rate);
+
+        if let Some(sequencer) = self.sequencer.as_ref() {
+            let step = self.sequencer_clock.tick(beat, sequencer.division).filter(|_| !sequencer.steps.is_empty());
+            if let Some(Some(note)) = step.map(|step| sequencer.steps[step.rem_euclid(sequencer.steps.len() as i64) as usize]) {
+                let length = (sequencer.gate.clamp(0.0, 1.0) as f64 * sequencer.division as f64 * samples_per_beat) as usize;
+                let (channel, humanize) = (sequencer.channel, sequencer.humanize);
+                self.trigger_step(frame, channel, note.pitch, note.velocity, length, humanize);
+            }
+        }
+
+        if let Some(arpeggiator) = self.arpeggiator {
+            let step = self.arpeggiator_clock.tick(beat, arpeggiator.division);
+            if let Some((pitch, velocity)) = step.and_then(|step| arpeggiator.note(&self.arpeggiator_held, step.max(0) as usize)) {
+                let length = (arpeggiator.gate.clamp(0.0, 1.0) as f64 * arpeggiator.division as f64 * samples_per_beat) as usize;
+                self.trigger_step(frame, arpeggiator.channel, pitch, velocity, length, arpeggiator.humanize);
+            }
+        }
+    }
+
+    fn trigger_step(&mut self, frame: usize, channel: u8, pitch: u8, velocity: u8, length: usize, humanize_amount: f32) {
+        let (delay, velocity) = humanize(&mut self.random, humanize_amount, velocity, self.sample_rate);
+        self.schedule(frame + delay, Event::StepOn(channel, pitch, velocity));
+        self.schedule(frame + delay + length.max(1), Event::StepOff(channel, pitch));
+    }
+
+    // Restarts every beat-synced LFO, on the master effects and in each
+    // channel's modulation matrix.
+    fn beat(&mut self) {
+        self.master.beat();
+        for channel in self.channels.iter_mut() {
+            channel.patch.modulation.retrigger_lfos(&mut channel.mod_lfos, LfoRetrigger::BeatSync);
+        }
+    }
+
+    // Commands from the control interface apply at the start of the next block.
+    pub fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::NoteOn(channel, pitch, velocity) => self.schedule(0, Event::NoteOn(channel, pitch, velocity)),
+            Command::NoteOff(channel, pitch) => self.schedule(0, Event::NoteOff(channel, pitch)),
+            Command::Set(channel, target, value) => self.set_param(channel, target, value),
+            Command::Panic => self.reset(),
+        }
+    }
+
+    // Events are kept sorted by frame, and events on the same frame stay in the
+    // order they arrived, so the last event for a pitch always wins.
+    fn schedule(&mut self, time: usize, event: Event) {
+        let index = self.events.partition_point(|&(t, _)| t <= time);
+        self.events.insert(index, (time, event));
+    }
+
+    fn dispatch_events(&mut self, frame: usize) {
+        while let Some(&(time, event)) = self.events.get(self.next_event) {
+            if time > frame {
+                break;
+            }
+            // A note-on with velocity 0 is a note-off, however it arrived.
+            let event = match event {
+                Event::NoteOn(channel, pitch, 0) => Event::NoteOff(channel, pitch),
+                event => event,
+            };
+            match event {
+                // The panic key must not reach the arpeggiator's held keys either.
+                Event::NoteOn(_, pitch, _) if self.panic_note == Some(pitch) => self.reset(),
+                Event::NoteOn(_, pitch, _) | Event::NoteOff(_, pitch) if !self.in_key_range(pitch) => {}
+                Event::NoteOn(channel, pitch, velocity) if self.arpeggiates(channel) => {
+                    self.arpeggiator_held.retain(|&(held, _)| held != pitch);
+                    self.arpeggiator_held.push((pitch, velocity));
+                }
+                Event::NoteOff(channel, pitch) if self.arpeggiates(channel) => self.arpeggiator_held.retain(|&(held, _)| held != pitch),
+                Event::NoteOn(channel, pitch, velocity) | Event::StepOn(channel, pitch, velocity) => self.note_on(channel, pitch, velocity, frame),
+                Event::NoteOff(channel, pitch) | Event::StepOff(channel, pitch) => self.note_off(channel, pitch),
+                Event::PitchBend(channel, bend) => self.pitch_bend(channel, bend),
+                Event::Control(channel, cc, value) => self.control_change(channel, cc, value),
+                Event::Aftertouch(channel, pressure) => self.channels[channel as usize % CHANNELS].aftertouch = pressure as f32 / 127.0,
+                Event::Clock => self.transport.clock_pulse(),
+                Event::Start => {
+                    self.transport.play();
+                    self.beat();
+                }
+                Event::Continue => self.transport.resume(),
+                Event::Stop => self.transport.stop(),
+            }
+            self.next_event += 1;
+        }
+    }
+
+    pub fn note_on(&mut self, channel: u8, pitch: u8, velocity: u8, start_time: usize) {
+        if velocity == 0 {
+            return self.note_off(channel, pitch);
+        }
+        if self.panic_note == Some(pitch) {
+            return self.reset();
+        }
+        if !self.in_key_range(pitch) {
+            return;
+        }
+        let fraction = self.channels[channel as usize % CHANNELS].velocity_prefix.take().unwrap_or(0);
+        if self.frequencies[pitch as usize] == 0.0 {
+            return;
+        }
+        let time = self.block_start + start_time;
+        let window = (self.debounce_time * self.sample_rate as f32) as usize;
+        let note_on_at = &mut self.channels[channel as usize % CHANNELS].note_on_at[pitch as usize];
+        if self.debounce_time > 0.0 && note_on_at.is_some_and(|last| time.saturating_sub(last) < window) {
+            return;
+        }
+        *note_on_at = Some(time);
+        let previous = self.channels[channel as usize % CHANNELS].last_pitch.replace(pitch);
+        let gap = {
+            let channel = &self.channels[channel as usize % CHANNELS];
+            let held = channel.notes.iter().any(|note| !note.is_released() && !note.sustained);
+            if held { 0.0 } else { self.clock.saturating_sub(channel.released_at) as f32 * self.time_step }
+        };
+        if self.channels[channel as usize % CHANNELS].patch.mono && self.mono_note_on(channel, pitch) {
+            return;
+        }
+        if self.repeat_note(channel, pitch, velocity, fraction) {
+            return;
+        }
+        // A patch over its own voice limit steals from itself. When the global
+        // pool is full the new note steals from the lowest voice_priority
+        // patch sounding, never from a higher one; if there is none it is
+        // dropped.
+        let patch = &self.channels[channel as usize % CHANNELS].patch;
+        let priority = patch.voice_priority;
+        if patch.max_voices.is_some_and(|limit| self.channels[channel as usize % CHANNELS].notes.len() >= limit.max(1)) {
+            self.steal_voice(Some(pitch), Some(channel as usize % CHANNELS), priority);
+        }
+        if self.voice_count() >= self.max_voices && !self.steal_voice(Some(pitch), None, priority) {
+            return;
+        }
+        self.master.note_on();
+        let voice = self.next_voice;
+        self.next_voice = (self.next_voice + 1) % self.max_voices;
+        let channel_index = channel as usize % CHANNELS;
+        let channel = &mut self.channels[channel_index];
+        channel.patch.modulation.retrigger_lfos(&mut channel.mod_lfos, LfoRetrigger::NoteOn);
+        // A random start phase decorrelates notes of a chord; it is drawn from
+        // the seeded generator, so renders stay repeatable.
+        let phase = if channel.patch.random_phase { channel.patch.phase + self.random.next_f32() } else { channel.patch.phase };
+        let mut note = Note::new(pitch, velocity, self.block_start + start_time, phase);
+        note.velocity_fraction = fraction;
+        note.voice = voice;
+        if channel.patch.pan_spread != 0.0 {
+            let position = match channel.patch.pan_spread_mode {
+                PanSpreadMode::Pitch => ((pitch as f32 - 63.5) / 63.5).clamp(-1.0, 1.0),
+                PanSpreadMode::Alternate => if voice.is_multiple_of(2) { -1.0 } else { 1.0 },
+                PanSpreadMode::Random => self.random.next_bipolar(),
+            };
+            note.pan = position * channel.patch.pan_spread.clamp(0.0, 1.0);
+        }
+        if channel.patch.drift_amount != 0.0 {
+            note.drift = self.random.next_bipolar();
+            note.drift_target = note.drift;
+        }
+        if channel.patch.unison_drifts() {
+            for index in 0..channel.patch.unison_voices.min(MAX_UNISON) {
+                note.unison_drifts[index] = self.random.next_bipolar();
+                note.unison_drift_targets[index] = note.unison_drifts[index];
+            }
+        }
+        if let Some(previous) = previous.filter(|&previous| channel.patch.glides_after(gap) && self.frequencies[previous as usize] > 0.0) {
+            let interval = 12.0 * (self.frequencies[previous as usize] / self.frequencies[pitch as usize]).log2();
+            note.start_glide(interval, &channel.patch, self.time_step);
+        }
+        note.id = self.next_voice_id;
+        self.next_voice_id += 1;
+        if let Some(events) = self.voice_events.as_mut() {
+            events.push(VoiceEventKind::NoteOn, channel_index, note.id, pitch, velocity);
+        }
+        channel.notes.push(note);
+    }
+
+    // A repeated pitch reuses its sounding voice. With env_retrigger the attack
+    // restarts; without it a held voice keeps its envelope stage and only takes
+    // the new velocity. A voice that is already releasing is always retriggered.
+    // retrigger_phase_reset and retrigger_envelope_reset make every repeat start
+    // from the patch phase and from silence, so rhythmic repeats are identical.
+    fn repeat_note(&mut self, channel: u8, pitch: u8, velocity: u8, fraction: u8) -> bool {
+        let channel_index = channel as usize % CHANNELS;
+        let channel = &mut self.channels[channel_index];
+        let Some(note) = channel.notes.iter_mut().find(|note| note.pitch == pitch && note.env_phase != EnvelopePhase::Off) else {
+            return false;
+        };
+
+        if channel.patch.retrigger_phase_reset {
+            note.reset_phase(channel.patch.phase);
+        }
+        if channel.patch.retrigger_envelope_reset {
+            note.restart_envelopes();
+        } else if channel.patch.env_retrigger || note.is_released() {
+            note.retrigger(&channel.patch);
+        }
+        note.velocity = velocity;
+        note.velocity_fraction = fraction;
+        note.sustained = false;
+        // The voice keeps its id; a retriggered release counts as a new note-on.
+        note.release_reported = false;
+        if let Some(events) = self.voice_events.as_mut() {
+            events.push(VoiceEventKind::NoteOn, channel_index, note.id, pitch, velocity);
+        }
+        true
+    }
+
+    // In mono mode held keys are stacked per channel. While the channel's voice
+    // is still held a new key only moves it to whichever key wins under the
+    // patch's note priority, gliding if glide is on, without retriggering.
+    // Returns false when there is no held voice and a fresh note should start.
+    fn mono_note_on(&mut self, channel: u8, pitch: u8) -> bool {
+        let channel = &mut self.channels[channel as usize % CHANNELS];
+        channel.held.retain(|&held| held != pitch);
+        channel.held.push(pitch);
+        let winner = channel.patch.note_priority.select(&channel.held).unwrap_or(pitch);
+        let Some(note) = channel.notes.iter_mut().find(|note| !note.is_released()) else {
+            return false;
+        };
+        note.sustained = false;
+        if note.pitch != winner {
+            glide_to(note, &channel.patch, &self.frequencies, winner, self.time_step);
+        }
+        true
+    }
+
+    pub fn note_off(&mut self, channel: u8, pitch: u8) {
+        if self.in_key_range(pitch) {
+            self.release_key(channel, pitch);
+        }
+    }
+
+    fn release_key(&mut self, channel: u8, pitch: u8) {
+        let channel = &mut self.channels[channel as usize % CHANNELS];
+        channel.released_at = self.clock;
+        if channel.patch.mono {
+            // Releasing the sounding key falls back to the next winner among the
+            // keys still down; the voice only releases once none are left.
+            channel.held.retain(|&held| held != pitch);
+            let winner = channel.patch.note_priority.select(&channel.held);
+            if let (Some(winner), Some(note)) = (winner, channel.notes.iter_mut().find(|note| note.pitch == pitch && !note.is_released())) {
+                glide_to(note, &channel.patch, &self.frequencies, winner, self.time_step);
+                return;
+            }
+        }
+        for note in channel.notes.iter_mut() {
+            if note.pitch == pitch && !note.is_released() {
+                if channel.sustain_pedal {
+                    note.sustained = true;
+                } else {
+                    note.release(&channel.patch);
+                }
+            }
+        }
+    }
+
+    // Channel mode messages (120 and up) and the CC88 high-resolution velocity
+    // prefix are handled directly and can't be learned or rebound. A CC88 value
+    // only applies to the next note-on on its channel. CC74 is brightness unless
+    // it has been bound to something else.
+    pub fn control_change(&mut self, channel: u8, cc: u8, value: u8) {
+        match cc {
+            88 => {
+                self.channels[channel as usize % CHANNELS].velocity_prefix = Some(value & 0x7F);
+                return;
+            }
+            74 if self.mpe.is_some_and(|mpe| mpe.is_member(channel as usize % CHANNELS)) => {
+                self.channels[channel as usize % CHANNELS].slide = value as f32 / 127.0;
+                return;
+            }
+            120 => return self.all_sound_off(channel),
+            121 => return self.reset_controllers(channel),
+            _ => (),
+        }
+
+        if let Some(target) = self.learning.take() {
+            self.bind_cc(cc, target);
+        }
+
+        if let Some(&index) = self.macro_cc_map.get(&cc) {
+            self.set_macro(channel, index, value as f32 / 127.0);
+        } else if let Some(&target) = self.cc_map.get(&cc) {
+            self.set_param(channel, target, value as f32 / 127.0);
+        } else if cc == 74 {
+            self.channels[channel as usize % CHANNELS].slide = value as f32 / 127.0;
+        }
+    }
+
+    // Per the MIDI spec this resets pitch bend, mod wheel, expression and the
+    // sustain pedal, but leaves channel volume alone and doesn't touch sounding
+    // notes beyond releasing the ones held only by the pedal.
+    pub fn reset_controllers(&mut self, channel: u8) {
+        self.channels[channel as usize % CHANNELS].aftertouch = 0.0;
+        self.pitch_bend(channel, 0.0);
+        self.set_param(channel, ParamTarget::ModWheel, 0.0);
+        self.set_param(channel, ParamTarget::Expression, 1.0);
+        self.set_param(channel, ParamTarget::SustainPedal, 0.0);
+    }
+
+    pub fn mod_wheel(&self, channel: u8) -> f32 {
+        self.channels[channel as usize % CHANNELS].mod_wheel
+    }
+
+    pub fn pitch_bend_target(&self, channel: u8) -> f32 {
+        self.channels[channel as usize % CHANNELS].bend_target
+    }
+
+    // `value` is normalized 0..1 and mapped onto the target's range as
+    // described by ParamTarget::info.
+    pub fn set_param(&mut self, channel: u8, target: ParamTarget, value: f32) {
+        let value = target.info().denormalize(value);
+        let channel_number = channel;
+        let channel = &mut self.channels[channel as usize % CHANNELS];
+        let patch = &mut channel.patch;
+        match target {
+            ParamTarget::Volume => channel.volume = value,
+            ParamTarget::Expression => channel.expression = value,
+            ParamTarget::Mute => self.muted = value >= 0.5,
+            ParamTarget::Freeze => self.frozen = value >= 0.5,
+            ParamTarget::MidiLog => self.set_midi_logging(value >= 0.5),
+            ParamTarget::ModWheel => channel.mod_wheel = value,
+            ParamTarget::CoarseTune => self.set_tuning(value as i32, self.fine_tune),
+            ParamTarget::FineTune => self.set_tuning(self.coarse_tune, value),
+            ParamTarget::SustainPedal => {
+                channel.sustain_pedal = value >= 0.5;
+                if !channel.sustain_pedal {
+                    for note in channel.notes.iter_mut() {
+                        if note.sustained {
+                            note.sustained = false;
+                            note.release(patch);
+                        }
+                    }
+                }
+            }
+            _ => {
+                if patch.set_param(target, value) {
+                    self.sync_mpe_patches(channel_number);
+                }
+            }
+        }
+    }
+
+    // The current value of `target` on `channel`, normalized 0..1.
+    pub fn get_param(&self, channel: u8, target: ParamTarget) -> f32 {
+        let channel = &self.channels[channel as usize % CHANNELS];
+        let patch = &channel.patch;
+        let value = match target {
+            ParamTarget::Volume => channel.volume,
+            ParamTarget::Expression => channel.expression,
+            ParamTarget::Mute => if self.muted { 1.0 } else { 0.0 },
+            ParamTarget::Freeze => if self.frozen { 1.0 } else { 0.0 },
+            ParamTarget::MidiLog => if self.midi_log.is_some() { 1.0 } else { 0.0 },
+            ParamTarget::ModWheel => channel.mod_wheel,
+            ParamTarget::CoarseTune => self.coarse_tune as f32,
+            ParamTarget::FineTune => self.fine_tune,
+            ParamTarget::SustainPedal => if channel.sustain_pedal { 1.0 } else { 0.0 },
+            _ => patch.param(target).unwrap_or(0.0),
+        };
+        target.info().normalize(value)
+    }
+
+    // Unknown ids are ignored.
+    pub fn set_param_by_id(&mut self, channel: u8, id: usize, value: f32) {
+        if let Some(target) = ParamTarget::from_id(id) {
+            self.set_param(channel, target, value);
+        }
+    }
+
+    pub fn get_param_by_id(&self, channel: u8, id: usize) -> Option<f32> {
+        ParamTarget::from_id(id).map(|target| self.get_param(channel, target))
+    }
+
+    // `bend` runs -1..1 and is scaled by the patch's bend range.
+    pub fn pitch_bend(&mut self, channel: u8, bend: f32) {
+        self.channels[channel as usize % CHANNELS].bend_target = bend.clamp(-1.0, 1.0);
+    }
+
+    // `pitch` is the incoming note, if any; SamePitch prefers a voice already
+    // playing it. Only voices on `channel`, if given, and on patches of at
+    // most `priority` are candidates, lowest priority first. Returns whether
+    // a voice was freed.
+    fn steal_voice(&mut self, pitch: Option<u8>, channel: Option<usize>, priority: u8) -> bool {
+        let mut victim: Option<(usize, usize, u8, f32)> = None;
+        for (c, channel) in self.channels.iter().enumerate().filter(|&(c, _)| channel.is_none_or(|only| only == c)) {
+            let rank = channel.patch.voice_priority;
+            if rank > priority {
+                continue;
+            }
+            for (n, note) in channel.notes.iter().enumerate() {
+                let score = match self.steal_mode {
+                    VoiceStealMode::Quietest => note.amplitude(&channel.patch.envelope),
+                    VoiceStealMode::Oldest => -(note.time as f32),
+                    VoiceStealMode::RoundRobin => ((note.voice % self.max_voices + self.max_voices - self.next_voice) % self.max_voices) as f32,
+                    VoiceStealMode::SamePitch => {
+                        let amplitude = note.amplitude(&channel.patch.envelope);
+                        if Some(note.pitch) == pitch { amplitude - 2.0 } else { amplitude }
+                    }
+                };
+                if victim.is_none_or(|(_, _, r, s)| (rank, score) < (r, s)) {
+                    victim = Some((c, n, rank, score));
+                }
+            }
+        }
+
+        let Some((c, n, _, _)) = victim else {
+            return false;
+        };
+        let note = self.channels[c].notes.swap_remove(n);
+        report_freed(&mut self.voice_events, c, &note);
+        true
+    }
+
+    pub fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let frames = left.len().min(right.len());
+        // Only a block longer than prepare() was told about gets here.
+        if frames > self.prepared_frames {
+            self.prepare(frames);
+        }
+        self.block_frames = frames;
+        self.block_start = self.clock;
+
+        let mut peak: f32 = 0.0;
+        let mut non_finite = 0;
+        for frame in 0..frames {
+            self.run_steps(frame);
+            let (l, r) = self.get_audio_data(frame);
+            let (l, r) = self.master.process(l, r, self.send, self.transport.tempo(), self.time_step);
+            let (l, r) = (guard_output(l, &mut non_finite), guard_output(r, &mut non_finite));
+            let gain = self.fade_gain * self.mute_gain * self.start_gain;
+            left[frame] = l * gain;
+            right[frame] = r * gain;
+            peak = peak.max(l.abs()).max(r.abs());
+
+            for (buffer, value) in self.stem_buffers.iter_mut().zip(self.stem_values.iter()) {
+                buffer[frame] = guard_output(*value, &mut non_finite) * gain;
+            }
+            for (buffer, value) in self.speaker_buffers.iter_mut().zip(self.speaker_values.iter()) {
+                buffer[frame] = guard_output(*value, &mut non_finite) * gain;
+            }
+            self.update_fade();
+            self.update_mute();
+            if self.start_gain < 1.0 {
+                self.start_gain = (self.start_gain + self.time_step / self.start_time).min(1.0);
+            }
+            let beat = self.transport.beat_position().floor();
+            self.transport.advance(self.time_step);
+            if self.transport.is_playing() && self.transport.beat_position().floor() > beat {
+                self.beat();
+            }
+        }
+
+        self.block_start = self.clock;
+        self.overload.update(peak, frames);
+        self.non_finite.add(non_finite);
+        self.events.drain(..self.next_event);
+        for (time, _) in self.events.iter_mut() {
+            *time -= frames;
+        }
+        self.next_event = 0;
+
+        if let Some(monitor) = self.voice_monitor.as_ref() {
+            monitor.publish(self.voice_states());
+        }
+        if let Some(events) = self.voice_events.as_mut() {
+            events.publish();
+        }
+    }
+
+    // Returns the left and right mix for one frame. Stems carry the mono sum
+    // of each channel before per-note panning.
+    // Renders the stereo mix and folds it to mono as (left + right) / 2, so a
+    // centered signal comes out at the same level as on either stereo side.
+    pub fn process_mono(&mut self, out: &mut [f32]) {
+        let mut right = std::mem::take(&mut self.mono_buffer);
+        right.resize(out.len(), 0.0);
+        self.process_block(out, &mut right);
+        for (value, right) in out.iter_mut().zip(right.iter()) {
+            *value = 0.5 * (*value + *right);
+        }
+        self.mono_buffer = right;
+    }
+
+    // Plays one note on channel 0 with its current patch, starting at frame 0
+    // and held until the last of `frames`, and returns the mono render. The
+    // note goes through the whole engine, so anything else sounding is mixed
+    // in; it is meant for tests and previews on a synthesizer that isn't
+    // running.
+    pub fn render_note(&mut self, pitch: u8, velocity: u8, frames: usize) -> Vec<f32> {
+        self.render_note_released(pitch, velocity, frames, frames)
+    }
+
+    // As render_note, with the note released at frame `release` so the
+    // render can run on into the release stage. A release at or past the end
+    // comes on the last frame, so the note never outlives its render.
+    pub fn render_note_released(&mut self, pitch: u8, velocity: u8, frames: usize, release: usize) -> Vec<f32> {
+        if frames == 0 {
+            return Vec::new();
+        }
+        let pitch = pitch.min(127);
+        self.note_on(0, pitch, velocity, 0);
+        self.schedule(release.min(frames - 1), Event::NoteOff(0, pitch));
+        let mut out = vec![0.0; frames];
+        self.process_mono(&mut out);
+        out
+    }
+
+    pub fn get_audio_data(&mut self, frame: usize) -> (f32, f32) {
+        self.dispatch_events(frame);
+        self.stem_values.fill(0.0);
+        self.speaker_values.fill(0.0);
+
+        let (mut left, mut right) = (0.0, 0.0);
+        let (mut send_left, mut send_right, mut sending) = (0.0, 0.0, false);
+        let ducking_target = (self.voice_count().max(1) as f32).powf(-0.5 * self.voice_ducking);
+        self.ducking_gain += (ducking_target - self.ducking_gain) * self.ducking_coefficient;
+        // The master channel's bend moves every note in an MPE zone.
+        let zone_bend = self.mpe.map_or(0.0, |mpe| {
+            let master = &self.channels[mpe.master_channel()];
+            master.bend * master.patch.bend_range
+        });
+        for (c, channel) in self.channels.iter_mut().enumerate() {
+            let mut channel_value = 0.0;
+            let (mut channel_left, mut channel_right) = (0.0, 0.0);
+            self.channel_speakers.fill(0.0);
+            channel.bend += (channel.bend_target - channel.bend) * self.bend_coefficient;
+            let member = self.mpe.filter(|mpe| mpe.is_member(c));
+            let expressive_patch;
+            let patch = match member {
+                // Pressure and slide move an MPE note's cutoff; with one note
+                // per member channel that is per note.
+                Some(mpe) if mpe.pressure_cutoff != 0.0 || mpe.slide_cutoff != 0.0 => {
+                    let info = ParamTarget::Cutoff.info();
+                    let mut modulated = channel.patch;
+                    let offset = mpe.pressure_cutoff * channel.aftertouch + mpe.slide_cutoff * channel.slide;
+                    modulated.filter.cutoff = info.denormalize(info.normalize(modulated.filter.cutoff) + offset);
+                    expressive_patch = modulated;
+                    &expressive_patch
+                }
+                // Outside MPE, CC74 brightness opens the whole channel's cutoff.
+                None if self.brightness_cutoff != 0.0 && channel.slide != 0.0 => {
+                    let info = ParamTarget::Cutoff.info();
+                    let mut modulated = channel.patch;
+                    modulated.filter.cutoff = info.denormalize(info.normalize(modulated.filter.cutoff) + self.brightness_cutoff * channel.slide);
+                    expressive_patch = modulated;
+                    &expressive_patch
+                }
+                _ => &channel.patch,
+            };
+            let bend_semitones = match member {
+                Some(mpe) => channel.bend * mpe.bend_range + zone_bend,
+                None => channel.bend * patch.bend_range,
+            };
+            let bend = 2.0_f32.powf(bend_semitones / 12.0);
+            let filter = channel.filter_coefficients.get((patch.filter, self.sample_rate as f32), filter_coefficients);
+            let drift_coefficient = channel.drift_coefficient.get((patch.drift_rate, self.time_step), |&(rate, time_step)| {
+                smoothing_coefficient(1.0 / (2.0 * std::f32::consts::PI * rate), time_step)
+            });
+            let routed = !patch.modulation.is_empty();
+            let param_routes = patch.modulation.has_param_routes();
+            let sends = patch.modulation.has_send_routes();
+            sending |= sends;
+            let (mut channel_send_left, mut channel_send_right) = (0.0, 0.0);
+            let lfos = patch.modulation.next_lfos(&mut channel.mod_lfos, self.transport.tempo(), self.time_step);
+            let voice_lfos = patch.modulation.has_voice_lfos();
+            for note in channel.notes.iter_mut() {
+                note.start_if_due(self.clock);
+                // Notes with routes to parameters each get their own modulated
+                // copy of the patch and filter.
+                let sources = if routed {
+                    // A note waiting on its start time keeps its own LFOs at
+                    // phase 0 until it sounds.
+                    let lfos = if voice_lfos && !matches!(note.env_phase, EnvelopePhase::Stage(_)) {
+                        patch.modulation.next_voice_lfos(&mut note.mod_lfos, lfos, self.transport.tempo(), self.time_step)
+                    } else {
+                        lfos
+                    };
+                    ModSources {
+                        envelopes: note.envelope_levels(patch),
+                        lfos,
+                        velocity: note.fractional_velocity(),
+                        mod_wheel: channel.mod_wheel,
+                        aftertouch: channel.aftertouch,
+                    }
+                } else {
+                    ModSources::default()
+                };
+                let (note_patch, note_filter);
+                let (patch, filter) = if param_routes {
+                    let mut modulated = *patch;
+                    patch.modulation.modulate(&mut modulated, &sources);
+                    note_patch = modulated;
+                    note_filter = note.filter_coefficients.get((note_patch.filter, self.sample_rate as f32), filter_coefficients);
+                    (&note_patch, &note_filter)
+                } else {
+                    (patch, &filter)
+                };
+                let per_voice = patch.filter.placement == FilterPlacement::PerVoice;
+                // The frequency is built in a fixed order: the table entry for
+                // the note, then the glide still to travel, then the channel's
+                // pitch bend, then drift, then the coarse and fine tuning. Glide
+                // and bend are independent ratios, so a bend moves a gliding note
+                // by exactly the bend from wherever the glide has reached, and
+                // the glide's own progress ignores the bend.
+                let mut frequency = self.frequencies[note.pitch as usize];
+                if patch.snap_scale == Scale::Chromatic {
+                    frequency *= note.glide_ratio(patch.glide_curve);
+                    frequency *= bend;
+                } else {
+                    // Glides and bends land on the patch's scale, so they step
+                    // through its notes instead of sweeping.
+                    let offset = 12.0 * (note.glide_ratio(patch.glide_curve) * bend).log2();
+                    let target = patch.snap_scale.snap(note.pitch as f32 + offset, patch.snap_root);
+                    frequency = match self.frequencies.get(target as usize).filter(|_| target >= 0.0) {
+                        Some(&snapped) if snapped > 0.0 => snapped,
+                        _ => frequency * 2.0_f32.powf((target - note.pitch as f32) / 12.0),
+                    };
+                }
+                if patch.drift_amount != 0.0 {
+                    if patch.drift_rate > 0.0 {
+                        if self.random.next_f32() < patch.drift_rate * self.time_step {
+                            note.drift_target = self.random.next_bipolar();
+                        }
+                        note.drift += (note.drift_target - note.drift) * drift_coefficient;
+                        if patch.unison_drifts() {
+                            for index in 0..patch.unison_voices.min(MAX_UNISON) {
+                                if self.random.next_f32() < patch.drift_rate * self.time_step {
+                                    note.unison_drift_targets[index] = self.random.next_bipolar();
+                                }
+                                note.unison_drifts[index] += (note.unison_drift_targets[index] - note.unison_drifts[index]) * drift_coefficient;
+                            }
+                        }
+                    }
+                    frequency *= 2.0_f32.powf(patch.drift_amount * note.drift / 1200.0);
+                }
+                frequency *= self.tuning;
+                let increment = frequency * self.time_step;
+                let phase = note.phase;
+                let amplitude = note.amplitude(&patch.envelope);
+                let velocity = note.fractional_velocity();
+                let level = patch.velocity_gain(velocity) * patch.key_level(note.pitch);
+                let modulation = if routed { patch.modulation.amplitude(&sources) } else { 1.0 };
+                let gain = MAX_AMPLITUDE * amplitude * modulation * level * self.ducking_gain;
+                let (l, r) = pan_gains(note.pan);
+                let (before_value, before_left, before_right) = (channel_value, channel_left, channel_right);
+                let tilt = (patch.velocity_brightness != 0.0).then(|| {
+                    let key = (patch.velocity_tilt(velocity), self.sample_rate as f32);
+                    note.tilt_coefficients.get(key, |&(tilt, sample_rate)| Tilt::new(tilt, sample_rate))
+                });
+                let damping = patch.hf_damping(note.time as f32 * self.time_step, self.sample_rate as f32);
+                let stereo_detune = patch.stereo_detune();
+                if patch.waveform == Waveform::Sample {
+                    let value = self.sample.as_ref().and_then(|sample| {
+                        let root = self.frequencies[sample.root as usize % 128];
+                        let value = sample.read(note.sample_position).filter(|_| root > 0.0)?;
+                        if !matches!(note.env_phase, EnvelopePhase::Stage(_)) {
+                            note.sample_position += (frequency / root) as f64 * sample.sample_rate as f64 / self.sample_rate as f64;
+                        }
+                        Some(value)
+                    });
+                    match value {
+                        Some(value) => {
+                            let value = note.apply_tilt(0, tilt.as_ref(), patch.saturate(value));
+                            let value = note.apply_damping(0, damping, value);
+                            let y = gain * if per_voice { note.filter.process(filter, value) } else { value };
+                            channel_value += y;
+                            channel_left += y * l;
+                            channel_right += y * r;
+                        }
+                        // A one-shot sample that has run out ends its note.
+                        None => note.env_phase = EnvelopePhase::Off,
+                    }
+                } else if patch.unison_voices > 1 {
+                    let (unison_left, unison_right) = unison(&self.wavetable, patch, note, increment, amplitude, velocity);
+                    let unison_left = note.apply_tilt(0, tilt.as_ref(), patch.saturate(unison_left));
+                    let unison_left = note.apply_damping(0, damping, unison_left);
+                    let unison_right = if patch.unison_stereo_spread == 0.0 {
+                        unison_left
+                    } else {
+                        let unison_right = note.apply_tilt(1, tilt.as_ref(), patch.saturate(unison_right));
+                        note.apply_damping(1, damping, unison_right)
+                    };
+                    let (y_left, y_right) = if per_voice {
+                        let y_left = gain * note.filter.process(filter, unison_left);
+                        // The right side only needs its own filter once the stack is spread.
+                        let y_right = if patch.unison_stereo_spread == 0.0 { y_left } else { gain * note.filter_right.process(filter, unison_right) };
+                        (y_left, y_right)
+                    } else {
+                        (gain * unison_left, gain * unison_right)
+                    };
+                    channel_value += 0.5 * (y_left + y_right);
+                    channel_left += y_left * l;
+                    channel_right += y_right * r;
+                } else if let Some(ratio) = stereo_detune {
+                    // Each side gets its own detuned copy, and both are raised by
+                    // sqrt(2) so that, once they drift apart, the mono fold-down
+                    // keeps the level of the single oscillator.
+                    let left_sample = oscillator(&self.wavetable, patch, phase, increment / ratio, amplitude, velocity);
+                    let left_sample = note.apply_tilt(0, tilt.as_ref(), patch.saturate(left_sample));
+                    let left_sample = note.apply_damping(0, damping, left_sample);
+                    let right_sample = oscillator(&self.wavetable, patch, note.stereo_phase, increment * ratio, amplitude, velocity);
+                    let right_sample = note.apply_tilt(1, tilt.as_ref(), patch.saturate(right_sample));
+                    let right_sample = note.apply_damping(1, damping, right_sample);
+                    let gain = gain * std::f32::consts::SQRT_2;
+                    let (y_left, y_right) = if per_voice {
+                        (gain * note.filter.process(filter, left_sample), gain * note.filter_right.process(filter, right_sample))
+                    } else {
+                        (gain * left_sample, gain * right_sample)
+                    };
+                    channel_value += 0.5 * (y_left + y_right);
+                    channel_left += y_left * l;
+                    channel_right += y_right * r;
+                    note.advance_stereo_phase(increment * ratio);
+                } else {
+                    let sample = oscillator(&self.wavetable, patch, phase, increment, amplitude, velocity);
+                    let sample = note.apply_tilt(0, tilt.as_ref(), patch.saturate(sample));
+                    let sample = note.apply_damping(0, damping, sample);
+                    let y = gain * if per_voice { note.filter.process(filter, sample) } else { sample };
+                    channel_value += y;
+                    channel_left += y * l;
+                    channel_right += y * r;
+                }
+
+                if !self.channel_speakers.is_empty() {
+                    speaker_gains(note.pan, &mut self.speaker_gains);
+                    let y = channel_value - before_value;
+                    for (speaker, gain) in self.channel_speakers.iter_mut().zip(self.speaker_gains.iter()) {
+                        *speaker += y * gain;
+                    }
+                }
+                if sends {
+                    let send = patch.modulation.send(&sources);
+                    channel_send_left += (channel_left - before_left) * send;
+                    channel_send_right += (channel_right - before_right) * send;
+                }
+
+                note.advance_phase(increment / stereo_detune.unwrap_or(1.0));
+                note.advance_glide();
+                note.increment_time(self.clock, patch, self.frozen);
+                note.retire_below(&patch.envelope, self.silence_threshold);
+                if self.max_note_duration.is_some_and(|limit| note.time >= limit) && !note.is_released() {
+                    note.sustained = false;
+                    note.release(patch);
+                }
+                if note.is_released() && !note.release_reported {
+                    note.release_reported = true;
+                    if let Some(events) = self.voice_events.as_mut() {
+                        events.push(VoiceEventKind::NoteOff, c, note.id, note.pitch, note.velocity);
+                    }
+                }
+            }
+            if patch.filter.placement == FilterPlacement::Master {
+                channel_value = channel.filters[0].process(&filter, channel_value);
+                channel_left = channel.filters[1].process(&filter, channel_left);
+                channel_right = channel.filters[2].process(&filter, channel_right);
+            }
+            if patch.formant.mix != 0.0 {
+                let formant = &patch.formant;
+                let coefficients = channel.formant_coefficients.get((*formant, self.sample_rate as f32), |(formant, sample_rate)| formant.coefficients(*sample_rate));
+                channel_value = channel.formants[0].process(formant, &coefficients, channel_value);
+                channel_left = channel.formants[1].process(formant, &coefficients, channel_left);
+                channel_right = channel.formants[2].process(formant, &coefficients, channel_right);
+            }
+            let mut level = channel.patch.output_gain() * channel.volume * channel.expression;
+            if let Some(mpe) = member {
+                level *= mpe.pressure_gain(channel.aftertouch);
+            }
+            let channel_value = channel_value * level;
+            left += channel_left * level;
+            right += channel_right * level;
+            // Per-note sends are tapped ahead of the channel's shared filter
+            // and formant bank; other channels send everything.
+            if sends {
+                send_left += channel_send_left * level;
+                send_right += channel_send_right * level;
+            } else {
+                send_left += channel_left * level;
+                send_right += channel_right * level;
+            }
+            if !self.stem_values.is_empty() {
+                let stems = self.stem_values.len();
+                self.stem_values[channel.bus % stems] += channel_value;
+            }
+            for (value, speaker) in self.speaker_values.iter_mut().zip(self.channel_speakers.iter()) {
+                *value += speaker * level;
+            }
+        }
+
+        // Finished notes are dropped every frame rather than once per block,
+        // so voice counts and stealing never depend on the block size.
+        self.notes_gc();
+
+        if let Some(tone) = self.test_tone.as_mut() {
+            let value = tone.next(self.time_step);
+            left += value;
+            right += value;
+            send_left += value;
+            send_right += value;
+        }
+        self.send = sending.then_some((send_left, send_right));
+        self.clock += 1;
+        (left, right)
+    }
+
+    // Keeps the remaining notes in order so the mix is summed the same way
+    // whatever the block size.
+    pub fn notes_gc(&mut self) {
+        for (c, channel) in self.channels.iter_mut().enumerate() {
+            if self.voice_events.is_some() {
+                for note in channel.notes.iter().filter(|note| note.env_phase == EnvelopePhase::Off) {
+                    report_freed(&mut self.voice_events, c, note);
+                }
+            }
+            channel.notes.retain(|note| note.env_phase != EnvelopePhase::Off);
+        }
+    }
+}
+
+// A voice leaving the engine: its note-off first if that hasn't been
+// reported yet, then that it's free.
+fn report_freed(voice_events: &mut Option<VoiceEvents>, channel: usize, note: &Note) {
+    if let Some(events) = voice_events.as_mut() {
+        if !note.release_reported {
+            events.push(VoiceEventKind::NoteOff, channel, note.id, note.pitch, note.velocity);
+        }
+        events.push(VoiceEventKind::Freed, channel, note.id, note.pitch, note.velocity);
+    }
+}
+
+fn default_cc_map() -> HashMap<u8, ParamTarget> {
+    let mut cc_map = HashMap::new();
+    cc_map.insert(1, ParamTarget::ModWheel);
+    cc_map.insert(7, ParamTarget::Volume);
+    cc_map.insert(11, ParamTarget::Expression);
+    cc_map.insert(64, ParamTarget::SustainPedal);
+    cc_map
+}
+
+// Moves a sounding note to `pitch`, gliding from wherever it currently is
+// when the patch has glide on.
+fn glide_to(note: &mut Note, patch: &Patch, frequencies: &[f32; 128], pitch: u8, time_step: f32) {
+    if patch.glides_after(0.0) && frequencies[note.pitch as usize] > 0.0 {
+        // Under GlideCurve::Hz the pitch reached so far isn't `glide`, so start
+        // from where the note actually sounds.
+        let current = 12.0 * note.glide_ratio(patch.glide_curve).log2();
+        let interval = current + 12.0 * (frequencies[note.pitch as usize] / frequencies[pitch as usize]).log2();
+        note.start_glide(interval, patch, time_step);
+    } else {
+        note.glide = 0.0;
+    }
+    note.pitch = pitch;
+}
+
+// Zeroes every entry that isn't a positive, finite frequency and returns
+// their pitches.
+fn sanitize_frequencies(frequencies: &mut [f32; 128]) -> Vec<u8> {
+    let mut rejected = Vec::new();
+    for (pitch, frequency) in frequencies.iter_mut().enumerate() {
+        if !(frequency.is_finite() && *frequency > 0.0) {
+            *frequency = 0.0;
+            rejected.push(pitch as u8);
+        }
+    }
+    rejected
+}
+
+fn smoothing_coefficient(seconds: f32, time_step: f32) -> f32 {
+    if seconds <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-time_step / seconds).exp()
+    }
+}
+
+fn oscillator(wavetable: &Wavetable, patch: &Patch, phase: f32, increment: f32, envelope: f32, velocity: f32) -> f32 {
+    let position = patch.wavetable_position + patch.wavetable_envelope * envelope + patch.wavetable_velocity * velocity;
+    let distortion = patch.pd_amount + patch.pd_envelope * envelope;
+
+    match patch.velocity_layers {
+        Some(layers) => {
+            let (soft, hard) = layers.gains(velocity);
+            let mut value = 0.0;
+            if soft != 0.0 {
+                value += soft * waveform(wavetable, layers.soft, phase, increment, position, distortion);
+            }
+            if hard != 0.0 {
+                let phase = (phase + layers.phase_offset).rem_euclid(1.0);
+                value += hard * waveform(wavetable, layers.hard, phase, increment, position, distortion);
+            }
+            value
+        }
+        None => waveform(wavetable, patch.waveform, phase, increment, position, distortion),
+    }
+}
+
+fn filter_coefficients(&(filter, sample_rate): &(Filter, f32)) -> FilterCoefficients {
+    FilterCoefficients::new(&filter, filter.cutoff, sample_rate)
+}
+
+// Sums the detuned unison stack for one frame into left and right and
+// advances each voice's phase. The stack is scaled by 1/sqrt(voices) to keep
+// its loudness close to a single voice. Below a drift correlation of 1 each
+// voice's drift moves from the note's shared drift toward its own.
+fn unison(wavetable: &Wavetable, patch: &Patch, note: &mut Note, increment: f32, envelope: f32, velocity: f32) -> (f32, f32) {
+    let voices = patch.unison_voices.clamp(1, MAX_UNISON);
+    let (mut left, mut right) = (0.0, 0.0);
+    for index in 0..voices {
+        let offset = patch.unison_offset(index);
+        let mut cents = offset * patch.unison_detune;
+        if patch.unison_drifts() {
+            cents += patch.drift_amount * (1.0 - patch.drift_correlation.max(0.0)) * (note.unison_drifts[index] - note.drift);
+        }
+        let increment = increment * 2.0_f32.powf(cents / 1200.0);
+        let sample = oscillator(wavetable, patch, note.unison_phases[index], increment, envelope, velocity);
+        let (l, r) = pan_gains(offset * patch.unison_stereo_spread.clamp(0.0, 1.0));
+        left += sample * l;
+        right += sample * r;
+        note.advance_unison_phase(index, increment);
+    }
+    let scale = 1.0 / (voices as f32).sqrt();
+    (left * scale, right * scale)
+}
+
+fn waveform(wavetable: &Wavetable, waveform: Waveform, phase: f32, increment: f32, position: f32, distortion: f32) -> f32 {
+    match waveform {
+        Waveform::Sine => (phase * 2.0 * std::f32::consts::PI).sin(),
+        Waveform::Saw => 2.0 * phase - 1.0,
+        Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+        Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        Waveform::Wavetable => wavetable.sample(position, phase, increment),
+        // Samples are read per note in get_audio_data.
+        Waveform::Sample => 0.0,
+        Waveform::PhaseDistortion => (phase_distortion(phase, distortion) * 2.0 * std::f32::consts::PI).sin(),
+    }
+}
+
+// Casio CZ style phase warp: the first half of the sine cycle is squeezed
+// into a shorter stretch of the phase as `amount` rises, turning the sine
+// into a brighter, saw-like shape. Amount 0 leaves the phase untouched.
+fn phase_distortion(phase: f32, amount: f32) -> f32 {
+    let knee = 0.5 * (1.0 - amount.clamp(0.0, MAX_PHASE_DISTORTION));
+    if phase < knee {
+        0.5 * phase / knee
+    } else {
+        0.5 + 0.5 * (phase - knee) / (1.0 - knee)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formant::Vowel;
+    use crate::lfo::{LfoMode, LfoRate, LfoRetrigger, LfoWaveform};
+    use crate::mod_matrix::{ModDestination, ModLfo, ModRoute, ModSource};
+    use crate::monitor::VoiceEvent;
+    use crate::overload::MAX_OUTPUT;
+    use crate::params::PARAM_TARGETS;
+    use crate::patch::{Crossfade, Envelope, NotePriority, VelocityLayers, BEND_RANGE};
+    use crate::sequencer::Step;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    fn synthesizer() -> Synthesizer {
+        Synthesizer::new(SAMPLE_RATE, std::array::from_fn(|pitch| 440.0 * 2.0_f32.powf((pitch as f32 - 69.0) / 12.0)))
+    }
+
+    fn render(synthesizer: &mut Synthesizer, frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; frames];
+        synthesizer.process_mono(&mut out);
+        out
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, sample| peak.max(sample.abs()))
+    }
+
+    fn voice_states(synthesizer: &Synthesizer) -> Vec<VoiceState> {
+        let mut voices = Vec::new();
+        synthesizer.voices(&mut voices);
+        voices
+    }
+
+    #[test]
+    fn a_cc_shortening_the_attack_mid_note_ends_the_attack() {
+        let mut synthesizer = synthesizer();
+        synthesizer.bind_cc(20, ParamTarget::Attack);
+        synthesizer.note_on(0, 69, 127, 0);
+        render(&mut synthesizer, 1000);
+        synthesizer.control_change(0, 20, 0);
+        let out = render(&mut synthesizer, 4000);
+        assert!(peak(&out) < MAX_OUTPUT);
+        let voice = voice_states(&synthesizer)[0];
+        assert!(matches!(voice.stage, EnvelopePhase::Decay(_)), "{:?}", voice.stage);
+        assert!((0.0..=1.0).contains(&voice.amplitude));
+    }
+
+    #[test]
+    fn a_cc_shortening_the_decay_mid_note_reaches_sustain() {
+        let mut synthesizer = synthesizer();
+        synthesizer.bind_cc(21, ParamTarget::Decay);
+        synthesizer.note_on(0, 69, 127, 0);
+        render(&mut synthesizer, 5000);
+        synthesizer.control_change(0, 21, 0);
+        let out = render(&mut synthesizer, 1000);
+        assert!(peak(&out) < MAX_OUTPUT);
+        let voice = voice_states(&synthesizer)[0];
+        assert!(matches!(voice.stage, EnvelopePhase::Sustain(_)), "{:?}", voice.stage);
+        assert!((0.0..=1.0).contains(&voice.amplitude));
+    }
+
+    #[test]
+    fn envelope_times_cannot_be_routed_per_note() {
+        let mut patch = Patch::default();
+        for target in [ParamTarget::Attack, ParamTarget::Decay] {
+            let route = ModRoute { source: ModSource::ModWheel, destination: ModDestination::Param(target), amount: -1.0 };
+            assert!(!patch.modulation.set_route(0, Some(route)));
+        }
+    }
+
+    fn sustained_peak(synthesizer: &mut Synthesizer, frames: usize) -> f32 {
+        let out = render(synthesizer, frames);
+        peak(&out[frames / 2..])
+    }
+
+    fn sine_synthesizer(floor: f32) -> Synthesizer {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, velocity_floor: floor, ..Patch::default() });
+        synthesizer
+    }
+
+    #[test]
+    fn a_velocity_one_note_sounds_at_least_at_the_floor() {
+        let full = {
+            let mut synthesizer = sine_synthesizer(0.0);
+            synthesizer.note_on(0, 69, 127, 0);
+            sustained_peak(&mut synthesizer, 24000)
+        };
+        let mut synthesizer = sine_synthesizer(0.1);
+        synthesizer.note_on(0, 69, 1, 0);
+        assert!(sustained_peak(&mut synthesizer, 24000) >= 0.1 * full * 0.999);
+        // A repeat of the sounding pitch takes the same floor.
+        synthesizer.note_on(0, 69, 1, 0);
+        assert!(sustained_peak(&mut synthesizer, 24000) >= 0.1 * full * 0.999);
+    }
+
+    #[test]
+    fn the_velocity_floor_leaves_velocity_routes_alone() {
+        let mut synthesizer = sine_synthesizer(0.5);
+        synthesizer.note_on(0, 69, 1, 0);
+        render(&mut synthesizer, 10);
+        assert_eq!(voice_states(&synthesizer)[0].velocity, 1);
+        assert_eq!(synthesizer.channels[0].notes[0].fractional_velocity(), 1.0 / 127.0);
+    }
+
+    #[test]
+    fn a_velocity_zero_note_on_is_a_note_off_even_with_a_floor() {
+        let mut synthesizer = sine_synthesizer(0.1);
+        synthesizer.handle_midi(jack::RawMidi { time: 0, bytes: &[0x90, 69, 0] });
+        synthesizer.note_on(0, 70, 0, 0);
+        assert_eq!(peak(&render(&mut synthesizer, 4800)), 0.0);
+        assert_eq!(synthesizer.voice_count(), 0);
+
+        synthesizer.note_on(0, 69, 100, 0);
+        render(&mut synthesizer, 4800);
+        synthesizer.handle_midi(jack::RawMidi { time: 0, bytes: &[0x90, 69, 0] });
+        render(&mut synthesizer, 48000);
+        assert_eq!(synthesizer.voice_count(), 0);
+    }
+
+    fn equal_temperament() -> [f32; 128] {
+        std::array::from_fn(|pitch| 440.0 * 2.0_f32.powf((pitch as f32 - 69.0) / 12.0))
+    }
+
+    #[test]
+    fn disabling_a_sounding_pitch_silences_its_note() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.note_on(0, 69, 100, 0);
+        synthesizer.note_on(0, 72, 100, 0);
+        render(&mut synthesizer, 4800);
+        let mut frequencies = equal_temperament();
+        frequencies[69] = 0.0;
+        frequencies[72] = f32::NAN;
+        assert_eq!(synthesizer.set_frequencies(frequencies), [69, 72]);
+        assert_eq!(synthesizer.disabled_pitches(), [69, 72]);
+        let out = render(&mut synthesizer, 4800);
+        assert!(out.iter().all(|&sample| sample == 0.0));
+        assert_eq!(synthesizer.voice_count(), 0);
+    }
+
+    #[test]
+    fn disabling_one_pitch_leaves_the_others_sounding() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.note_on(0, 69, 100, 0);
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 4800);
+        let mut frequencies = equal_temperament();
+        frequencies[69] = 0.0;
+        synthesizer.set_frequencies(frequencies);
+        let out = render(&mut synthesizer, 4800);
+        assert_eq!(voice_states(&synthesizer).iter().map(|voice| voice.pitch).collect::<Vec<_>>(), vec![60]);
+        let mean = out.iter().sum::<f32>() / out.len() as f32;
+        assert!(peak(&out) > 0.01 && mean.abs() < 0.005, "peak {} mean {}", peak(&out), mean);
+    }
+
+    // Hz from the rising zero crossings of a steady tone.
+    fn frequency_of(samples: &[f32]) -> f32 {
+        let crossings: Vec<usize> = (1..samples.len()).filter(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0).collect();
+        (crossings.len() - 1) as f32 * SAMPLE_RATE as f32 / (crossings[crossings.len() - 1] - crossings[0]) as f32
+    }
+
+    #[test]
+    fn retuning_a_held_note_glides_to_the_new_table() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.set_retune_time(0.1);
+        synthesizer.note_on(0, 69, 100, 0);
+        render(&mut synthesizer, 4800);
+        synthesizer.set_frequencies(equal_temperament().map(|frequency| frequency * 2.0));
+        let start = render(&mut synthesizer, 960);
+        assert!(frequency_of(&start) < 500.0, "{}", frequency_of(&start));
+        render(&mut synthesizer, 4800);
+        let end = render(&mut synthesizer, 4800);
+        assert!((frequency_of(&end) - 880.0).abs() < 2.0, "{}", frequency_of(&end));
+    }
+
+    #[test]
+    fn retuning_a_held_note_to_a_disabled_entry_silences_it() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.set_retune_time(0.1);
+        synthesizer.note_on(0, 69, 100, 0);
+        render(&mut synthesizer, 4800);
+        let mut frequencies = equal_temperament();
+        frequencies[69] = 0.0;
+        synthesizer.set_frequencies(frequencies);
+        assert!(render(&mut synthesizer, 9600).iter().all(|&sample| sample == 0.0));
+        assert_eq!(synthesizer.voice_count(), 0);
+    }
+
+    #[test]
+    fn init_patch_resets_every_setting_a_preset_touches() {
+        let mut synthesizer = synthesizer();
+        for (i, target) in PARAM_TARGETS.into_iter().enumerate() {
+            if target != ParamTarget::MidiLog {
+                synthesizer.set_param(3, target, (i as f32 * 0.37) % 1.0);
+            }
+        }
+        synthesizer.set_max_voices(4);
+        synthesizer.set_delay(Delay { level: 0.5, time: 0.1, ..Default::default() });
+        synthesizer.set_reverb(Reverb { level: 0.3, size: 0.9, ..Default::default() });
+        synthesizer.set_tremolo(Modulation { depth: 0.5, ..Default::default() });
+        synthesizer.set_watchdog_threshold(2.0);
+        synthesizer.set_brightness_cutoff(0.0);
+        let mapping = MacroMapping { target: ParamTarget::Cutoff, min: 0.0, max: 1.0 };
+        synthesizer.set_macro_mappings(0, vec![mapping]);
+        synthesizer.set_macro(3, 0, 0.5);
+        synthesizer.bind_macro_cc(30, 0);
+        synthesizer.bind_cc(31, ParamTarget::Resonance);
+        synthesizer.learn(ParamTarget::Attack);
+
+        synthesizer.init_patch();
+
+        let fresh = self::synthesizer();
+        for channel in 0..CHANNELS as u8 {
+            assert_eq!(synthesizer.patch(channel), &Patch::default());
+        }
+        assert_eq!(synthesizer.max_voices(), MAX_VOICES);
+        assert_eq!(synthesizer.delay(), fresh.delay());
+        assert_eq!(synthesizer.reverb(), fresh.reverb());
+        assert_eq!(synthesizer.tremolo(), fresh.tremolo());
+        assert_eq!(synthesizer.watchdog_threshold(), fresh.watchdog_threshold());
+        assert_eq!(synthesizer.brightness_cutoff(), fresh.brightness_cutoff());
+        assert_eq!(synthesizer.macro_knob(0), fresh.macro_knob(0));
+        assert_eq!(synthesizer.macro_cc_map(), fresh.macro_cc_map());
+        assert_eq!(synthesizer.cc_map(), fresh.cc_map());
+        assert_eq!(synthesizer.learning(), None);
+        for channel in 0..CHANNELS as u8 {
+            for target in PARAM_TARGETS {
+                let info = target.info();
+                assert_eq!(synthesizer.get_param(channel, target), info.normalize(info.default), "{:?} on {}", target, channel);
+            }
+        }
+    }
+
+    #[test]
+    fn an_out_of_range_macro_value_moves_its_targets_to_the_end_of_their_range() {
+        let mut synthesizer = synthesizer();
+        let mapping = MacroMapping { target: ParamTarget::Resonance, min: 0.2, max: 0.6 };
+        synthesizer.set_macro_mappings(0, vec![mapping]);
+        synthesizer.set_macro(0, 0, 1.0);
+        let top = synthesizer.get_param(0, ParamTarget::Resonance);
+        synthesizer.set_macro(0, 0, 5.0);
+        assert_eq!(synthesizer.macro_knob(0).unwrap().value, 1.0);
+        assert_eq!(synthesizer.get_param(0, ParamTarget::Resonance), top);
+        synthesizer.set_macro(0, 0, 0.0);
+        let bottom = synthesizer.get_param(0, ParamTarget::Resonance);
+        synthesizer.set_macro(0, 0, -5.0);
+        assert_eq!(synthesizer.macro_knob(0).unwrap().value, 0.0);
+        assert_eq!(synthesizer.get_param(0, ParamTarget::Resonance), bottom);
+        synthesizer.set_macro(0, 0, f32::NAN);
+        assert_eq!(synthesizer.macro_knob(0).unwrap().value, 0.0);
+        assert_eq!(synthesizer.get_param(0, ParamTarget::Resonance), bottom);
+    }
+
+    #[test]
+    fn stems_and_speakers_added_after_prepare_are_not_resized_while_rendering() {
+        let mut synthesizer = synthesizer();
+        synthesizer.prepare(256);
+        synthesizer.set_stem_count(2);
+        synthesizer.set_speaker_count(4);
+        let stem = synthesizer.stem_buffers[1].as_ptr();
+        let speaker = synthesizer.speaker_buffers[3].as_ptr();
+        synthesizer.note_on(1, 60, 100, 0);
+        let (mut left, mut right) = (vec![0.0; 256], vec![0.0; 256]);
+        synthesizer.process_block(&mut left, &mut right);
+        assert_eq!(synthesizer.stem_buffers[1].as_ptr(), stem);
+        assert_eq!(synthesizer.speaker_buffers[3].as_ptr(), speaker);
+        assert_eq!(synthesizer.stem(1).len(), 256);
+        assert!(peak(synthesizer.stem(1)) > 0.0);
+    }
+
+    #[test]
+    fn a_formant_change_mid_note_takes_effect() {
+        let formant_synthesizer = |vowel: f32| {
+            let mut synthesizer = synthesizer();
+            synthesizer.set_param(0, ParamTarget::FormantMix, 1.0);
+            synthesizer.set_param(0, ParamTarget::Vowel, vowel);
+            synthesizer.note_on(0, 48, 100, 0);
+            synthesizer
+        };
+        let mut changed = formant_synthesizer(0.0);
+        render(&mut changed, 4800);
+        changed.set_param(0, ParamTarget::Vowel, 1.0);
+        render(&mut changed, 4800);
+        let mut fixed = formant_synthesizer(1.0);
+        render(&mut fixed, 9600);
+        let (changed, fixed) = (render(&mut changed, 4800), render(&mut fixed, 4800));
+        assert!(changed.iter().zip(fixed.iter()).all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn a_cutoff_change_mid_note_takes_effect() {
+        let filtered_synthesizer = |cutoff: f32| {
+            let mut synthesizer = synthesizer();
+            synthesizer.set_param(0, ParamTarget::Cutoff, cutoff);
+            synthesizer.set_param(0, ParamTarget::Resonance, 0.5);
+            synthesizer.note_on(0, 48, 100, 0);
+            synthesizer
+        };
+        let mut changed = filtered_synthesizer(1.0);
+        render(&mut changed, 4800);
+        changed.set_param(0, ParamTarget::Cutoff, 0.3);
+        render(&mut changed, 4800);
+        let mut fixed = filtered_synthesizer(0.3);
+        render(&mut fixed, 9600);
+        let (changed, fixed) = (render(&mut changed, 4800), render(&mut fixed, 4800));
+        assert!(changed.iter().zip(fixed.iter()).all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn a_cutoff_routed_per_note_follows_the_mod_wheel() {
+        let routed_synthesizer = |mod_wheel: u8| {
+            let mut synthesizer = synthesizer();
+            let mut patch = Patch::default();
+            patch.filter.cutoff = ParamTarget::Cutoff.info().denormalize(0.3);
+            let route = ModRoute { source: ModSource::ModWheel, destination: ModDestination::Param(ParamTarget::Cutoff), amount: 0.5 };
+            assert!(patch.modulation.set_route(0, Some(route)));
+            synthesizer.set_patch(0, patch);
+            synthesizer.control_change(0, 1, mod_wheel);
+            synthesizer.note_on(0, 48, 100, 0);
+            synthesizer
+        };
+        let mut changed = routed_synthesizer(0);
+        render(&mut changed, 4800);
+        changed.control_change(0, 1, 127);
+        render(&mut changed, 4800);
+        let mut fixed = routed_synthesizer(127);
+        render(&mut fixed, 9600);
+        let (changed, fixed) = (render(&mut changed, 4800), render(&mut fixed, 4800));
+        assert!(changed.iter().zip(fixed.iter()).all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn a_rendered_note_does_not_outlive_its_render() {
+        let mut synthesizer = synthesizer();
+        let released = |synthesizer: &Synthesizer| voice_states(synthesizer).iter().all(|voice| matches!(voice.stage, EnvelopePhase::Release(..)));
+        synthesizer.render_note_released(69, 100, 4800, 9600);
+        assert!(released(&synthesizer));
+        synthesizer.render_note(72, 100, 4800);
+        assert!(released(&synthesizer));
+        render(&mut synthesizer, 48000);
+        assert_eq!(synthesizer.voice_count(), 0);
+        assert!(synthesizer.render_note(60, 100, 0).is_empty());
+        assert_eq!(synthesizer.voice_count(), 0);
+    }
+
+    // The share of samples above 70% of the peak: nearly all of a square
+    // wave, even with the filter ringing on its edges, and half of a sine.
+    fn near_peak(samples: &[f32]) -> f32 {
+        let peak = peak(samples);
+        samples.iter().filter(|sample| sample.abs() > 0.7 * peak).count() as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn channels_play_their_own_waveforms_at_once() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, ..Patch::default() });
+        synthesizer.set_patch(1, Patch { waveform: Waveform::Square, ..Patch::default() });
+        synthesizer.set_stem_count(2);
+        synthesizer.note_on(0, 57, 100, 0);
+        synthesizer.note_on(1, 57, 100, 0);
+        let (mut left, mut right) = (vec![0.0; 24000], vec![0.0; 24000]);
+        synthesizer.process_block(&mut left, &mut right);
+        assert!(near_peak(&synthesizer.stem(0)[12000..]) < 0.6);
+        assert!(near_peak(&synthesizer.stem(1)[12000..]) > 0.9);
+    }
+
+    #[test]
+    fn the_voice_limit_is_shared_across_channels() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_max_voices(3);
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_on(0, 64, 100, 0);
+        synthesizer.note_on(1, 67, 100, 0);
+        synthesizer.note_on(2, 72, 100, 0);
+        render(&mut synthesizer, 10);
+        let voices = voice_states(&synthesizer);
+        assert_eq!(voices.len(), 3);
+        assert!(voices.iter().any(|voice| voice.channel == 2 && voice.pitch == 72));
+    }
+
+    // The level of harmonic `n` of a steady tone at `frequency`, by a single
+    // DFT bin over a whole number of cycles.
+    fn harmonic(samples: &[f32], frequency: f32, n: usize) -> f32 {
+        let step = 2.0 * std::f32::consts::PI * frequency * n as f32 / SAMPLE_RATE as f32;
+        let (cos, sin) = samples.iter().enumerate().fold((0.0, 0.0), |(cos, sin), (i, &sample)| {
+            let (s, c) = (step * i as f32).sin_cos();
+            (cos + sample * c, sin + sample * s)
+        });
+        2.0 * (cos * cos + sin * sin).sqrt() / samples.len() as f32
+    }
+
+    #[test]
+    fn mid_velocity_blends_the_two_layers() {
+        // 375 Hz is 128 samples a cycle, so 12800 samples hold exactly 100.
+        let third_harmonic = |velocity: u8| {
+            let mut synthesizer = Synthesizer::new(SAMPLE_RATE, [375.0; 128]);
+            let layers = VelocityLayers { soft: Waveform::Sine, hard: Waveform::Square, ..VelocityLayers::default() };
+            synthesizer.set_patch(0, Patch { velocity_layers: Some(layers), ..Patch::default() });
+            synthesizer.note_on(0, 60, velocity, 0);
+            render(&mut synthesizer, 12800);
+            let out = render(&mut synthesizer, 12800);
+            harmonic(&out, 375.0, 3) / harmonic(&out, 375.0, 1)
+        };
+        let (soft, mid, hard) = (third_harmonic(1), third_harmonic(64), third_harmonic(127));
+        assert!(soft < 0.01, "{}", soft);
+        assert!((hard - 1.0 / 3.0).abs() < 0.02, "{}", hard);
+        assert!(mid > soft + 0.05 && mid < hard - 0.05, "{}", mid);
+    }
+
+    fn midi(synthesizer: &mut Synthesizer, time: u32, bytes: &[u8]) {
+        synthesizer.handle_midi(jack::RawMidi { time, bytes });
+    }
+
+    fn stages(synthesizer: &Synthesizer, pitch: u8) -> Vec<EnvelopePhase> {
+        voice_states(synthesizer).iter().filter(|voice| voice.pitch == pitch).map(|voice| voice.stage).collect()
+    }
+
+    #[test]
+    fn an_off_then_on_in_one_buffer_ends_up_attacking() {
+        let mut synthesizer = synthesizer();
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 4800);
+        midi(&mut synthesizer, 20, &[0x90, 60, 100]);
+        midi(&mut synthesizer, 10, &[0x80, 60, 0]);
+        render(&mut synthesizer, 15);
+        assert!(stages(&synthesizer, 60).iter().all(|stage| matches!(stage, EnvelopePhase::Release(..))));
+        render(&mut synthesizer, 15);
+        let stages = stages(&synthesizer, 60);
+        assert_eq!(stages.iter().filter(|stage| matches!(stage, EnvelopePhase::Attack(_))).count(), 1, "{:?}", stages);
+        assert!(synthesizer.held_notes().contains(&60));
+    }
+
+    #[test]
+    fn an_on_then_off_in_one_buffer_ends_up_releasing() {
+        let mut synthesizer = synthesizer();
+        midi(&mut synthesizer, 10, &[0x90, 60, 100]);
+        midi(&mut synthesizer, 20, &[0x80, 60, 0]);
+        render(&mut synthesizer, 15);
+        assert!(matches!(stages(&synthesizer, 60)[..], [EnvelopePhase::Attack(_)]));
+        render(&mut synthesizer, 15);
+        assert!(matches!(stages(&synthesizer, 60)[..], [EnvelopePhase::Release(..)]));
+        assert!(!synthesizer.held_notes().contains(&60));
+    }
+
+    #[test]
+    fn events_at_the_same_frame_keep_their_order() {
+        let mut synthesizer = synthesizer();
+        midi(&mut synthesizer, 5, &[0x90, 60, 100]);
+        midi(&mut synthesizer, 5, &[0x80, 60, 0]);
+        midi(&mut synthesizer, 5, &[0x90, 60, 100]);
+        render(&mut synthesizer, 10);
+        assert!(stages(&synthesizer, 60).iter().any(|stage| matches!(stage, EnvelopePhase::Attack(_))));
+        assert!(synthesizer.held_notes().contains(&60));
+    }
+
+    #[test]
+    fn a_note_starts_at_the_patch_phase() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, phase: 1.25, ..Patch::default() });
+        synthesizer.note_on(0, 69, 100, 0);
+        assert_eq!(synthesizer.channels[0].notes[0].phase, 0.25);
+        let note = synthesizer.channels[0].notes[0];
+        let patch = *synthesizer.patch(0);
+        assert_eq!(oscillator(&synthesizer.wavetable, &patch, note.phase, 0.01, 0.0, 0.5), 1.0);
+    }
+
+    #[test]
+    fn the_hard_layer_starts_at_its_offset() {
+        let wavetable = Wavetable::default();
+        let layered = |phase_offset: f32| {
+            let layers = VelocityLayers { soft: Waveform::Sine, hard: Waveform::Sine, crossfade: Crossfade::Linear, phase_offset };
+            let patch = Patch { velocity_layers: Some(layers), ..Patch::default() };
+            oscillator(&wavetable, &patch, 0.0, 0.01, 0.0, 0.5)
+        };
+        assert_eq!(layered(0.0), 0.0);
+        assert!((layered(0.25) - 0.5).abs() < 1e-6);
+        assert!(VelocityLayers::default().phase_offset > 0.0);
+    }
+
+    #[test]
+    fn the_test_tone_sounds_without_midi_at_the_requested_frequency() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_test_tone(1000.0, 0.2);
+        assert!((frequency_of(&render(&mut synthesizer, 48000)) - 1000.0).abs() < 1.0);
+        synthesizer.set_test_tone(250.0, 0.2);
+        assert!((frequency_of(&render(&mut synthesizer, 48000)) - 250.0).abs() < 1.0);
+        assert_eq!(synthesizer.voice_count(), 0);
+    }
+
+    #[test]
+    fn a_bend_step_ramps_the_frequency() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_pitch_bend_smoothing(0.01);
+        synthesizer.note_on(0, 69, 100, 0);
+        render(&mut synthesizer, 100);
+        synthesizer.pitch_bend(0, 1.0);
+        let range = synthesizer.patch(0).bend_range;
+        let ratios: Vec<f32> = (0..4800)
+            .map(|_| {
+                render(&mut synthesizer, 1);
+                2.0_f32.powf(synthesizer.channels[0].bend * range / 12.0)
+            })
+            .collect();
+        // A whole tone up, reached gradually: no single frame moves more than
+        // a small part of the way.
+        assert!(ratios.windows(2).all(|pair| pair[1] >= pair[0] && pair[1] - pair[0] < 0.01));
+        assert!(ratios[0] < 1.01);
+        assert!((ratios[4799] - 2.0_f32.powf(range / 12.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn zero_bend_smoothing_applies_bends_at_once() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_pitch_bend_smoothing(0.0);
+        synthesizer.pitch_bend(0, -1.0);
+        render(&mut synthesizer, 1);
+        assert_eq!(synthesizer.channels[0].bend, -1.0);
+        // The default is a few milliseconds, short enough to feel immediate.
+        assert!(self::synthesizer().pitch_bend_smoothing() <= 0.005);
+    }
+
+    fn drifting_synthesizer(seed: u64) -> Synthesizer {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_seed(seed);
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, drift_amount: 10.0, drift_rate: 5.0, ..Patch::default() });
+        synthesizer
+    }
+
+    #[test]
+    fn drift_is_repeatable_under_a_seed_and_stays_in_bounds() {
+        let play = |synthesizer: &mut Synthesizer| {
+            synthesizer.note_on(0, 69, 100, 0);
+            synthesizer.note_on(0, 76, 100, 0);
+            let mut out = Vec::new();
+            for _ in 0..48 {
+                out.extend(render(synthesizer, 500));
+                assert!(synthesizer.channels[0].notes.iter().all(|note| note.drift.abs() <= 1.0));
+            }
+            out
+        };
+        let mut synthesizer = drifting_synthesizer(7);
+        let first = play(&mut synthesizer);
+        assert!(synthesizer.channels[0].notes.iter().any(|note| note.drift != 0.0));
+        assert_eq!(first, play(&mut drifting_synthesizer(7)));
+        assert_ne!(first, play(&mut drifting_synthesizer(8)));
+    }
+
+    #[test]
+    fn zero_drift_plays_the_table_pitch() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, drift_rate: 5.0, ..Patch::default() });
+        synthesizer.note_on(0, 69, 100, 0);
+        render(&mut synthesizer, 9600);
+        assert_eq!(synthesizer.channels[0].notes[0].drift, 0.0);
+        assert!((frequency_of(&render(&mut synthesizer, 48000)) - 440.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn a_learned_cc_moves_its_parameter() {
+        let mut synthesizer = synthesizer();
+        synthesizer.learn(ParamTarget::Resonance);
+        synthesizer.control_change(0, 20, 127);
+        assert_eq!(synthesizer.learning(), None);
+        assert_eq!(synthesizer.cc_map().get(&20), Some(&ParamTarget::Resonance));
+        assert_eq!(synthesizer.get_param(0, ParamTarget::Resonance), 1.0);
+        synthesizer.control_change(0, 20, 0);
+        assert_eq!(synthesizer.get_param(0, ParamTarget::Resonance), 0.0);
+        // The default bindings stay in place next to the learned one.
+        synthesizer.control_change(0, 7, 0);
+        assert_eq!(synthesizer.get_param(0, ParamTarget::Volume), 0.0);
+    }
+
+    #[test]
+    fn an_unmapped_cc_is_ignored() {
+        let mut synthesizer = synthesizer();
+        let patch = *synthesizer.patch(0);
+        synthesizer.control_change(0, 21, 100);
+        assert_eq!(synthesizer.patch(0), &patch);
+        assert_eq!(synthesizer.cc_map(), &default_cc_map());
+    }
+
+    #[test]
+    fn loud_chords_set_the_overload_flag_and_a_quiet_note_does_not() {
+        let mut synthesizer = synthesizer();
+        let indicator = synthesizer.overload_indicator();
+        synthesizer.note_on(0, 60, 20, 0);
+        render(&mut synthesizer, 9600);
+        assert!(!indicator.is_set());
+
+        for pitch in 40..72 {
+            synthesizer.note_on(0, pitch, 127, 0);
+        }
+        render(&mut synthesizer, 9600);
+        assert!(indicator.take());
+    }
+
+    #[test]
+    fn a_duplicate_note_off_does_not_restart_the_release() {
+        let mut synthesizer = synthesizer();
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 4800);
+        synthesizer.note_off(0, 60);
+        render(&mut synthesizer, 1000);
+        let stage = voice_states(&synthesizer)[0].stage;
+        synthesizer.note_off(0, 60);
+        midi(&mut synthesizer, 0, &[0x80, 60, 0]);
+        render(&mut synthesizer, 1);
+        let EnvelopePhase::Release(before, _) = stage else { panic!("{:?}", stage) };
+        assert!(matches!(voice_states(&synthesizer)[0].stage, EnvelopePhase::Release(timer, _) if timer == before + 1));
+    }
+
+    fn stems(synthesizer: &mut Synthesizer, frames: usize) -> Vec<Vec<f32>> {
+        let (mut left, mut right) = (vec![0.0; frames], vec![0.0; frames]);
+        synthesizer.process_block(&mut left, &mut right);
+        (0..synthesizer.stem_count()).map(|bus| synthesizer.stem(bus).to_vec()).collect()
+    }
+
+    #[test]
+    fn voices_accumulate_into_their_own_bus() {
+        let stem_synthesizer = |notes: &[(u8, u8)]| {
+            let mut synthesizer = synthesizer();
+            synthesizer.set_stem_count(2);
+            synthesizer.set_bus(2, 0);
+            for &(channel, pitch) in notes {
+                synthesizer.note_on(channel, pitch, 100, 0);
+            }
+            stems(&mut synthesizer, 4800)
+        };
+        let all = stem_synthesizer(&[(0, 60), (1, 64), (2, 67)]);
+        let bus_a = stem_synthesizer(&[(0, 60), (2, 67)]);
+        let bus_b = stem_synthesizer(&[(1, 64)]);
+        assert_eq!(all[0], bus_a[0]);
+        assert_eq!(all[1], bus_b[1]);
+        assert!(bus_a[1].iter().all(|&sample| sample == 0.0));
+        assert!(peak(&all[0]) > 0.0 && peak(&all[1]) > 0.0);
+    }
+
+    #[test]
+    fn buses_wrap_to_the_stem_count() {
+        let mut synthesizer = synthesizer();
+        assert_eq!(synthesizer.stem_count(), 0);
+        assert_eq!(synthesizer.bus(5), 5);
+        synthesizer.set_stem_count(4);
+        assert_eq!(synthesizer.bus(5), 1);
+    }
+
+    #[test]
+    fn a_retriggered_repeat_restarts_the_attack_from_its_level() {
+        let mut synthesizer = synthesizer();
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 9600);
+        let before = voice_states(&synthesizer)[0];
+        assert!(matches!(before.stage, EnvelopePhase::Sustain(_)));
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 1);
+        let after = voice_states(&synthesizer);
+        assert_eq!(after.len(), 1);
+        assert!(matches!(after[0].stage, EnvelopePhase::Attack(timer) if timer > 0));
+        assert!((after[0].amplitude - before.amplitude).abs() < 0.01, "{} {}", before.amplitude, after[0].amplitude);
+    }
+
+    #[test]
+    fn without_retrigger_a_repeat_keeps_the_envelope_stage() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { env_retrigger: false, ..Patch::default() });
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 9600);
+        synthesizer.note_on(0, 60, 50, 0);
+        let voices = voice_states(&synthesizer);
+        assert_eq!(voices.len(), 1);
+        assert!(matches!(voices[0].stage, EnvelopePhase::Sustain(_)));
+        assert_eq!(voices[0].velocity, 50);
+
+        // In mono mode a new key only moves the pitch.
+        synthesizer.set_patch(1, Patch { env_retrigger: false, mono: true, ..Patch::default() });
+        synthesizer.note_on(1, 60, 100, 0);
+        render(&mut synthesizer, 9600);
+        synthesizer.note_on(1, 67, 100, 0);
+        render(&mut synthesizer, 1);
+        let voices: Vec<_> = voice_states(&synthesizer).into_iter().filter(|voice| voice.channel == 1).collect();
+        assert_eq!(voices.len(), 1);
+        assert_eq!(voices[0].pitch, 67);
+        assert!(matches!(voices[0].stage, EnvelopePhase::Sustain(_)));
+    }
+
+    fn largest_step(samples: &[f32]) -> f32 {
+        samples.windows(2).fold(0.0, |step, pair| step.max((pair[1] - pair[0]).abs()))
+    }
+
+    #[test]
+    fn a_sample_rate_change_fades_instead_of_jumping() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.note_on(0, 69, 127, 0);
+        render(&mut synthesizer, 9600);
+        let steady = largest_step(&render(&mut synthesizer, 4800));
+        synthesizer.set_sample_rate(44100);
+        let out = render(&mut synthesizer, 4800);
+        assert_eq!(synthesizer.sample_rate(), 44100);
+        assert!(out.contains(&0.0));
+        assert!(largest_step(&out) < 1.1 * steady, "{} {}", largest_step(&out), steady);
+    }
+
+    #[test]
+    fn all_sound_off_fades_out_unless_the_fade_is_zero() {
+        let step_after_sound_off = |fade_time: f32| {
+            let mut synthesizer = sine_synthesizer(0.0);
+            synthesizer.set_fade_time(fade_time);
+            // 55 Hz, cut off well away from a zero crossing.
+            synthesizer.note_on(0, 33, 127, 0);
+            render(&mut synthesizer, 9600);
+            let steady = largest_step(&render(&mut synthesizer, 4900));
+            synthesizer.all_sound_off(0);
+            let out = render(&mut synthesizer, 4800);
+            assert_eq!(synthesizer.voice_count(), 0);
+            largest_step(&out) / steady
+        };
+        assert!(step_after_sound_off(FADE_TIME) < 1.1);
+        assert!(step_after_sound_off(0.0) > 2.0);
+    }
+
+    #[test]
+    fn a_voice_snapshot_shows_a_rising_attack() {
+        let mut synthesizer = synthesizer();
+        let monitor = synthesizer.voice_monitor();
+        synthesizer.note_on(3, 64, 90, 0);
+        let mut snapshot = Vec::new();
+        let mut amplitudes = Vec::new();
+        for _ in 0..4 {
+            render(&mut synthesizer, 256);
+            monitor.snapshot(&mut snapshot);
+            assert_eq!(snapshot.len(), 1);
+            let voice = snapshot[0];
+            assert_eq!((voice.channel, voice.pitch, voice.velocity), (3, 64, 90));
+            assert!(matches!(voice.stage, EnvelopePhase::Attack(_)));
+            amplitudes.push(voice.amplitude);
+        }
+        assert!(amplitudes.windows(2).all(|pair| pair[1] > pair[0]));
+        let mut voices = Vec::new();
+        synthesizer.voices(&mut voices);
+        assert_eq!(voices, snapshot);
+    }
+
+    #[test]
+    fn mute_ramps_to_silence_and_back_over_the_mute_time() {
+        let tone_synthesizer = || {
+            let mut synthesizer = synthesizer();
+            synthesizer.set_test_tone(1000.0, 0.5);
+            synthesizer.set_mute_time(0.01);
+            render(&mut synthesizer, 4800);
+            synthesizer
+        };
+        let mut reference = tone_synthesizer();
+        let mut muted = tone_synthesizer();
+        muted.set_muted(true);
+        let (expected, out) = (render(&mut reference, 960), render(&mut muted, 960));
+        for (i, (&expected, &out)) in expected.iter().zip(out.iter()).enumerate() {
+            let gain = (1.0 - i as f32 / 480.0).max(0.0);
+            assert!((out - expected * gain).abs() < 1e-4, "frame {}", i);
+        }
+        assert!(out[480..].iter().all(|&sample| sample == 0.0));
+
+        muted.set_muted(false);
+        let (expected, out) = (render(&mut reference, 960), render(&mut muted, 960));
+        assert_eq!(out[0], 0.0);
+        assert!((out[240] - expected[240] * 0.5).abs() < 1e-4);
+        assert_eq!(out[480..], expected[480..]);
+    }
+
+    #[test]
+    fn voices_keep_running_while_muted() {
+        let mut synthesizer = synthesizer();
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.set_muted(true);
+        render(&mut synthesizer, 4800);
+        synthesizer.note_off(0, 60);
+        render(&mut synthesizer, 48000);
+        assert_eq!(synthesizer.voice_count(), 0);
+    }
+
+    #[test]
+    fn reset_all_controllers_centers_bend_and_mod_wheel_but_keeps_notes() {
+        let mut synthesizer = synthesizer();
+        synthesizer.note_on(0, 60, 100, 0);
+        midi(&mut synthesizer, 0, &[0xE0, 0x7F, 0x7F]);
+        midi(&mut synthesizer, 0, &[0xB0, 1, 100]);
+        midi(&mut synthesizer, 0, &[0xB0, 11, 40]);
+        midi(&mut synthesizer, 0, &[0xB0, 7, 50]);
+        render(&mut synthesizer, 100);
+        assert!(synthesizer.pitch_bend_target(0) > 0.99);
+        assert!(synthesizer.mod_wheel(0) > 0.7);
+
+        midi(&mut synthesizer, 0, &[0xB0, 121, 0]);
+        render(&mut synthesizer, 100);
+        assert_eq!(synthesizer.pitch_bend_target(0), 0.0);
+        assert_eq!(synthesizer.mod_wheel(0), 0.0);
+        assert_eq!(synthesizer.get_param(0, ParamTarget::Expression), 1.0);
+        // Channel volume is left alone.
+        assert!((synthesizer.get_param(0, ParamTarget::Volume) - 50.0 / 127.0).abs() < 1e-6);
+        assert_eq!(synthesizer.held_notes(), vec![60]);
+        assert!(peak(&render(&mut synthesizer, 4800)) > 0.0);
+    }
+
+    #[test]
+    fn reset_all_controllers_releases_notes_held_by_the_pedal() {
+        let mut synthesizer = synthesizer();
+        synthesizer.control_change(0, 64, 127);
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_off(0, 60);
+        render(&mut synthesizer, 100);
+        assert!(!voice_states(&synthesizer)[0].stage.eq(&EnvelopePhase::Off));
+        synthesizer.control_change(0, 121, 0);
+        assert!(matches!(voice_states(&synthesizer)[0].stage, EnvelopePhase::Release(..)));
+    }
+
+    fn level_with(volume: u8, expression: u8) -> f32 {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.control_change(0, 7, volume);
+        synthesizer.control_change(0, 11, expression);
+        synthesizer.note_on(0, 69, 100, 0);
+        sustained_peak(&mut synthesizer, 24000)
+    }
+
+    #[test]
+    fn expression_scales_on_top_of_channel_volume() {
+        let full = level_with(127, 127);
+        let half = 64.0 / 127.0;
+        assert!(full > 0.0);
+        assert!((level_with(127, 64) / full - half).abs() < 1e-3);
+        assert!((level_with(64, 64) / full - half * half).abs() < 1e-3);
+    }
+
+    #[test]
+    fn expression_defaults_to_full() {
+        let synthesizer = synthesizer();
+        assert_eq!(synthesizer.get_param(0, ParamTarget::Expression), 1.0);
+    }
+
+    fn tuned_frequency(pitch: u8, coarse: i32, fine: f32) -> f32 {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.set_tuning(coarse, fine);
+        synthesizer.note_on(0, pitch, 100, 0);
+        frequency_of(&render(&mut synthesizer, 48000)[24000..])
+    }
+
+    #[test]
+    fn fine_tune_raises_every_note_by_the_same_ratio() {
+        let ratio = 2.0_f32.powf(50.0 / 1200.0);
+        for pitch in [45, 69, 81] {
+            let shifted = tuned_frequency(pitch, 0, 50.0) / tuned_frequency(pitch, 0, 0.0);
+            assert!((shifted - ratio).abs() < 1e-3, "pitch {} ratio {}", pitch, shifted);
+        }
+        let octave = tuned_frequency(57, 12, 0.0) / tuned_frequency(57, 0, 0.0);
+        assert!((octave - 2.0).abs() < 2e-3);
+    }
+
+    #[test]
+    fn zero_tuning_leaves_the_table_untouched() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_tuning(3, -20.0);
+        synthesizer.set_tuning(0, 0.0);
+        assert_eq!(synthesizer.tuning, 1.0);
+        synthesizer.set_tuning(99, 500.0);
+        assert_eq!((synthesizer.coarse_tune(), synthesizer.fine_tune()), (24, 100.0));
+    }
+
+    // Three voices: 60 is the oldest, 64 holds the next round-robin slot and
+    // 65 is the quietest, fading out. 62 was played and has finished in between so the
+    // allocation order and the starting order differ.
+    fn crafted_voices(mode: VoiceStealMode) -> Synthesizer {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_max_voices(3);
+        synthesizer.set_steal_mode(mode);
+        for (pitch, velocity) in [(60, 100), (62, 100), (64, 100)] {
+            synthesizer.note_on(0, pitch, velocity, 0);
+            render(&mut synthesizer, 4800);
+        }
+        synthesizer.note_off(0, 62);
+        render(&mut synthesizer, 96000);
+        synthesizer.note_on(0, 65, 100, 0);
+        render(&mut synthesizer, 24000);
+        synthesizer.note_off(0, 65);
+        render(&mut synthesizer, 2400);
+        let mut pitches: Vec<u8> = voice_states(&synthesizer).iter().map(|voice| voice.pitch).collect();
+        pitches.sort();
+        assert_eq!(pitches, vec![60, 64, 65]);
+        synthesizer
+    }
+
+    fn victim(mode: VoiceStealMode, incoming: u8) -> u8 {
+        let mut synthesizer = crafted_voices(mode);
+        synthesizer.note_on(0, incoming, 100, 0);
+        // What's left is two of the originals plus the incoming note.
+        let mut survivors: Vec<u8> = voice_states(&synthesizer).iter().map(|voice| voice.pitch).collect();
+        assert_eq!(survivors.len(), 3);
+        survivors.remove(survivors.iter().position(|&pitch| pitch == incoming).unwrap());
+        [60, 64, 65].into_iter().find(|pitch| !survivors.contains(pitch)).unwrap()
+    }
+
+    #[test]
+    fn each_steal_mode_picks_its_victim() {
+        assert_eq!(VoiceStealMode::default(), VoiceStealMode::Quietest);
+        assert_eq!(victim(VoiceStealMode::Quietest, 72), 65);
+        assert_eq!(victim(VoiceStealMode::Oldest, 72), 60);
+        assert_eq!(victim(VoiceStealMode::RoundRobin, 72), 64);
+        assert_eq!(victim(VoiceStealMode::SamePitch, 60), 60);
+        assert_eq!(victim(VoiceStealMode::SamePitch, 72), 65);
+    }
+
+    #[test]
+    fn zero_and_negative_entries_play_nothing() {
+        let mut frequencies = equal_temperament();
+        frequencies[60] = 0.0;
+        frequencies[62] = -261.6;
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, frequencies);
+        assert_eq!(synthesizer.frequencies()[62], 0.0);
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_on(0, 62, 100, 0);
+        assert!(render(&mut synthesizer, 4800).iter().all(|&sample| sample == 0.0));
+        assert_eq!(synthesizer.voice_count(), 0);
+    }
+
+    fn stereo_peaks(pitch: u8, pan_spread: f32) -> (f32, f32) {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, pan_spread, ..Patch::default() });
+        synthesizer.note_on(0, pitch, 100, 0);
+        let (mut left, mut right) = (vec![0.0; 9600], vec![0.0; 9600]);
+        synthesizer.process_block(&mut left, &mut right);
+        (peak(&left[4800..]), peak(&right[4800..]))
+    }
+
+    #[test]
+    fn pitch_spread_puts_low_notes_left_and_high_notes_right() {
+        let (left, right) = stereo_peaks(36, 1.0);
+        assert!(left > 2.0 * right, "left {} right {}", left, right);
+        let (left, right) = stereo_peaks(96, 1.0);
+        assert!(right > 2.0 * left, "left {} right {}", left, right);
+    }
+
+    #[test]
+    fn zero_spread_keeps_every_note_centered() {
+        for pitch in [36, 96] {
+            let (left, right) = stereo_peaks(pitch, 0.0);
+            assert!(left > 0.0 && left == right);
+        }
+    }
+
+    #[test]
+    fn noteon_and_noteoff_commands_drive_a_note() {
+        let mut synthesizer = synthesizer();
+        synthesizer.handle_command(Command::parse("noteon 60 100").unwrap());
+        assert!(peak(&render(&mut synthesizer, 4800)) > 0.0);
+        assert_eq!(stages(&synthesizer, 60).len(), 1);
+        synthesizer.handle_command(Command::parse("noteoff 60").unwrap());
+        render(&mut synthesizer, 1);
+        assert!(matches!(stages(&synthesizer, 60)[..], [EnvelopePhase::Release(..)]));
+    }
+
+    fn frames_until_release(synthesizer: &mut Synthesizer, limit: usize) -> Option<usize> {
+        (1..=limit).find(|_| {
+            render(synthesizer, 1);
+            matches!(stages(synthesizer, 60)[..], [EnvelopePhase::Release(..)])
+        })
+    }
+
+    #[test]
+    fn a_note_with_no_note_off_releases_at_the_maximum_duration() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_max_note_duration(Some(1000));
+        synthesizer.note_on(0, 60, 100, 0);
+        assert_eq!(frames_until_release(&mut synthesizer, 2000), Some(1000));
+    }
+
+    #[test]
+    fn the_maximum_duration_overrides_the_sustain_pedal() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_max_note_duration(Some(1000));
+        synthesizer.control_change(0, 64, 127);
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_off(0, 60);
+        assert_eq!(frames_until_release(&mut synthesizer, 2000), Some(1000));
+    }
+
+    #[test]
+    fn the_maximum_duration_counts_from_a_delayed_onset() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_max_note_duration(Some(1000));
+        synthesizer.note_on(0, 60, 100, 300);
+        assert_eq!(frames_until_release(&mut synthesizer, 2000), Some(1300));
+    }
+
+    #[test]
+    fn notes_run_on_with_no_maximum_duration() {
+        let mut synthesizer = synthesizer();
+        assert_eq!(synthesizer.max_note_duration(), None);
+        synthesizer.note_on(0, 60, 100, 0);
+        assert_eq!(frames_until_release(&mut synthesizer, 48000), None);
+    }
+
+    // A 1 Hz square tremolo gates a held note on for half a second and off for
+    // the other half, while the transport beats every 32000 frames at 90 BPM.
+    fn gated_by_tremolo(retrigger: LfoRetrigger) -> Vec<f32> {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.set_tremolo(Modulation { depth: 1.0, rate: LfoRate { hz: 1.0, division: None }, waveform: LfoWaveform::Square, retrigger });
+        synthesizer.transport_mut().set_bpm(90.0);
+        synthesizer.transport_mut().play();
+        synthesizer.note_on(0, 69, 100, 0);
+        render(&mut synthesizer, 72000)
+    }
+
+    #[test]
+    fn beat_sync_restarts_the_lfo_on_every_beat() {
+        let out = gated_by_tremolo(LfoRetrigger::BeatSync);
+        assert!(peak(&out[24010..31990]) == 0.0);
+        assert!(peak(&out[32010..32500]) > 0.05);
+        // The next beat cuts the next off half short as well.
+        assert!(peak(&out[56010..63990]) == 0.0);
+        assert!(peak(&out[64010..64500]) > 0.05);
+    }
+
+    #[test]
+    fn a_free_lfo_ignores_the_beat() {
+        let out = gated_by_tremolo(LfoRetrigger::Free);
+        assert!(peak(&out[24010..47900]) == 0.0);
+        assert!(peak(&out[48010..48500]) > 0.05);
+    }
+
+    #[test]
+    fn a_cc88_prefix_lands_between_two_velocity_steps() {
+        let mut synthesizer = synthesizer();
+        midi(&mut synthesizer, 0, &[0xB0, 88, 64]);
+        midi(&mut synthesizer, 0, &[0x90, 60, 100]);
+        midi(&mut synthesizer, 0, &[0x90, 62, 100]);
+        render(&mut synthesizer, 1);
+        let velocity = |pitch: u8| synthesizer.channels[0].notes.iter().find(|note| note.pitch == pitch).unwrap().fractional_velocity();
+        let fine = velocity(60);
+        assert!(fine > 100.0 / 127.0 && fine < 101.0 / 127.0, "{}", fine);
+        // The prefix only applies to the note right after it.
+        assert_eq!(velocity(62), 100.0 / 127.0);
+    }
+
+    #[test]
+    fn a_cc88_prefix_is_kept_per_channel() {
+        let mut synthesizer = synthesizer();
+        synthesizer.control_change(1, 88, 64);
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_on(1, 60, 100, 0);
+        assert_eq!(synthesizer.channels[0].notes[0].fractional_velocity(), 100.0 / 127.0);
+        assert!(synthesizer.channels[1].notes[0].fractional_velocity() > 100.0 / 127.0);
+    }
+
+    #[test]
+    fn held_and_sounding_notes_tell_keys_pedal_and_release_apart() {
+        let mut synthesizer = synthesizer();
+        // 64 is released with no pedal, 62 is let go under the pedal, 60 stays down.
+        synthesizer.note_on(0, 64, 100, 0);
+        render(&mut synthesizer, 4800);
+        synthesizer.note_off(0, 64);
+        synthesizer.control_change(0, 64, 127);
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_on(1, 62, 100, 0);
+        synthesizer.control_change(1, 64, 127);
+        synthesizer.note_off(1, 62);
+        render(&mut synthesizer, 100);
+        assert_eq!(synthesizer.held_notes(), vec![60]);
+        assert_eq!(synthesizer.sounding_notes(), vec![60, 62, 64]);
+
+        synthesizer.control_change(1, 64, 0);
+        render(&mut synthesizer, 48000);
+        assert_eq!(synthesizer.held_notes(), vec![60]);
+        assert_eq!(synthesizer.sounding_notes(), vec![60]);
+    }
+
+    #[test]
+    fn notes_held_on_two_channels_are_listed_once() {
+        let mut synthesizer = synthesizer();
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_on(1, 60, 100, 0);
+        synthesizer.note_on(1, 55, 100, 0);
+        assert_eq!(synthesizer.held_notes(), vec![55, 60]);
+        assert_eq!(synthesizer.sounding_notes(), vec![55, 60]);
+    }
+
+    // Ten cycles of a 100 Hz phase-distortion oscillator.
+    fn phase_distorted(patch: &Patch, envelope: f32) -> Vec<f32> {
+        let wavetable = Wavetable::default();
+        (0..4800).map(|i| oscillator(&wavetable, patch, (i % 480) as f32 / 480.0, 1.0 / 480.0, envelope, 0.5)).collect()
+    }
+
+    fn overtones(samples: &[f32]) -> f32 {
+        (2..=10).map(|n| harmonic(samples, 100.0, n)).sum()
+    }
+
+    #[test]
+    fn more_phase_distortion_brightens_the_sine() {
+        let distorted = |pd_amount| phase_distorted(&Patch { waveform: Waveform::PhaseDistortion, pd_amount, ..Patch::default() }, 0.0);
+        let pure = distorted(0.0);
+        assert!(overtones(&pure) < 1e-3);
+        assert!((harmonic(&pure, 100.0, 1) - 1.0).abs() < 1e-3);
+        let brightness: Vec<f32> = [0.25, 0.5, 0.75].into_iter().map(|amount| overtones(&distorted(amount))).collect();
+        assert!(brightness[0] > 0.05 && brightness[0] < brightness[1] && brightness[1] < brightness[2], "{:?}", brightness);
+        // Like a saw, the second harmonic is the strongest overtone.
+        let saw_like = distorted(0.75);
+        assert!((3..=10).all(|n| harmonic(&saw_like, 100.0, 2) > harmonic(&saw_like, 100.0, n)));
+    }
+
+    #[test]
+    fn the_phase_distortion_envelope_adds_to_the_amount() {
+        let patch = Patch { waveform: Waveform::PhaseDistortion, pd_envelope: 0.5, ..Patch::default() };
+        assert!(overtones(&phase_distorted(&patch, 0.0)) < 1e-3);
+        let swept = phase_distorted(&patch, 1.0);
+        let fixed = phase_distorted(&Patch { pd_amount: 0.5, pd_envelope: 0.0, ..patch }, 0.0);
+        assert_eq!(swept, fixed);
+    }
+
+    // The same timed MIDI, fed to whichever block each event falls in.
+    fn render_in_blocks(block: usize) -> (Vec<f32>, Vec<f32>) {
+        let script: [(usize, [u8; 3]); 8] = [
+            (0, [0x90, 60, 100]),
+            (700, [0x90, 64, 90]),
+            (1500, [0x90, 67, 80]),
+            (2100, [0x80, 60, 0]),
+            (2900, [0xB0, 7, 90]),
+            (3300, [0x90, 71, 110]),
+            (5000, [0x80, 64, 0]),
+            (6100, [0x80, 67, 0]),
+        ];
+        let mut synthesizer = synthesizer();
+        synthesizer.set_max_voices(2);
+        let (mut left, mut right) = (vec![0.0; 19200], vec![0.0; 19200]);
+        for start in (0..19200).step_by(block) {
+            let end = (start + block).min(19200);
+            for (time, bytes) in script.iter().filter(|(time, _)| (start..end).contains(time)) {
+                midi(&mut synthesizer, (time - start) as u32, bytes);
+            }
+            synthesizer.process_block(&mut left[start..end], &mut right[start..end]);
+        }
+        (left, right)
+    }
+
+    #[test]
+    fn output_is_the_same_at_any_block_size() {
+        let whole = render_in_blocks(1024);
+        assert!(peak(&whole.0) > 0.0);
+        assert!(render_in_blocks(1) == whole);
+        assert!(render_in_blocks(64) == whole);
+    }
+
+    fn unison_stereo(unison_stereo_spread: f32) -> (Vec<f32>, Vec<f32>) {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Saw, unison_voices: 2, unison_detune: 30.0, unison_stereo_spread, ..Patch::default() });
+        synthesizer.note_on(0, 57, 100, 0);
+        let (mut left, mut right) = (vec![0.0; 48000], vec![0.0; 48000]);
+        synthesizer.process_block(&mut left, &mut right);
+        (left.split_off(9600), right.split_off(9600))
+    }
+
+    fn correlation(a: &[f32], b: &[f32]) -> f32 {
+        let dot = |x: &[f32], y: &[f32]| x.iter().zip(y).map(|(x, y)| x * y).sum::<f32>();
+        dot(a, b) / (dot(a, a) * dot(b, b)).sqrt()
+    }
+
+    #[test]
+    fn spread_unison_decorrelates_the_sides() {
+        let (left, right) = unison_stereo(1.0);
+        let correlation = correlation(&left, &right);
+        assert!(correlation.abs() < 0.1, "correlation {}", correlation);
+    }
+
+    #[test]
+    fn unspread_unison_is_the_same_on_both_sides() {
+        let (left, right) = unison_stereo(0.0);
+        assert!(peak(&left) > 0.0);
+        assert!(left == right);
+    }
+
+    // The first frames after each of four repeats of one pitch, 1000 frames
+    // apart, which isn't a whole number of cycles. The start-up fade is over
+    // before the first.
+    fn repeated_hits(reset: bool) -> Vec<Vec<f32>> {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, retrigger_phase_reset: reset, retrigger_envelope_reset: reset, ..Patch::default() });
+        render(&mut synthesizer, 480);
+        (0..4).map(|_| {
+            synthesizer.note_on(0, 69, 100, 0);
+            render(&mut synthesizer, 1000)[..64].to_vec()
+        }).collect()
+    }
+
+    #[test]
+    fn resetting_retriggers_makes_every_hit_identical() {
+        let hits = repeated_hits(true);
+        assert!(peak(&hits[0]) > 0.0);
+        assert!(hits.iter().all(|hit| *hit == hits[0]));
+    }
+
+    #[test]
+    fn without_the_reset_the_phase_runs_on_across_repeats() {
+        let hits = repeated_hits(false);
+        assert!(hits[1..].iter().all(|hit| *hit != hits[0]));
+        assert!(!Patch::default().retrigger_phase_reset);
+    }
+
+    #[test]
+    fn every_parameter_reads_back_what_was_set_by_id() {
+        let mut synthesizer = synthesizer();
+        for (id, target) in PARAM_TARGETS.into_iter().enumerate() {
+            let info = target.info();
+            for normalized in [0.0, 0.3, 1.0] {
+                synthesizer.set_param_by_id(0, id, normalized);
+                // Stepped parameters read back snapped to their step.
+                let expected = info.normalize(info.denormalize(normalized));
+                let value = synthesizer.get_param_by_id(0, id).unwrap();
+                assert!((value - expected).abs() < 1e-3, "{} {} {}", info.name, normalized, value);
+            }
+        }
+    }
+
+    // A 10000-frame ramp rooted at middle C, played as `pitch`.
+    fn sampler(sample_rate: usize, looped: bool, pitch: u8) -> Synthesizer {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sample, ..Patch::default() });
+        let data = (0..10000).map(|i| i as f32 / 10000.0).collect();
+        synthesizer.set_sample(Some(Sample { root: 60, looped, ..Sample::new(data, sample_rate) }));
+        synthesizer.note_on(0, pitch, 100, 0);
+        synthesizer
+    }
+
+    #[test]
+    fn a_sample_an_octave_up_plays_at_double_speed() {
+        let mut synthesizer = sampler(SAMPLE_RATE, false, 72);
+        render(&mut synthesizer, 1000);
+        let position = synthesizer.channels[0].notes[0].sample_position;
+        assert!((position - 2000.0).abs() < 2.0, "{}", position);
+
+        // The sample's own rate counts too: half the rate reads half as fast.
+        let mut synthesizer = sampler(SAMPLE_RATE / 2, false, 72);
+        render(&mut synthesizer, 1000);
+        assert!((synthesizer.channels[0].notes[0].sample_position - 1000.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn a_one_shot_sample_ends_its_note() {
+        let mut synthesizer = sampler(SAMPLE_RATE, false, 72);
+        render(&mut synthesizer, 4900);
+        assert_eq!(synthesizer.voice_count(), 1);
+        render(&mut synthesizer, 200);
+        assert_eq!(synthesizer.voice_count(), 0);
+    }
+
+    #[test]
+    fn a_looped_sample_keeps_its_note_playing() {
+        let mut synthesizer = sampler(SAMPLE_RATE, true, 72);
+        render(&mut synthesizer, 24000);
+        assert_eq!(synthesizer.voice_count(), 1);
+        assert!(peak(&render(&mut synthesizer, 4800)) > 0.0);
+    }
+
+    // Panned voices and the auto-pan make the two sides differ.
+    fn panned_synthesizer() -> Synthesizer {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { pan_spread: 1.0, ..Patch::default() });
+        synthesizer.set_auto_pan(Modulation { depth: 0.5, ..Modulation::default() });
+        synthesizer.note_on(0, 40, 100, 0);
+        synthesizer.note_on(0, 90, 100, 0);
+        synthesizer
+    }
+
+    #[test]
+    fn mono_output_is_the_mono_sum_of_the_stereo_output() {
+        let mono = render(&mut panned_synthesizer(), 9600);
+        let (mut left, mut right) = (vec![0.0; 9600], vec![0.0; 9600]);
+        panned_synthesizer().process_block(&mut left, &mut right);
+        assert!(left != right);
+        let sum: Vec<f32> = left.iter().zip(&right).map(|(left, right)| 0.5 * (left + right)).collect();
+        assert_eq!(mono, sum);
+    }
+
+    // Holds 60, 67 and 64 in that order in mono mode, then lets go of whichever
+    // key wins. Returns the sounding pitch before and after.
+    fn mono_priority(note_priority: NotePriority) -> (u8, u8) {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { mono: true, note_priority, ..Patch::default() });
+        for pitch in [60, 67, 64] {
+            synthesizer.note_on(0, pitch, 100, 0);
+            render(&mut synthesizer, 100);
+        }
+        let sounding = |synthesizer: &Synthesizer| {
+            let voices = voice_states(synthesizer);
+            assert_eq!(voices.len(), 1);
+            voices[0].pitch
+        };
+        let winner = sounding(&synthesizer);
+        synthesizer.note_off(0, winner);
+        render(&mut synthesizer, 100);
+        let fallback = sounding(&synthesizer);
+        assert!(!matches!(stages(&synthesizer, fallback)[..], [EnvelopePhase::Release(..)]));
+        (winner, fallback)
+    }
+
+    #[test]
+    fn each_note_priority_picks_and_falls_back_to_its_key() {
+        assert_eq!(mono_priority(NotePriority::Last), (64, 67));
+        assert_eq!(mono_priority(NotePriority::Low), (60, 64));
+        assert_eq!(mono_priority(NotePriority::High), (67, 64));
+    }
+
+    #[test]
+    fn the_mono_voice_releases_once_every_key_is_up() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { mono: true, ..Patch::default() });
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_on(0, 64, 100, 0);
+        render(&mut synthesizer, 100);
+        synthesizer.note_off(0, 60);
+        render(&mut synthesizer, 100);
+        assert!(matches!(stages(&synthesizer, 64)[..], [EnvelopePhase::Attack(..)]));
+        synthesizer.note_off(0, 64);
+        render(&mut synthesizer, 1);
+        assert!(matches!(stages(&synthesizer, 64)[..], [EnvelopePhase::Release(..)]));
+    }
+
+    #[test]
+    fn freezing_holds_the_level_while_the_oscillator_runs() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.note_on(0, 69, 100, 0);
+        render(&mut synthesizer, 1000);
+        synthesizer.set_frozen(true);
+        let amplitude = voice_states(&synthesizer)[0].amplitude;
+        let out = render(&mut synthesizer, 4800);
+        assert_eq!(voice_states(&synthesizer)[0].amplitude, amplitude);
+        assert!(peak(&out) > 0.0 && out.windows(2).any(|pair| pair[0] != pair[1]));
+        synthesizer.set_frozen(false);
+        render(&mut synthesizer, 100);
+        assert!(voice_states(&synthesizer)[0].amplitude > amplitude);
+    }
+
+    #[test]
+    fn the_first_output_fades_in_over_the_start_time() {
+        let tone_synthesizer = |start_time| {
+            let mut synthesizer = synthesizer();
+            synthesizer.set_start_time(start_time);
+            synthesizer.set_test_tone(1000.0, 0.5);
+            synthesizer
+        };
+        let (mut reference, mut started) = (tone_synthesizer(0.0), tone_synthesizer(0.01));
+        let (expected, out) = (render(&mut reference, 960), render(&mut started, 960));
+        assert!(peak(&expected[..10]) > 0.1);
+        for (i, (&expected, &out)) in expected.iter().zip(out.iter()).take(480).enumerate() {
+            assert!((out - expected * i as f32 / 480.0).abs() < 1e-4, "frame {}", i);
+        }
+        assert!(out[480..] == expected[480..]);
+        // It only happens once.
+        assert!(render(&mut started, 960) == render(&mut reference, 960));
+    }
+
+    // A synthesizer at 375 Hz, 128 samples a cycle, with one extra envelope
+    // that decays to silence over 0.5 s routed to `destination`.
+    fn mod_enveloped(destination: ModDestination, amount: f32) -> Synthesizer {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, [375.0; 128]);
+        let mut patch = Patch::default();
+        patch.filter.cutoff = ParamTarget::Cutoff.info().denormalize(0.2);
+        patch.modulation.envelope_count = 1;
+        patch.modulation.envelopes[0] = Envelope { attack: 1, hold: 0, decay: 24000, sustain: 0.0, ..Envelope::default() };
+        assert!(patch.modulation.set_route(0, Some(ModRoute { source: ModSource::Envelope(1), destination, amount })));
+        synthesizer.set_patch(0, patch);
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer
+    }
+
+    // The eighth harmonic against the fundamental, over 20 cycles.
+    fn brightness(synthesizer: &mut Synthesizer) -> f32 {
+        let samples = render(synthesizer, 2560);
+        harmonic(&samples, 375.0, 8) / harmonic(&samples, 375.0, 1)
+    }
+
+    #[test]
+    fn a_mod_envelope_routed_to_cutoff_sweeps_the_filter() {
+        let mut synthesizer = mod_enveloped(ModDestination::Param(ParamTarget::Cutoff), 0.8);
+        render(&mut synthesizer, 2400);
+        let early = brightness(&mut synthesizer);
+        render(&mut synthesizer, 24000);
+        let late = brightness(&mut synthesizer);
+        assert!(early > 4.0 * late, "{} {}", early, late);
+    }
+
+    #[test]
+    fn a_mod_envelope_routed_to_amplitude_shapes_the_level() {
+        let mut routed = mod_enveloped(ModDestination::Amplitude, 0.8);
+        let mut unrouted = mod_enveloped(ModDestination::Amplitude, 0.0);
+        let mut level = |frames| {
+            render(&mut routed, frames);
+            render(&mut unrouted, frames);
+            peak(&render(&mut routed, 480)) / peak(&render(&mut unrouted, 480))
+        };
+        // Half way down the envelope, 0.8 of the route follows it.
+        let middle = level(11520);
+        assert!((middle - 0.6).abs() < 0.03, "{}", middle);
+        let late = level(12000);
+        assert!((late - 0.2).abs() < 0.03, "{}", late);
+        // The route leaves the tone alone.
+        let (routed, unrouted) = (brightness(&mut routed), brightness(&mut unrouted));
+        assert!((routed - unrouted).abs() < 1e-3 * unrouted.max(1.0), "{} {}", routed, unrouted);
+    }
+
+    #[test]
+    fn a_velocity_route_opens_the_cutoff_for_harder_notes() {
+        let played = |velocity| {
+            let mut synthesizer = Synthesizer::new(SAMPLE_RATE, [375.0; 128]);
+            let mut patch = Patch::default();
+            patch.filter.cutoff = ParamTarget::Cutoff.info().denormalize(0.2);
+            assert!(patch.modulation.set_route(0, Some(ModRoute { source: ModSource::Velocity, destination: ModDestination::Param(ParamTarget::Cutoff), amount: 0.8 })));
+            synthesizer.set_patch(0, patch);
+            synthesizer.note_on(0, 60, velocity, 0);
+            render(&mut synthesizer, 4800);
+            brightness(&mut synthesizer)
+        };
+        let (soft, medium, hard) = (played(1), played(64), played(127));
+        assert!(soft < medium && medium < hard, "{} {} {}", soft, medium, hard);
+        assert!(hard > 4.0 * soft, "{} {}", soft, hard);
+    }
+
+    // The frames at which the steps of a four-step sixteenth-note sequence
+    // with `humanize` start, at 120 BPM: 6000 frames a step on the grid.
+    fn step_onsets(humanize: f32, seed: u64) -> Vec<usize> {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_seed(seed);
+        let steps = [60, 62, 64, 65].map(|pitch| Some(Step { pitch, velocity: 100 })).to_vec();
+        synthesizer.set_sequencer(Some(Sequencer { steps, humanize, ..Sequencer::default() }));
+        synthesizer.transport_mut().set_bpm(120.0);
+        synthesizer.transport_mut().play();
+        let mut onsets = Vec::new();
+        let mut held = Vec::new();
+        for frame in 0..24000 {
+            render(&mut synthesizer, 1);
+            let now = synthesizer.held_notes();
+            if now.iter().any(|pitch| !held.contains(pitch)) {
+                onsets.push(frame);
+            }
+            held = now;
+        }
+        onsets
+    }
+
+    #[test]
+    fn humanized_steps_fall_behind_the_grid_reproducibly() {
+        let grid = step_onsets(0.0, 1);
+        assert_eq!(grid.len(), 4);
+        // The beat is summed frame by frame, so a step may land a frame late.
+        assert!(grid.iter().enumerate().all(|(step, &frame)| (6000 * step..=6000 * step + 1).contains(&frame)), "{:?}", grid);
+        assert_eq!(step_onsets(0.0, 2), grid);
+        // At half the amount, steps come up to 10 ms, 480 frames, late.
+        let humanized = step_onsets(0.5, 1);
+        assert_eq!(humanized.len(), 4);
+        assert!(humanized.iter().zip(grid.iter()).all(|(step, on_grid)| (*on_grid..=on_grid + 480).contains(step)), "{:?}", humanized);
+        assert!(humanized != grid);
+        assert_eq!(step_onsets(0.5, 1), humanized);
+        assert!(step_onsets(0.5, 2) != humanized);
+    }
+
+    #[test]
+    fn a_note_reports_its_life_in_order_with_one_id() {
+        let mut synthesizer = synthesizer();
+        let queue = synthesizer.voice_event_queue();
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_on(1, 64, 90, 0);
+        render(&mut synthesizer, 4800);
+        synthesizer.note_off(0, 60);
+        render(&mut synthesizer, 2 * crate::patch::RELEASE);
+        let mut events = Vec::new();
+        queue.drain(&mut events);
+        let event = |kind, id, channel, pitch, velocity| VoiceEvent { kind, id, channel, pitch, velocity };
+        assert_eq!(
+            events,
+            [
+                event(VoiceEventKind::NoteOn, 0, 0, 60, 100),
+                event(VoiceEventKind::NoteOn, 1, 1, 64, 90),
+                event(VoiceEventKind::NoteOff, 0, 0, 60, 100),
+                event(VoiceEventKind::Freed, 0, 0, 60, 100),
+            ]
+        );
+        // Ids keep counting, and reading the queue empties it.
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 64);
+        events.clear();
+        queue.drain(&mut events);
+        assert_eq!(events, [event(VoiceEventKind::NoteOn, 2, 0, 60, 100)]);
+    }
+
+    #[test]
+    fn an_mpe_bend_moves_only_the_note_on_its_channel() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.set_mpe(Some(Mpe::default()));
+        synthesizer.set_stem_count(3);
+        for channel in 1..=2 {
+            synthesizer.set_bus(channel, channel as usize);
+            synthesizer.note_on(channel, 57, 100, 0);
+        }
+        stems(&mut synthesizer, 4800);
+        // A quarter of the 48 semitone member range is an octave.
+        synthesizer.pitch_bend(2, 0.25);
+        stems(&mut synthesizer, 24000);
+        let bent = stems(&mut synthesizer, 24000);
+        let (still, raised) = (frequency_of(&bent[1]), frequency_of(&bent[2]));
+        assert!((still - 220.0).abs() < 0.5, "{}", still);
+        assert!((raised - 440.0).abs() < 1.0, "{}", raised);
+    }
+
+    #[test]
+    fn the_master_channel_bends_the_whole_zone() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.set_mpe(Some(Mpe::default()));
+        synthesizer.set_stem_count(3);
+        for channel in 1..=2 {
+            synthesizer.set_bus(channel, channel as usize);
+            synthesizer.note_on(channel, 57, 100, 0);
+        }
+        synthesizer.pitch_bend(0, 1.0);
+        stems(&mut synthesizer, 24000);
+        let bent = stems(&mut synthesizer, 24000);
+        let expected = 220.0 * 2.0_f32.powf(Patch::default().bend_range / 12.0);
+        for stem in &bent[1..] {
+            assert!((frequency_of(stem) - expected).abs() < 1.0, "{}", frequency_of(stem));
+        }
+    }
+
+    #[test]
+    fn a_nan_in_the_signal_path_is_silenced_and_counted() {
+        let mut broken = synthesizer();
+        let counter = broken.non_finite_counter();
+        let mut patch = Patch::default();
+        patch.filter.cutoff = f32::NAN;
+        broken.set_patch(0, patch);
+        broken.note_on(0, 60, 100, 0);
+        let (mut left, mut right) = (vec![0.0; 4800], vec![0.0; 4800]);
+        broken.process_block(&mut left, &mut right);
+        assert!(left.iter().chain(right.iter()).all(|sample| sample.is_finite()));
+        let count = counter.count();
+        assert!(count > 0);
+        broken.process_block(&mut left, &mut right);
+        assert!(counter.count() > count);
+        // A healthy patch adds nothing.
+        let mut healthy = synthesizer();
+        let counter = healthy.non_finite_counter();
+        healthy.note_on(0, 60, 100, 0);
+        healthy.process_block(&mut left, &mut right);
+        assert_eq!(counter.count(), 0);
+    }
+
+    fn ducked_peak(ducking: f32, pitches: &[u8]) -> f32 {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_voice_ducking(ducking);
+        for &pitch in pitches {
+            synthesizer.note_on(0, pitch, 100, 0);
+        }
+        sustained_peak(&mut synthesizer, 48000)
+    }
+
+    #[test]
+    fn ducking_holds_a_chord_down_and_leaves_a_single_note_alone() {
+        let chord = [48, 52, 55, 60, 64, 67];
+        let single = ducked_peak(0.0, &[60]);
+        assert_eq!(ducked_peak(1.0, &[60]), single);
+        let (undamped, ducked) = (ducked_peak(0.0, &chord), ducked_peak(1.0, &chord));
+        assert!(undamped > 3.0 * single, "{} {}", undamped, single);
+        assert!(ducked > single && ducked < 2.0 * single, "{} {}", ducked, single);
+    }
+
+    #[test]
+    fn ducking_glides_as_voices_come_and_go() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_voice_ducking(1.0);
+        for pitch in [48, 52, 55, 60] {
+            synthesizer.note_on(0, pitch, 100, 0);
+        }
+        render(&mut synthesizer, 1);
+        assert!(synthesizer.ducking_gain > 0.95, "{}", synthesizer.ducking_gain);
+        render(&mut synthesizer, 9600);
+        assert!((synthesizer.ducking_gain - 0.5).abs() < 1e-3, "{}", synthesizer.ducking_gain);
+    }
+
+    // Counts allocations per thread, so tests running alongside don't
+    // disturb the count.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    fn count_allocation() {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            count_allocation();
+            unsafe { std::alloc::System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            unsafe { std::alloc::System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+            count_allocation();
+            unsafe { std::alloc::System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    fn rendering_after_prepare_does_not_allocate() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_stem_count(2);
+        synthesizer.set_bus(1, 1);
+        let queue = synthesizer.voice_event_queue();
+        synthesizer.prepare(256);
+        synthesizer.prepare(128);
+        let stem = synthesizer.stem_buffers[1].as_ptr();
+        let (mut left, mut right) = (vec![0.0; 256], vec![0.0; 256]);
+        let mut events = Vec::with_capacity(64);
+        let before = ALLOCATIONS.with(|count| count.get());
+        for block in 0..400 {
+            match block % 20 {
+                0 => synthesizer.note_on((block / 20 % 2) as u8, 48 + (block / 20) as u8, 100, 0),
+                10 => midi(&mut synthesizer, 100, &[0x80 | (block / 20 % 2) as u8, 48 + (block / 20) as u8, 0]),
+                _ => {}
+            }
+            synthesizer.process_block(&mut left, &mut right);
+            queue.drain(&mut events);
+            events.clear();
+        }
+        assert_eq!(ALLOCATIONS.with(|count| count.get()) - before, 0);
+        assert_eq!(synthesizer.stem_buffers[1].as_ptr(), stem);
+        assert_eq!(synthesizer.stem(1).len(), 256);
+    }
+
+    #[test]
+    fn a_sample_rate_change_after_prepare_does_not_allocate() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_delay(Delay { level: 0.5, ..Delay::default() });
+        synthesizer.set_reverb(Reverb { level: 0.5, ..Reverb::default() });
+        synthesizer.set_limiter(Limiter { enabled: true, lookahead: 0.005, ..Limiter::default() });
+        synthesizer.set_sample_rate(96000);
+        synthesizer.prepare(256);
+        let (mut left, mut right) = (vec![0.0; 256], vec![0.0; 256]);
+        let before = ALLOCATIONS.with(|count| count.get());
+        for _ in 0..100 {
+            synthesizer.process_block(&mut left, &mut right);
+        }
+        assert_eq!(ALLOCATIONS.with(|count| count.get()) - before, 0);
+        assert_eq!(synthesizer.sample_rate(), 96000);
+        assert_eq!(synthesizer.latency(), 480);
+    }
+
+    #[test]
+    fn a_rendered_note_rises_through_its_attack_and_falls_after_release() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        let out = synthesizer.render_note_released(69, 100, 24000, 12000);
+        let window = |start: usize| peak(&out[start..start + 480]);
+        // The attack runs over the first 2000 frames, from frame 0.
+        assert!(out[0].abs() < 1e-3);
+        assert!(window(0) < window(720) && window(720) < window(1440), "{} {} {}", window(0), window(720), window(1440));
+        let sustained = window(11000);
+        assert!(window(14000) < 0.9 * sustained && window(18000) < window(14000) && window(23000) < 0.1 * sustained);
+        assert_eq!(synthesizer.render_note(69, 100, 9600).len(), 9600);
+    }
+
+    fn trimmed(trim: f32, invert: bool) -> Vec<f32> {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { trim, invert, ..Patch::default() });
+        synthesizer.render_note(57, 100, 9600)
+    }
+
+    #[test]
+    fn trim_scales_and_invert_flips_the_patch_output() {
+        let plain = trimmed(0.0, false);
+        let mut untrimmed = synthesizer();
+        assert!(untrimmed.render_note(57, 100, 9600) == plain);
+        let quieter = trimmed(-6.0, false);
+        let factor = 10.0_f32.powf(-6.0 / 20.0);
+        assert!(quieter.iter().zip(plain.iter()).all(|(q, p)| (q - factor * p).abs() < 1e-5));
+        let inverted = trimmed(0.0, true);
+        assert!(inverted.iter().zip(plain.iter()).all(|(i, p)| *i == -p));
+        assert!(peak(&plain) > 0.05);
+    }
+
+    #[test]
+    fn the_panic_note_clears_every_voice_and_stays_silent() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_panic_note(Some(21));
+        synthesizer.note_on(0, 60, 100, 0);
+        synthesizer.note_on(1, 64, 100, 0);
+        render(&mut synthesizer, 4800);
+        assert_eq!(synthesizer.voice_count(), 2);
+        midi(&mut synthesizer, 0, &[0x93, 21, 100]);
+        render(&mut synthesizer, 4800);
+        assert_eq!(synthesizer.voice_count(), 0);
+        assert!(synthesizer.held_notes().is_empty());
+        // Pressed on its own it makes no sound at all.
+        synthesizer.note_on(2, 21, 127, 0);
+        assert_eq!(synthesizer.voice_count(), 0);
+        assert_eq!(peak(&render(&mut synthesizer, 4800)), 0.0);
+        // Other keys still play, and without a panic note the key is a note.
+        synthesizer.note_on(0, 22, 100, 0);
+        assert_eq!(synthesizer.voice_count(), 1);
+        synthesizer.set_panic_note(None);
+        synthesizer.note_on(0, 21, 100, 0);
+        assert_eq!(synthesizer.voice_count(), 2);
+    }
+
+    // The seventh harmonic against the fundamental for a 370 Hz note at
+    // `hard` velocity and a 250 Hz one at `soft`, played together through a
+    // velocity to cutoff route, over a window holding whole cycles of both.
+    // The two are far enough from sharing harmonics that what the filter
+    // adds by mixing them stays off the measured ones.
+    fn velocity_filtered(placement: FilterPlacement, soft: u8, hard: u8) -> (f32, f32) {
+        let mut frequencies = [370.0; 128];
+        frequencies[61] = 250.0;
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, frequencies);
+        let mut patch = Patch::default();
+        patch.filter.placement = placement;
+        patch.filter.cutoff = ParamTarget::Cutoff.info().denormalize(0.2);
+        assert!(patch.modulation.set_route(0, Some(ModRoute { source: ModSource::Velocity, destination: ModDestination::Param(ParamTarget::Cutoff), amount: 0.8 })));
+        synthesizer.set_patch(0, patch);
+        synthesizer.note_on(0, 60, hard, 0);
+        synthesizer.note_on(0, 61, soft, 0);
+        render(&mut synthesizer, 4800);
+        let samples = render(&mut synthesizer, 4800);
+        let brightness = |frequency| harmonic(&samples, frequency, 7) / harmonic(&samples, frequency, 1);
+        (brightness(370.0), brightness(250.0))
+    }
+
+    #[test]
+    fn per_voice_filters_follow_each_notes_own_velocity() {
+        let (hard, soft) = velocity_filtered(FilterPlacement::PerVoice, 40, 127);
+        assert!(hard > 2.0 * soft, "{} {}", hard, soft);
+        // Swapping the velocities swaps the tones.
+        let (soft, hard) = velocity_filtered(FilterPlacement::PerVoice, 127, 40);
+        assert!(hard > 2.0 * soft, "{} {}", hard, soft);
+    }
+
+    #[test]
+    fn a_master_filter_is_shared_by_every_note() {
+        // The shared filter sits at the patch's own cutoff, so neither note
+        // opens it however hard it is played.
+        let (hard, _) = velocity_filtered(FilterPlacement::PerVoice, 40, 127);
+        let (first, second) = velocity_filtered(FilterPlacement::Master, 40, 127);
+        let (swapped_first, swapped_second) = velocity_filtered(FilterPlacement::Master, 127, 40);
+        assert!([first, second, swapped_first, swapped_second].iter().all(|&brightness| brightness < 0.1 * hard), "{} {} {} {}", first, second, swapped_first, swapped_second);
+    }
+
+    #[test]
+    fn the_velocity_auto_range_rescales_incoming_notes_once_enabled() {
+        let mut synthesizer = synthesizer();
+        let velocity_of = |synthesizer: &mut Synthesizer, velocity: u8| {
+            midi(synthesizer, 0, &[0x90, 60, velocity]);
+            render(synthesizer, 1);
+            let played = voice_states(synthesizer).iter().find(|voice| voice.pitch == 60 && !matches!(voice.stage, EnvelopePhase::Release(..))).map(|voice| voice.velocity);
+            midi(synthesizer, 0, &[0x80, 60, 0]);
+            render(synthesizer, 1);
+            played
+        };
+        assert_eq!(velocity_of(&mut synthesizer, 40), Some(40));
+        synthesizer.set_velocity_auto_range(true);
+        for note in 0..100 {
+            velocity_of(&mut synthesizer, 40 + (note * 7 % 61) as u8);
+        }
+        assert!(velocity_of(&mut synthesizer, 40).unwrap() <= 10);
+        assert!(velocity_of(&mut synthesizer, 100).unwrap() >= 117);
+        synthesizer.reset_velocity_range();
+        assert_eq!(velocity_of(&mut synthesizer, 40), Some(40));
+    }
+
+    // Frames from the note-off of a fully sustained note with a one second
+    // linear release until its voice is freed.
+    fn frames_until_freed(threshold: f32) -> usize {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_silence_threshold(threshold);
+        let mut patch = Patch::default();
+        patch.envelope.release = 48000;
+        synthesizer.set_patch(0, patch);
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 24000);
+        synthesizer.note_off(0, 60);
+        (1..=96000).find(|_| {
+            render(&mut synthesizer, 1);
+            synthesizer.voice_count() == 0
+        }).unwrap()
+    }
+
+    #[test]
+    fn a_release_below_the_silence_threshold_is_freed_early() {
+        // The release falls from 0.6 and passes 0.06 nine tenths of the way in.
+        let (early, full) = (frames_until_freed(0.06), frames_until_freed(0.0));
+        assert!(early.abs_diff(43200) <= 2, "{}", early);
+        assert!(full.abs_diff(48000) <= 2, "{}", full);
+        assert!(frames_until_freed(SILENCE_THRESHOLD) < full);
+    }
+
+    #[test]
+    fn resetting_the_clock_renders_the_same_again() {
+        let mut synthesizer = sine_synthesizer(0.0);
+        synthesizer.set_tremolo(Modulation { depth: 0.8, rate: LfoRate { hz: 3.0, division: None }, waveform: LfoWaveform::Sine, retrigger: LfoRetrigger::Free });
+        render(&mut synthesizer, 480);
+        assert_eq!(synthesizer.clock(), 480);
+        synthesizer.reset_clock();
+        assert_eq!(synthesizer.clock(), 0);
+        let first = synthesizer.render_note_released(69, 100, 24000, 12000);
+        assert_eq!(synthesizer.clock(), 24000);
+        render(&mut synthesizer, 17000);
+        assert_eq!(synthesizer.voice_count(), 0);
+        // Without the reset the tremolo has moved on.
+        let moved_on = synthesizer.render_note_released(69, 100, 24000, 12000);
+        assert!(moved_on != first);
+        render(&mut synthesizer, 17000);
+        synthesizer.reset_clock();
+        assert!(synthesizer.render_note_released(69, 100, 24000, 12000) == first);
+    }
+
+    #[test]
+    fn the_frame_argument_only_places_events() {
+        let rendered = |offset: usize| {
+            let mut synthesizer = sine_synthesizer(0.0);
+            synthesizer.note_on(0, 69, 100, 0);
+            let out: Vec<_> = (0..9600).map(|frame| synthesizer.get_audio_data(offset + frame).0).collect();
+            assert_eq!(synthesizer.clock(), 9600);
+            out
+        };
+        assert!(rendered(0) == rendered(123456));
+    }
+
+    // Whether a note played `gap` frames after the last key-up glides from
+    // the note before it.
+    fn glides_after_gap(glide_window: f32, gap: usize) -> bool {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { glide_time: 0.1, glide_window, ..Patch::default() });
+        synthesizer.note_on(0, 57, 100, 0);
+        render(&mut synthesizer, 4800);
+        synthesizer.note_off(0, 57);
+        render(&mut synthesizer, gap);
+        synthesizer.note_on(0, 69, 100, 0);
+        render(&mut synthesizer, 1);
+        let note = synthesizer.channels[0].notes.iter().find(|note| note.pitch == 69).unwrap();
+        note.glide != 0.0
+    }
+
+    #[test]
+    fn only_notes_inside_the_glide_window_glide() {
+        assert!(glides_after_gap(0.05, 480));
+        assert!(!glides_after_gap(0.05, 9600));
+        assert!(!glides_after_gap(0.0, 0));
+        assert!(glides_after_gap(f32::INFINITY, 96000));
+    }
+
+    #[test]
+    fn a_key_still_held_always_glides_inside_the_window() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { glide_time: 0.1, glide_window: 0.01, ..Patch::default() });
+        synthesizer.note_on(0, 57, 100, 0);
+        render(&mut synthesizer, 48000);
+        synthesizer.note_on(0, 69, 100, 0);
+        render(&mut synthesizer, 1);
+        assert!(synthesizer.channels[0].notes.iter().any(|note| note.pitch == 69 && note.glide != 0.0));
+    }
+
+    #[test]
+    fn the_a_formant_shapes_a_saw_around_its_formants() {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, [100.0; 128]);
+        synthesizer.set_patch(0, Patch { formant: Formant { mix: 1.0, vowel: Vowel::A.position() }, ..Patch::default() });
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 9600);
+        let samples = render(&mut synthesizer, 4800);
+        // Scaled by n, a plain saw's harmonics would all be level.
+        let level = |n: usize| n as f32 * harmonic(&samples, 100.0, n);
+        assert!(level(8) > 2.0 * level(4) && level(8) > 2.0 * level(16), "{} {} {}", level(4), level(8), level(16));
+        assert!(level(11) > level(16), "{} {}", level(11), level(16));
+    }
+
+    #[test]
+    fn notes_outside_the_key_range_are_ignored() {
+        let played = |pitch: u8| {
+            let mut synthesizer = synthesizer();
+            synthesizer.set_key_range(48, 72);
+            midi(&mut synthesizer, 0, &[0x90, pitch, 100]);
+            peak(&render(&mut synthesizer, 4800))
+        };
+        assert_eq!(played(47), 0.0);
+        assert_eq!(played(73), 0.0);
+        assert!(played(48) > 0.05 && played(60) > 0.05 && played(72) > 0.05);
+        assert_eq!(synthesizer().key_range(), (0, 127));
+    }
+
+    #[test]
+    fn narrowing_the_key_range_strands_no_note() {
+        let mut synthesizer = synthesizer();
+        synthesizer.note_on(0, 40, 100, 0);
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 4800);
+        synthesizer.set_key_range(48, 72);
+        // The note-off for the out-of-range key is ignored, but the key was
+        // already released when the range moved.
+        synthesizer.note_off(0, 40);
+        assert_eq!(synthesizer.held_notes(), [60]);
+        render(&mut synthesizer, 2 * crate::patch::RELEASE);
+        assert_eq!(synthesizer.sounding_notes(), [60]);
+    }
+
+    // The share of 20 ms windows through a two second octave glide up from
+    // middle C that sit on a note of C major.
+    fn glide_on_scale(snap_scale: Scale) -> f32 {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, glide_time: 2.0, snap_scale, ..Patch::default() });
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 4800);
+        synthesizer.note_off(0, 60);
+        synthesizer.note_on(0, 72, 100, 0);
+        let out = render(&mut synthesizer, 96000);
+        let table = equal_temperament();
+        let on_scale = |frequency: f32| (60..=72).filter(|&pitch| Scale::Major.contains(pitch, 0)).any(|pitch| (frequency / table[pitch as usize] - 1.0).abs() < 0.01);
+        let windows: Vec<f32> = out.chunks(960).map(frequency_of).collect();
+        windows.iter().filter(|&&frequency| on_scale(frequency)).count() as f32 / windows.len() as f32
+    }
+
+    #[test]
+    fn a_snapped_glide_steps_through_the_scale() {
+        let snapped = glide_on_scale(Scale::Major);
+        assert!(snapped > 0.9, "{}", snapped);
+        let swept = glide_on_scale(Scale::Chromatic);
+        assert!(swept < 0.4, "{}", swept);
+    }
+
+    // The share of a note's level sent on to the effects, one entry a frame,
+    // with the note released at frame 12000.
+    fn send_shares(patch: Patch) -> Vec<Option<f32>> {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, patch);
+        synthesizer.note_on(0, 60, 100, 0);
+        let mut shares = Vec::new();
+        let (mut dry, mut sent) = (0.0, 0.0);
+        for frame in 0..24000 {
+            if frame == 12000 {
+                synthesizer.note_off(0, 60);
+            }
+            let (left, _) = synthesizer.get_audio_data(frame);
+            dry += left.abs();
+            sent += synthesizer.send.map_or(0.0, |(send, _)| send.abs());
+            if frame % 480 == 479 {
+                shares.push(synthesizer.send.map(|_| sent / dry));
+                (dry, sent) = (0.0, 0.0);
+            }
+        }
+        shares
+    }
+
+    #[test]
+    fn an_envelope_send_route_sends_more_as_the_note_releases() {
+        let mut patch = Patch::default();
+        assert!(patch.modulation.set_route(0, Some(ModRoute { source: ModSource::Envelope(0), destination: ModDestination::Send, amount: -1.0 })));
+        let shares: Vec<f32> = send_shares(patch).into_iter().map(Option::unwrap).collect();
+        // The attack peaks around frame 2000, and the send ducks under it.
+        let (peak, sustain, release) = (shares[4], shares[20], shares[40]);
+        assert!(peak < 0.1, "{:?}", shares);
+        assert!((sustain - 0.4).abs() < 0.05, "{}", sustain);
+        assert!(release > sustain + 0.2, "{}", release);
+        // Without a send route the effects hear the plain sum.
+        assert!(send_shares(Patch::default()).iter().all(Option::is_none));
+    }
+
+    // A 375 Hz and a 250 Hz sine on one channel, gated by a free-running 1 Hz
+    // square LFO in `mode`, the second note starting a quarter cycle after
+    // the first. Returns whether each is sounding over 40 ms from `frame`.
+    fn lfo_gates(mode: LfoMode, frames: &[usize]) -> Vec<(bool, bool)> {
+        let mut frequencies = [375.0; 128];
+        frequencies[61] = 250.0;
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, frequencies);
+        let mut patch = Patch { waveform: Waveform::Sine, ..Patch::default() };
+        patch.modulation.lfos[0] = ModLfo { rate: LfoRate { hz: 1.0, division: None }, waveform: LfoWaveform::Square, retrigger: LfoRetrigger::Free, mode };
+        assert!(patch.modulation.set_route(0, Some(ModRoute { source: ModSource::Lfo(0), destination: ModDestination::Amplitude, amount: 1.0 })));
+        synthesizer.set_patch(0, patch);
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 12000);
+        synthesizer.note_on(0, 61, 100, 0);
+        let out = render(&mut synthesizer, 60000);
+        frames
+            .iter()
+            .map(|&frame| {
+                // 1920 frames hold whole cycles of both.
+                let window = &out[frame - 12000..frame - 12000 + 1920];
+                (harmonic(window, 375.0, 1) > 0.02, harmonic(window, 250.0, 1) > 0.02)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn per_voice_lfos_run_from_each_notes_start() {
+        let frames = [14000, 30000, 40000, 50000];
+        assert_eq!(lfo_gates(LfoMode::PerVoice, &frames), [(true, true), (false, true), (false, false), (true, false)]);
+        assert_eq!(lfo_gates(LfoMode::Global, &frames), [(true, true), (false, false), (false, false), (true, true)]);
+    }
+
+    // The drift in cents of the first two unison voices of a note, sampled
+    // every 10 ms for two seconds.
+    fn unison_drift_paths(drift_correlation: f32, seed: u64) -> (Vec<f32>, Vec<f32>) {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_seed(seed);
+        let patch = Patch { drift_amount: 10.0, drift_rate: 5.0, drift_correlation, unison_voices: 2, ..Patch::default() };
+        synthesizer.set_patch(0, patch);
+        synthesizer.note_on(0, 69, 100, 0);
+        let (mut first, mut second) = (Vec::new(), Vec::new());
+        for _ in 0..200 {
+            render(&mut synthesizer, 480);
+            let note = &synthesizer.channels[0].notes[0];
+            let cents = |index: usize| {
+                let own = if patch.unison_drifts() { (1.0 - drift_correlation) * (note.unison_drifts[index] - note.drift) } else { 0.0 };
+                patch.drift_amount * (note.drift + own)
+            };
+            first.push(cents(0));
+            second.push(cents(1));
+        }
+        (first, second)
+    }
+
+    fn centered(values: &[f32]) -> Vec<f32> {
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        values.iter().map(|value| value - mean).collect()
+    }
+
+    #[test]
+    fn drift_correlation_sets_how_far_unison_voices_drift_together() {
+        let (first, second) = unison_drift_paths(1.0, 3);
+        assert!(first == second);
+        assert!(first.iter().any(|&cents| cents.abs() > 1.0));
+        let (first, second) = unison_drift_paths(0.0, 3);
+        let independent = correlation(&centered(&first), &centered(&second));
+        assert!(independent.abs() < 0.5, "{}", independent);
+        assert!(unison_drift_paths(0.0, 3) == (first, second));
+    }
+
+    #[test]
+    fn a_host_position_moves_the_sequencer_to_its_step() {
+        let mut synthesizer = synthesizer();
+        let steps = [60, 62, 64, 65].map(|pitch| Some(Step { pitch, velocity: 100 })).to_vec();
+        synthesizer.set_sequencer(Some(Sequencer { steps, ..Sequencer::default() }));
+        // Beat 2.25 is the tenth sixteenth, the second step of the pattern.
+        synthesizer.transport_mut().follow_host(2.25, 120.0);
+        render(&mut synthesizer, 64);
+        assert_eq!(synthesizer.held_notes(), [62]);
+        // Half a beat on at 120 BPM is two steps later.
+        render(&mut synthesizer, 12000);
+        assert_eq!(synthesizer.held_notes(), [65]);
+        synthesizer.transport_mut().release_host();
+        render(&mut synthesizer, 24000);
+        assert!(synthesizer.held_notes().is_empty());
+    }
+
+    fn velocity_brightened(amount: f32, velocity: u8) -> f32 {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, [375.0; 128]);
+        synthesizer.set_patch(0, Patch { velocity_brightness: amount, ..Patch::default() });
+        synthesizer.note_on(0, 60, velocity, 0);
+        render(&mut synthesizer, 2400);
+        brightness(&mut synthesizer)
+    }
+
+    #[test]
+    fn velocity_brightness_brightens_harder_notes() {
+        let (soft, hard) = (velocity_brightened(1.0, 20), velocity_brightened(1.0, 127));
+        assert!(hard > 2.0 * soft, "{} {}", soft, hard);
+        let (soft, hard) = (velocity_brightened(0.0, 20), velocity_brightened(0.0, 127));
+        assert!((hard / soft - 1.0).abs() < 0.01, "{} {}", soft, hard);
+    }
+
+    fn brightness_macro() -> Vec<MacroMapping> {
+        vec![
+            MacroMapping { target: ParamTarget::Cutoff, min: 0.2, max: 1.0 },
+            MacroMapping { target: ParamTarget::Resonance, min: 0.8, max: 0.0 },
+            MacroMapping { target: ParamTarget::Volume, min: 0.5, max: 0.7 },
+        ]
+    }
+
+    #[test]
+    fn a_macro_moves_every_mapped_parameter() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_macro_mappings(2, brightness_macro());
+        synthesizer.set_macro(0, 2, 0.25);
+        assert_eq!(synthesizer.macro_knob(2).unwrap().value, 0.25);
+        for (target, expected) in [(ParamTarget::Cutoff, 0.4), (ParamTarget::Resonance, 0.6), (ParamTarget::Volume, 0.55)] {
+            assert!((synthesizer.get_param(0, target) - expected).abs() < 1e-3, "{:?}", target);
+        }
+        // Other macros and channels are left alone.
+        synthesizer.set_macro(1, 3, 1.0);
+        assert_eq!(synthesizer.get_param(1, ParamTarget::Cutoff), 1.0);
+        synthesizer.set_macro(0, MAX_MACROS, 1.0);
+    }
+
+    #[test]
+    fn a_macro_follows_its_controller() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_macro_mappings(0, brightness_macro());
+        synthesizer.bind_macro_cc(74, 0);
+        assert!(!synthesizer.cc_map().contains_key(&74));
+        synthesizer.control_change(0, 74, 95);
+        let value = 95.0 / 127.0;
+        assert!((synthesizer.get_param(0, ParamTarget::Cutoff) - (0.2 + 0.8 * value)).abs() < 1e-3);
+        assert!((synthesizer.get_param(0, ParamTarget::Resonance) - 0.8 * (1.0 - value)).abs() < 1e-3);
+        // Moving the macro to another controller frees the first.
+        synthesizer.bind_macro_cc(20, 0);
+        assert_eq!(synthesizer.macro_cc_map().len(), 1);
+        synthesizer.control_change(0, 20, 0);
+        assert!((synthesizer.get_param(0, ParamTarget::Resonance) - 0.8).abs() < 1e-3);
+        synthesizer.bind_cc(20, ParamTarget::Resonance);
+        assert!(synthesizer.macro_cc_map().is_empty());
+    }
+
+    #[test]
+    fn a_retune_passes_the_midpoint_at_half_the_retune_time() {
+        let retuned = |seconds: f32| {
+            let mut synthesizer = sine_synthesizer(0.0);
+            synthesizer.set_retune_time(seconds);
+            synthesizer.note_on(0, 69, 100, 0);
+            render(&mut synthesizer, 4800);
+            synthesizer.set_frequencies(equal_temperament().map(|frequency| frequency * 2.0));
+            render(&mut synthesizer, 1920);
+            frequency_of(&render(&mut synthesizer, 960))
+        };
+        // Halfway in pitch from A4 to A5 is 440 * √2 Hz.
+        let halfway = retuned(0.1);
+        assert!((halfway - 440.0 * std::f32::consts::SQRT_2).abs() < 30.0, "{}", halfway);
+        let instant = retuned(0.0);
+        assert!((instant - 880.0).abs() < 2.0, "{}", instant);
+    }
+
+    // The third harmonic against the fundamental of a sustained sine.
+    fn voice_driven(drive: f32, velocity: u8) -> f32 {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, [375.0; 128]);
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, voice_drive: drive, ..Patch::default() });
+        synthesizer.note_on(0, 60, velocity, 0);
+        render(&mut synthesizer, 24000);
+        let samples = render(&mut synthesizer, 2560);
+        harmonic(&samples, 375.0, 3) / harmonic(&samples, 375.0, 1)
+    }
+
+    #[test]
+    fn voice_drive_adds_harmonics_to_a_sustained_note() {
+        assert!(voice_driven(0.0, 100) < 0.001, "{}", voice_driven(0.0, 100));
+        let driven = voice_driven(0.5, 100);
+        assert!(driven > 0.1, "{}", driven);
+        // The shaper comes before the envelope, so a softer note saturates just as much.
+        assert!((voice_driven(0.5, 30) / driven - 1.0).abs() < 0.02);
+    }
+
+    // The sustained peak of sine notes on `pitches`, all tuned to 375 Hz.
+    fn phased_peak(random_phase: bool, seed: u64, pitches: &[u8]) -> f32 {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, [375.0; 128]);
+        synthesizer.set_seed(seed);
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, random_phase, ..Patch::default() });
+        for &pitch in pitches {
+            synthesizer.note_on(0, pitch, 100, 0);
+        }
+        sustained_peak(&mut synthesizer, 9600)
+    }
+
+    #[test]
+    fn random_phase_decorrelates_identical_notes() {
+        let single = phased_peak(false, 1, &[60]);
+        assert!((phased_peak(false, 1, &[60, 61]) / single - 2.0).abs() < 1e-3);
+        for seed in 1..6 {
+            let pair = phased_peak(true, seed, &[60, 61]);
+            assert!(pair < 1.9 * single, "{} {} {}", seed, single, pair);
+            assert_eq!(phased_peak(true, seed, &[60, 61]), pair);
+        }
+    }
+
+    #[test]
+    fn a_panned_voice_feeds_its_speakers_by_their_gains() {
+        // Pitch 127 at half spread sits at pan 0.5, three quarters of the way
+        // from the third speaker to the fourth.
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, pan_spread: 0.5, ..Patch::default() });
+        synthesizer.set_speaker_count(4);
+        synthesizer.note_on(0, 127, 100, 0);
+        let (mut left, mut right) = (vec![0.0; 4800], vec![0.0; 4800]);
+        synthesizer.process_block(&mut left, &mut right);
+        let peaks: Vec<f32> = (0..4).map(|speaker| peak(synthesizer.speaker(speaker))).collect();
+        let total = peaks.iter().map(|peak| peak * peak).sum::<f32>().sqrt();
+        let eighth = std::f32::consts::PI / 8.0;
+        let expected = [0.0, 0.0, eighth.cos(), eighth.sin()];
+        for (peak, expected) in peaks.iter().zip(expected) {
+            assert!((peak / total - expected).abs() < 1e-3, "{:?}", peaks);
+        }
+        // A speaker output carries each sample of the voice at the same gain.
+        let (third, fourth) = (synthesizer.speaker(2), synthesizer.speaker(3));
+        assert!(third.iter().zip(fourth).all(|(a, b)| (a * expected[3] - b * expected[2]).abs() < 1e-6));
+    }
+
+    #[test]
+    fn a_bend_during_a_glide_rides_on_the_gliding_pitch() {
+        let gliding = || {
+            let mut synthesizer = synthesizer();
+            synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, glide_time: 2.0, ..Patch::default() });
+            synthesizer.note_on(0, 60, 100, 0);
+            render(&mut synthesizer, 4800);
+            synthesizer.note_off(0, 60);
+            synthesizer.note_on(0, 72, 100, 0);
+            render(&mut synthesizer, 24000);
+            synthesizer
+        };
+        let (mut straight, mut bent) = (gliding(), gliding());
+        bent.pitch_bend(0, 1.0);
+        render(&mut straight, 4800);
+        render(&mut bent, 4800);
+        let (straight, bent) = (render(&mut straight, 48000), render(&mut bent, 48000));
+        let bend = 2.0_f32.powf(BEND_RANGE / 12.0);
+        let mut last = 0.0;
+        for (straight, bent) in straight.chunks(960).zip(bent.chunks(960)) {
+            let frequency = frequency_of(straight);
+            assert!(frequency > last);
+            assert!((frequency_of(bent) / frequency / bend - 1.0).abs() < 0.003, "{} {}", frequency, frequency_of(bent));
+            last = frequency;
+        }
+    }
+
+    // The first frame of each stem that isn't silent.
+    fn stem_onsets(notes: &[(u8, u32)]) -> Vec<Option<usize>> {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_stem_count(notes.len());
+        for (bus, &(channel, _)) in notes.iter().enumerate() {
+            synthesizer.set_patch(channel, Patch { waveform: Waveform::Square, ..Patch::default() });
+            synthesizer.set_bus(channel, bus);
+        }
+        for &(channel, time) in notes {
+            midi(&mut synthesizer, time, &[0x90 | channel, 60, 100]);
+        }
+        stems(&mut synthesizer, 512).iter().map(|stem| stem.iter().position(|&sample| sample != 0.0)).collect()
+    }
+
+    #[test]
+    fn notes_start_on_their_frame_within_the_block() {
+        // The attack starts from zero, so the first sound is a frame later.
+        assert_eq!(stem_onsets(&[(0, 100), (1, 300)]), [Some(101), Some(301)]);
+        assert_eq!(stem_onsets(&[(0, 200), (1, 200)]), [Some(201), Some(201)]);
+    }
+
+    fn channel_pitches(synthesizer: &Synthesizer, channel: u8) -> Vec<u8> {
+        let mut pitches: Vec<u8> = voice_states(synthesizer).iter().filter(|voice| voice.channel == channel).map(|voice| voice.pitch).collect();
+        pitches.sort_unstable();
+        pitches
+    }
+
+    // Drums on channel 0 outrank a pad on channel 1, with four voices between
+    // them, stolen oldest first.
+    fn layered() -> Synthesizer {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_max_voices(4);
+        synthesizer.set_steal_mode(VoiceStealMode::Oldest);
+        synthesizer.set_patch(0, Patch { voice_priority: 5, max_voices: Some(3), ..Patch::default() });
+        for (channel, pitch) in [(1, 60), (1, 64), (0, 36), (0, 38)] {
+            synthesizer.note_on(channel, pitch, 100, 0);
+            render(&mut synthesizer, 64);
+        }
+        synthesizer
+    }
+
+    #[test]
+    fn a_higher_priority_layer_steals_from_a_lower_one() {
+        let mut synthesizer = layered();
+        synthesizer.note_on(0, 42, 100, 0);
+        render(&mut synthesizer, 64);
+        assert_eq!(channel_pitches(&synthesizer, 0), [36, 38, 42]);
+        assert_eq!(channel_pitches(&synthesizer, 1), [64]);
+        // At its own limit the drum layer takes its own oldest voice.
+        synthesizer.note_on(0, 46, 100, 0);
+        render(&mut synthesizer, 64);
+        assert_eq!(channel_pitches(&synthesizer, 0), [38, 42, 46]);
+        assert_eq!(channel_pitches(&synthesizer, 1), [64]);
+    }
+
+    #[test]
+    fn a_lower_priority_layer_never_steals_from_a_higher_one() {
+        let mut synthesizer = layered();
+        synthesizer.set_patch(0, Patch { voice_priority: 5, ..Patch::default() });
+        synthesizer.note_on(0, 42, 100, 0);
+        synthesizer.note_on(0, 46, 100, 0);
+        render(&mut synthesizer, 64);
+        assert_eq!(channel_pitches(&synthesizer, 0), [36, 38, 42, 46]);
+        synthesizer.note_on(1, 67, 100, 0);
+        render(&mut synthesizer, 64);
+        assert!(channel_pitches(&synthesizer, 1).is_empty());
+        assert_eq!(synthesizer.voice_count(), 4);
+    }
+
+    // Whether a repeat `gap` frames after the first note-on restarts the attack.
+    fn repeat_restarts(debounce_time: f32, gap: usize) -> bool {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { retrigger_envelope_reset: true, ..Patch::default() });
+        synthesizer.set_debounce_time(debounce_time);
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, gap);
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 1);
+        assert_eq!(synthesizer.voice_count(), 1);
+        matches!(stages(&synthesizer, 60)[..], [EnvelopePhase::Attack(frames)] if frames < 10)
+    }
+
+    #[test]
+    fn a_bounce_inside_the_debounce_window_is_ignored() {
+        // 8 ms is 384 frames.
+        assert!(!repeat_restarts(0.008, 300));
+        assert!(repeat_restarts(0.008, 490));
+        assert!(repeat_restarts(0.0, 300));
+        assert!(repeat_restarts(0.0, 10));
+    }
+
+    #[test]
+    fn an_ignored_bounce_does_not_extend_the_window() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { retrigger_envelope_reset: true, ..Patch::default() });
+        synthesizer.set_debounce_time(0.008);
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 300);
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 200);
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 1);
+        assert!(matches!(stages(&synthesizer, 60)[..], [EnvelopePhase::Attack(frames)] if frames < 10));
+        // Other pitches keep their own windows.
+        synthesizer.note_on(0, 64, 100, 0);
+        render(&mut synthesizer, 1);
+        assert_eq!(synthesizer.voice_count(), 2);
+    }
+
+    // The brightness of a held 375 Hz saw 0.2 s and 1.8 s into the note.
+    fn hf_decayed(hf_decay: f32) -> (f32, f32) {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, [375.0; 128]);
+        synthesizer.set_patch(0, Patch { hf_decay, ..Patch::default() });
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 8320);
+        let early = brightness(&mut synthesizer);
+        render(&mut synthesizer, 76800);
+        (early, brightness(&mut synthesizer))
+    }
+
+    #[test]
+    fn hf_decay_dulls_a_note_as_it_ages() {
+        let (early, late) = hf_decayed(2.0);
+        assert!(late < 0.3 * early, "{} {}", early, late);
+        let (early, late) = hf_decayed(0.0);
+        assert!((late / early - 1.0).abs() < 0.01, "{} {}", early, late);
+    }
+
+    fn dark_saw() -> Synthesizer {
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, [375.0; 128]);
+        let mut patch = Patch::default();
+        patch.filter.cutoff = ParamTarget::Cutoff.info().denormalize(0.3);
+        synthesizer.set_patch(0, patch);
+        synthesizer
+    }
+
+    fn slid_brightness(amount: f32, cc74: Option<u8>) -> f32 {
+        let mut synthesizer = dark_saw();
+        synthesizer.set_brightness_cutoff(amount);
+        if let Some(value) = cc74 {
+            synthesizer.control_change(0, 74, value);
+        }
+        synthesizer.note_on(0, 60, 100, 0);
+        render(&mut synthesizer, 9600);
+        brightness(&mut synthesizer)
+    }
+
+    #[test]
+    fn cc74_opens_the_channel_filter() {
+        let amount = synthesizer().brightness_cutoff();
+        let unset = slid_brightness(amount, None);
+        let (closed, half, open) = (slid_brightness(amount, Some(0)), slid_brightness(amount, Some(64)), slid_brightness(amount, Some(127)));
+        assert_eq!(closed, unset);
+        assert!(half > 1.5 * closed && open > 1.5 * half, "{} {} {}", closed, half, open);
+        assert_eq!(slid_brightness(0.0, Some(127)), unset);
+    }
+
+    #[test]
+    fn cc74_on_an_mpe_member_opens_only_its_note() {
+        let mut synthesizer = dark_saw();
+        synthesizer.set_mpe(Some(Mpe::default()));
+        synthesizer.set_stem_count(3);
+        for channel in 1..=2 {
+            synthesizer.set_bus(channel, channel as usize);
+            synthesizer.note_on(channel, 60, 100, 0);
+        }
+        synthesizer.control_change(2, 74, 127);
+        stems(&mut synthesizer, 9600);
+        let slid = stems(&mut synthesizer, 2560);
+        let brightness = |samples: &[f32]| harmonic(samples, 375.0, 8) / harmonic(samples, 375.0, 1);
+        assert!(brightness(&slid[2]) > 3.0 * brightness(&slid[1]), "{} {}", brightness(&slid[1]), brightness(&slid[2]));
+    }
+
+    #[test]
+    fn a_patch_string_reproduces_the_sound_on_another_synthesizer() {
+        let mut source = synthesizer();
+        for (i, target) in [ParamTarget::Cutoff, ParamTarget::Resonance, ParamTarget::VoiceDrive, ParamTarget::HfDecay].into_iter().enumerate() {
+            source.set_param(0, target, 0.2 + 0.15 * i as f32);
+        }
+        let text = source.patch_to_string(0);
+        let mut copy = synthesizer();
+        copy.patch_from_string(3, &text).unwrap();
+        assert!(*copy.patch(3) == *source.patch(0));
+        assert_eq!(copy.cc_map(), source.cc_map());
+        source.note_on(0, 57, 100, 0);
+        copy.note_on(3, 57, 100, 0);
+        assert!(render(&mut source, 4800) == render(&mut copy, 4800));
+    }
+
+    #[test]
+    fn a_bad_patch_string_leaves_the_patch_alone() {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_param(0, ParamTarget::Cutoff, 0.4);
+        let before = *synthesizer.patch(0);
+        let text = synthesizer.patch_to_string(0);
+        for bad in ["", "not a patch", &text[..text.len() / 2]] {
+            let error = synthesizer.patch_from_string(0, bad).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(*synthesizer.patch(0) == before);
+        }
+    }
+
+    // Two seconds of a held sine middle C, less the first 0.1 s.
+    fn stereo_detuned(voice_stereo_detune: f32) -> (Vec<f32>, Vec<f32>) {
+        let mut synthesizer = synthesizer();
+        synthesizer.set_patch(0, Patch { waveform: Waveform::Sine, voice_stereo_detune, ..Patch::default() });
+        synthesizer.note_on(0, 60, 100, 0);
+        let (mut left, mut right) = (vec![0.0; 96000], vec![0.0; 96000]);
+        synthesizer.process_block(&mut left, &mut right);
+        (left.split_off(4800), right.split_off(4800))
+    }
+
+    fn mono_rms(left: &[f32], right: &[f32]) -> f32 {
+        (left.iter().zip(right).map(|(l, r)| (0.5 * (l + r)).powi(2)).sum::<f32>() / left.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn stereo_detune_widens_a_single_note() {
+        let (left, right) = stereo_detuned(0.0);
+        assert!(left == right);
+        let centred = mono_rms(&left, &right);
+        let (left, right) = stereo_detuned(15.0);
+        assert!(correlation(&left, &right).abs() < 0.2, "{}", correlation(&left, &right));
+        let wide = mono_rms(&left, &right);
+        assert!((wide / centred - 1.0).abs() < 0.05, "{} {}", centred, wide);
+    }
+
+    #[test]
+    fn a_velocity_floor_only_lifts_notes_below_it() {
+        let level = |floor: f32, velocity: u8| {
+            let mut synthesizer = sine_synthesizer(floor);
+            synthesizer.note_on(0, 69, velocity, 0);
+            sustained_peak(&mut synthesizer, 24000)
+        };
+        let full = level(0.0, 127);
+        assert_eq!(level(0.1, 127), full);
+        assert_eq!(level(0.1, 100), level(0.0, 100));
+        assert!(level(0.0, 1) < 0.02 * full, "{} {}", level(0.0, 1), full);
+    }
+
+    #[test]
+    fn a_learned_binding_survives_a_patch_string() {
+        let mut source = synthesizer();
+        source.learn(ParamTarget::Resonance);
+        source.control_change(0, 20, 64);
+        source.bind_macro_cc(21, 2);
+        source.unbind_cc(7);
+        let text = source.patch_to_string(0);
+        let mut copy = synthesizer();
+        copy.bind_cc(30, ParamTarget::Cutoff);
+        copy.patch_from_string(0, &text).unwrap();
+        assert_eq!(copy.cc_map().get(&20), Some(&ParamTarget::Resonance));
+        assert_eq!(copy.cc_map(), source.cc_map());
+        assert_eq!(copy.macro_cc_map(), source.macro_cc_map());
+        copy.control_change(0, 20, 127);
+        assert_eq!(copy.get_param(0, ParamTarget::Resonance), 1.0);
+    }
+
+    #[test]
+    fn invalid_frequencies_are_reported_instead_of_printed() {
+        let mut frequencies = equal_temperament();
+        frequencies[0] = -1.0;
+        frequencies[127] = f32::INFINITY;
+        let mut synthesizer = Synthesizer::new(SAMPLE_RATE, frequencies);
+        assert_eq!(synthesizer.disabled_pitches(), [0, 127]);
+        assert!(synthesizer.set_frequencies(equal_temperament()).is_empty());
+        assert!(synthesizer.disabled_pitches().is_empty());
+    }
+}
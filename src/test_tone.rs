@@ -0,0 +1,53 @@
+use std::f32::consts::PI;
+
+pub const TEST_TONE_LEVEL: f32 = 0.2;
+
+#[derive(Copy, Clone, Debug)]
+pub struct TestTone {
+    pub frequency: f32,
+    pub level: f32,
+    phase: f32,
+}
+
+impl TestTone {
+    pub fn new(frequency: f32, level: f32) -> TestTone {
+        TestTone {
+            frequency,
+            level,
+            phase: 0.0,
+        }
+    }
+
+    pub fn next(&mut self, time_step: f32) -> f32 {
+        let value = self.level * (self.phase * 2.0 * PI).sin();
+        self.phase = (self.phase + self.frequency * time_step).rem_euclid(1.0);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    #[test]
+    fn the_tone_runs_at_its_frequency_and_level() {
+        let mut tone = TestTone::new(1000.0, 0.5);
+        let samples: Vec<f32> = (0..48000).map(|_| tone.next(1.0 / SAMPLE_RATE)).collect();
+        let rising = (1..samples.len()).filter(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0).count();
+        assert!((999..=1000).contains(&rising), "{}", rising);
+        let peak = samples.iter().fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        assert!((peak - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn a_live_change_keeps_the_phase() {
+        let mut tone = TestTone::new(440.0, 0.5);
+        let samples: Vec<f32> = (0..100).map(|_| tone.next(1.0 / SAMPLE_RATE)).collect();
+        tone.frequency = 441.0;
+        let next = tone.next(1.0 / SAMPLE_RATE);
+        let step = samples[99] - samples[98];
+        assert!((next - samples[99] - step).abs() < 1e-3);
+    }
+}
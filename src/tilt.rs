@@ -0,0 +1,76 @@
+use std::f32::consts::PI;
+
+const TILT_PIVOT: f32 = 1000.0;
+const MAX_TILT: f32 = 6.0;
+
+// A first-order tilt EQ: the input is split at TILT_PIVOT by a one-pole
+// low-pass, and the lows and highs are turned in opposite directions by up
+// to MAX_TILT dB each. A tilt of 1 is brightest, -1 darkest, 0 flat.
+#[derive(Copy, Clone, Debug)]
+pub struct Tilt {
+    coefficient: f32,
+    low_gain: f32,
+    high_gain: f32,
+}
+
+impl Tilt {
+    pub fn new(tilt: f32, sample_rate: f32) -> Tilt {
+        let high_gain = 10.0_f32.powf(tilt.clamp(-1.0, 1.0) * MAX_TILT / 20.0);
+        Tilt {
+            coefficient: 1.0 - (-2.0 * PI * TILT_PIVOT / sample_rate).exp(),
+            low_gain: 1.0 / high_gain,
+            high_gain,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TiltState {
+    low: f32,
+}
+
+impl TiltState {
+    pub fn reset(&mut self) {
+        *self = TiltState::default();
+    }
+
+    pub fn process(&mut self, tilt: &Tilt, input: f32) -> f32 {
+        self.low += (input - self.low) * tilt.coefficient;
+        self.low * tilt.low_gain + (input - self.low) * tilt.high_gain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    // Steady-state peak of a sine at `frequency` through `tilt`.
+    fn gain(tilt: f32, frequency: f32) -> f32 {
+        let tilt = Tilt::new(tilt, SAMPLE_RATE);
+        let mut state = TiltState::default();
+        let step = 2.0 * PI * frequency / SAMPLE_RATE;
+        (0..48000).map(|i| state.process(&tilt, (step * i as f32).sin())).skip(24000).fold(0.0, |peak: f32, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn zero_tilt_is_flat() {
+        let tilt = Tilt::new(0.0, SAMPLE_RATE);
+        let mut state = TiltState::default();
+        for i in 0..100 {
+            let input = (i as f32 * 0.37).sin();
+            assert!((state.process(&tilt, input) - input).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn tilt_turns_lows_and_highs_in_opposite_directions() {
+        let decibels = |gain: f32| 20.0 * gain.log10();
+        assert!((decibels(gain(1.0, 20.0)) + MAX_TILT).abs() < 0.3);
+        assert!(decibels(gain(1.0, 16000.0)) > MAX_TILT - 2.0);
+        assert!((decibels(gain(-1.0, 20.0)) - MAX_TILT).abs() < 0.3);
+        assert!(decibels(gain(-1.0, 16000.0)) < 2.0 - MAX_TILT);
+        assert_eq!(gain(2.0, 20.0), gain(1.0, 20.0));
+    }
+}
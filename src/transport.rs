@@ -0,0 +1,231 @@
+const DEFAULT_BPM: f32 = 120.0;
+const PULSES_PER_BEAT: f64 = 24.0;
+const CLOCK_TIMEOUT: f64 = 0.5;
+const CLOCK_SMOOTHING: f32 = 0.2;
+
+// Beat clock for everything tempo-synced. It runs on its own BPM, but follows
+// MIDI clock (24 pulses per beat) whenever pulses keep arriving, and a host
+// transport such as JACK's over both while the host is rolling.
+#[derive(Clone, Debug)]
+pub struct Transport {
+    bpm: f32,
+    playing: bool,
+    beat: f64,
+    now: f64,
+    last_pulse: Option<f64>,
+    pulses: u64,
+    external_bpm: Option<f32>,
+    host_bpm: Option<f32>,
+}
+
+impl Transport {
+    pub fn new() -> Transport {
+        Transport {
+            bpm: DEFAULT_BPM,
+            playing: false,
+            beat: 0.0,
+            now: 0.0,
+            last_pulse: None,
+            pulses: 0,
+            external_bpm: None,
+            host_bpm: None,
+        }
+    }
+
+    pub fn bpm(&self) -> f32 {
+        self.bpm
+    }
+
+    pub fn set_bpm(&mut self, bpm: f32) {
+        if bpm > 0.0 {
+            self.bpm = bpm;
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+        self.beat = 0.0;
+        self.pulses = 0;
+    }
+
+    pub fn resume(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn beat_position(&self) -> f64 {
+        self.beat
+    }
+
+    pub fn external_clock(&self) -> bool {
+        self.external_bpm.is_some()
+    }
+
+    pub fn host_sync(&self) -> bool {
+        self.host_bpm.is_some()
+    }
+
+    // Locks the beat position and tempo to a rolling host transport. Called
+    // once per block with the host's position at the block's first frame; the
+    // beat runs on at the host's tempo in between.
+    pub fn follow_host(&mut self, beat: f64, bpm: f32) {
+        if bpm <= 0.0 {
+            return;
+        }
+        self.playing = true;
+        self.beat = beat;
+        self.host_bpm = Some(bpm);
+    }
+
+    // The host transport has stopped: playback stops with it, and the
+    // internal and MIDI clocks take over again.
+    pub fn release_host(&mut self) {
+        if self.host_bpm.take().is_some() {
+            self.playing = false;
+        }
+    }
+
+    // The tempo in effect: the host transport if it is rolling, then MIDI
+    // clock if present, the internal BPM while playing, otherwise none and
+    // synced features free-run.
+    pub fn tempo(&self) -> Option<f32> {
+        match self.host_bpm.or(self.external_bpm) {
+            Some(bpm) => Some(bpm),
+            None if self.playing => Some(self.bpm),
+            None => None,
+        }
+    }
+
+    pub fn samples_per_beat(&self, sample_rate: usize) -> f64 {
+        60.0 * sample_rate as f64 / self.tempo().unwrap_or(self.bpm) as f64
+    }
+
+    pub fn advance(&mut self, time_step: f32) {
+        self.now += time_step as f64;
+        if self.last_pulse.is_some_and(|pulse| self.now - pulse > CLOCK_TIMEOUT) {
+            self.last_pulse = None;
+            self.external_bpm = None;
+        }
+
+        if self.playing {
+            match (self.host_bpm, self.external_bpm) {
+                (Some(bpm), _) => self.beat += bpm as f64 / 60.0 * time_step as f64,
+                // Between pulses, never run ahead of the next one.
+                (None, Some(bpm)) => {
+                    let next_pulse = self.pulses as f64 / PULSES_PER_BEAT;
+                    self.beat = (self.beat + bpm as f64 / 60.0 * time_step as f64).min(next_pulse);
+                }
+                (None, None) => self.beat += self.bpm as f64 / 60.0 * time_step as f64,
+            }
+        }
+    }
+
+    pub fn clock_pulse(&mut self) {
+        if let Some(last) = self.last_pulse {
+            let interval = self.now - last;
+            if interval > 0.0 {
+                let bpm = (60.0 / (interval * PULSES_PER_BEAT)) as f32;
+                self.external_bpm = Some(match self.external_bpm {
+                    Some(previous) => previous + (bpm - previous) * CLOCK_SMOOTHING,
+                    None => bpm,
+                });
+            }
+        }
+        self.last_pulse = Some(self.now);
+
+        if self.playing && self.host_bpm.is_none() {
+            self.beat = self.pulses as f64 / PULSES_PER_BEAT;
+            self.pulses += 1;
+        }
+    }
+}
+
+impl Default for Transport {
+    fn default() -> Transport {
+        Transport::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    fn run(transport: &mut Transport, frames: usize) {
+        for _ in 0..frames {
+            transport.advance(1.0 / SAMPLE_RATE as f32);
+        }
+    }
+
+    #[test]
+    fn the_beat_advances_once_per_samples_per_beat() {
+        let mut transport = Transport::new();
+        transport.set_bpm(90.0);
+        transport.play();
+        let samples_per_beat = transport.samples_per_beat(SAMPLE_RATE);
+        assert_eq!(samples_per_beat, 32000.0);
+        run(&mut transport, 3 * samples_per_beat as usize);
+        assert!((transport.beat_position() - 3.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn the_beat_holds_while_stopped() {
+        let mut transport = Transport::new();
+        run(&mut transport, SAMPLE_RATE);
+        assert_eq!(transport.beat_position(), 0.0);
+        assert_eq!(transport.tempo(), None);
+    }
+
+    #[test]
+    fn midi_clock_takes_over_and_times_out() {
+        let mut transport = Transport::new();
+        transport.play();
+        // 24 pulses per beat at 150 BPM is a pulse every 800 samples.
+        for _ in 0..48 {
+            transport.clock_pulse();
+            run(&mut transport, 800);
+        }
+        assert!(transport.external_clock());
+        assert!((transport.tempo().unwrap() - 150.0).abs() < 0.5);
+        run(&mut transport, SAMPLE_RATE);
+        assert!(!transport.external_clock());
+        assert_eq!(transport.tempo(), Some(DEFAULT_BPM));
+    }
+
+    #[test]
+    fn a_host_position_drives_the_beat() {
+        let mut transport = Transport::new();
+        transport.follow_host(4.0, 120.0);
+        assert!(transport.is_playing() && transport.host_sync());
+        assert_eq!(transport.tempo(), Some(120.0));
+        run(&mut transport, SAMPLE_RATE / 2);
+        assert!((transport.beat_position() - 5.0).abs() < 1e-3);
+        // The next block's position wins over the beat counted since.
+        transport.follow_host(8.5, 120.0);
+        assert_eq!(transport.beat_position(), 8.5);
+    }
+
+    #[test]
+    fn the_host_outranks_midi_clock_and_hands_back_on_release() {
+        let mut transport = Transport::new();
+        transport.play();
+        for _ in 0..48 {
+            transport.clock_pulse();
+            run(&mut transport, 800);
+        }
+        transport.follow_host(0.0, 100.0);
+        assert_eq!(transport.tempo(), Some(100.0));
+        transport.release_host();
+        assert!(!transport.is_playing() && !transport.host_sync());
+        transport.release_host();
+        assert!(!transport.is_playing());
+    }
+}
@@ -0,0 +1,79 @@
+const REFERENCE_FREQUENCY: f32 = 440.0;
+const REFERENCE_PITCH: u8 = 69;
+
+// Generates the frequency table the synthesizer plays from. Equal
+// temperament around `reference_pitch`, stretched the way pianos are tuned:
+// every octave away from the reference widens by `stretch` cents, and
+// `inharmonicity` adds cents growing with the square of the distance in
+// octaves, so the top end goes progressively sharp and the bottom flat. Both
+// at 0 give plain 12-TET.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Tuning {
+    pub reference_frequency: f32,
+    pub reference_pitch: u8,
+    pub stretch: f32,
+    pub inharmonicity: f32,
+}
+
+impl Default for Tuning {
+    fn default() -> Tuning {
+        Tuning {
+            reference_frequency: REFERENCE_FREQUENCY,
+            reference_pitch: REFERENCE_PITCH,
+            stretch: 0.0,
+            inharmonicity: 0.0,
+        }
+    }
+}
+
+impl Tuning {
+    // The deviation from equal temperament in cents for `pitch`.
+    pub fn offset(&self, pitch: u8) -> f32 {
+        let octaves = (pitch as f32 - self.reference_pitch as f32) / 12.0;
+        octaves * self.stretch + octaves * octaves.abs() * self.inharmonicity
+    }
+
+    pub fn frequencies(&self) -> [f32; 128] {
+        std::array::from_fn(|pitch| {
+            let semitones = pitch as f32 - self.reference_pitch as f32 + self.offset(pitch as u8) / 100.0;
+            self.reference_frequency * 2.0_f32.powf(semitones / 12.0)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn equal_temperament() -> [f32; 128] {
+        std::array::from_fn(|pitch| 440.0 * 2.0_f32.powf((pitch as f32 - 69.0) / 12.0))
+    }
+
+    fn cents(frequency: f32, reference: f32) -> f32 {
+        1200.0 * (frequency / reference).log2()
+    }
+
+    #[test]
+    fn no_stretch_is_plain_equal_temperament() {
+        assert_eq!(Tuning::default().frequencies(), equal_temperament());
+    }
+
+    #[test]
+    fn stretch_sharpens_the_top_and_flattens_the_bottom() {
+        let (stretched, equal) = (Tuning { stretch: 3.0, ..Tuning::default() }.frequencies(), equal_temperament());
+        assert_eq!(stretched[69], 440.0);
+        assert!((116..128).all(|pitch| cents(stretched[pitch], equal[pitch]) > 10.0));
+        assert!((0..12).all(|pitch| cents(stretched[pitch], equal[pitch]) < -10.0));
+        // Each octave from the reference is another `stretch` cents out.
+        assert!((cents(stretched[93], equal[93]) - 6.0).abs() < 0.01);
+        assert!((cents(stretched[45], equal[45]) + 6.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn inharmonicity_grows_with_the_square_of_the_distance() {
+        let tuning = Tuning { inharmonicity: 1.0, ..Tuning::default() };
+        assert_eq!(tuning.offset(81), 1.0);
+        assert_eq!(tuning.offset(93), 4.0);
+        assert_eq!(tuning.offset(45), -4.0);
+    }
+}
@@ -0,0 +1,97 @@
+const LEARNING_NOTES: usize = 16;
+const WIDEN: f32 = 0.25;
+const NARROW: f32 = 0.005;
+const MIN_SPAN: f32 = 16.0;
+
+// Learns the velocity range a controller actually sends and stretches it
+// over 1..127. Each bound jumps a quarter of the way out to a velocity beyond
+// it but creeps back in only slowly, so a single outlier moves the range a
+// little and is forgotten again. Velocities pass through unchanged for the
+// first LEARNING_NOTES notes while the range settles.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct VelocityRange {
+    low: f32,
+    high: f32,
+    notes: usize,
+}
+
+impl VelocityRange {
+    pub fn new() -> VelocityRange {
+        VelocityRange::default()
+    }
+
+    pub fn range(&self) -> (f32, f32) {
+        (self.low, self.high)
+    }
+
+    pub fn reset(&mut self) {
+        *self = VelocityRange::default();
+    }
+
+    // Learns from a note-on velocity and returns the rescaled one. 0, a
+    // note-off, is left alone.
+    pub fn apply(&mut self, velocity: u8) -> u8 {
+        if velocity == 0 {
+            return 0;
+        }
+        let value = velocity as f32;
+        if self.notes == 0 {
+            self.low = value;
+            self.high = value;
+        } else {
+            self.low += (value - self.low) * if value < self.low { WIDEN } else { NARROW };
+            self.high += (value - self.high) * if value > self.high { WIDEN } else { NARROW };
+        }
+        self.notes = self.notes.saturating_add(1);
+        if self.notes <= LEARNING_NOTES {
+            return velocity;
+        }
+
+        let span = (self.high - self.low).max(MIN_SPAN);
+        let center = 0.5 * (self.low + self.high);
+        let low = (center - 0.5 * span).max(1.0);
+        (1.0 + (value - low) / span * 126.0).round().clamp(1.0, 127.0) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Velocities cycling evenly through 40..=100.
+    fn narrow_band(range: &mut VelocityRange, notes: usize) -> Vec<u8> {
+        (0..notes).map(|note| range.apply(40 + (note * 7 % 61) as u8)).collect()
+    }
+
+    #[test]
+    fn a_narrow_band_is_stretched_over_the_full_range() {
+        let mut range = VelocityRange::new();
+        let learning = narrow_band(&mut range, LEARNING_NOTES);
+        assert!(learning.iter().all(|&velocity| (40..=100).contains(&velocity)));
+        narrow_band(&mut range, 200);
+        assert!(range.apply(40) <= 10, "{:?}", range.range());
+        assert!(range.apply(100) >= 117, "{:?}", range.range());
+        assert!(range.apply(70).abs_diff(64) <= 8);
+    }
+
+    #[test]
+    fn one_outlier_barely_moves_the_range() {
+        let mut range = VelocityRange::new();
+        narrow_band(&mut range, 200);
+        let (low, high) = range.range();
+        range.apply(127);
+        narrow_band(&mut range, 200);
+        assert!((range.range().1 - high).abs() < 3.0, "{} {}", range.range().1, high);
+        assert!((range.range().0 - low).abs() < 3.0);
+        assert_eq!(range.apply(0), 0);
+    }
+
+    #[test]
+    fn reset_forgets_the_learned_range() {
+        let mut range = VelocityRange::new();
+        narrow_band(&mut range, 200);
+        range.reset();
+        assert_eq!(range, VelocityRange::new());
+        assert_eq!(range.apply(40), 40);
+    }
+}
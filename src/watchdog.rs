@@ -0,0 +1,153 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub const WATCHDOG_THRESHOLD: f32 = 8.0;
+// How long a stage may stay over the threshold before it is reset, so a
+// single loud transient never trips it.
+const WATCHDOG_HOLD: f32 = 0.05;
+const WATCHDOG_FADE: f32 = 0.01;
+// The release of the peak follower the threshold is compared with, long
+// enough to ride over a waveform's zero crossings.
+const WATCHDOG_RELEASE: f32 = 0.02;
+
+// The master feedback stages the watchdog looks after.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FeedbackStage {
+    Delay,
+    Reverb,
+}
+
+// Cloneable handle counting watchdog resets per stage, for another thread to
+// log; the audio thread never prints.
+#[derive(Clone, Debug, Default)]
+pub struct WatchdogLog {
+    delay: Arc<AtomicU64>,
+    reverb: Arc<AtomicU64>,
+}
+
+impl WatchdogLog {
+    fn counter(&self, stage: FeedbackStage) -> &AtomicU64 {
+        match stage {
+            FeedbackStage::Delay => &self.delay,
+            FeedbackStage::Reverb => &self.reverb,
+        }
+    }
+
+    pub fn count(&self, stage: FeedbackStage) -> u64 {
+        self.counter(stage).load(Ordering::Relaxed)
+    }
+
+    // Reads and clears the count for `stage`.
+    pub fn take(&self, stage: FeedbackStage) -> u64 {
+        self.counter(stage).swap(0, Ordering::Relaxed)
+    }
+
+    pub fn add(&self, stage: FeedbackStage) {
+        self.counter(stage).fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Watches one feedback stage's output. A stage whose output peak stays above
+// `threshold` for WATCHDOG_HOLD seconds, or goes NaN or infinite at all, has
+// run away and should have its state cleared; its wet signal is then faded
+// back in over WATCHDOG_FADE seconds.
+#[derive(Copy, Clone, Debug)]
+pub struct Watchdog {
+    peak: f32,
+    over: f32,
+    fade: f32,
+}
+
+impl Watchdog {
+    pub fn new() -> Watchdog {
+        Watchdog { peak: 0.0, over: 0.0, fade: 1.0 }
+    }
+
+    // Whether the stage must be reset now, given its output for this frame.
+    pub fn check(&mut self, left: f32, right: f32, threshold: f32, time_step: f32) -> bool {
+        self.peak = left.abs().max(right.abs()).max(self.peak * (1.0 - time_step / WATCHDOG_RELEASE));
+        // f32::max drops a NaN, so the output itself is checked.
+        if !left.is_finite() || !right.is_finite() {
+            self.over = WATCHDOG_HOLD;
+        } else if self.peak > threshold {
+            self.over += time_step;
+        } else {
+            self.over = 0.0;
+        }
+        if self.over < WATCHDOG_HOLD {
+            return false;
+        }
+        *self = Watchdog { fade: 0.0, ..Watchdog::new() };
+        true
+    }
+
+    // Mixes the stage's output back over its input at the current fade gain
+    // and moves the fade on one frame.
+    pub fn blend(&mut self, input: (f32, f32), output: (f32, f32), time_step: f32) -> (f32, f32) {
+        if self.fade >= 1.0 {
+            return output;
+        }
+        let gain = self.fade;
+        self.fade = (self.fade + time_step / WATCHDOG_FADE).min(1.0);
+        (input.0 + (output.0 - input.0) * gain, input.1 + (output.1 - input.1) * gain)
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Watchdog {
+        Watchdog::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME_STEP: f32 = 1.0 / 48000.0;
+
+    // Frames of a steady `level` until the watchdog trips, if it does within a second.
+    fn frames_to_trip(level: f32) -> Option<usize> {
+        let mut watchdog = Watchdog::new();
+        (1..=48000).find(|_| watchdog.check(level, -level, 1.0, TIME_STEP))
+    }
+
+    #[test]
+    fn only_a_sustained_overload_trips_it() {
+        let frames = frames_to_trip(2.0).unwrap();
+        assert!((frames as f32 * TIME_STEP - WATCHDOG_HOLD).abs() < 2.0 * TIME_STEP, "{}", frames);
+        assert_eq!(frames_to_trip(0.9), None);
+        let mut watchdog = Watchdog::new();
+        let transient = (0..48000).map(|frame| if frame % 4800 < 480 { 2.0 } else { 0.0 });
+        assert!(!transient.into_iter().any(|level| watchdog.check(level, level, 1.0, TIME_STEP)));
+    }
+
+    #[test]
+    fn non_finite_output_trips_it_at_once() {
+        assert_eq!(frames_to_trip(f32::NAN), Some(1));
+        assert_eq!(frames_to_trip(f32::INFINITY), Some(1));
+    }
+
+    #[test]
+    fn a_reset_stage_fades_back_in() {
+        let mut watchdog = Watchdog::new();
+        assert_eq!(watchdog.blend((0.0, 0.0), (1.0, 1.0), TIME_STEP), (1.0, 1.0));
+        assert!(watchdog.check(f32::NAN, 0.0, 1.0, TIME_STEP));
+        let fade: Vec<f32> = (0..960).map(|_| watchdog.blend((0.0, 0.0), (1.0, 1.0), TIME_STEP).0).collect();
+        assert_eq!(fade[0], 0.0);
+        assert!(fade.windows(2).all(|pair| pair[1] >= pair[0]));
+        assert!((fade[240] - 0.5).abs() < 0.01, "{}", fade[240]);
+        assert_eq!(fade[959], 1.0);
+    }
+
+    #[test]
+    fn the_log_counts_resets_per_stage() {
+        let log = WatchdogLog::default();
+        let reader = log.clone();
+        log.add(FeedbackStage::Delay);
+        log.add(FeedbackStage::Delay);
+        assert_eq!(reader.count(FeedbackStage::Delay), 2);
+        assert_eq!(reader.count(FeedbackStage::Reverb), 0);
+        assert_eq!(reader.take(FeedbackStage::Delay), 2);
+        assert_eq!(reader.count(FeedbackStage::Delay), 0);
+    }
+}
@@ -0,0 +1,198 @@
+use std::f32::consts::PI;
+use std::io;
+use std::path::Path;
+
+const TABLE_SIZE: usize = 2048;
+const HARMONICS: usize = TABLE_SIZE / 2;
+const MIP_LEVELS: usize = 11;
+
+// Each table is stored as a set of band-limited copies, one per octave: level 0
+// keeps every harmonic, level n keeps HARMONICS >> n of them. Every copy has one
+// guard sample at the end so interpolation never needs to wrap.
+pub struct Wavetable {
+    tables: Vec<Vec<Vec<f32>>>,
+}
+
+impl Wavetable {
+    pub fn from_cycles(cycles: &[Vec<f32>]) -> Wavetable {
+        let harmonics: Vec<Vec<(f32, f32)>> = cycles.iter().map(|cycle| analyze(cycle)).collect();
+        Wavetable::from_harmonics(&harmonics)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Wavetable> {
+        let text = std::fs::read_to_string(path)?;
+        let mut cycles = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let cycle = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if cycle.len() < 2 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "wavetable cycle needs at least two samples"));
+            }
+            cycles.push(cycle);
+        }
+        if cycles.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "wavetable file contains no cycles"));
+        }
+        Ok(Wavetable::from_cycles(&cycles))
+    }
+
+    fn from_harmonics(harmonics: &[Vec<(f32, f32)>]) -> Wavetable {
+        let sine: Vec<f32> = (0..TABLE_SIZE).map(|i| (2.0 * PI * i as f32 / TABLE_SIZE as f32).sin()).collect();
+        let mut tables = Vec::with_capacity(harmonics.len());
+
+        for coefficients in harmonics {
+            let mut levels = Vec::with_capacity(MIP_LEVELS);
+            for level in 0..MIP_LEVELS {
+                let max_harmonic = (HARMONICS >> level).min(coefficients.len());
+                let mut samples = vec![0.0; TABLE_SIZE + 1];
+                for (h, &(cos, sin)) in coefficients.iter().enumerate().take(max_harmonic) {
+                    let harmonic = h + 1;
+                    for (i, sample) in samples.iter_mut().enumerate().take(TABLE_SIZE) {
+                        let angle = harmonic * i % TABLE_SIZE;
+                        *sample += cos * sine[(angle + TABLE_SIZE / 4) % TABLE_SIZE] + sin * sine[angle];
+                    }
+                }
+                samples[TABLE_SIZE] = samples[0];
+                levels.push(samples);
+            }
+            tables.push(levels);
+        }
+
+        Wavetable { tables }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    // `position` runs 0..1 across the tables, `phase` 0..1 across one cycle and
+    // `increment` is the phase advance per sample, used to pick the mip level.
+    pub fn sample(&self, position: f32, phase: f32, increment: f32) -> f32 {
+        if self.tables.is_empty() {
+            return 0.0;
+        }
+
+        let level = mip_level(increment);
+        let position = position.clamp(0.0, 1.0) * (self.tables.len() - 1) as f32;
+        let index = (position as usize).min(self.tables.len() - 1);
+        let blend = position - index as f32;
+
+        let a = read(&self.tables[index][level], phase);
+        if blend == 0.0 || index + 1 == self.tables.len() {
+            return a;
+        }
+        let b = read(&self.tables[index + 1][level], phase);
+        a + (b - a) * blend
+    }
+}
+
+impl Default for Wavetable {
+    fn default() -> Wavetable {
+        let sine = vec![(0.0, 1.0)];
+        let saw = (1..=HARMONICS)
+            .map(|h| {
+                let sign = if h % 2 == 1 { 1.0 } else { -1.0 };
+                (0.0, sign * 2.0 / (PI * h as f32))
+            })
+            .collect();
+        let square = (1..=HARMONICS)
+            .map(|h| if h % 2 == 1 { (0.0, 4.0 / (PI * h as f32)) } else { (0.0, 0.0) })
+            .collect();
+        Wavetable::from_harmonics(&[sine, saw, square])
+    }
+}
+
+fn mip_level(increment: f32) -> usize {
+    let max_harmonic = 0.5 / increment.abs().max(f32::EPSILON);
+    let mut level = 0;
+    while level + 1 < MIP_LEVELS && (HARMONICS >> level) as f32 > max_harmonic {
+        level += 1;
+    }
+    level
+}
+
+fn read(table: &[f32], phase: f32) -> f32 {
+    let position = phase.rem_euclid(1.0) * TABLE_SIZE as f32;
+    let index = (position as usize).min(TABLE_SIZE - 1);
+    let fraction = position - index as f32;
+    table[index] + (table[index + 1] - table[index]) * fraction
+}
+
+fn analyze(cycle: &[f32]) -> Vec<(f32, f32)> {
+    let n = cycle.len();
+    let harmonics = (n / 2).min(HARMONICS);
+    (1..=harmonics)
+        .map(|h| {
+            let mut cos = 0.0;
+            let mut sin = 0.0;
+            for (i, &value) in cycle.iter().enumerate() {
+                let angle = 2.0 * PI * (h * i % n) as f32 / n as f32;
+                cos += value * angle.cos();
+                sin += value * angle.sin();
+            }
+            let scale = if 2 * h == n { 1.0 } else { 2.0 } / n as f32;
+            (cos * scale, sin * scale)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INCREMENT: f32 = 440.0 / 48000.0;
+
+    fn cycle(harmonic: usize) -> Vec<f32> {
+        (0..64).map(|i| (2.0 * PI * (harmonic * i) as f32 / 64.0).sin()).collect()
+    }
+
+    #[test]
+    fn position_zero_reads_the_first_table() {
+        let wavetable = Wavetable::default();
+        for phase in [0.0, 0.1, 0.25, 0.6, 0.9] {
+            assert!((wavetable.sample(0.0, phase, INCREMENT) - (2.0 * PI * phase).sin()).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn the_midpoint_blends_two_tables() {
+        let wavetable = Wavetable::from_cycles(&[cycle(1), cycle(2)]);
+        for phase in [0.1, 0.2, 0.3, 0.7] {
+            let expected = 0.5 * ((2.0 * PI * phase).sin() + (4.0 * PI * phase).sin());
+            assert!((wavetable.sample(0.5, phase, INCREMENT) - expected).abs() < 1e-3);
+        }
+        assert!((wavetable.sample(1.0, 0.125, INCREMENT) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn high_notes_read_a_band_limited_copy() {
+        // At 0.3 of the sample rate the second harmonic would be past Nyquist.
+        let wavetable = Wavetable::default();
+        let fundamental = 2.0 / PI;
+        for phase in [0.1, 0.25, 0.4] {
+            let expected = fundamental * (2.0 * PI * phase).sin();
+            assert!((wavetable.sample(0.5, phase, 0.3) - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn a_file_without_cycles_is_rejected() {
+        let path = std::env::temp_dir().join(format!("wavetable-test-{}.txt", std::process::id()));
+        std::fs::write(&path, "# nothing but a comment\n").unwrap();
+        assert_eq!(Wavetable::from_file(&path).err().map(|error| error.kind()), Some(io::ErrorKind::InvalidData));
+        std::fs::write(&path, "0 1 0 -1\n0 1\n").unwrap();
+        assert_eq!(Wavetable::from_file(&path).map(|wavetable| wavetable.len()).ok(), Some(2));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use crate::lfo::{Lfo, LfoWaveform};
+use crate::random::Random;
+
+const WOW_RATE: f32 = 0.5;
+const FLUTTER_RATE: f32 = 8.0;
+const RANDOMNESS: f32 = 0.3;
+const MAX_DEPTH: f32 = 10.0;
+
+// Tape-style pitch instability: the master signal runs through a delay whose
+// length is swept by a slow wow LFO and a fast flutter LFO, each wandering by
+// `randomness` of its depth. Depths are the delay swing in milliseconds (up
+// to MAX_DEPTH each); with both at 0 the effect is bypassed.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct WowFlutter {
+    pub wow_depth: f32,
+    pub wow_rate: f32,
+    pub flutter_depth: f32,
+    pub flutter_rate: f32,
+    pub randomness: f32,
+}
+
+impl WowFlutter {
+    fn is_bypassed(&self) -> bool {
+        self.wow_depth <= 0.0 && self.flutter_depth <= 0.0
+    }
+
+    // Center delay in seconds; the sweep moves either side of it.
+    fn center(&self) -> f32 {
+        (self.wow_depth.clamp(0.0, MAX_DEPTH) + self.flutter_depth.clamp(0.0, MAX_DEPTH)) / 1000.0
+    }
+}
+
+impl Default for WowFlutter {
+    fn default() -> WowFlutter {
+        WowFlutter {
+            wow_depth: 0.0,
+            wow_rate: WOW_RATE,
+            flutter_depth: 0.0,
+            flutter_rate: FLUTTER_RATE,
+            randomness: RANDOMNESS,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WowFlutterState {
+    buffer: Vec<(f32, f32)>,
+    position: usize,
+    center: f32,
+    wow_lfo: Lfo,
+    flutter_lfo: Lfo,
+    random: Random,
+    wander: [f32; 2],
+    wander_target: [f32; 2],
+}
+
+impl WowFlutterState {
+    pub fn new() -> WowFlutterState {
+        WowFlutterState {
+            buffer: Vec::new(),
+            position: 0,
+            center: 0.0,
+            wow_lfo: Lfo::default(),
+            flutter_lfo: Lfo::default(),
+            random: Random::default(),
+            wander: [0.0; 2],
+            wander_target: [0.0; 2],
+        }
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    pub fn configure(&mut self, wow_flutter: &WowFlutter, sample_rate: usize) {
+        self.buffer.clear();
+        self.position = 0;
+        self.center = 0.0;
+        if wow_flutter.is_bypassed() {
+            return;
+        }
+        self.center = wow_flutter.center() * sample_rate as f32;
+        self.buffer.resize((2.0 * self.center).ceil() as usize + 2, (0.0, 0.0));
+    }
+
+    // Frames of delay at the center of the sweep.
+    pub fn latency(&self) -> usize {
+        self.center.round() as usize
+    }
+
+    pub fn process(&mut self, wow_flutter: &WowFlutter, left: f32, right: f32, time_step: f32) -> (f32, f32) {
+        if wow_flutter.is_bypassed() || self.buffer.is_empty() {
+            return (left, right);
+        }
+        self.buffer[self.position] = (left, right);
+
+        let randomness = wow_flutter.randomness.clamp(0.0, 1.0);
+        let rates = [wow_flutter.wow_rate, wow_flutter.flutter_rate];
+        for (i, rate) in rates.iter().enumerate() {
+            if self.random.next_f32() < rate * time_step {
+                self.wander_target[i] = self.random.next_bipolar();
+            }
+            self.wander[i] += (self.wander_target[i] - self.wander[i]) * (rate * time_step).min(1.0);
+        }
+        let wow = self.wow_lfo.next(LfoWaveform::Sine, wow_flutter.wow_rate, time_step);
+        let flutter = self.flutter_lfo.next(LfoWaveform::Sine, wow_flutter.flutter_rate, time_step);
+        let wow = (1.0 - randomness) * wow + randomness * self.wander[0];
+        let flutter = (1.0 - randomness) * flutter + randomness * self.wander[1];
+
+        let sample_rate = 1.0 / time_step;
+        let sweep = wow_flutter.wow_depth.clamp(0.0, MAX_DEPTH) * wow + wow_flutter.flutter_depth.clamp(0.0, MAX_DEPTH) * flutter;
+        let delay = (self.center + sweep / 1000.0 * sample_rate).clamp(0.0, (self.buffer.len() - 2) as f32);
+
+        let length = self.buffer.len();
+        let read = (self.position + length) as f32 - delay;
+        let index = read.floor() as usize;
+        let fraction = read - read.floor();
+        let (l0, r0) = self.buffer[index % length];
+        let (l1, r1) = self.buffer[(index + 1) % length];
+        self.position = (self.position + 1) % length;
+        (l0 + (l1 - l0) * fraction, r0 + (r1 - r0) * fraction)
+    }
+}
+
+impl Default for WowFlutterState {
+    fn default() -> WowFlutterState {
+        WowFlutterState::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: usize = 48000;
+
+    // Two seconds of a steady 1 kHz sine through the effect.
+    fn wobble(wow_flutter: &WowFlutter, seed: u64) -> Vec<f32> {
+        let mut state = WowFlutterState::new();
+        state.set_seed(seed);
+        state.configure(wow_flutter, SAMPLE_RATE);
+        let step = 2.0 * std::f32::consts::PI * 1000.0 / SAMPLE_RATE as f32;
+        (0..2 * SAMPLE_RATE).map(|i| state.process(wow_flutter, (step * i as f32).sin(), 0.0, 1.0 / SAMPLE_RATE as f32).0).collect()
+    }
+
+    // Frequency from the first and last rising zero crossing, each placed
+    // between samples by linear interpolation.
+    fn frequency(samples: &[f32]) -> f32 {
+        let crossings: Vec<f32> = (1..samples.len()).filter(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0).map(|i| i as f32 - samples[i] / (samples[i] - samples[i - 1])).collect();
+        (crossings.len() - 1) as f32 * SAMPLE_RATE as f32 / (crossings[crossings.len() - 1] - crossings[0])
+    }
+
+    #[test]
+    fn wow_bends_a_steady_tone_slowly_up_and_down() {
+        let wow = WowFlutter { wow_depth: 2.0, randomness: 0.0, ..WowFlutter::default() };
+        let out = wobble(&wow, 1);
+        let frequencies: Vec<f32> = out[4800..].chunks(2400).map(frequency).collect();
+        let (low, high) = frequencies.iter().fold((f32::MAX, 0.0_f32), |(low, high), &f| (low.min(f), high.max(f)));
+        assert!(low < 997.0 && high > 1003.0, "{} {}", low, high);
+        assert!(high < 1010.0 && low > 990.0, "{} {}", low, high);
+    }
+
+    #[test]
+    fn the_random_wander_follows_the_seed() {
+        let wow = WowFlutter { wow_depth: 2.0, flutter_depth: 0.5, ..WowFlutter::default() };
+        assert!(wobble(&wow, 7) == wobble(&wow, 7));
+        assert!(wobble(&wow, 7) != wobble(&wow, 8));
+    }
+
+    #[test]
+    fn zero_depth_is_a_bypass() {
+        let wow = WowFlutter::default();
+        let mut state = WowFlutterState::new();
+        state.configure(&wow, SAMPLE_RATE);
+        assert_eq!(state.latency(), 0);
+        assert_eq!(state.process(&wow, 0.3, -0.2, 1.0 / SAMPLE_RATE as f32), (0.3, -0.2));
+    }
+}